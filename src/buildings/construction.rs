@@ -0,0 +1,430 @@
+//! Construction sites, the builders that work on them and repairing finished buildings
+//!
+//! A site only makes progress while builders stand next to it under a `Construct` order.
+//! Each extra builder on the same site helps less than the one before, so piling workers
+//! onto one site pays off less than spreading them out. Repairing costs a share of the
+//! building's price for every hit point restored.
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use crate::combat::Health;
+use crate::defs::{BuildingDef, BuildingDefs};
+use crate::mobile_base::spawn_mobile_base;
+use crate::navigation::{NavBlocker, NavGrid};
+use crate::player::{LocalPlayer, Owner, PlayerId};
+use crate::production::ProductionQueue;
+use crate::resources::Treasury;
+use crate::units::{Command, CommandHotkeys, OrderQueue, Selectable, Selected};
+use super::BuildRadius;
+
+/// Fraction of the finished building's health a new construction site starts with
+const SITE_START_HEALTH: f32 = 0.1;
+
+/// Distance in meters the blocker of a building stays inside its footprint, so rounding at
+/// the edges never blocks the neighbouring cells
+const BLOCKER_MARGIN: f32 = 0.1;
+
+/// How much each further builder on a site counts compared to the one before it
+const BUILDER_FALLOFF: f32 = 0.75;
+
+/// Fraction of a building's cost spent repairing it from no health to full
+const REPAIR_COST_FRACTION: f64 = 0.5;
+
+/// Default distance in meters from a footprint within which a builder can work on it,
+/// enough to reach from the middle of a neighbouring tile
+const DEFAULT_BUILD_RANGE: f32 = 15.0;
+
+/// How advanced the buildings a builder can construct are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize, Reflect)]
+pub enum BuildTier {
+    /// Simple structures any worker can put up
+    #[default]
+    Basic,
+    /// Structures that need engineers
+    Advanced,
+}
+
+/// Component for units that can construct and repair buildings
+#[derive(Component, Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+#[reflect(Component, Default)]
+#[serde(default)]
+pub struct Builder {
+    /// Most advanced buildings the unit can construct
+    pub tier: BuildTier,
+    /// Construction speed, where 1.0 finishes a building in its build time
+    pub rate: f32,
+    /// Hit points restored per second when repairing, or 0 if the unit cannot repair
+    pub repair_rate: f32,
+    /// Distance in meters from the edge of a footprint within which the unit can work on it
+    pub range: f32,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new(BuildTier::Basic, 1.0)
+    }
+}
+
+impl Builder {
+    /// Create a builder that cannot repair
+    pub fn new(tier: BuildTier, rate: f32) -> Self {
+        Self { tier, rate, repair_rate: 0.0, range: DEFAULT_BUILD_RANGE }
+    }
+}
+
+/// Component for finished buildings
+#[derive(Component, Debug, Clone, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct Building {
+    /// Id of the building definition
+    pub def: String,
+}
+
+/// Component for a building that is still being constructed
+#[derive(Component, Debug, Clone, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct ConstructionSite {
+    /// Id of the building definition being constructed
+    pub building: String,
+    /// Builders below this tier cannot work on the site
+    pub tier: BuildTier,
+    /// Size of the footprint in meters
+    pub size: Vec2,
+    /// Fraction of the construction done (0.0 to 1.0)
+    pub progress: f32,
+}
+
+/// Request to cancel a construction site and get its cost back
+#[derive(Event, Debug, Clone)]
+pub struct CancelConstruction {
+    /// Player giving the order, who must own the site
+    pub player: PlayerId,
+    /// The construction site
+    pub site: Entity,
+}
+
+/// A construction site was finished and replaced by its building
+#[derive(Event, Debug, Clone)]
+pub struct BuildingCompleted {
+    /// Owner of the building
+    pub player: PlayerId,
+    /// The finished building
+    pub building: Entity,
+    /// Id of the building definition
+    pub def: String,
+}
+
+/// Get the size of a building's footprint in meters
+pub fn footprint_size(def: &BuildingDef, cell_size: f32) -> Vec2 {
+    def.footprint.as_vec2() * cell_size
+}
+
+/// Get the distance from a point to the nearest edge of a footprint, or zero inside it
+fn distance_to_footprint(point: Vec2, centre: Vec2, size: Vec2) -> f32 {
+    ((point - centre).abs() - size / 2.0).max(Vec2::ZERO).length()
+}
+
+/// Spawn a construction site for a player's building centred on `position`
+pub fn spawn_construction_site(
+    commands: &mut Commands,
+    def: &BuildingDef,
+    owner: PlayerId,
+    position: Vec2,
+    cell_size: f32,
+) -> Entity {
+    let size = footprint_size(def, cell_size);
+    commands.spawn((
+        Name::new(format!("{} (under construction)", def.name)),
+        ConstructionSite {
+            building: def.id.clone(),
+            tier: def.tier,
+            size,
+            progress: 0.0,
+        },
+        Health {
+            current: def.health * SITE_START_HEALTH,
+            max: def.health,
+        },
+        def.armor,
+        Owner(owner),
+        Selectable::new(def.id.clone(), size.min_element() / 2.0),
+        NavBlocker { half_extents: size / 2.0 - Vec2::splat(BLOCKER_MARGIN) },
+        TransformBundle::from_transform(Transform::from_translation(position.extend(1.0))),
+    )).id()
+}
+
+/// Spawn a finished building for a player centred on `position`
+pub fn spawn_building(
+    commands: &mut Commands,
+    def: &BuildingDef,
+    owner: PlayerId,
+    position: Vec2,
+    cell_size: f32,
+) -> Entity {
+    if let Some(base) = spawn_mobile_base(commands, def, owner, position) {
+        return base;
+    }
+    let size = footprint_size(def, cell_size);
+    let mut building = commands.spawn((
+        Name::new(def.name.clone()),
+        Building { def: def.id.clone() },
+        def.health(),
+        def.armor,
+        Owner(owner),
+        Selectable::new(def.id.clone(), size.min_element() / 2.0),
+        NavBlocker { half_extents: size / 2.0 - Vec2::splat(BLOCKER_MARGIN) },
+        TransformBundle::from_transform(Transform::from_translation(position.extend(1.0))),
+    ));
+    if let Some(weapon) = def.weapon() {
+        building.insert(weapon);
+    }
+    if def.build_radius > 0.0 {
+        building.insert(BuildRadius::new(def.build_radius));
+    }
+    if let Some(queue) = ProductionQueue::for_building(def) {
+        building.insert(queue);
+    }
+    building.id()
+}
+
+/// System to advance construction sites with the builders working on them and replace
+/// finished sites with their buildings
+pub fn construct_buildings(
+    mut commands: Commands,
+    time: Res<Time>,
+    defs: Res<BuildingDefs>,
+    grid: Res<NavGrid>,
+    mut builders: Query<(&Builder, &Owner, &mut OrderQueue, &GlobalTransform)>,
+    mut sites: Query<(&mut ConstructionSite, &mut Health, &Owner, &GlobalTransform)>,
+    mut completed_events: EventWriter<BuildingCompleted>,
+) {
+    let mut crews: HashMap<Entity, Vec<f32>> = HashMap::new();
+    for (builder, owner, mut queue, transform) in builders.iter_mut() {
+        let Some(&Command::Construct { site: site_entity }) = queue.current() else { continue };
+        // Orders for sites that are gone are dropped when the builder looks for them
+        let Ok((site, _, site_owner, site_transform)) = sites.get(site_entity) else { continue };
+        if site_owner != owner || builder.tier < site.tier {
+            warn!("A builder of {} cannot work on the '{}' site {:?}", owner.0, site.building, site_entity);
+            queue.complete_current();
+            continue;
+        }
+        let distance = distance_to_footprint(
+            transform.translation().truncate(),
+            site_transform.translation().truncate(),
+            site.size,
+        );
+        if distance <= builder.range {
+            crews.entry(site_entity).or_default().push(builder.rate);
+        }
+    }
+
+    let mut crews: Vec<(Entity, Vec<f32>)> = crews.into_iter().collect();
+    crews.sort_by_key(|(site, _)| *site);
+    for (site_entity, mut rates) in crews {
+        let Ok((mut site, mut health, owner, transform)) = sites.get_mut(site_entity) else { continue };
+        let Some(def) = defs.get(&site.building) else { continue };
+        rates.sort_by(|a, b| b.total_cmp(a));
+        let rate: f32 = rates.iter()
+            .enumerate()
+            .map(|(index, rate)| rate * BUILDER_FALLOFF.powi(index as i32))
+            .sum();
+        let remaining = 1.0 - site.progress;
+        let step = (rate * time.delta_seconds() / def.build_time).min(remaining);
+        site.progress = if step >= remaining { 1.0 } else { site.progress + step };
+        // The site grows sturdier as it goes up, keeping any damage it has taken
+        let max = health.max;
+        health.heal(max * (1.0 - SITE_START_HEALTH) * step);
+
+        if site.progress >= 1.0 {
+            commands.entity(site_entity).despawn_recursive();
+            let position = transform.translation().truncate();
+            let building = spawn_building(&mut commands, def, owner.0, position, grid.cell_size());
+            commands.entity(building).insert(health.clone());
+            completed_events.send(BuildingCompleted { player: owner.0, building, def: def.id.clone() });
+        }
+    }
+}
+
+/// System to restore the health of buildings being repaired, charging for every hit point
+pub fn repair_buildings(
+    time: Res<Time>,
+    defs: Res<BuildingDefs>,
+    grid: Res<NavGrid>,
+    mut treasury: Treasury,
+    mut repairers: Query<(&Builder, &Owner, &mut OrderQueue, &GlobalTransform)>,
+    mut buildings: Query<(&Building, &mut Health, &Owner, &GlobalTransform)>,
+) {
+    for (builder, owner, mut queue, transform) in repairers.iter_mut() {
+        let Some(&Command::Repair { target }) = queue.current() else { continue };
+        let Ok((building, mut health, target_owner, target_transform)) = buildings.get_mut(target) else { continue };
+        let Some(def) = defs.get(&building.def) else { continue };
+        if builder.repair_rate <= 0.0 || target_owner != owner || health.current >= health.max {
+            queue.complete_current();
+            continue;
+        }
+        let distance = distance_to_footprint(
+            transform.translation().truncate(),
+            target_transform.translation().truncate(),
+            footprint_size(def, grid.cell_size()),
+        );
+        if distance > builder.range {
+            continue;
+        }
+
+        let amount = (builder.repair_rate * time.delta_seconds()).min(health.max - health.current);
+        let cost = def.cost.scaled(REPAIR_COST_FRACTION * (amount / health.max) as f64);
+        match treasury.try_spend(owner.0, &cost) {
+            Ok(()) => {
+                health.heal(amount);
+            }
+            Err(err) => {
+                info!("{} stops repairing {}: {}", owner.0, def.name, err);
+                queue.complete_current();
+            }
+        }
+    }
+}
+
+/// System to cancel requested construction sites, refunding their full cost
+pub fn handle_cancel_requests(
+    mut commands: Commands,
+    defs: Res<BuildingDefs>,
+    mut treasury: Treasury,
+    mut requests: EventReader<CancelConstruction>,
+    sites: Query<(&ConstructionSite, &Owner)>,
+) {
+    // Despawning waits until the end of the frame, so repeated requests must not refund twice
+    let mut cancelled: Vec<Entity> = Vec::new();
+    for request in requests.read() {
+        let Ok((site, owner)) = sites.get(request.site) else {
+            warn!("{:?} is not a construction site", request.site);
+            continue;
+        };
+        if owner.0 != request.player {
+            warn!("{} cannot cancel {:?} owned by {}", request.player, request.site, owner.0);
+            continue;
+        }
+        if cancelled.contains(&request.site) {
+            continue;
+        }
+        cancelled.push(request.site);
+        if let Some(def) = defs.get(&site.building) {
+            treasury.refund(request.player, &def.cost);
+        }
+        commands.entity(request.site).despawn_recursive();
+    }
+}
+
+/// Selected construction sites and who owns them
+type SelectedSites<'w, 's> =
+    Query<'w, 's, (Entity, &'static Owner), (With<ConstructionSite>, With<Selected>)>;
+
+/// System to cancel the local player's selected construction sites with the hotkey
+pub fn cancel_selected_sites(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    hotkeys: Res<CommandHotkeys>,
+    local_player: Res<LocalPlayer>,
+    sites: SelectedSites,
+    mut cancel_events: EventWriter<CancelConstruction>,
+) {
+    if !keyboard_input.just_pressed(hotkeys.cancel_construction) {
+        return;
+    }
+    for (site, owner) in sites.iter() {
+        if owner.0 == local_player.0 {
+            cancel_events.send(CancelConstruction { player: local_player.0, site });
+        }
+    }
+}
+
+/// System to draw the outline and progress of each construction site
+pub fn draw_construction_sites(
+    sites: Query<(&ConstructionSite, &GlobalTransform)>,
+    mut gizmos: Gizmos,
+) {
+    let color = Color::srgb(1.0, 0.8, 0.3);
+    for (site, transform) in sites.iter() {
+        let centre = transform.translation().truncate();
+        gizmos.rect_2d(centre, 0.0, site.size, color);
+        let start = centre - site.size / 2.0;
+        gizmos.line_2d(start, start + Vec2::new(site.size.x * site.progress, 0.0), color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::transform::systems::sync_simple_transforms;
+    use crate::defs::Definition;
+    use crate::resources::{spawn_stocked_storage, step_seconds, treasury_test_world, ResourceStorage, ResourceType};
+
+    #[test]
+    fn test_builders_construct_repair_and_cancel() {
+        let mut world = treasury_test_world();
+        world.insert_resource(NavGrid::new(20, 20, Vec2::ZERO, 20.0));
+        let defs = BuildingDefs::load_from_file(BuildingDef::FILE).expect("building definitions load");
+        let barracks = defs.get("barracks").unwrap().clone();
+        let command_center = defs.get("command_center").unwrap().clone();
+        world.insert_resource(defs);
+        world.init_resource::<Events<BuildingCompleted>>();
+        world.init_resource::<Events<CancelConstruction>>();
+        let player = PlayerId(0);
+        let storage = spawn_stocked_storage(&mut world, player, &[(ResourceType::Wood, 500), (ResourceType::Stone, 200)]);
+
+        let centre = Vec2::splat(100.0);
+        let site = spawn_construction_site(&mut world.commands(), &barracks, player, centre, 20.0);
+        let advanced_site = spawn_construction_site(&mut world.commands(), &command_center, player, Vec2::splat(300.0), 20.0);
+        world.flush();
+        let spawn_builder = |world: &mut World, builder: Builder, position: Vec2, command: Command| {
+            let mut queue = OrderQueue::default();
+            queue.push(command, false);
+            world.spawn((builder, Owner(player), queue, GlobalTransform::from_translation(position.extend(0.0)))).id()
+        };
+        let near = centre + Vec2::new(40.0, 0.0);
+        spawn_builder(&mut world, Builder::default(), near, Command::Construct { site });
+        let late = spawn_builder(&mut world, Builder::default(), Vec2::splat(350.0), Command::Construct { site });
+        let too_basic = spawn_builder(&mut world, Builder::default(), Vec2::splat(300.0), Command::Construct { site: advanced_site });
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((sync_simple_transforms, handle_cancel_requests, construct_buildings, repair_buildings).chain());
+        let progress = |world: &World| world.get::<ConstructionSite>(site).map(|site| site.progress);
+
+        // Only builders next to the site work on it, and basic builders cannot raise advanced buildings
+        step_seconds(&mut world, &mut schedule, 10);
+        assert!((progress(&world).unwrap() - 0.25).abs() < 1e-3);
+        assert!((world.get::<Health>(site).unwrap().current - (80.0 + 720.0 * 0.25)).abs() < 0.5);
+        assert!(world.get::<OrderQueue>(too_basic).unwrap().is_empty());
+
+        // A second builder adds less than the first
+        world.entity_mut(late).insert(GlobalTransform::from_translation(near.extend(0.0)));
+        step_seconds(&mut world, &mut schedule, 10);
+        assert!((progress(&world).unwrap() - (0.25 + 1.75 * 10.0 / 40.0)).abs() < 1e-3);
+        step_seconds(&mut world, &mut schedule, 8);
+        assert!(world.get_entity(site).is_none());
+        let (building, health) = world.query::<(Entity, &Health)>()
+            .iter(&world)
+            .find(|&(entity, _)| world.get::<Building>(entity).is_some())
+            .map(|(entity, health)| (entity, health.clone()))
+            .expect("the barracks was built");
+        assert!((health.current - 800.0).abs() < 0.5);
+        assert_eq!(world.resource::<Events<BuildingCompleted>>().len(), 1);
+
+        // Repairing restores health and charges half the cost for every full health bar
+        world.get_mut::<Health>(building).unwrap().current = 400.0;
+        let engineer = Builder { repair_rate: 25.0, ..Builder::new(BuildTier::Advanced, 1.5) };
+        spawn_builder(&mut world, engineer, near, Command::Repair { target: building });
+        step_seconds(&mut world, &mut schedule, 4);
+        assert!((world.get::<Health>(building).unwrap().current - 500.0).abs() < 0.5);
+        let wood = |world: &World| world.get::<ResourceStorage>(storage).unwrap().get_amount(ResourceType::Wood).to_f64();
+        assert!((wood(&world) - (500.0 - 150.0 * 0.5 * 100.0 / 800.0)).abs() < 0.1);
+
+        // Cancelling refunds the full cost once, however often it is requested, while the repair goes on
+        let before = wood(&world) - 150.0 * 0.5 * 25.0 / 800.0;
+        world.send_event(CancelConstruction { player, site: advanced_site });
+        world.send_event(CancelConstruction { player, site: advanced_site });
+        step_seconds(&mut world, &mut schedule, 1);
+        assert!(world.get_entity(advanced_site).is_none());
+        assert!((wood(&world) - before - 400.0).abs() < 0.1);
+    }
+}
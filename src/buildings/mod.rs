@@ -0,0 +1,71 @@
+//! Placing and constructing buildings
+//!
+//! The local player picks a building and moves a ghost of its footprint over the map,
+//! snapped to the tile grid and coloured by whether it can go there. Confirming sends a
+//! `PlaceBuilding` request, which is checked again, paid for and turned into a construction
+//! site. Buildings can only go on open, buildable tiles inside the playable area and within
+//! the build radius of one of the player's bases. Builder units then raise the site into
+//! the finished building, and can later repair it.
+
+mod construction;
+mod placement;
+
+pub use construction::*;
+pub use placement::*;
+
+use bevy::prelude::*;
+use crate::state::GameState;
+use crate::units::{handle_selection_input, issue_player_commands};
+
+/// Component for entities whose owner may place buildings around them
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct BuildRadius {
+    /// Distance in meters from the entity within which buildings can be placed
+    pub radius: f32,
+}
+
+impl BuildRadius {
+    /// Create a build radius of the given size
+    pub fn new(radius: f32) -> Self {
+        Self { radius }
+    }
+}
+
+impl Default for BuildRadius {
+    fn default() -> Self {
+        Self::new(100.0)
+    }
+}
+
+/// Plugin for placing and constructing buildings
+pub struct BuildingsPlugin;
+
+impl Plugin for BuildingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<BuildRadius>()
+            .register_type::<ConstructionSite>()
+            .register_type::<Building>()
+            .register_type::<Builder>()
+            .init_resource::<PlacementInput>()
+            .add_event::<PlaceBuilding>()
+            .add_event::<BuildingPlaced>()
+            .add_event::<CancelConstruction>()
+            .add_event::<BuildingCompleted>()
+            .add_systems(Update, (
+                update_placement_input
+                    .before(handle_selection_input)
+                    .before(issue_player_commands),
+                draw_placement_ghost.after(update_placement_input),
+                cancel_selected_sites,
+            ).run_if(in_state(GameState::InGame { is_paused: false })))
+            .add_systems(Update, (
+                handle_place_requests.after(update_placement_input),
+                handle_cancel_requests.after(cancel_selected_sites),
+                construct_buildings,
+                repair_buildings,
+                draw_construction_sites,
+            ))
+            .add_systems(OnExit(GameState::InGame { is_paused: false }), clear_placement);
+    }
+}
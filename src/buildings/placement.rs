@@ -0,0 +1,432 @@
+//! Placing buildings on the map
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use thiserror::Error;
+use crate::camera_controls::GameCursor;
+use crate::campaign_menu::Faction;
+use crate::defs::{BuildingDef, BuildingDefs};
+use crate::navigation::NavGrid;
+use crate::player::{LocalPlayer, Owner, PlayerFactions, PlayerId};
+use crate::resources::{SpendError, Treasury};
+use crate::technology::{check_unlocked, LockedError, Technologies};
+use crate::units::{Command, CommandHotkeys, IssueCommand, Selected};
+use crate::world::{TerrainType, WorldConfig};
+use super::{spawn_construction_site, BuildRadius, Builder};
+
+/// Reasons a building cannot be placed
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PlacementError {
+    /// No building definition has the id
+    #[error("Unknown building '{0}'")]
+    UnknownBuilding(String),
+
+    /// Part of the footprint is outside the playable area
+    #[error("Outside the playable area")]
+    OutOfBounds,
+
+    /// Part of the footprint is on terrain that cannot be built on
+    #[error("Cannot build on {0:?}")]
+    Terrain(TerrainType),
+
+    /// Part of the footprint is covered by another building or a resource node
+    #[error("Something is in the way")]
+    Obstructed,
+
+    /// The building is not within the build radius of any of the player's bases
+    #[error("Too far from a base")]
+    OutsideBuildRadius,
+
+    /// The player has not unlocked the building
+    #[error(transparent)]
+    Locked(#[from] LockedError),
+
+    /// The player cannot pay for the building
+    #[error("Cannot afford building: {0}")]
+    CannotAfford(#[from] SpendError),
+}
+
+/// Request to place a building and start constructing it
+#[derive(Event, Debug, Clone)]
+pub struct PlaceBuilding {
+    /// Player paying for the building
+    pub player: PlayerId,
+    /// Id of the building definition
+    pub building: String,
+    /// Where to put the building; it is snapped to the tile grid
+    pub position: Vec2,
+    /// Units to order to construct the building
+    pub builders: Vec<Entity>,
+    /// Queue the construction after the builders' current orders instead of replacing them
+    pub queued: bool,
+}
+
+/// A building was placed and its construction site spawned
+#[derive(Event, Debug, Clone)]
+pub struct BuildingPlaced {
+    /// Player who placed the building
+    pub player: PlayerId,
+    /// The construction site
+    pub site: Entity,
+    /// Id of the building definition
+    pub building: String,
+}
+
+/// Where the building being placed would go and whether it can go there
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacementGhost {
+    /// Snapped centre of the footprint
+    pub position: Vec2,
+    /// Size of the footprint in tiles
+    pub footprint: UVec2,
+    /// Whether the building can be placed, and why not
+    pub result: Result<(), PlacementError>,
+}
+
+/// Resource tracking the local player's building placement
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PlacementInput {
+    /// Id of the building being placed, if any
+    pub building: Option<String>,
+    /// The ghost under the cursor, updated every frame while placing
+    pub ghost: Option<PlacementGhost>,
+}
+
+/// Check whether buildings can stand on a terrain type
+pub fn is_buildable(terrain_type: TerrainType) -> bool {
+    matches!(terrain_type, TerrainType::Grassland | TerrainType::Desert | TerrainType::Quarry)
+}
+
+/// Snap a point to the centre of the nearest footprint that lines up with the tile grid
+pub fn snap_to_grid(grid: &NavGrid, footprint: UVec2, point: Vec2) -> Vec2 {
+    // Footprints with an even size are centred on a tile corner rather than a tile centre
+    let offset = (footprint.as_vec2() - Vec2::ONE) * grid.cell_size() / 2.0;
+    grid.cell_to_world(grid.world_to_cell(point - offset)) + offset
+}
+
+/// Get the cells covered by a footprint centred on a snapped position
+pub fn footprint_cells(grid: &NavGrid, footprint: UVec2, position: Vec2) -> Vec<IVec2> {
+    let offset = (footprint.as_vec2() - Vec2::ONE) * grid.cell_size() / 2.0;
+    let corner = grid.world_to_cell(position - offset);
+    (0..footprint.y as i32)
+        .flat_map(|y| (0..footprint.x as i32).map(move |x| corner + IVec2::new(x, y)))
+        .collect()
+}
+
+/// Check that a single cell of a footprint can be built on
+pub fn check_cell(grid: &NavGrid, config: &WorldConfig, cell: IVec2) -> Result<(), PlacementError> {
+    let terrain_type = grid.terrain(cell).ok_or(PlacementError::OutOfBounds)?;
+    let half = Vec2::splat(grid.cell_size() / 2.0);
+    let centre = grid.cell_to_world(cell);
+    let min = Vec2::splat(config.border_width);
+    let max = Vec2::new(config.width_meters, config.height_meters) - min;
+    if (centre - half).cmplt(min).any() || (centre + half).cmpgt(max).any() {
+        return Err(PlacementError::OutOfBounds);
+    }
+    if !is_buildable(terrain_type) {
+        return Err(PlacementError::Terrain(terrain_type));
+    }
+    if grid.is_blocked(cell) {
+        return Err(PlacementError::Obstructed);
+    }
+    Ok(())
+}
+
+/// Check that a building fits at a snapped position and lies within one of the build areas,
+/// given as centres and radii
+pub fn check_placement(
+    def: &BuildingDef,
+    position: Vec2,
+    grid: &NavGrid,
+    config: &WorldConfig,
+    build_areas: &[(Vec2, f32)],
+) -> Result<(), PlacementError> {
+    for cell in footprint_cells(grid, def.footprint, position) {
+        check_cell(grid, config, cell)?;
+    }
+    if !build_areas.iter().any(|&(centre, radius)| centre.distance(position) <= radius) {
+        return Err(PlacementError::OutsideBuildRadius);
+    }
+    Ok(())
+}
+
+/// Collect the centres and radii of a player's build areas
+fn build_areas(areas: &Query<(&BuildRadius, &Owner, &GlobalTransform)>, player: PlayerId) -> Vec<(Vec2, f32)> {
+    areas.iter()
+        .filter(|(_, owner, _)| owner.0 == player)
+        .map(|(area, _, transform)| (transform.translation().truncate(), area.radius))
+        .collect()
+}
+
+/// Get the ids of the buildings a faction can place on their own, in the order the build
+/// hotkey cycles through them
+fn placeable_buildings(defs: &BuildingDefs, faction: Faction) -> Vec<&str> {
+    let mut ids: Vec<&str> = defs.iter()
+        // Modules do nothing on the ground and are bought onto bases instead, except armed
+        // ones that double as defences
+        .filter(|def| def.module.is_none() || def.weapon.is_some())
+        .filter(|def| def.available_to(faction))
+        .map(|def| def.id.as_str())
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// Mouse buttons, keys and hotkeys used while placing; the mouse buttons are writable so
+/// clicks used for placing are not seen by selection and orders
+#[derive(SystemParam)]
+pub struct PlacementControls<'w> {
+    mouse_buttons: ResMut<'w, ButtonInput<MouseButton>>,
+    keyboard_input: Res<'w, ButtonInput<KeyCode>>,
+    hotkeys: Res<'w, CommandHotkeys>,
+}
+
+/// Everything that decides whether a player can place a building somewhere
+#[derive(SystemParam)]
+pub struct PlacementRules<'w, 's> {
+    defs: Res<'w, BuildingDefs>,
+    config: Res<'w, WorldConfig>,
+    grid: Res<'w, NavGrid>,
+    technologies: Res<'w, Technologies>,
+    player_factions: Res<'w, PlayerFactions>,
+    areas: Query<'w, 's, (&'static BuildRadius, &'static Owner, &'static GlobalTransform)>,
+    treasury: Treasury<'w, 's>,
+}
+
+impl PlacementRules<'_, '_> {
+    /// Check that a player has unlocked a building and that it fits at a snapped position
+    fn check(&self, player: PlayerId, def: &BuildingDef, position: Vec2) -> Result<(), PlacementError> {
+        check_unlocked(&self.technologies, &self.player_factions, player, &def.factions, &def.requires)?;
+        check_placement(def, position, &self.grid, &self.config, &build_areas(&self.areas, player))
+    }
+}
+
+/// System to pick a building with the hotkey, move its ghost with the cursor and confirm
+/// placement with a left click; shift-click keeps placing copies
+pub fn update_placement_input(
+    controls: PlacementControls,
+    game_cursor: GameCursor,
+    local_player: Res<LocalPlayer>,
+    rules: PlacementRules,
+    builders: Query<(Entity, &Builder, &Owner), With<Selected>>,
+    mut input: ResMut<PlacementInput>,
+    mut place_events: EventWriter<PlaceBuilding>,
+) {
+    let PlacementControls { mut mouse_buttons, keyboard_input, hotkeys } = controls;
+    let player = local_player.0;
+    if keyboard_input.just_pressed(hotkeys.build) {
+        let ids = placeable_buildings(&rules.defs, rules.player_factions.get(player));
+        input.building = match input.building.as_deref().and_then(|current| ids.iter().position(|&id| id == current)) {
+            Some(index) => ids.get(index + 1).map(|id| id.to_string()),
+            None => ids.first().map(|id| id.to_string()),
+        };
+        info!("Placing: {:?}", input.building);
+    }
+    if input.building.is_some()
+        && (keyboard_input.just_pressed(KeyCode::Escape) || mouse_buttons.just_pressed(MouseButton::Right))
+    {
+        // Cancelling takes the right-click so it does not also give an order
+        mouse_buttons.clear_just_pressed(MouseButton::Right);
+        input.building = None;
+    }
+
+    input.ghost = None;
+    let Some(def) = input.building.as_deref().and_then(|id| rules.defs.get(id)) else { return };
+    let Some(point) = game_cursor.world_position() else { return };
+
+    let position = snap_to_grid(&rules.grid, def.footprint, point);
+    let result = rules.check(player, def, position)
+        .and_then(|()| Ok(rules.treasury.can_afford(player, &def.cost)?));
+
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        // The click is ours, so it must not also change the selection
+        mouse_buttons.clear_just_pressed(MouseButton::Left);
+        match &result {
+            Ok(()) => {
+                let shift = keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+                let mut selected_builders: Vec<Entity> = builders.iter()
+                    .filter(|(_, builder, owner)| owner.0 == player && builder.tier >= def.tier)
+                    .map(|(entity, ..)| entity)
+                    .collect();
+                selected_builders.sort();
+                place_events.send(PlaceBuilding {
+                    player,
+                    building: def.id.clone(),
+                    position,
+                    builders: selected_builders,
+                    queued: shift,
+                });
+                if !shift {
+                    input.building = None;
+                }
+            }
+            Err(err) => info!("Cannot place {}: {}", def.name, err),
+        }
+    }
+    input.ghost = Some(PlacementGhost { position, footprint: def.footprint, result });
+}
+
+/// System to check, pay for and start constructing requested buildings
+pub fn handle_place_requests(
+    mut commands: Commands,
+    mut rules: PlacementRules,
+    mut requests: EventReader<PlaceBuilding>,
+    mut placed_events: EventWriter<BuildingPlaced>,
+    mut command_events: EventWriter<IssueCommand>,
+) {
+    // Sites placed this frame are not on the grid yet, so their cells are tracked here
+    let mut claimed: Vec<IVec2> = Vec::new();
+    for request in requests.read() {
+        let result = (|| -> Result<(BuildingDef, Vec2), PlacementError> {
+            let def = rules.defs.get(&request.building)
+                .ok_or_else(|| PlacementError::UnknownBuilding(request.building.clone()))?
+                .clone();
+            let position = snap_to_grid(&rules.grid, def.footprint, request.position);
+            rules.check(request.player, &def, position)?;
+            if footprint_cells(&rules.grid, def.footprint, position).iter().any(|cell| claimed.contains(cell)) {
+                return Err(PlacementError::Obstructed);
+            }
+            rules.treasury.try_spend(request.player, &def.cost)?;
+            Ok((def, position))
+        })();
+
+        match result {
+            Ok((def, position)) => {
+                claimed.extend(footprint_cells(&rules.grid, def.footprint, position));
+                let site = spawn_construction_site(&mut commands, &def, request.player, position, rules.grid.cell_size());
+                placed_events.send(BuildingPlaced { player: request.player, site, building: def.id.clone() });
+                if !request.builders.is_empty() {
+                    command_events.send(IssueCommand {
+                        player: request.player,
+                        units: request.builders.clone(),
+                        command: Command::Construct { site },
+                        queued: request.queued,
+                    });
+                }
+            }
+            Err(err) => warn!("{} cannot place '{}': {}", request.player, request.building, err),
+        }
+    }
+}
+
+/// System to draw the placement ghost, marking the tiles that block it, and the build areas
+pub fn draw_placement_ghost(
+    input: Res<PlacementInput>,
+    grid: Res<NavGrid>,
+    config: Res<WorldConfig>,
+    local_player: Res<LocalPlayer>,
+    areas: Query<(&BuildRadius, &Owner, &GlobalTransform)>,
+    mut gizmos: Gizmos,
+) {
+    let Some(ghost) = &input.ghost else { return };
+    let valid = Color::srgb(0.3, 1.0, 0.4);
+    let invalid = Color::srgb(1.0, 0.3, 0.3);
+
+    for (centre, radius) in build_areas(&areas, local_player.0) {
+        gizmos.circle_2d(centre, radius, Color::srgba(0.6, 0.8, 1.0, 0.4));
+    }
+    let color = if ghost.result.is_ok() { valid } else { invalid };
+    gizmos.rect_2d(ghost.position, 0.0, ghost.footprint.as_vec2() * grid.cell_size(), color);
+    for cell in footprint_cells(&grid, ghost.footprint, ghost.position) {
+        if check_cell(&grid, &config, cell).is_err() {
+            gizmos.rect_2d(grid.cell_to_world(cell), 0.0, Vec2::splat(grid.cell_size() * 0.8), invalid);
+        }
+    }
+}
+
+/// System to leave placement mode when leaving the game
+pub fn clear_placement(mut input: ResMut<PlacementInput>) {
+    *input = PlacementInput::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildings::ConstructionSite;
+    use crate::defs::Definition;
+    use crate::resources::{ResourceAmount, ResourceRefunded, ResourceSpent, ResourceStorage, ResourceType};
+
+    #[test]
+    fn test_placement_checks_footprint_and_spends_cost() {
+        let config = WorldConfig {
+            width_meters: 300.0,
+            height_meters: 300.0,
+            tile_size: 20.0,
+            border_width: 50.0,
+            ..default()
+        };
+        let mut grid = NavGrid::from_config(&config);
+        grid.set_terrain(IVec2::new(6, 6), TerrainType::Water);
+        let defs = BuildingDefs::load_from_file(BuildingDef::FILE).expect("building definitions load");
+        let barracks = defs.get("barracks").unwrap().clone();
+        assert_eq!(barracks.footprint, UVec2::new(3, 3));
+
+        // Odd footprints centre on a tile, even ones on a tile corner
+        let cell_centre = grid.cell_to_world(IVec2::new(4, 4));
+        assert_eq!(snap_to_grid(&grid, barracks.footprint, cell_centre + Vec2::splat(4.0)), cell_centre);
+        assert_eq!(snap_to_grid(&grid, UVec2::new(2, 2), cell_centre + Vec2::splat(4.0)), cell_centre + Vec2::splat(10.0));
+
+        // Modules and buildings of other factions are not offered for placement
+        let placeable = placeable_buildings(&defs, Faction::Mechanists);
+        assert!(placeable.contains(&"barracks") && placeable.contains(&"turret") && !placeable.contains(&"cargo_hold"));
+        let synthetic = BuildingDef { factions: vec![Faction::Synthetics], ..barracks.clone() };
+        let synthetic_defs = BuildingDefs::from_defs("buildings.ron", vec![synthetic]).unwrap();
+        assert!(placeable_buildings(&synthetic_defs, Faction::Mechanists).is_empty());
+        assert_eq!(placeable_buildings(&synthetic_defs, Faction::Synthetics), vec!["barracks"]);
+
+        let areas = [(cell_centre, 60.0)];
+        assert_eq!(check_placement(&barracks, cell_centre, &grid, &config, &areas), Ok(()));
+        assert_eq!(
+            check_placement(&barracks, grid.cell_to_world(IVec2::new(1, 4)), &grid, &config, &areas),
+            Err(PlacementError::OutOfBounds)
+        );
+        assert_eq!(
+            check_placement(&barracks, grid.cell_to_world(IVec2::new(6, 5)), &grid, &config, &areas),
+            Err(PlacementError::Terrain(TerrainType::Water))
+        );
+        assert_eq!(
+            check_placement(&barracks, cell_centre, &grid, &config, &[(Vec2::ZERO, 60.0)]),
+            Err(PlacementError::OutsideBuildRadius)
+        );
+
+        let mut world = World::new();
+        world.insert_resource(config);
+        world.insert_resource(grid);
+        world.insert_resource(defs);
+        world.init_resource::<Technologies>();
+        world.init_resource::<PlayerFactions>();
+        world.init_resource::<Events<PlaceBuilding>>();
+        world.init_resource::<Events<BuildingPlaced>>();
+        world.init_resource::<Events<IssueCommand>>();
+        world.init_resource::<Events<ResourceSpent>>();
+        world.init_resource::<Events<ResourceRefunded>>();
+        let player = PlayerId(0);
+        let mut storage = ResourceStorage::new();
+        storage.add_resource(ResourceType::Wood, ResourceAmount::from_units(1000));
+        storage.add_resource(ResourceType::Stone, ResourceAmount::from_units(500));
+        world.spawn((
+            Owner(player),
+            storage,
+            BuildRadius::new(60.0),
+            GlobalTransform::from_translation(cell_centre.extend(0.0)),
+        ));
+        let mut schedule = Schedule::default();
+        schedule.add_systems(handle_place_requests);
+
+        // The second request overlaps the first and is refused without being charged
+        let place = |world: &mut World, position: Vec2| {
+            world.send_event(PlaceBuilding { player, building: "barracks".to_string(), position, builders: Vec::new(), queued: false });
+        };
+        place(&mut world, cell_centre);
+        place(&mut world, cell_centre + Vec2::X * 20.0);
+        schedule.run(&mut world);
+
+        let sites: Vec<&ConstructionSite> = world.query::<&ConstructionSite>().iter(&world).collect();
+        assert_eq!(sites.len(), 1);
+        assert_eq!(sites[0].building, "barracks");
+        assert_eq!(sites[0].size, Vec2::splat(60.0));
+        let wood = world.query::<&ResourceStorage>().single(&world).get_amount(ResourceType::Wood);
+        assert_eq!(wood, ResourceAmount::from_units(1000) - barracks.cost.get(ResourceType::Wood));
+        assert_eq!(world.resource::<Events<BuildingPlaced>>().len(), 1);
+    }
+}
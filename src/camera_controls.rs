@@ -6,6 +6,7 @@ use bevy::{
     render::camera::Camera,
     math::{Vec2, Vec3},
     ecs::query::{With, Without},
+    ecs::system::SystemParam,
 };
 
 use crate::world::WorldConfig;
@@ -53,6 +54,36 @@ pub struct GameCamera {
     pub target_position: Vec3,
 }
 
+/// The primary window and game camera, for mapping the cursor onto the map
+#[derive(SystemParam)]
+pub struct GameCursor<'w, 's> {
+    windows: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    cameras: Query<'w, 's, (&'static Camera, &'static GlobalTransform), With<GameCamera>>,
+}
+
+impl GameCursor<'_, '_> {
+    /// Get the cursor position in window coordinates
+    pub fn screen_position(&self) -> Option<Vec2> {
+        self.windows.get_single().ok()?.cursor_position()
+    }
+
+    /// Get the size of the window
+    pub fn window_size(&self) -> Option<Vec2> {
+        self.windows.get_single().ok().map(|window| window.size())
+    }
+
+    /// Convert a window position to a position on the map
+    pub fn to_world(&self, screen: Vec2) -> Option<Vec2> {
+        let (camera, camera_transform) = self.cameras.get_single().ok()?;
+        camera.viewport_to_world_2d(camera_transform, screen)
+    }
+
+    /// Get the position on the map under the cursor
+    pub fn world_position(&self) -> Option<Vec2> {
+        self.to_world(self.screen_position()?)
+    }
+}
+
 // Update the camera pan system to use smoothing
 pub fn camera_pan_system(
     window_query: Query<&Window, With<PrimaryWindow>>,
@@ -1,10 +1,10 @@
 //! Faction definitions for StrategyForge campaigns
 
 use bevy::prelude::*;
-use bevy_reflect::Reflect;
+use serde::{Serialize, Deserialize};
 
 /// Available factions in the game
-#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Default)]
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Default, Serialize, Deserialize)]
 #[reflect(Component)]
 pub enum Faction {
     /// Masters of industrial machinery and heavy weaponry
@@ -0,0 +1,5 @@
+//! Campaign data shared between the menus and the game
+
+mod faction;
+
+pub use faction::*;
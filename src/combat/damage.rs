@@ -0,0 +1,141 @@
+//! Damage types, armour classes and the damage multiplier table
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+use crate::data_file::{parse_builtin, read_data_file, DataFileError};
+
+const DAMAGE_TABLE_FILE: &str = "assets/data/damage.ron";
+
+/// Kind of damage a weapon deals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Reflect)]
+pub enum DamageType {
+    /// Standard damage
+    #[default]
+    Normal,
+    /// Armour-piercing rounds, effective against heavy armour
+    Piercing,
+    /// Blast damage, effective against structures and groups
+    Explosive,
+    /// Flak and missiles made for hitting aircraft
+    AntiAir,
+}
+
+/// Kind of protection a target has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Reflect)]
+pub enum ArmorClass {
+    /// Infantry and workers
+    #[default]
+    Unarmored,
+    /// Light vehicles
+    Light,
+    /// Tanks and other heavy vehicles
+    Heavy,
+    /// Buildings and bases
+    Structure,
+    /// Aircraft
+    Air,
+}
+
+/// Errors that can occur when loading the damage table
+#[derive(Error, Debug)]
+pub enum DamageTableLoadError {
+    /// The damage table file could not be read or parsed
+    #[error(transparent)]
+    File(#[from] DataFileError),
+
+    /// A multiplier is negative or not a number
+    #[error("Damage multiplier for {damage_type:?} against {armor_class:?} must be at least 0, got {value}")]
+    InvalidMultiplier {
+        /// Damage type of the entry
+        damage_type: DamageType,
+        /// Armour class of the entry
+        armor_class: ArmorClass,
+        /// The rejected multiplier
+        value: f32,
+    },
+}
+
+/// Resource holding how much of each damage type gets through each armour class
+///
+/// Pairs missing from the table deal full damage. A multiplier of zero means the damage
+/// type cannot hurt that armour class at all.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct DamageTable {
+    multipliers: HashMap<DamageType, HashMap<ArmorClass, f32>>,
+}
+
+impl DamageTable {
+    /// Build a damage table from multipliers per damage type and armour class
+    pub fn from_multipliers(
+        multipliers: HashMap<DamageType, HashMap<ArmorClass, f32>>,
+    ) -> Result<Self, DamageTableLoadError> {
+        for (&damage_type, row) in &multipliers {
+            for (&armor_class, &value) in row {
+                if value.is_nan() || value < 0.0 {
+                    return Err(DamageTableLoadError::InvalidMultiplier { damage_type, armor_class, value });
+                }
+            }
+        }
+        Ok(Self { multipliers })
+    }
+
+    /// Load a damage table from a RON file mapping damage types to armour class multipliers
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self, DamageTableLoadError> {
+        Self::from_multipliers(read_data_file(path)?)
+    }
+
+    /// Built-in damage table used when no data file is available
+    pub fn builtin() -> Self {
+        Self::from_multipliers(parse_builtin(DAMAGE_TABLE_FILE, include_str!("../../assets/data/damage.ron")))
+        .expect("built-in damage table is valid")
+    }
+
+    /// Get the multiplier for a damage type hitting an armour class
+    pub fn multiplier(&self, damage_type: DamageType, armor_class: ArmorClass) -> f32 {
+        self.multipliers.get(&damage_type)
+            .and_then(|row| row.get(&armor_class))
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    /// Check whether a damage type can hurt an armour class at all
+    pub fn can_damage(&self, damage_type: DamageType, armor_class: ArmorClass) -> bool {
+        self.multiplier(damage_type, armor_class) > 0.0
+    }
+}
+
+/// System to load the damage table, falling back to the built-in table
+pub fn load_damage_table(mut commands: Commands) {
+    let table = match DamageTable::load_from_file(DAMAGE_TABLE_FILE) {
+        Ok(table) => table,
+        Err(err) => {
+            warn!("{}; using built-in damage table", err);
+            DamageTable::builtin()
+        }
+    };
+    commands.insert_resource(table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_damage_table_file_matches_format() {
+        let table = DamageTable::load_from_file(DAMAGE_TABLE_FILE).expect("damage table loads");
+        assert_eq!(DamageTable::builtin().multipliers, table.multipliers);
+        assert!(!table.can_damage(DamageType::Normal, ArmorClass::Air));
+        assert!(table.multiplier(DamageType::AntiAir, ArmorClass::Air) > 1.0);
+        // Pairs that are not listed deal full damage
+        assert_eq!(table.multiplier(DamageType::Normal, ArmorClass::Light), 1.0);
+
+        let invalid = ron::from_str("{ Normal: { Heavy: -1.0 } }").unwrap();
+        assert!(matches!(
+            DamageTable::from_multipliers(invalid),
+            Err(DamageTableLoadError::InvalidMultiplier { damage_type: DamageType::Normal, .. })
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+//! Health, armour and applying damage
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::player::{Owner, PlayerId};
+use crate::units::FormationMember;
+use super::{ArmorClass, DamageTable, DamageType};
+
+/// Smallest fraction of a hit that gets through armour, as long as the damage type can hurt the target
+const MIN_DAMAGE_FRACTION: f32 = 0.1;
+
+/// Component for entities that can be damaged and destroyed
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Health {
+    /// Current hit points
+    pub current: f32,
+    /// Maximum hit points
+    pub max: f32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new(100.0)
+    }
+}
+
+impl Health {
+    /// Create full health with the given maximum
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    /// Get the fraction of health remaining (0.0 to 1.0)
+    pub fn fraction(&self) -> f32 {
+        if self.max > 0.0 { (self.current / self.max).clamp(0.0, 1.0) } else { 0.0 }
+    }
+
+    /// Check whether the entity has no health left
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+
+    /// Remove health, returning how much was actually removed
+    pub fn damage(&mut self, amount: f32) -> f32 {
+        let removed = amount.clamp(0.0, self.current.max(0.0));
+        self.current -= removed;
+        removed
+    }
+
+    /// Restore health up to the maximum, returning how much was actually restored
+    pub fn heal(&mut self, amount: f32) -> f32 {
+        let restored = amount.clamp(0.0, (self.max - self.current).max(0.0));
+        self.current += restored;
+        restored
+    }
+
+    /// Change the maximum, keeping the damage taken; this never heals fully nor kills
+    pub fn set_max(&mut self, max: f32) {
+        self.current = (self.current + max - self.max).max(self.current.min(1.0)).min(max);
+        self.max = max;
+    }
+}
+
+/// Component describing how an entity is protected
+#[derive(Component, Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, Reflect)]
+#[reflect(Component, Default)]
+pub struct Armor {
+    /// Armour class, which decides the damage multiplier of each damage type
+    pub class: ArmorClass,
+    /// Flat damage removed from every hit
+    pub value: f32,
+}
+
+impl Armor {
+    /// Create armour of a class with a flat reduction
+    pub fn new(class: ArmorClass, value: f32) -> Self {
+        Self { class, value }
+    }
+}
+
+/// Request to deal damage to an entity
+#[derive(Event, Debug, Clone)]
+pub struct DamageEvent {
+    /// Entity being hit
+    pub target: Entity,
+    /// Entity that dealt the damage, if it still exists
+    pub source: Option<Entity>,
+    /// Player who dealt the damage, if any
+    pub attacker: Option<PlayerId>,
+    /// Damage before armour
+    pub amount: f32,
+    /// Kind of damage
+    pub damage_type: DamageType,
+}
+
+/// An entity lost health
+#[derive(Event, Debug, Clone)]
+pub struct EntityDamaged {
+    /// Entity that was hit
+    pub entity: Entity,
+    /// Entity that dealt the damage, if known
+    pub source: Option<Entity>,
+    /// Health actually removed after armour
+    pub amount: f32,
+}
+
+/// An entity ran out of health and is being removed
+#[derive(Event, Debug, Clone)]
+pub struct EntityDied {
+    /// The destroyed entity
+    pub entity: Entity,
+    /// Player who owned it, if any
+    pub owner: Option<PlayerId>,
+    /// Entity that dealt the final blow, if known
+    pub killer: Option<Entity>,
+    /// Player who dealt the final blow, if any
+    pub killer_owner: Option<PlayerId>,
+    /// Where it died
+    pub position: Vec2,
+}
+
+/// Get the damage a hit does after the damage table and armour
+pub fn damage_after_armor(table: &DamageTable, amount: f32, damage_type: DamageType, armor: Armor) -> f32 {
+    let multiplier = table.multiplier(damage_type, armor.class);
+    if multiplier <= 0.0 {
+        return 0.0;
+    }
+    let scaled = amount * multiplier;
+    (scaled - armor.value).max(scaled * MIN_DAMAGE_FRACTION)
+}
+
+/// Entities that can take damage, with what affects how much they take
+type DamageTargets<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Health,
+        Option<&'static Armor>,
+        Option<&'static FormationMember>,
+        Option<&'static Owner>,
+        &'static GlobalTransform,
+    ),
+>;
+
+/// System to apply damage requests to health and announce deaths
+pub fn apply_damage(
+    table: Res<DamageTable>,
+    mut damage_events: EventReader<DamageEvent>,
+    mut targets: DamageTargets,
+    mut damaged_events: EventWriter<EntityDamaged>,
+    mut died_events: EventWriter<EntityDied>,
+) {
+    for event in damage_events.read() {
+        let Ok((mut health, armor, formation, owner, transform)) = targets.get_mut(event.target) else { continue };
+        if health.is_dead() {
+            continue;
+        }
+        let mut armor = armor.copied().unwrap_or_default();
+        if let Some(member) = formation {
+            armor.value += member.bonus.armor;
+        }
+        let removed = health.damage(damage_after_armor(&table, event.amount, event.damage_type, armor));
+        if removed <= 0.0 {
+            continue;
+        }
+        damaged_events.send(EntityDamaged { entity: event.target, source: event.source, amount: removed });
+        if health.is_dead() {
+            died_events.send(EntityDied {
+                entity: event.target,
+                owner: owner.map(|owner| owner.0),
+                killer: event.source,
+                killer_owner: event.attacker,
+                position: transform.translation().truncate(),
+            });
+        }
+    }
+}
+
+/// System to despawn destroyed entities
+pub fn despawn_dead(
+    mut commands: Commands,
+    mut died_events: EventReader<EntityDied>,
+) {
+    for event in died_events.read() {
+        if let Some(entity) = commands.get_entity(event.entity) {
+            entity.despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Combat: health, armour, weapons and projectiles
+//!
+//! Weapons fire at their target once it is in range. Every hit, direct or splash, becomes a
+//! `DamageEvent`, which is scaled by the damage table for the target's armour class and
+//! reduced by its flat armour before it comes off the target's health. Entities that run
+//! out of health announce their death and are despawned. Everything runs on the fixed
+//! timestep after movement, so fights play out the same way every time.
+//!
+//! Units that are not under an explicit attack order pick their own targets according to
+//! their stance, looking them up in a spatial index that is rebuilt every tick.
+
+mod damage;
+mod health;
+mod spatial;
+mod targeting;
+mod weapons;
+
+pub use damage::*;
+pub use health::*;
+pub use spatial::*;
+pub use targeting::*;
+pub use weapons::*;
+
+use bevy::prelude::*;
+use crate::units::integrate_movement;
+
+/// Plugin for combat
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Health>()
+            .register_type::<Armor>()
+            .register_type::<Weapon>()
+            .register_type::<Projectile>()
+            .register_type::<Stance>()
+            .register_type::<Targeting>()
+            .register_type::<Engagement>()
+            .init_resource::<DamageTable>()
+            .init_resource::<SpatialIndex>()
+            .add_event::<DamageEvent>()
+            .add_event::<EntityDamaged>()
+            .add_event::<EntityDied>()
+            .add_systems(Startup, load_damage_table)
+            .add_systems(FixedUpdate, (
+                update_spatial_index,
+                record_attackers,
+                acquire_targets,
+                fire_weapons,
+                update_projectiles,
+                apply_damage,
+                despawn_dead,
+            ).chain().after(integrate_movement))
+            .add_systems(Update, (add_targeting, draw_projectiles));
+    }
+}
@@ -0,0 +1,67 @@
+//! Spatial index of damageable entities for range queries
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+use super::Health;
+
+/// Resource bucketing every damageable entity by position, rebuilt each tick
+#[derive(Resource, Debug, Clone)]
+pub struct SpatialIndex {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<(Entity, Vec2)>>,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        Self::new(16.0)
+    }
+}
+
+impl SpatialIndex {
+    /// Create an empty index with buckets of the given size in meters
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size: cell_size.max(f32::EPSILON),
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell_of(&self, position: Vec2) -> IVec2 {
+        (position / self.cell_size).floor().as_ivec2()
+    }
+
+    /// Remove every entity
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    /// Add an entity at a position
+    pub fn insert(&mut self, entity: Entity, position: Vec2) {
+        let cell = self.cell_of(position);
+        self.cells.entry(cell).or_default().push((entity, position));
+    }
+
+    /// Iterate over every entity within `radius` of `centre`, with its position
+    pub fn within(&self, centre: Vec2, radius: f32) -> impl Iterator<Item = (Entity, Vec2)> + '_ {
+        let min = self.cell_of(centre - Vec2::splat(radius));
+        let max = self.cell_of(centre + Vec2::splat(radius));
+        let radius_sq = radius * radius;
+        (min.y..=max.y)
+            .flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .filter(move |(_, position)| position.distance_squared(centre) <= radius_sq)
+    }
+}
+
+/// System to rebuild the spatial index from every damageable entity
+pub fn update_spatial_index(
+    mut index: ResMut<SpatialIndex>,
+    entities: Query<(Entity, &GlobalTransform), With<Health>>,
+) {
+    index.clear();
+    for (entity, transform) in entities.iter() {
+        index.insert(entity, transform.translation().truncate());
+    }
+}
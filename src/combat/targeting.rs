@@ -0,0 +1,378 @@
+//! Stances and automatic target acquisition
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::cmp::Ordering;
+use crate::navigation::{FlowFieldGoal, NavGrid, NavPath};
+use crate::player::{Alliances, Owner};
+use crate::units::{Command, Destination, FormationMember, Movement, OrderQueue};
+use super::{Armor, DamageTable, EntityDamaged, Health, SpatialIndex, Weapon};
+
+/// How much further than its sight range a unit looks for whoever is shooting at it
+const RETALIATION_RANGE_FACTOR: f32 = 2.0;
+
+/// How a unit engages enemies it has not been ordered to attack
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Reflect)]
+#[reflect(Component, Default)]
+pub enum Stance {
+    /// Attack anything in sight and chase it for as long as it stays in sight
+    #[default]
+    Aggressive,
+    /// Attack anything in sight, but give up the chase past the leash and walk back
+    Defensive,
+    /// Never fire without an explicit attack order
+    HoldFire,
+    /// Attack anything in weapon range without moving
+    HoldPosition,
+}
+
+impl Stance {
+    /// Every stance, in the order the stance hotkey cycles through them
+    pub fn all() -> [Stance; 4] {
+        [Stance::Aggressive, Stance::Defensive, Stance::HoldFire, Stance::HoldPosition]
+    }
+
+    /// Get the stance after this one
+    pub fn next(self) -> Stance {
+        let all = Self::all();
+        let index = all.iter().position(|&stance| stance == self).unwrap_or(0);
+        all[(index + 1) % all.len()]
+    }
+}
+
+/// Component for armed units that look for targets on their own
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Targeting {
+    /// Distance in meters within which enemies are noticed
+    pub sight_range: f32,
+    /// Distance in meters a defensive unit chases an enemy before returning
+    pub leash: f32,
+    /// Entity that last damaged this unit
+    pub last_attacker: Option<Entity>,
+}
+
+impl Default for Targeting {
+    fn default() -> Self {
+        Self {
+            sight_range: 30.0,
+            leash: 20.0,
+            last_attacker: None,
+        }
+    }
+}
+
+/// Component for units fighting an enemy they found themselves
+///
+/// While engaged the unit's current order is on hold; it is picked up again once the fight is over.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct Engagement {
+    /// Enemy being fought
+    pub target: Entity,
+    /// Where the unit was when the fight started
+    pub home: Vec2,
+}
+
+/// What a unit knows about a possible target when choosing between them
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    entity: Entity,
+    /// Whether the candidate attacked this unit
+    attacker: bool,
+    /// Whether the candidate's weapon can hurt this unit
+    threat: bool,
+    health: f32,
+    distance: f32,
+}
+
+impl Candidate {
+    /// Order candidates so the preferred one comes first: attackers, then threats, then the weakest,
+    /// then the closest, with the entity breaking ties so every client picks the same one
+    fn priority(&self, other: &Candidate) -> Ordering {
+        other.attacker.cmp(&self.attacker)
+            .then(other.threat.cmp(&self.threat))
+            .then(self.health.total_cmp(&other.health))
+            .then(self.distance.total_cmp(&other.distance))
+            .then(self.entity.cmp(&other.entity))
+    }
+}
+
+/// Potential targets, with what decides whether and how hard they can be hit
+type TargetInfo<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static GlobalTransform,
+        Option<&'static Health>,
+        Option<&'static Armor>,
+        Option<&'static Owner>,
+        Option<&'static Weapon>,
+    ),
+>;
+
+/// Armed units that have not been given targeting yet
+type UntargetedUnits<'w, 's> =
+    Query<'w, 's, (Entity, Has<Stance>), (With<Weapon>, Without<Targeting>)>;
+
+/// Units with targeting, with the orders and movement that decide whether they look for targets
+type TargetingUnits<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut Targeting,
+        &'static Stance,
+        &'static GlobalTransform,
+        &'static Owner,
+        Option<&'static mut OrderQueue>,
+        Option<&'static Engagement>,
+        Option<&'static Destination>,
+        Has<FormationMember>,
+        Has<Movement>,
+    ),
+>;
+
+/// System to give armed units targeting and a stance
+pub fn add_targeting(
+    mut commands: Commands,
+    units: UntargetedUnits,
+) {
+    for (entity, has_stance) in units.iter() {
+        let mut unit = commands.entity(entity);
+        unit.insert(Targeting::default());
+        if !has_stance {
+            unit.insert(Stance::default());
+        }
+    }
+}
+
+/// System to remember who damaged each unit so it can fight back
+pub fn record_attackers(
+    mut damaged_events: EventReader<EntityDamaged>,
+    mut units: Query<&mut Targeting>,
+) {
+    for event in damaged_events.read() {
+        if let (Ok(mut targeting), Some(source)) = (units.get_mut(event.entity), event.source) {
+            targeting.last_attacker = Some(source);
+        }
+    }
+}
+
+/// System to pick targets for units that are free to fight, and chase or return according to stance
+///
+/// Only units that are idle, holding position, attack-moving or patrolling look for targets;
+/// explicit attack orders choose their own target and plain moves ignore enemies. Candidates
+/// come from the spatial index, and must be enemies the unit's weapon can actually hurt.
+pub fn acquire_targets(
+    mut commands: Commands,
+    index: Res<SpatialIndex>,
+    table: Res<DamageTable>,
+    alliances: Res<Alliances>,
+    grid: Res<NavGrid>,
+    mut units: TargetingUnits,
+    mut weapons: ParamSet<(TargetInfo, Query<&mut Weapon>)>,
+) {
+    let repath_distance = grid.cell_size().max(1.0);
+    let mut chosen: Vec<(Entity, Option<Entity>)> = Vec::new();
+    for (entity, mut targeting, stance, transform, owner, queue, engagement, destination, in_formation, mobile) in units.iter_mut() {
+        let info = weapons.p0();
+        let Ok((_, _, armor, _, Some(weapon))) = info.get(entity) else { continue };
+        let position = transform.translation().truncate();
+        let armor_class = armor.map(|armor| armor.class).unwrap_or_default();
+        let current = queue.as_ref().and_then(|queue| queue.current().copied());
+
+        // Explicit attack orders are handled by the order queue
+        if matches!(current, Some(Command::Attack { .. })) {
+            if engagement.is_some() {
+                commands.entity(entity).remove::<Engagement>();
+            }
+            continue;
+        }
+
+        let free = matches!(
+            current,
+            None | Some(Command::AttackMove { .. } | Command::Patrol { .. } | Command::HoldPosition)
+        );
+        // Turrets and formation members fire from where they are
+        let holding = *stance == Stance::HoldPosition
+            || matches!(current, Some(Command::HoldPosition))
+            || in_formation
+            || !mobile;
+        let defensive = *stance == Stance::Defensive && !holding;
+        let home = engagement.map_or(position, |engagement| engagement.home);
+        let leash = targeting.leash;
+        let search_range = if holding { weapon.range } else { targeting.sight_range.max(weapon.range) };
+
+        let hostile = |candidate: Entity| -> Option<(Vec2, Candidate)> {
+            let (candidate_transform, health, candidate_armor, candidate_owner, candidate_weapon) =
+                info.get(candidate).ok()?;
+            let (health, candidate_owner) = (health?, candidate_owner?.0);
+            if health.is_dead() || candidate_owner == owner.0 || alliances.are_allied(candidate_owner, owner.0) {
+                return None;
+            }
+            let candidate_class = candidate_armor.map(|armor| armor.class).unwrap_or_default();
+            if !table.can_damage(weapon.damage_type, candidate_class) {
+                return None;
+            }
+            let candidate_position = candidate_transform.translation().truncate();
+            // Defensive units only take on enemies they can hit without straying past the leash
+            if defensive && candidate_position.distance(home) > leash + weapon.range {
+                return None;
+            }
+            Some((candidate_position, Candidate {
+                entity: candidate,
+                attacker: false,
+                threat: candidate_weapon.is_some_and(|w| table.can_damage(w.damage_type, armor_class)),
+                health: health.fraction(),
+                distance: candidate_position.distance(position),
+            }))
+        };
+
+        // Forget attackers that are gone or out of reach
+        let reach = if holding { weapon.range } else { search_range * RETALIATION_RANGE_FACTOR };
+        let last_attacker = targeting.last_attacker
+            .filter(|&attacker| hostile(attacker).is_some_and(|(_, candidate)| candidate.distance <= reach));
+        targeting.last_attacker = last_attacker;
+        let rank = |candidate: Entity| {
+            hostile(candidate).map(|(candidate_position, mut candidate)| {
+                candidate.attacker = last_attacker == Some(candidate.entity);
+                (candidate_position, candidate)
+            })
+        };
+
+        let target = if *stance == Stance::HoldFire || !free {
+            None
+        } else {
+            // Stick with the current target while it stays valid, unless it cannot fight back and
+            // something that can is shooting at us
+            let kept = engagement
+                .and_then(|engagement| rank(engagement.target))
+                .filter(|(_, candidate)| candidate.distance <= search_range || candidate.attacker)
+                .filter(|(_, candidate)| candidate.threat || last_attacker.is_none());
+            kept.or_else(|| {
+                index.within(position, search_range)
+                    .map(|(candidate, _)| candidate)
+                    .chain(last_attacker)
+                    .filter(|&candidate| candidate != entity)
+                    .filter_map(rank)
+                    .min_by(|(_, a), (_, b)| a.priority(b))
+            })
+        };
+        let beyond_leash = defensive && position.distance(home) > targeting.leash;
+
+        match target {
+            Some((target_position, candidate)) if !beyond_leash => {
+                let mut unit = commands.entity(entity);
+                if engagement.is_none() {
+                    // Put the current order on hold while fighting
+                    unit.remove::<(NavPath, FlowFieldGoal, Destination)>();
+                }
+                if engagement.map(|engagement| engagement.target) != Some(candidate.entity) {
+                    unit.insert(Engagement { target: candidate.entity, home });
+                }
+                let moving = engagement.is_some() && destination.is_some();
+                if holding || weapon.in_range(candidate.distance) {
+                    if moving {
+                        unit.remove::<(NavPath, Destination)>();
+                    }
+                } else if !moving || destination.is_some_and(|d| d.0.distance(target_position) > repath_distance) {
+                    unit.insert(Destination(target_position));
+                }
+                if weapon.target != Some(candidate.entity) {
+                    chosen.push((entity, Some(candidate.entity)));
+                }
+            }
+            _ => {
+                if let Some(engagement) = engagement {
+                    // The fight is over: resume the order, or head back if defensive and idle
+                    let mut unit = commands.entity(entity);
+                    unit.remove::<(Engagement, NavPath, Destination)>();
+                    match queue {
+                        Some(mut queue) if !queue.is_empty() => queue.restart_current(),
+                        _ if defensive => {
+                            unit.insert(Destination(engagement.home));
+                        }
+                        _ => {}
+                    }
+                }
+                if weapon.target.is_some() {
+                    chosen.push((entity, None));
+                }
+            }
+        }
+    }
+
+    let mut weapons = weapons.p1();
+    for (entity, target) in chosen {
+        if let Ok(mut weapon) = weapons.get_mut(entity) {
+            weapon.target = target;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combat::{update_spatial_index, ArmorClass, DamageType};
+    use crate::player::PlayerId;
+
+    #[test]
+    fn test_targets_follow_priority_and_stance() {
+        let mut world = World::new();
+        world.insert_resource(DamageTable::builtin());
+        world.insert_resource(NavGrid::default());
+        world.init_resource::<Alliances>();
+        world.init_resource::<SpatialIndex>();
+        world.init_resource::<Events<EntityDamaged>>();
+
+        let at = |x: f32| (Transform::from_xyz(x, 0.0, 0.0), GlobalTransform::from(Transform::from_xyz(x, 0.0, 0.0)));
+        let unit = world.spawn((
+            Weapon::new(10.0, DamageType::Normal, 10.0, 1.0),
+            Targeting::default(),
+            Stance::Aggressive,
+            Movement::default(),
+            Health::new(100.0),
+            Owner(PlayerId(0)),
+            at(0.0),
+        )).id();
+        let mut wounded = Health::new(100.0);
+        wounded.current = 20.0;
+        let harmless = world.spawn((wounded, Owner(PlayerId(1)), at(5.0))).id();
+        let gunner = world.spawn((Health::new(100.0), Weapon::default(), Owner(PlayerId(1)), at(8.0))).id();
+        // Aircraft cannot be hit by normal damage, and far-off units are out of sight
+        world.spawn((Health::new(1.0), Armor::new(ArmorClass::Air, 0.0), Weapon::default(), Owner(PlayerId(1)), at(3.0)));
+        world.spawn((Health::new(1.0), Owner(PlayerId(1)), at(100.0)));
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((update_spatial_index, record_attackers, acquire_targets).chain());
+
+        // Armed enemies come before weaker ones that cannot fight back
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Weapon>(unit).unwrap().target, Some(gunner));
+
+        // Without threats the weakest enemy is picked
+        world.despawn(gunner);
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Weapon>(unit).unwrap().target, Some(harmless));
+
+        // Whoever shoots at the unit is fought back, even from beyond sight range
+        let sniper = world.spawn((Health::new(100.0), Weapon::default(), Owner(PlayerId(1)), at(50.0))).id();
+        world.send_event(EntityDamaged { entity: unit, source: Some(sniper), amount: 1.0 });
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Weapon>(unit).unwrap().target, Some(sniper));
+        assert_eq!(world.get::<Destination>(unit), Some(&Destination(Vec2::new(50.0, 0.0))));
+
+        // Holding fire drops the target, holding position only fires within weapon range
+        *world.get_mut::<Stance>(unit).unwrap() = Stance::HoldFire;
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Weapon>(unit).unwrap().target, None);
+        assert!(world.get::<Engagement>(unit).is_none());
+
+        world.entity_mut(unit).remove::<Destination>();
+        *world.get_mut::<Stance>(unit).unwrap() = Stance::HoldPosition;
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Weapon>(unit).unwrap().target, Some(harmless));
+        assert!(world.get::<Destination>(unit).is_none());
+    }
+}
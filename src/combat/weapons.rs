@@ -0,0 +1,360 @@
+//! Weapons, projectiles and splash damage
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use crate::player::{Alliances, Owner, PlayerId};
+use crate::units::FormationMember;
+use super::{Armor, DamageEvent, DamageTable, DamageType, Health, SpatialIndex};
+
+/// Distance in meters at which a projectile counts as having reached its destination
+const PROJECTILE_HIT_RADIUS: f32 = 0.5;
+
+/// Fraction of splash damage dealt at the edge of the splash radius
+const SPLASH_EDGE_FRACTION: f32 = 0.5;
+
+/// How a weapon's damage reaches the target
+#[derive(Debug, Clone, Copy, PartialEq, Default, Reflect)]
+pub enum WeaponDelivery {
+    /// The hit lands the moment the weapon fires
+    #[default]
+    Hitscan,
+    /// A projectile flies to the target and can be outrun
+    Projectile {
+        /// Flight speed in meters per second
+        speed: f32,
+    },
+}
+
+/// Component for entities that can attack
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Weapon {
+    /// Damage per shot before armour
+    pub damage: f32,
+    /// Kind of damage dealt
+    pub damage_type: DamageType,
+    /// Maximum distance to the target in meters
+    pub range: f32,
+    /// Seconds between shots
+    pub cooldown: f32,
+    /// How the damage reaches the target
+    pub delivery: WeaponDelivery,
+    /// Radius in meters around the point of impact that also takes damage, or zero for none
+    pub splash_radius: f32,
+    /// Entity the weapon is attacking
+    pub target: Option<Entity>,
+    /// Seconds until the weapon can fire again
+    pub cooldown_remaining: f32,
+}
+
+impl Default for Weapon {
+    fn default() -> Self {
+        Self::new(10.0, DamageType::Normal, 20.0, 1.0)
+    }
+}
+
+impl Weapon {
+    /// Create a hitscan weapon without splash
+    pub fn new(damage: f32, damage_type: DamageType, range: f32, cooldown: f32) -> Self {
+        Self {
+            damage,
+            damage_type,
+            range,
+            cooldown,
+            delivery: WeaponDelivery::Hitscan,
+            splash_radius: 0.0,
+            target: None,
+            cooldown_remaining: 0.0,
+        }
+    }
+
+    /// Fire projectiles with the given speed instead of hitting instantly
+    pub fn with_projectile(mut self, speed: f32) -> Self {
+        self.delivery = WeaponDelivery::Projectile { speed };
+        self
+    }
+
+    /// Damage everything within a radius of the point of impact
+    pub fn with_splash(mut self, radius: f32) -> Self {
+        self.splash_radius = radius;
+        self
+    }
+
+    /// Check whether a target at a distance is within range
+    pub fn in_range(&self, distance: f32) -> bool {
+        distance <= self.range
+    }
+}
+
+/// Component for projectiles in flight
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct Projectile {
+    /// Entity that fired the projectile
+    pub source: Entity,
+    /// Player who fired it, if any
+    pub attacker: Option<PlayerId>,
+    /// Entity the projectile is homing on, while it exists
+    pub target: Option<Entity>,
+    /// Where the projectile will land if the target is gone
+    pub destination: Vec2,
+    /// Flight speed in meters per second
+    pub speed: f32,
+    /// Damage on impact before armour
+    pub damage: f32,
+    /// Kind of damage dealt
+    pub damage_type: DamageType,
+    /// Splash radius around the point of impact
+    pub splash_radius: f32,
+}
+
+/// A shot or projectile landing, with the damage it deals
+#[derive(Debug, Clone, Copy)]
+struct Impact {
+    /// Where the hit lands
+    point: Vec2,
+    /// Entity hit directly, which takes the full damage
+    direct_target: Option<Entity>,
+    /// Entity that fired
+    source: Entity,
+    /// Player who fired, if any
+    attacker: Option<PlayerId>,
+    /// Damage before armour
+    damage: f32,
+    /// Kind of damage dealt
+    damage_type: DamageType,
+    /// Radius around the point that also takes damage, or zero for none
+    splash_radius: f32,
+}
+
+/// Sends damage for impacts to the entities they reach
+#[derive(SystemParam)]
+pub struct DamageDealer<'w> {
+    alliances: Res<'w, Alliances>,
+    index: Res<'w, SpatialIndex>,
+    damage_events: EventWriter<'w, DamageEvent>,
+}
+
+impl DamageDealer<'_> {
+    /// Damage the direct target and every entity around the point of impact except the
+    /// attacker's own and allied ones
+    fn hit(&mut self, impact: Impact, owner_of: impl Fn(Entity) -> Option<Owner>) {
+        if let Some(target) = impact.direct_target {
+            self.damage_events.send(DamageEvent {
+                target,
+                source: Some(impact.source),
+                attacker: impact.attacker,
+                amount: impact.damage,
+                damage_type: impact.damage_type,
+            });
+        }
+        let radius = impact.splash_radius;
+        if radius <= 0.0 {
+            return;
+        }
+        for (entity, position) in self.index.within(impact.point, radius) {
+            if Some(entity) == impact.direct_target || entity == impact.source {
+                continue;
+            }
+            let friendly = match (impact.attacker, owner_of(entity)) {
+                (Some(attacker), Some(owner)) => owner.0 == attacker || self.alliances.are_allied(owner.0, attacker),
+                _ => false,
+            };
+            let distance = position.distance(impact.point);
+            if friendly || distance > radius {
+                continue;
+            }
+            let falloff = 1.0 - (1.0 - SPLASH_EDGE_FRACTION) * distance / radius;
+            self.damage_events.send(DamageEvent {
+                target: entity,
+                source: Some(impact.source),
+                attacker: impact.attacker,
+                amount: impact.damage * falloff,
+                damage_type: impact.damage_type,
+            });
+        }
+    }
+}
+
+/// Armed entities, with what decides who they may fire at
+type ArmedUnits<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static mut Weapon, &'static GlobalTransform, Option<&'static Owner>, Option<&'static FormationMember>),
+>;
+
+/// Entities that can be shot at
+type ShotTargets<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static GlobalTransform, Option<&'static Armor>, Option<&'static Owner>),
+    With<Health>,
+>;
+
+/// System to count down weapon cooldowns and fire at targets in range
+pub fn fire_weapons(
+    mut commands: Commands,
+    time: Res<Time>,
+    table: Res<DamageTable>,
+    mut dealer: DamageDealer,
+    mut weapons: ArmedUnits,
+    targets: ShotTargets,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut weapon, transform, owner, formation) in weapons.iter_mut() {
+        weapon.cooldown_remaining = (weapon.cooldown_remaining - delta).max(0.0);
+        let Some(target_entity) = weapon.target else { continue };
+        let Ok((_, target_transform, target_armor, _)) = targets.get(target_entity) else {
+            weapon.target = None;
+            continue;
+        };
+        let armor_class = target_armor.map(|armor| armor.class).unwrap_or_default();
+        let position = transform.translation().truncate();
+        let target_position = target_transform.translation().truncate();
+        if weapon.cooldown_remaining > 0.0
+            || !weapon.in_range(position.distance(target_position))
+            || !table.can_damage(weapon.damage_type, armor_class)
+        {
+            continue;
+        }
+
+        weapon.cooldown_remaining = weapon.cooldown;
+        let attacker = owner.map(|owner| owner.0);
+        let damage = weapon.damage * (1.0 + formation.map_or(0.0, |member| member.bonus.damage)).max(0.0);
+        match weapon.delivery {
+            WeaponDelivery::Hitscan => {
+                let impact = Impact {
+                    point: target_position,
+                    direct_target: Some(target_entity),
+                    source: entity,
+                    attacker,
+                    damage,
+                    damage_type: weapon.damage_type,
+                    splash_radius: weapon.splash_radius,
+                };
+                dealer.hit(impact, |other| targets.get(other).ok().and_then(|(.., other_owner)| other_owner.copied()));
+            }
+            WeaponDelivery::Projectile { speed } => {
+                commands.spawn((
+                    Projectile {
+                        source: entity,
+                        attacker,
+                        target: Some(target_entity),
+                        destination: target_position,
+                        speed,
+                        damage,
+                        damage_type: weapon.damage_type,
+                        splash_radius: weapon.splash_radius,
+                    },
+                    TransformBundle::from_transform(Transform::from_translation(position.extend(1.0))),
+                ));
+            }
+        }
+    }
+}
+
+/// System to fly projectiles towards their targets and deal damage on impact
+pub fn update_projectiles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut dealer: DamageDealer,
+    mut projectiles: Query<(Entity, &mut Projectile, &mut Transform)>,
+    targets: Query<(Entity, &GlobalTransform, Option<&Owner>), With<Health>>,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut projectile, mut transform) in projectiles.iter_mut() {
+        // Home in on the target while it exists, otherwise land where it was last seen
+        match projectile.target.and_then(|target| targets.get(target).ok()) {
+            Some((_, target_transform, _)) => projectile.destination = target_transform.translation().truncate(),
+            None => projectile.target = None,
+        }
+
+        let position = transform.translation.truncate();
+        let offset = projectile.destination - position;
+        let step = projectile.speed * delta;
+        if offset.length() > step.max(PROJECTILE_HIT_RADIUS) {
+            transform.translation += (offset.normalize() * step).extend(0.0);
+            continue;
+        }
+
+        let impact = Impact {
+            point: projectile.destination,
+            direct_target: projectile.target,
+            source: projectile.source,
+            attacker: projectile.attacker,
+            damage: projectile.damage,
+            damage_type: projectile.damage_type,
+            splash_radius: projectile.splash_radius,
+        };
+        dealer.hit(impact, |other| targets.get(other).ok().and_then(|(.., owner)| owner.copied()));
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// System to draw projectiles in flight
+pub fn draw_projectiles(
+    projectiles: Query<(&Projectile, &GlobalTransform)>,
+    mut gizmos: Gizmos,
+) {
+    for (projectile, transform) in projectiles.iter() {
+        let color = match projectile.damage_type {
+            DamageType::Normal => Color::srgb(1.0, 1.0, 0.6),
+            DamageType::Piercing => Color::srgb(0.7, 0.9, 1.0),
+            DamageType::Explosive => Color::srgb(1.0, 0.5, 0.1),
+            DamageType::AntiAir => Color::srgb(0.9, 0.4, 1.0),
+        };
+        gizmos.circle_2d(transform.translation().truncate(), 0.5, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::combat::{apply_damage, despawn_dead, update_spatial_index, ArmorClass, EntityDamaged, EntityDied};
+    use crate::player::PlayerId;
+
+    #[test]
+    fn test_projectile_splash_kills_enemies_and_spares_friends() {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.insert_resource(DamageTable::builtin());
+        world.init_resource::<Alliances>();
+        world.init_resource::<SpatialIndex>();
+        world.init_resource::<Events<DamageEvent>>();
+        world.init_resource::<Events<EntityDamaged>>();
+        world.init_resource::<Events<EntityDied>>();
+
+        let at = |x: f32| GlobalTransform::from(Transform::from_xyz(x, 0.0, 0.0));
+        let artillery = world.spawn((
+            Weapon::new(60.0, DamageType::Explosive, 50.0, 5.0).with_projectile(40.0).with_splash(5.0),
+            Owner(PlayerId(0)),
+            at(0.0),
+        )).id();
+        let target = world.spawn((Health::new(50.0), Armor::new(ArmorClass::Heavy, 2.0), Owner(PlayerId(1)), at(40.0))).id();
+        let nearby = world.spawn((Health::new(100.0), Owner(PlayerId(1)), at(43.0))).id();
+        let friend = world.spawn((Health::new(100.0), Owner(PlayerId(0)), at(41.0))).id();
+        world.get_mut::<Weapon>(artillery).unwrap().target = Some(target);
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((update_spatial_index, fire_weapons, update_projectiles, apply_damage, despawn_dead).chain());
+        for _ in 0..64 * 2 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs(1) / 64);
+            schedule.run(&mut world);
+        }
+
+        // 60 explosive against heavy armour is 45, minus 2 armour, so one shot is not enough yet
+        assert_eq!(world.get::<Health>(target).unwrap().current, 7.0);
+        let splashed = world.get::<Health>(nearby).unwrap().current;
+        assert!(splashed < 100.0 && splashed > 100.0 - 60.0 * 1.25, "splash left {}", splashed);
+        assert_eq!(world.get::<Health>(friend).unwrap().current, 100.0);
+
+        // The second shot after the cooldown destroys the target
+        for _ in 0..64 * 5 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs(1) / 64);
+            schedule.run(&mut world);
+        }
+        assert!(world.get_entity(target).is_none());
+        assert!(world.get::<Weapon>(artillery).unwrap().target.is_none());
+    }
+}
@@ -0,0 +1,37 @@
+//! Reading game data from RON files under `assets/data/`
+//!
+//! Every data file is also embedded in the binary with `include_str!`, so a game started
+//! without its assets folder falls back to the data it was built with.
+
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+/// Errors that can occur when reading a data file
+#[derive(Error, Debug)]
+pub enum DataFileError {
+    /// The file could not be read
+    #[error("Failed to read {0}: {1}")]
+    Io(String, #[source] std::io::Error),
+
+    /// The file is not valid RON
+    #[error("Failed to parse {0}: {1}")]
+    Parse(String, #[source] ron::error::SpannedError),
+}
+
+/// Read and parse a RON data file
+pub fn read_data_file<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, DataFileError> {
+    let path = path.as_ref();
+    let display = path.display().to_string();
+    let contents = fs::read_to_string(path)
+        .map_err(|e| DataFileError::Io(display.clone(), e))?;
+    ron::from_str(&contents).map_err(|e| DataFileError::Parse(display, e))
+}
+
+/// Parse the copy of a data file embedded in the binary
+///
+/// The embedded copy is checked by the tests that load each data file, so a failure here is a bug.
+pub fn parse_builtin<T: DeserializeOwned>(file: &str, source: &str) -> T {
+    ron::from_str(source).unwrap_or_else(|err| panic!("built-in copy of {} is invalid: {}", file, err))
+}
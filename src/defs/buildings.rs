@@ -0,0 +1,176 @@
+//! Building definitions
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::buildings::BuildTier;
+use crate::campaign_menu::Faction;
+use crate::combat::{Armor, ArmorClass, Health, Weapon};
+use crate::resources::ResourceCost;
+use super::{check_non_negative, check_positive, parse_builtin, Definition, FieldError, WeaponDef};
+
+fn default_armor() -> Armor {
+    Armor::new(ArmorClass::Structure, 0.0)
+}
+
+/// Stats of a building that drives around as a mobile base
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+pub struct MobileDef {
+    /// Top speed in meters per second with no modules attached
+    pub speed: f32,
+    /// Mass of the bare base; modules slow it down in proportion to their share of the total
+    pub mass: f32,
+    /// Offsets in meters from the centre of the base where modules can be attached
+    pub hardpoints: Vec<Vec2>,
+}
+
+impl MobileDef {
+    /// Check every field, returning the first one that is invalid
+    pub fn validate(&self) -> Result<(), FieldError> {
+        check_positive("speed", self.speed)?;
+        check_positive("mass", self.mass)
+    }
+}
+
+/// What a building does when attached to a mobile base as a module
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, Reflect)]
+#[serde(default)]
+pub struct ModuleDef {
+    /// Mass added to the base
+    pub mass: f32,
+    /// Extra hardpoints the module provides, as offsets in meters from the centre of the base
+    pub hardpoints: Vec<Vec2>,
+    /// Storage capacity added to the base for every resource
+    pub storage: u32,
+    /// Supply added to the base's owner
+    pub supply: u32,
+    /// Fractional production speed bonus (0.25 = +25%)
+    pub production: f32,
+    /// Flat armour added to the base
+    pub armor: f32,
+    /// Maximum health added to the base
+    pub health: f32,
+    /// Fractional speed bonus; several engines stack with diminishing returns
+    pub engine: f32,
+}
+
+impl ModuleDef {
+    /// Check every field, returning the first one that is invalid
+    pub fn validate(&self) -> Result<(), FieldError> {
+        check_non_negative("mass", self.mass)?;
+        check_non_negative("production", self.production)?;
+        check_non_negative("armor", self.armor)?;
+        check_non_negative("health", self.health)?;
+        check_non_negative("engine", self.engine)
+    }
+}
+
+/// Everything needed to place and construct one kind of building
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+pub struct BuildingDef {
+    /// Unique identifier used to refer to this building from other data
+    pub id: String,
+    /// Human-readable name
+    pub name: String,
+    /// Path of the sprite under `assets/`
+    pub sprite: String,
+    /// Maximum hit points
+    pub health: f32,
+    /// Protection against incoming damage
+    #[serde(default = "default_armor")]
+    pub armor: Armor,
+    /// Size in map tiles
+    pub footprint: UVec2,
+    /// Radius in meters around the finished building within which its owner may place new buildings
+    #[serde(default)]
+    pub build_radius: f32,
+    /// Resources spent to build the building
+    pub cost: ResourceCost,
+    /// Seconds one builder takes to construct the building
+    pub build_time: f32,
+    /// Builders below this tier cannot construct the building
+    #[serde(default)]
+    pub tier: BuildTier,
+    /// The building's weapon, if it can attack
+    #[serde(default)]
+    pub weapon: Option<WeaponDef>,
+    /// Factions that can build the building, or empty for every faction
+    #[serde(default)]
+    pub factions: Vec<Faction>,
+    /// Ids of the technologies that must be researched before the building can be built
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Ids of the units the finished building can train
+    #[serde(default)]
+    pub produces: Vec<String>,
+    /// Supply the finished building provides to its owner
+    #[serde(default)]
+    pub supply: u32,
+    /// Whether technologies can be researched at the finished building
+    #[serde(default)]
+    pub research: bool,
+    /// Movement stats and hardpoint layout, if the building is a mobile base
+    #[serde(default)]
+    pub mobile: Option<MobileDef>,
+    /// Effects when attached to a mobile base, if the building is a module
+    #[serde(default)]
+    pub module: Option<ModuleDef>,
+}
+
+impl BuildingDef {
+    /// Check whether a faction can build the building
+    pub fn available_to(&self, faction: Faction) -> bool {
+        self.factions.is_empty() || self.factions.contains(&faction)
+    }
+
+    /// Create the health component for a finished building
+    pub fn health(&self) -> Health {
+        Health::new(self.health)
+    }
+
+    /// Create the weapon component for the building, if it has one
+    pub fn weapon(&self) -> Option<Weapon> {
+        self.weapon.as_ref().map(WeaponDef::weapon)
+    }
+}
+
+impl Definition for BuildingDef {
+    const KIND: &'static str = "building";
+    const FILE: &'static str = "assets/data/buildings.ron";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn validate(&self) -> Result<(), FieldError> {
+        if self.sprite.is_empty() {
+            return Err(FieldError::new("sprite", "must not be empty"));
+        }
+        check_positive("health", self.health)?;
+        check_non_negative("armor.value", self.armor.value)?;
+        if self.footprint.x == 0 || self.footprint.y == 0 {
+            return Err(FieldError::new("footprint", format!("must be at least one tile each way, got {}", self.footprint)));
+        }
+        check_non_negative("build_radius", self.build_radius)?;
+        check_positive("build_time", self.build_time)?;
+        if let Some(weapon) = &self.weapon {
+            weapon.validate().map_err(|err| err.within("weapon"))?;
+        }
+        if let Some(index) = self.requires.iter().position(String::is_empty) {
+            return Err(FieldError::new(format!("requires[{}]", index), "must not be empty"));
+        }
+        if let Some(index) = self.produces.iter().position(String::is_empty) {
+            return Err(FieldError::new(format!("produces[{}]", index), "must not be empty"));
+        }
+        if let Some(mobile) = &self.mobile {
+            mobile.validate().map_err(|err| err.within("mobile"))?;
+        }
+        if let Some(module) = &self.module {
+            module.validate().map_err(|err| err.within("module"))?;
+        }
+        Ok(())
+    }
+
+    fn builtin() -> Vec<Self> {
+        parse_builtin(Self::FILE, include_str!("../../assets/data/buildings.ron"))
+    }
+}
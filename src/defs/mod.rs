@@ -0,0 +1,388 @@
+//! Unit, building and technology definitions loaded from data files
+//!
+//! Every kind of unit, building and technology is described in RON under `assets/data/`, so new ones
+//! can be added without recompiling. Each file is validated as a whole when loaded; a bad
+//! file is rejected with an error naming the file, the definition and the field, and the
+//! previous definitions stay in use. Debug builds watch the files and reload them when they
+//! change.
+
+mod buildings;
+mod technologies;
+mod units;
+
+pub use buildings::*;
+pub use technologies::*;
+pub use units::*;
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+use crate::combat::{DamageType, Weapon};
+
+pub use crate::data_file::{parse_builtin, read_data_file, DataFileError};
+
+/// Seconds between checks for changed definition files in debug builds
+#[cfg(debug_assertions)]
+const HOT_RELOAD_INTERVAL: f32 = 1.0;
+
+/// Errors that can occur when loading definitions
+#[derive(Error, Debug)]
+pub enum DefLoadError {
+    /// The definition file could not be read or parsed
+    #[error(transparent)]
+    File(#[from] DataFileError),
+
+    /// Two definitions in a file share the same id
+    #[error("{file}: duplicate {kind} id '{id}'")]
+    DuplicateId {
+        /// File the definitions came from
+        file: String,
+        /// Kind of definition, e.g. "unit"
+        kind: &'static str,
+        /// The repeated id
+        id: String,
+    },
+
+    /// A field of a definition has a value that makes no sense
+    #[error("{file}: {kind} '{id}' field `{field}` {reason}")]
+    InvalidField {
+        /// File the definitions came from
+        file: String,
+        /// Kind of definition, e.g. "unit"
+        kind: &'static str,
+        /// Id of the definition
+        id: String,
+        /// Path to the offending field, e.g. `weapon.range`
+        field: String,
+        /// What is wrong with the value
+        reason: String,
+    },
+}
+
+/// A field that failed validation and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// Path to the field, e.g. `weapon.range`
+    pub field: String,
+    /// What is wrong with the value
+    pub reason: String,
+}
+
+impl FieldError {
+    /// Fail validation of a field
+    pub fn new(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self { field: field.into(), reason: reason.into() }
+    }
+
+    /// Nest the field inside a parent field
+    pub fn within(mut self, parent: &str) -> Self {
+        self.field = format!("{}.{}", parent, self.field);
+        self
+    }
+}
+
+/// Check that a number is finite and greater than zero
+pub(crate) fn check_positive(field: &str, value: f32) -> Result<(), FieldError> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(FieldError::new(field, format!("must be greater than 0, got {}", value)))
+    }
+}
+
+/// Check that a number is finite and not negative
+pub(crate) fn check_non_negative(field: &str, value: f32) -> Result<(), FieldError> {
+    if value.is_finite() && value >= 0.0 {
+        Ok(())
+    } else {
+        Err(FieldError::new(field, format!("must be at least 0, got {}", value)))
+    }
+}
+
+/// A kind of definition kept in a `DefRegistry`
+pub trait Definition: Clone + Send + Sync + 'static + for<'de> Deserialize<'de> {
+    /// Name of this kind of definition in error messages
+    const KIND: &'static str;
+    /// Default location of the data file
+    const FILE: &'static str;
+
+    /// Get the unique id of the definition
+    fn id(&self) -> &str;
+
+    /// Check every field, returning the first one that is invalid
+    fn validate(&self) -> Result<(), FieldError>;
+
+    /// Check the definition against the rest of its file, e.g. that the ids it names exist
+    fn validate_in(&self, _registry: &DefRegistry<Self>) -> Result<(), FieldError> {
+        Ok(())
+    }
+
+    /// Definitions used when no data file is available, usually the data file embedded with `include_str!`
+    fn builtin() -> Vec<Self>;
+}
+
+/// Stats of a weapon in a definition
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+pub struct WeaponDef {
+    /// Damage per shot before armour
+    pub damage: f32,
+    /// Kind of damage dealt
+    #[serde(default)]
+    pub damage_type: DamageType,
+    /// Maximum distance to the target in meters
+    pub range: f32,
+    /// Seconds between shots
+    pub cooldown: f32,
+    /// Projectile flight speed in meters per second, or none for hitscan
+    #[serde(default)]
+    pub projectile_speed: Option<f32>,
+    /// Radius in meters around the point of impact that also takes damage
+    #[serde(default)]
+    pub splash_radius: f32,
+}
+
+impl WeaponDef {
+    /// Create the weapon component described by this definition
+    pub fn weapon(&self) -> Weapon {
+        let mut weapon = Weapon::new(self.damage, self.damage_type, self.range, self.cooldown)
+            .with_splash(self.splash_radius);
+        if let Some(speed) = self.projectile_speed {
+            weapon = weapon.with_projectile(speed);
+        }
+        weapon
+    }
+
+    /// Check every field, returning the first one that is invalid
+    pub fn validate(&self) -> Result<(), FieldError> {
+        check_non_negative("damage", self.damage)?;
+        check_positive("range", self.range)?;
+        check_positive("cooldown", self.cooldown)?;
+        if let Some(speed) = self.projectile_speed {
+            check_positive("projectile_speed", speed)?;
+        }
+        check_non_negative("splash_radius", self.splash_radius)
+    }
+}
+
+/// Resource holding every definition of one kind, by id
+#[derive(Resource, Debug, Clone)]
+pub struct DefRegistry<T: Definition> {
+    defs: HashMap<String, T>,
+}
+
+impl<T: Definition> Default for DefRegistry<T> {
+    fn default() -> Self {
+        Self { defs: HashMap::new() }
+    }
+}
+
+impl<T: Definition> DefRegistry<T> {
+    /// Build a registry from a list of definitions, naming `file` in any errors
+    pub fn from_defs(file: &str, defs: Vec<T>) -> Result<Self, DefLoadError> {
+        let mut registry = Self::default();
+        for def in defs {
+            let id = def.id().to_string();
+            if id.is_empty() {
+                return Err(DefLoadError::InvalidField {
+                    file: file.to_string(),
+                    kind: T::KIND,
+                    id,
+                    field: "id".to_string(),
+                    reason: "must not be empty".to_string(),
+                });
+            }
+            if let Err(err) = def.validate() {
+                return Err(DefLoadError::InvalidField {
+                    file: file.to_string(),
+                    kind: T::KIND,
+                    id,
+                    field: err.field,
+                    reason: err.reason,
+                });
+            }
+            if registry.defs.contains_key(&id) {
+                return Err(DefLoadError::DuplicateId { file: file.to_string(), kind: T::KIND, id });
+            }
+            registry.defs.insert(id, def);
+        }
+        // Sorted so the same broken file always reports the same error
+        let mut ids: Vec<&String> = registry.defs.keys().collect();
+        ids.sort_unstable();
+        for id in ids {
+            if let Err(err) = registry.defs[id].validate_in(&registry) {
+                return Err(DefLoadError::InvalidField {
+                    file: file.to_string(),
+                    kind: T::KIND,
+                    id: id.clone(),
+                    field: err.field,
+                    reason: err.reason,
+                });
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Load definitions from a RON file containing a list of them
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self, DefLoadError> {
+        let path = path.as_ref();
+        let defs: Vec<T> = read_data_file(path)?;
+        Self::from_defs(&path.display().to_string(), defs)
+    }
+
+    /// Built-in definitions used when no data file is available
+    pub fn builtin() -> Self {
+        Self::from_defs("built-in", T::builtin()).expect("built-in definitions are valid")
+    }
+
+    /// Look up a definition by id
+    pub fn get(&self, id: &str) -> Option<&T> {
+        self.defs.get(id)
+    }
+
+    /// Iterate over every definition
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.defs.values()
+    }
+
+    /// Get the number of definitions
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    /// Check whether there are no definitions
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+}
+
+/// Resource holding every unit definition
+pub type UnitDefs = DefRegistry<UnitDef>;
+
+/// Resource holding every building definition
+pub type BuildingDefs = DefRegistry<BuildingDef>;
+
+/// Resource holding every technology definition
+pub type TechnologyDefs = DefRegistry<TechnologyDef>;
+
+/// System to load definitions of one kind, falling back to the built-in ones
+pub fn load_defs<T: Definition>(mut commands: Commands) {
+    let registry = match DefRegistry::<T>::load_from_file(T::FILE) {
+        Ok(registry) => registry,
+        Err(err) => {
+            warn!("{}; using built-in {} definitions", err, T::KIND);
+            DefRegistry::<T>::builtin()
+        }
+    };
+    commands.insert_resource(registry);
+}
+
+/// System to reload definitions of one kind when their file changes, keeping the old ones if the new file is invalid
+#[cfg(debug_assertions)]
+pub fn hot_reload_defs<T: Definition>(
+    time: Res<Time>,
+    mut timer: Local<Option<Timer>>,
+    mut last_modified: Local<Option<std::time::SystemTime>>,
+    mut registry: ResMut<DefRegistry<T>>,
+) {
+    let timer = timer.get_or_insert_with(|| Timer::from_seconds(HOT_RELOAD_INTERVAL, TimerMode::Repeating));
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Ok(modified) = fs::metadata(T::FILE).and_then(|metadata| metadata.modified()) else { return };
+    // The first check only records the time of the file loaded at startup
+    let previous = last_modified.replace(modified);
+    if previous.is_none_or(|previous| previous == modified) {
+        return;
+    }
+    match DefRegistry::<T>::load_from_file(T::FILE) {
+        Ok(reloaded) => {
+            info!("Reloaded {} {} definitions from {}", reloaded.len(), T::KIND, T::FILE);
+            *registry = reloaded;
+        }
+        Err(err) => warn!("{}; keeping the previous {} definitions", err, T::KIND),
+    }
+}
+
+/// System to warn about units and buildings that require technologies nobody can research
+pub fn check_required_technologies(
+    units: Res<UnitDefs>,
+    buildings: Res<BuildingDefs>,
+    technologies: Res<TechnologyDefs>,
+) {
+    let required = units.iter()
+        .map(|def| (UnitDef::KIND, &def.id, &def.requires))
+        .chain(buildings.iter().map(|def| (BuildingDef::KIND, &def.id, &def.requires)));
+    for (kind, id, requires) in required {
+        for technology in requires.iter().filter(|technology| technologies.get(technology).is_none()) {
+            warn!("{} '{}' requires unknown technology '{}' and can never be built", kind, id, technology);
+        }
+    }
+}
+
+/// System to warn about buildings that train units with no definition
+pub fn check_produced_units(units: Res<UnitDefs>, buildings: Res<BuildingDefs>) {
+    for def in buildings.iter() {
+        for unit in def.produces.iter().filter(|unit| units.get(unit).is_none()) {
+            warn!("{} '{}' produces unknown unit '{}' which can never be trained", BuildingDef::KIND, def.id, unit);
+        }
+    }
+}
+
+/// Plugin for unit, building and technology definitions
+pub struct DefsPlugin;
+
+impl Plugin for DefsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UnitDefs>()
+            .init_resource::<BuildingDefs>()
+            .init_resource::<TechnologyDefs>()
+            .add_systems(Startup, (
+                (load_defs::<UnitDef>, load_defs::<BuildingDef>, load_defs::<TechnologyDef>),
+                (check_required_technologies, check_produced_units),
+            ).chain());
+
+        #[cfg(debug_assertions)]
+        app.add_systems(Update, (
+            hot_reload_defs::<UnitDef>,
+            hot_reload_defs::<BuildingDef>,
+            hot_reload_defs::<TechnologyDef>,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_definition_files_load_and_errors_name_the_field() {
+        let units = UnitDefs::load_from_file(UnitDef::FILE).expect("unit definitions load");
+        let tank = units.get("tank").expect("tank is defined");
+        assert!(tank.weapon().is_some_and(|weapon| weapon.range > 0.0));
+        assert!(units.get("gatherer").unwrap().weapon().is_none());
+        let buildings = BuildingDefs::load_from_file(BuildingDef::FILE).expect("building definitions load");
+        assert!(buildings.get("command_center").is_some());
+        assert_eq!(UnitDefs::builtin().len(), units.len());
+        assert_eq!(BuildingDefs::builtin().len(), buildings.len());
+        for def in buildings.iter() {
+            for unit in &def.produces {
+                assert!(units.get(unit).is_some(), "{} produces unknown unit '{}'", def.id, unit);
+            }
+        }
+
+        let mut broken = tank.clone();
+        broken.weapon.as_mut().unwrap().range = -1.0;
+        let err = UnitDefs::from_defs("units.ron", vec![broken]).unwrap_err();
+        assert!(matches!(&err, DefLoadError::InvalidField { field, .. } if field == "weapon.range"));
+        let message = err.to_string();
+        assert!(message.contains("units.ron") && message.contains("'tank'") && message.contains("weapon.range"), "{}", message);
+
+        assert!(matches!(
+            UnitDefs::from_defs("units.ron", vec![tank.clone(), tank.clone()]),
+            Err(DefLoadError::DuplicateId { .. })
+        ));
+    }
+}
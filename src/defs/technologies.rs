@@ -0,0 +1,197 @@
+//! Technology definitions
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::campaign_menu::Faction;
+use crate::resources::ResourceCost;
+use super::{check_positive, parse_builtin, DefRegistry, Definition, FieldError};
+
+/// Broad area a technology improves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
+pub enum TechCategory {
+    /// Weapons, defences and military units
+    Military,
+    /// Gathering, production and storage
+    Economy,
+    /// Base building, expansion and logistics
+    Infrastructure,
+    /// What sets a faction apart
+    Special,
+}
+
+/// How far along the tree a technology sits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize, Reflect)]
+pub enum TechLevel {
+    /// Available early in the game
+    #[default]
+    Basic,
+    /// Builds on basic research
+    Advanced,
+    /// Powerful but specialised
+    Experimental,
+    /// End-game
+    Ultimate,
+}
+
+/// A stat a technology can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
+pub enum Stat {
+    /// Maximum hit points
+    MaxHealth,
+    /// Flat damage removed from every hit taken
+    Armor,
+    /// Top speed, including that of mobile bases
+    Speed,
+    /// Weapon damage per shot
+    Damage,
+    /// Weapon range
+    Range,
+    /// Construction and repair speed of builders
+    BuildRate,
+    /// Gather rate of resource gatherers
+    GatherRate,
+    /// Capacity of every storage the player owns, for every resource
+    Storage,
+}
+
+/// Which entities a stat modifier applies to
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Reflect)]
+pub enum ModifierTarget {
+    /// Every unit
+    #[default]
+    Units,
+    /// Every building, mobile bases included
+    Buildings,
+    /// Units of one definition, by id
+    Unit(String),
+    /// Buildings of one definition, by id
+    Building(String),
+}
+
+/// A change to a stat, applied as `(base + flat) * (1 + percent)` summed over every modifier
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+pub struct StatModifier {
+    /// Stat changed
+    pub stat: Stat,
+    /// Entities affected; ignored for `Storage`, which applies to every storage
+    #[serde(default)]
+    pub target: ModifierTarget,
+    /// Amount added to the base value
+    #[serde(default)]
+    pub flat: f32,
+    /// Fractional change (0.15 = +15%)
+    #[serde(default)]
+    pub percent: f32,
+}
+
+impl StatModifier {
+    /// Check every field, returning the first one that is invalid
+    pub fn validate(&self) -> Result<(), FieldError> {
+        if !self.flat.is_finite() {
+            return Err(FieldError::new("flat", format!("must be finite, got {}", self.flat)));
+        }
+        if !(self.percent.is_finite() && self.percent > -1.0) {
+            return Err(FieldError::new("percent", format!("must be greater than -1, got {}", self.percent)));
+        }
+        match &self.target {
+            ModifierTarget::Unit(id) | ModifierTarget::Building(id) if id.is_empty() => {
+                Err(FieldError::new("target", "must not name an empty id"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Everything needed to research one technology
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+pub struct TechnologyDef {
+    /// Unique identifier used to refer to this technology from other data
+    pub id: String,
+    /// Human-readable name
+    pub name: String,
+    /// What the technology does, for the tech tree
+    #[serde(default)]
+    pub description: String,
+    /// Broad area the technology improves
+    pub category: TechCategory,
+    /// How far along the tree the technology sits
+    #[serde(default)]
+    pub level: TechLevel,
+    /// Factions that can research the technology, or empty for every faction
+    #[serde(default)]
+    pub factions: Vec<Faction>,
+    /// Ids of the technologies that must be researched first
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Resources spent to research the technology
+    pub cost: ResourceCost,
+    /// Seconds the technology takes to research
+    pub research_time: f32,
+    /// Stat changes applied to everything the researching player owns
+    #[serde(default)]
+    pub effects: Vec<StatModifier>,
+}
+
+impl TechnologyDef {
+    /// Check whether a faction can research the technology
+    pub fn available_to(&self, faction: Faction) -> bool {
+        self.factions.is_empty() || self.factions.contains(&faction)
+    }
+
+    /// Check whether `id` is among the technologies this one needs, directly or not
+    fn leads_to(&self, id: &str, registry: &DefRegistry<Self>, visited: &mut Vec<String>) -> bool {
+        for required in &self.requires {
+            if required == id {
+                return true;
+            }
+            if visited.contains(required) {
+                continue;
+            }
+            visited.push(required.clone());
+            if registry.get(required).is_some_and(|def| def.leads_to(id, registry, visited)) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Definition for TechnologyDef {
+    const KIND: &'static str = "technology";
+    const FILE: &'static str = "assets/data/technologies.ron";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn validate(&self) -> Result<(), FieldError> {
+        if self.name.is_empty() {
+            return Err(FieldError::new("name", "must not be empty"));
+        }
+        check_positive("research_time", self.research_time)?;
+        if let Some(index) = self.requires.iter().position(String::is_empty) {
+            return Err(FieldError::new(format!("requires[{}]", index), "must not be empty"));
+        }
+        for (index, effect) in self.effects.iter().enumerate() {
+            effect.validate().map_err(|err| err.within(&format!("effects[{}]", index)))?;
+        }
+        Ok(())
+    }
+
+    fn validate_in(&self, registry: &DefRegistry<Self>) -> Result<(), FieldError> {
+        for (index, required) in self.requires.iter().enumerate() {
+            let field = format!("requires[{}]", index);
+            let Some(def) = registry.get(required) else {
+                return Err(FieldError::new(field, format!("names unknown technology '{}'", required)));
+            };
+            if required == &self.id || def.leads_to(&self.id, registry, &mut Vec::new()) {
+                return Err(FieldError::new(field, format!("leads back to '{}'", self.id)));
+            }
+        }
+        Ok(())
+    }
+
+    fn builtin() -> Vec<Self> {
+        parse_builtin(Self::FILE, include_str!("../../assets/data/technologies.ron"))
+    }
+}
@@ -0,0 +1,181 @@
+//! Unit definitions
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::buildings::Builder;
+use crate::campaign_menu::Faction;
+use crate::combat::{Armor, Health, Weapon};
+use crate::navigation::MovementClass;
+use crate::resources::{ResourceAmount, ResourceCost, ResourceGatherer, ResourceType};
+use crate::units::Movement;
+use super::{check_non_negative, check_positive, parse_builtin, Definition, FieldError, WeaponDef};
+
+fn default_radius() -> f32 {
+    Movement::default().radius
+}
+
+fn default_supply() -> u32 {
+    1
+}
+
+/// How a unit gathers resources, in a definition
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+#[serde(default)]
+pub struct GathererDef {
+    /// Resources the unit can gather
+    pub can_gather: Vec<ResourceType>,
+    /// Most the unit can carry at once, in whole resource units
+    pub carry_capacity: u32,
+    /// Resources gathered per second, before research
+    pub gather_rate: f32,
+    /// Distance in meters from a node within which the unit can gather from it
+    pub gather_range: f32,
+}
+
+impl Default for GathererDef {
+    fn default() -> Self {
+        let gatherer = ResourceGatherer::default();
+        Self {
+            can_gather: gatherer.can_gather,
+            carry_capacity: gatherer.carry_capacity.whole_units(),
+            gather_rate: gatherer.gather_rate,
+            gather_range: gatherer.gather_range,
+        }
+    }
+}
+
+impl GathererDef {
+    /// Create the gatherer component described by this definition
+    pub fn gatherer(&self) -> ResourceGatherer {
+        ResourceGatherer {
+            can_gather: self.can_gather.clone(),
+            carry_capacity: ResourceAmount::from_units(self.carry_capacity),
+            gather_rate: self.gather_rate,
+            base_gather_rate: self.gather_rate,
+            gather_range: self.gather_range,
+            ..default()
+        }
+    }
+
+    /// Check every field, returning the first one that is invalid
+    pub fn validate(&self) -> Result<(), FieldError> {
+        if self.can_gather.is_empty() {
+            return Err(FieldError::new("can_gather", "must not be empty"));
+        }
+        if self.carry_capacity == 0 {
+            return Err(FieldError::new("carry_capacity", "must be positive, got 0"));
+        }
+        check_positive("gather_rate", self.gather_rate)?;
+        check_positive("gather_range", self.gather_range)
+    }
+}
+
+/// Everything needed to build and spawn one kind of unit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+pub struct UnitDef {
+    /// Unique identifier used to refer to this unit from other data
+    pub id: String,
+    /// Human-readable name
+    pub name: String,
+    /// Path of the sprite under `assets/`
+    pub sprite: String,
+    /// Maximum hit points
+    pub health: f32,
+    /// Protection against incoming damage
+    #[serde(default)]
+    pub armor: Armor,
+    /// Top speed in meters per second
+    pub speed: f32,
+    /// What terrain the unit can cross
+    #[serde(default)]
+    pub movement: MovementClass,
+    /// Radius of the unit's footprint in meters
+    #[serde(default = "default_radius")]
+    pub radius: f32,
+    /// Resources spent to build the unit
+    pub cost: ResourceCost,
+    /// Seconds the unit takes to build
+    pub build_time: f32,
+    /// Supply the unit takes up while it is alive
+    #[serde(default = "default_supply")]
+    pub supply: u32,
+    /// The unit's weapon, if it can attack
+    #[serde(default)]
+    pub weapon: Option<WeaponDef>,
+    /// How the unit constructs and repairs buildings, if it can
+    #[serde(default)]
+    pub builder: Option<Builder>,
+    /// How the unit gathers resources, if it can
+    #[serde(default)]
+    pub gatherer: Option<GathererDef>,
+    /// Factions that can build the unit, or empty for every faction
+    #[serde(default)]
+    pub factions: Vec<Faction>,
+    /// Ids of the technologies that must be researched before the unit can be built
+    #[serde(default)]
+    pub requires: Vec<String>,
+}
+
+impl UnitDef {
+    /// Check whether a faction can build the unit
+    pub fn available_to(&self, faction: Faction) -> bool {
+        self.factions.is_empty() || self.factions.contains(&faction)
+    }
+
+    /// Create the health component for a freshly built unit
+    pub fn health(&self) -> Health {
+        Health::new(self.health)
+    }
+
+    /// Create the movement component for the unit
+    pub fn movement(&self) -> Movement {
+        Movement {
+            radius: self.radius,
+            ..Movement::new(self.speed, self.movement)
+        }
+    }
+
+    /// Create the weapon component for the unit, if it has one
+    pub fn weapon(&self) -> Option<Weapon> {
+        self.weapon.as_ref().map(WeaponDef::weapon)
+    }
+}
+
+impl Definition for UnitDef {
+    const KIND: &'static str = "unit";
+    const FILE: &'static str = "assets/data/units.ron";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn validate(&self) -> Result<(), FieldError> {
+        if self.sprite.is_empty() {
+            return Err(FieldError::new("sprite", "must not be empty"));
+        }
+        check_positive("health", self.health)?;
+        check_non_negative("armor.value", self.armor.value)?;
+        check_positive("speed", self.speed)?;
+        check_positive("radius", self.radius)?;
+        check_positive("build_time", self.build_time)?;
+        if let Some(weapon) = &self.weapon {
+            weapon.validate().map_err(|err| err.within("weapon"))?;
+        }
+        if let Some(builder) = &self.builder {
+            check_positive("builder.rate", builder.rate)?;
+            check_non_negative("builder.repair_rate", builder.repair_rate)?;
+            check_non_negative("builder.range", builder.range)?;
+        }
+        if let Some(gatherer) = &self.gatherer {
+            gatherer.validate().map_err(|err| err.within("gatherer"))?;
+        }
+        if let Some(index) = self.requires.iter().position(String::is_empty) {
+            return Err(FieldError::new(format!("requires[{}]", index), "must not be empty"));
+        }
+        Ok(())
+    }
+
+    fn builtin() -> Vec<Self> {
+        parse_builtin(Self::FILE, include_str!("../../assets/data/units.ron"))
+    }
+}
@@ -18,6 +18,9 @@ pub mod state;
 /// This module contains the main menu UI components and systems for handling
 /// user interactions with the main menu.
 pub mod menu;
+
+/// Reading RON data files, with copies embedded in the binary as a fallback
+pub mod data_file;
 pub mod world;
 
 // Re-export commonly needed types
@@ -7,10 +7,23 @@ use bevy::{
     app::AppExit,
 };
 
+mod buildings;
 mod camera;
 mod camera_controls;
+mod campaign_menu;
+mod combat;
+mod data_file;
+mod defs;
+mod mobile_base;
+mod navigation;
+mod player;
+mod power;
+mod production;
 mod state;
+mod supply;
+mod technology;
 mod ui;
+mod units;
 mod resources;
 mod world;
 
@@ -29,7 +42,8 @@ pub struct CorePlugin;
 impl Plugin for CorePlugin {
     fn build(&self, app: &mut App) {
         // Initialize the game state system
-        app.init_state::<state::GameState>();
+        app.init_state::<state::GameState>()
+            .init_resource::<state::GameMode>();
         
         // Add the core startup system
         app.add_systems(Startup, core_startup_system);
@@ -76,10 +90,25 @@ fn main() {
     )
     .add_plugins((
         CorePlugin,
+        player::PlayerPlugin,
         camera::CameraPlugin,
         camera_controls::CameraControlsPlugin,
         world::WorldPlugin,
+        navigation::NavigationPlugin,
+        units::UnitsPlugin,
+        combat::CombatPlugin,
+        defs::DefsPlugin,
+        mobile_base::MobileBasePlugin,
+        buildings::BuildingsPlugin,
+        resources::ResourcePlugin,
+        power::PowerPlugin,
         ui::UIPlugin,  // UI plugin includes MainMenuPlugin and other UI components
+    ))
+    // A tuple holds at most 16 plugins
+    .add_plugins((
+        production::ProductionPlugin,
+        supply::SupplyPlugin,
+        technology::TechnologyPlugin,
     ));
 
     // Set the initial game state
@@ -91,8 +120,8 @@ fn main() {
         // Add debug tools here when we resolve the dependency issues
     }
     
-    // Always add exit on esc
-    app.add_systems(Update, exit_on_esc);
+    // Exit on esc from the main menu only; elsewhere it cancels commands, placement and rebinding
+    app.add_systems(Update, exit_on_esc.run_if(in_state(state::GameState::MainMenu)));
 
     // Run the app
     app.run();
@@ -100,7 +129,7 @@ fn main() {
 
 
 
-/// System to exit the application when ESC is pressed
+/// System to exit the application when ESC is pressed in the main menu
 fn exit_on_esc(
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut exit: EventWriter<AppExit>,
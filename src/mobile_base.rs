@@ -0,0 +1,661 @@
+//! Mobile bases and the modules attached to them
+//!
+//! A mobile base has a limited set of hardpoints. Each can hold one module: a building
+//! that is paid for up front, takes its build time to attach and keeps its effects on the
+//! base for as long as it stays attached. Modules can add hardpoints of their own. The base
+//! slows down as the mass of its modules grows, while engine modules speed it back up.
+//!
+//! Modules are child entities of the base, so storage modules raise the base's storage
+//! through the usual storage module path and weapon modules fire from where they sit.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use thiserror::Error;
+use crate::buildings::{BuildRadius, Building};
+use crate::combat::{Armor, Health, Weapon};
+use crate::defs::{BuildingDef, BuildingDefs, ModuleDef, Stat};
+use crate::player::{Owner, PlayerId};
+use crate::technology::{PlayerModifiers, Subject};
+use crate::production::ProductionQueue;
+use crate::resources::{ResourceStorage, SpendError, StorageModule, Treasury};
+use crate::units::{Movement, Selectable};
+
+/// Fraction of a module's build time that detaching it takes
+const DETACH_TIME_FRACTION: f32 = 0.5;
+
+/// Fraction of a module's cost returned when it is detached after being finished
+const DETACH_REFUND_FRACTION: f64 = 0.5;
+
+/// How much each further engine module counts compared to the one before it
+const ENGINE_FALLOFF: f32 = 0.5;
+
+/// A place on a mobile base where one module can be attached
+#[derive(Debug, Clone, PartialEq, Reflect)]
+pub struct Hardpoint {
+    /// Offset in meters from the centre of the base
+    pub offset: Vec2,
+    /// Module attached here, if any
+    pub module: Option<Entity>,
+    /// Module that provides this hardpoint, or `None` if it belongs to the base itself
+    pub provided_by: Option<Entity>,
+}
+
+/// Combined effects of every module attached to a base
+#[derive(Debug, Clone, Copy, PartialEq, Default, Reflect)]
+pub struct ModuleEffects {
+    /// Total mass of the modules
+    pub mass: f32,
+    /// Storage capacity added for every resource
+    pub storage: u32,
+    /// Supply added to the base's owner
+    pub supply: u32,
+    /// Fractional production speed bonus (0.25 = +25%)
+    pub production: f32,
+    /// Flat armour added
+    pub armor: f32,
+    /// Maximum health added
+    pub health: f32,
+    /// Fractional speed bonus from engines, after diminishing returns
+    pub engine: f32,
+}
+
+impl ModuleEffects {
+    /// Combine the effects of a set of modules
+    pub fn combine<'a>(modules: impl IntoIterator<Item = &'a ModuleDef>) -> Self {
+        let mut effects = Self::default();
+        let mut engines = Vec::new();
+        for module in modules {
+            effects.mass += module.mass;
+            effects.storage += module.storage;
+            effects.supply += module.supply;
+            effects.production += module.production;
+            effects.armor += module.armor;
+            effects.health += module.health;
+            if module.engine > 0.0 {
+                engines.push(module.engine);
+            }
+        }
+        // The strongest engine counts in full, each further one less than the last
+        engines.sort_by(|a, b| b.total_cmp(a));
+        effects.engine = engines.iter()
+            .enumerate()
+            .map(|(i, engine)| engine * ENGINE_FALLOFF.powi(i as i32))
+            .sum();
+        effects
+    }
+
+    /// Get the top speed of a base with these modules attached
+    pub fn speed(&self, base_speed: f32, base_mass: f32) -> f32 {
+        base_speed * base_mass / (base_mass + self.mass) * (1.0 + self.engine)
+    }
+}
+
+/// Component for mobile bases
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct MobileBase {
+    /// Id of the building definition of the base
+    pub def: String,
+    /// Every hardpoint, including those added by modules
+    pub hardpoints: Vec<Hardpoint>,
+    /// Combined effects of the attached modules, as last applied
+    pub effects: ModuleEffects,
+}
+
+impl MobileBase {
+    /// Create a base with the hardpoint layout of its definition
+    pub fn new(def: &BuildingDef) -> Self {
+        let offsets = def.mobile.as_ref().map(|mobile| mobile.hardpoints.clone()).unwrap_or_default();
+        Self {
+            def: def.id.clone(),
+            hardpoints: offsets.into_iter()
+                .map(|offset| Hardpoint { offset, module: None, provided_by: None })
+                .collect(),
+            effects: ModuleEffects::default(),
+        }
+    }
+
+    /// Get the number of hardpoints without a module
+    pub fn free_hardpoints(&self) -> usize {
+        self.hardpoints.iter().filter(|hardpoint| hardpoint.module.is_none()).count()
+    }
+}
+
+/// What a module is doing
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub enum ModuleState {
+    /// Being attached; its effects do not apply yet
+    Attaching {
+        /// Fraction of the build time done (0.0 to 1.0)
+        progress: f32,
+    },
+    /// Attached and working
+    Attached,
+    /// Being taken off; its effects no longer apply
+    Detaching {
+        /// Fraction of the detach time done (0.0 to 1.0)
+        progress: f32,
+    },
+}
+
+/// Component for modules on a mobile base, which are children of the base
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct Module {
+    /// Id of the building definition of the module
+    pub def: String,
+    /// What the module is doing
+    pub state: ModuleState,
+}
+
+/// Request to attach a module to a free hardpoint of a base
+#[derive(Event, Debug, Clone)]
+pub struct AttachModule {
+    /// Player paying for the module, who must own the base
+    pub player: PlayerId,
+    /// Base to attach to
+    pub base: Entity,
+    /// Index of the hardpoint
+    pub hardpoint: usize,
+    /// Id of the building definition of the module
+    pub module: String,
+}
+
+/// Request to detach the module on a hardpoint of a base
+#[derive(Event, Debug, Clone)]
+pub struct DetachModule {
+    /// Player giving the order, who must own the base
+    pub player: PlayerId,
+    /// Base to detach from
+    pub base: Entity,
+    /// Index of the hardpoint
+    pub hardpoint: usize,
+}
+
+/// A module finished attaching
+#[derive(Event, Debug, Clone)]
+pub struct ModuleAttached {
+    /// Base the module is on
+    pub base: Entity,
+    /// The module entity
+    pub module: Entity,
+    /// Index of the hardpoint
+    pub hardpoint: usize,
+}
+
+/// A module was taken off a base
+#[derive(Event, Debug, Clone)]
+pub struct ModuleDetached {
+    /// Base the module was on
+    pub base: Entity,
+    /// Id of the building definition of the module
+    pub module: String,
+    /// Index the hardpoint had; hardpoints provided by the module are gone
+    pub hardpoint: usize,
+}
+
+/// Reasons a module cannot be attached or detached
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ModuleError {
+    /// The entity is not a mobile base
+    #[error("{0:?} is not a mobile base")]
+    NotABase(Entity),
+
+    /// The player does not own the base
+    #[error("{player} does not own base {base:?}")]
+    NotOwner {
+        /// The commanding player
+        player: PlayerId,
+        /// The base
+        base: Entity,
+    },
+
+    /// The base has no hardpoint with that index
+    #[error("Base has no hardpoint {0}")]
+    NoSuchHardpoint(usize),
+
+    /// The hardpoint already holds a module
+    #[error("Hardpoint {0} is already in use")]
+    HardpointOccupied(usize),
+
+    /// The hardpoint holds no module
+    #[error("Hardpoint {0} has no module")]
+    HardpointEmpty(usize),
+
+    /// The hardpoint belongs to a module that is being detached
+    #[error("Hardpoint {0} is being removed with the module that provides it")]
+    HardpointRemoved(usize),
+
+    /// The module holds other modules on the hardpoints it provides
+    #[error("The module on hardpoint {0} still has modules attached to it")]
+    ModulesAttached(usize),
+
+    /// The module is already being detached
+    #[error("The module on hardpoint {0} is already being detached")]
+    AlreadyDetaching(usize),
+
+    /// No building definition has the id
+    #[error("Unknown building '{0}'")]
+    UnknownBuilding(String),
+
+    /// The building cannot be attached to a base
+    #[error("Building '{0}' is not a module")]
+    NotAModule(String),
+
+    /// The player cannot pay for the module
+    #[error("Cannot afford module: {0}")]
+    CannotAfford(#[from] SpendError),
+}
+
+/// Spawn a mobile base for a player, returning `None` if the definition is not a mobile base
+pub fn spawn_mobile_base(commands: &mut Commands, def: &BuildingDef, owner: PlayerId, position: Vec2) -> Option<Entity> {
+    let mobile = def.mobile.as_ref()?;
+    let radius = def.footprint.max_element() as f32;
+    let mut base = commands.spawn((
+        Name::new(def.name.clone()),
+        MobileBase::new(def),
+        Building { def: def.id.clone() },
+        Movement { radius, ..Movement::new(mobile.speed, default()) },
+        def.health(),
+        def.armor,
+        Owner(owner),
+        ResourceStorage::new(),
+        Selectable::new(def.id.clone(), radius),
+        TransformBundle::from_transform(Transform::from_translation(position.extend(2.0))),
+    ));
+    if def.build_radius > 0.0 {
+        base.insert(BuildRadius::new(def.build_radius));
+    }
+    if let Some(queue) = ProductionQueue::for_building(def) {
+        base.insert(queue);
+    }
+    Some(base.id())
+}
+
+/// Check that a player may change the module on a hardpoint of a base
+fn check_hardpoint<'a>(
+    player: PlayerId,
+    base_entity: Entity,
+    base: Option<(&'a MobileBase, &Owner)>,
+    hardpoint: usize,
+) -> Result<&'a Hardpoint, ModuleError> {
+    let (base, owner) = base.ok_or(ModuleError::NotABase(base_entity))?;
+    if owner.0 != player {
+        return Err(ModuleError::NotOwner { player, base: base_entity });
+    }
+    base.hardpoints.get(hardpoint).ok_or(ModuleError::NoSuchHardpoint(hardpoint))
+}
+
+/// System to pay for and start attaching requested modules
+pub fn handle_attach_requests(
+    mut commands: Commands,
+    defs: Res<BuildingDefs>,
+    mut treasury: Treasury,
+    mut requests: EventReader<AttachModule>,
+    mut bases: Query<(&mut MobileBase, &Owner)>,
+    modules: Query<&Module>,
+) {
+    for request in requests.read() {
+        let result = (|| -> Result<Vec2, ModuleError> {
+            let hardpoint = check_hardpoint(
+                request.player,
+                request.base,
+                bases.get(request.base).ok(),
+                request.hardpoint,
+            )?;
+            if hardpoint.module.is_some() {
+                return Err(ModuleError::HardpointOccupied(request.hardpoint));
+            }
+            let provider_detaching = hardpoint.provided_by
+                .and_then(|provider| modules.get(provider).ok())
+                .is_some_and(|provider| matches!(provider.state, ModuleState::Detaching { .. }));
+            if provider_detaching {
+                return Err(ModuleError::HardpointRemoved(request.hardpoint));
+            }
+            let def = defs.get(&request.module).ok_or_else(|| ModuleError::UnknownBuilding(request.module.clone()))?;
+            if def.module.is_none() {
+                return Err(ModuleError::NotAModule(def.id.clone()));
+            }
+            treasury.try_spend(request.player, &def.cost)?;
+            Ok(hardpoint.offset)
+        })();
+
+        match result {
+            Ok(offset) => {
+                let module = commands.spawn((
+                    Name::new(request.module.clone()),
+                    Module {
+                        def: request.module.clone(),
+                        state: ModuleState::Attaching { progress: 0.0 },
+                    },
+                    TransformBundle::from_transform(Transform::from_translation(offset.extend(1.0))),
+                )).id();
+                commands.entity(request.base).add_child(module);
+                if let Ok((mut base, _)) = bases.get_mut(request.base) {
+                    base.hardpoints[request.hardpoint].module = Some(module);
+                }
+            }
+            Err(err) => warn!("Cannot attach '{}' to {:?}: {}", request.module, request.base, err),
+        }
+    }
+}
+
+/// System to start detaching requested modules, cancelling and refunding those still being attached
+pub fn handle_detach_requests(
+    mut commands: Commands,
+    defs: Res<BuildingDefs>,
+    mut treasury: Treasury,
+    mut requests: EventReader<DetachModule>,
+    mut bases: Query<(&mut MobileBase, &Owner)>,
+    mut modules: Query<&mut Module>,
+    mut detached_events: EventWriter<ModuleDetached>,
+) {
+    for request in requests.read() {
+        let result = (|| -> Result<Entity, ModuleError> {
+            let hardpoint = check_hardpoint(
+                request.player,
+                request.base,
+                bases.get(request.base).ok(),
+                request.hardpoint,
+            )?;
+            let module_entity = hardpoint.module.ok_or(ModuleError::HardpointEmpty(request.hardpoint))?;
+            let (base, _) = bases.get(request.base).map_err(|_| ModuleError::NotABase(request.base))?;
+            let holds_modules = base.hardpoints.iter()
+                .any(|other| other.provided_by == Some(module_entity) && other.module.is_some());
+            if holds_modules {
+                return Err(ModuleError::ModulesAttached(request.hardpoint));
+            }
+            Ok(module_entity)
+        })();
+        let module_entity = match result {
+            Ok(module_entity) => module_entity,
+            Err(err) => {
+                warn!("Cannot detach hardpoint {} of {:?}: {}", request.hardpoint, request.base, err);
+                continue;
+            }
+        };
+        let Ok(mut module) = modules.get_mut(module_entity) else { continue };
+
+        match module.state {
+            ModuleState::Attaching { .. } => {
+                // Nothing was built yet, so cancelling returns everything
+                if let Some(def) = defs.get(&module.def) {
+                    treasury.refund(request.player, &def.cost);
+                }
+                commands.entity(module_entity).despawn_recursive();
+                if let Ok((mut base, _)) = bases.get_mut(request.base) {
+                    base.hardpoints[request.hardpoint].module = None;
+                }
+                detached_events.send(ModuleDetached {
+                    base: request.base,
+                    module: module.def.clone(),
+                    hardpoint: request.hardpoint,
+                });
+            }
+            ModuleState::Attached => {
+                module.state = ModuleState::Detaching { progress: 0.0 };
+            }
+            ModuleState::Detaching { .. } => {
+                warn!("Cannot detach hardpoint {} of {:?}: {}", request.hardpoint, request.base,
+                    ModuleError::AlreadyDetaching(request.hardpoint));
+            }
+        }
+    }
+}
+
+/// Writers announcing modules that finished attaching or detaching
+#[derive(SystemParam)]
+pub struct ModuleEvents<'w> {
+    attached: EventWriter<'w, ModuleAttached>,
+    detached: EventWriter<'w, ModuleDetached>,
+}
+
+/// System to advance modules being attached or detached
+pub fn update_module_work(
+    mut commands: Commands,
+    time: Res<Time>,
+    defs: Res<BuildingDefs>,
+    mut treasury: Treasury,
+    mut modules: Query<(Entity, &mut Module, &Parent)>,
+    mut bases: Query<(&mut MobileBase, &Owner)>,
+    mut module_events: ModuleEvents,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut module, parent) in modules.iter_mut() {
+        let Some(def) = defs.get(&module.def) else { continue };
+        let Ok((mut base, owner)) = bases.get_mut(parent.get()) else { continue };
+        // Hardpoints come and go with the modules that provide them, so look up where this one sits
+        let Some(hardpoint) = base.hardpoints.iter().position(|hardpoint| hardpoint.module == Some(entity)) else { continue };
+        match module.state {
+            ModuleState::Attaching { progress } => {
+                let progress = progress + delta / def.build_time;
+                if progress < 1.0 {
+                    module.state = ModuleState::Attaching { progress };
+                    continue;
+                }
+                module.state = ModuleState::Attached;
+                let mut module_entity = commands.entity(entity);
+                module_entity.insert(Owner(owner.0));
+                if let Some(weapon) = def.weapon() {
+                    module_entity.insert(weapon);
+                }
+                let module_def = def.module.as_ref();
+                if let Some(storage) = module_def.map(|module| module.storage).filter(|&storage| storage > 0) {
+                    module_entity.insert(StorageModule::flat(storage));
+                }
+                base.hardpoints.extend(module_def.into_iter().flat_map(|module| &module.hardpoints).map(|&offset| {
+                    Hardpoint { offset, module: None, provided_by: Some(entity) }
+                }));
+                module_events.attached.send(ModuleAttached { base: parent.get(), module: entity, hardpoint });
+            }
+            ModuleState::Attached => {}
+            ModuleState::Detaching { progress } => {
+                let progress = progress + delta / (def.build_time * DETACH_TIME_FRACTION);
+                if progress < 1.0 {
+                    module.state = ModuleState::Detaching { progress };
+                    continue;
+                }
+                treasury.refund(owner.0, &def.cost.scaled(DETACH_REFUND_FRACTION));
+                commands.entity(entity).despawn_recursive();
+                base.hardpoints[hardpoint].module = None;
+                base.hardpoints.retain(|hardpoint| hardpoint.provided_by != Some(entity));
+                module_events.detached.send(ModuleDetached {
+                    base: parent.get(),
+                    module: module.def.clone(),
+                    hardpoint,
+                });
+            }
+        }
+    }
+}
+
+/// System to take a module's weapon and storage away as soon as it starts detaching
+pub fn disable_detaching_modules(
+    mut commands: Commands,
+    modules: Query<(Entity, &Module), Changed<Module>>,
+) {
+    for (entity, module) in modules.iter() {
+        if matches!(module.state, ModuleState::Detaching { .. }) {
+            commands.entity(entity).remove::<(Weapon, StorageModule)>();
+        }
+    }
+}
+
+/// System to combine the effects of each base's attached modules and apply them to the base
+pub fn apply_module_effects(
+    defs: Res<BuildingDefs>,
+    player_modifiers: Res<PlayerModifiers>,
+    mut bases: Query<(&mut MobileBase, &Owner, &mut Movement, &mut Health, &mut Armor)>,
+    modules: Query<&Module>,
+) {
+    for (mut base, owner, mut movement, mut health, mut armor) in bases.iter_mut() {
+        let Some(base_def) = defs.get(&base.def) else { continue };
+        let Some(mobile) = base_def.mobile.as_ref() else { continue };
+        let attached = base.hardpoints.iter()
+            .filter_map(|hardpoint| modules.get(hardpoint.module?).ok())
+            .filter(|module| module.state == ModuleState::Attached)
+            .filter_map(|module| defs.get(&module.def)?.module.as_ref());
+        let effects = ModuleEffects::combine(attached);
+        if effects == base.effects && !base.is_added() && !player_modifiers.is_changed() {
+            continue;
+        }
+
+        // Research changes the bare base, and modules add to that
+        let apply = |stat: Stat, value: f32| player_modifiers.apply(owner.0, stat, Subject::Building(&base_def.id), value);
+        movement.max_speed = effects.speed(apply(Stat::Speed, mobile.speed), mobile.mass);
+        armor.value = apply(Stat::Armor, base_def.armor.value) + effects.armor;
+        // Extra health comes and goes with the modules, without healing or killing the base
+        health.set_max(apply(Stat::MaxHealth, base_def.health) + effects.health);
+        base.effects = effects;
+    }
+}
+
+/// System to draw each base's hardpoints, coloured by what their module is doing
+pub fn draw_mobile_bases(
+    bases: Query<(&MobileBase, &GlobalTransform)>,
+    modules: Query<&Module>,
+    mut gizmos: Gizmos,
+) {
+    for (base, transform) in bases.iter() {
+        let centre = transform.translation().truncate();
+        for hardpoint in &base.hardpoints {
+            let position = centre + hardpoint.offset;
+            let (color, progress) = match hardpoint.module.and_then(|module| modules.get(module).ok()) {
+                None => (Color::srgb(0.5, 0.5, 0.5), None),
+                Some(module) => match module.state {
+                    ModuleState::Attaching { progress } => (Color::srgb(1.0, 0.9, 0.3), Some(progress)),
+                    ModuleState::Attached => (Color::srgb(0.3, 1.0, 0.4), None),
+                    ModuleState::Detaching { progress } => (Color::srgb(1.0, 0.5, 0.2), Some(1.0 - progress)),
+                },
+            };
+            gizmos.rect_2d(position, 0.0, Vec2::splat(3.0), color);
+            if let Some(progress) = progress {
+                gizmos.line_2d(position - Vec2::new(1.5, 2.0), position + Vec2::new(3.0 * progress - 1.5, -2.0), color);
+            }
+        }
+    }
+}
+
+/// Plugin for mobile bases and their modules
+pub struct MobileBasePlugin;
+
+impl Plugin for MobileBasePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<MobileBase>()
+            .register_type::<Module>()
+            .add_event::<AttachModule>()
+            .add_event::<DetachModule>()
+            .add_event::<ModuleAttached>()
+            .add_event::<ModuleDetached>()
+            .add_systems(Update, (
+                handle_attach_requests,
+                handle_detach_requests,
+                update_module_work,
+                disable_detaching_modules,
+                apply_module_effects,
+            ).chain())
+            .add_systems(Update, draw_mobile_bases);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defs::MobileDef;
+    use crate::resources::{step_seconds, treasury_test_world, ResourceAmount, ResourceCost, ResourceType};
+
+    fn building(id: &str, mobile: Option<MobileDef>, module: Option<ModuleDef>) -> BuildingDef {
+        BuildingDef {
+            id: id.to_string(),
+            name: id.to_string(),
+            sprite: format!("{}.png", id),
+            health: 1000.0,
+            armor: Armor::default(),
+            footprint: UVec2::splat(2),
+            build_radius: 0.0,
+            cost: ResourceCost::new().with(ResourceType::Wood, 100),
+            build_time: 10.0,
+            tier: default(),
+            weapon: None,
+            factions: Vec::new(),
+            requires: Vec::new(),
+            produces: Vec::new(),
+            supply: 0,
+            research: false,
+            mobile,
+            module,
+        }
+    }
+
+    #[test]
+    fn test_modules_attach_detach_and_change_speed() {
+        let defs = BuildingDefs::from_defs("test", vec![
+            building("base", Some(MobileDef {
+                speed: 3.0,
+                mass: 100.0,
+                hardpoints: vec![Vec2::new(-5.0, 0.0), Vec2::new(5.0, 0.0)],
+            }), None),
+            building("frame", None, Some(ModuleDef { mass: 50.0, hardpoints: vec![Vec2::new(0.0, 5.0)], ..default() })),
+            building("engine", None, Some(ModuleDef { mass: 50.0, engine: 0.5, ..default() })),
+        ]).unwrap();
+
+        let mut world = treasury_test_world();
+        world.init_resource::<Events<AttachModule>>();
+        world.init_resource::<Events<DetachModule>>();
+        world.init_resource::<Events<ModuleAttached>>();
+        world.init_resource::<Events<ModuleDetached>>();
+        let player = PlayerId(0);
+        let base = spawn_mobile_base(&mut world.commands(), defs.get("base").unwrap(), player, Vec2::ZERO).unwrap();
+        world.insert_resource(defs);
+        world.init_resource::<PlayerModifiers>();
+        world.flush();
+        world.get_mut::<ResourceStorage>(base).unwrap().add_resource(ResourceType::Wood, ResourceAmount::from_units(300));
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((
+            handle_attach_requests,
+            handle_detach_requests,
+            update_module_work,
+            disable_detaching_modules,
+            apply_module_effects,
+        ).chain());
+        let attach = |world: &mut World, hardpoint: usize, module: &str| {
+            world.send_event(AttachModule { player, base, hardpoint, module: module.to_string() });
+        };
+        let detach = |world: &mut World, hardpoint: usize| {
+            world.send_event(DetachModule { player, base, hardpoint });
+        };
+        let wood = |world: &World| world.get::<ResourceStorage>(base).unwrap().get_amount(ResourceType::Wood);
+        let speed = |world: &World| world.get::<Movement>(base).unwrap().max_speed;
+
+        // Modules are paid for up front and only count once attached
+        attach(&mut world, 0, "frame");
+        attach(&mut world, 1, "engine");
+        attach(&mut world, 1, "frame");
+        step_seconds(&mut world, &mut schedule, 1);
+        assert_eq!(wood(&world), ResourceAmount::from_units(100));
+        assert_eq!(speed(&world), 3.0);
+        step_seconds(&mut world, &mut schedule, 10);
+        assert_eq!(world.get::<MobileBase>(base).unwrap().hardpoints.len(), 3);
+        assert!((speed(&world) - 3.0 * 100.0 / 200.0 * 1.5).abs() < 1e-4);
+
+        // A module holding others cannot be detached; cancelling an attachment refunds it in full
+        attach(&mut world, 2, "engine");
+        detach(&mut world, 0);
+        step_seconds(&mut world, &mut schedule, 1);
+        assert_eq!(wood(&world), ResourceAmount::ZERO);
+        detach(&mut world, 2);
+        step_seconds(&mut world, &mut schedule, 1);
+        assert_eq!(wood(&world), ResourceAmount::from_units(100));
+
+        // Detaching takes time, stops the effects at once and refunds half the cost
+        detach(&mut world, 0);
+        step_seconds(&mut world, &mut schedule, 1);
+        assert!((speed(&world) - 3.0 * 100.0 / 150.0 * 1.5).abs() < 1e-4);
+        step_seconds(&mut world, &mut schedule, 5);
+        let layout = &world.get::<MobileBase>(base).unwrap().hardpoints;
+        assert_eq!(layout.len(), 2);
+        assert!(layout[0].module.is_none());
+        assert_eq!(wood(&world), ResourceAmount::from_units(150));
+    }
+}
@@ -0,0 +1,240 @@
+//! A* search over the navigation grid and path smoothing
+
+use bevy::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use super::{MovementClass, NavGrid};
+
+/// Open-set entry ordered so the heap pops the lowest estimated total cost first, shared by
+/// every search over the navigation grid
+#[derive(Debug, Clone, Copy)]
+pub(super) struct OpenCell {
+    pub(super) estimate: f32,
+    pub(super) cell: IVec2,
+}
+
+impl PartialEq for OpenCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for OpenCell {}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+/// Octile distance between two cells, a lower bound on the cost of any path between them
+pub fn octile_distance(a: IVec2, b: IVec2) -> f32 {
+    let delta = (a - b).abs();
+    let (long, short) = (delta.x.max(delta.y) as f32, delta.x.min(delta.y) as f32);
+    long + (std::f32::consts::SQRT_2 - 1.0) * short
+}
+
+/// Find the cheapest path between two cells, including both ends
+pub fn find_path(grid: &NavGrid, start: IVec2, goal: IVec2, class: MovementClass) -> Option<Vec<IVec2>> {
+    find_path_within(grid, start, goal, class, |_| true)
+}
+
+/// Find the cheapest path between two cells that only visits cells accepted by `allowed`
+pub fn find_path_within(
+    grid: &NavGrid,
+    start: IVec2,
+    goal: IVec2,
+    class: MovementClass,
+    allowed: impl Fn(IVec2) -> bool,
+) -> Option<Vec<IVec2>> {
+    if !grid.is_passable(start, class) || !grid.is_passable(goal, class) {
+        return None;
+    }
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<IVec2, IVec2> = HashMap::new();
+    let mut cost_so_far: HashMap<IVec2, f32> = HashMap::new();
+    open.push(OpenCell { estimate: octile_distance(start, goal), cell: start });
+    cost_so_far.insert(start, 0.0);
+
+    while let Some(OpenCell { estimate, cell }) = open.pop() {
+        if cell == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(&previous) = came_from.get(&current) {
+                path.push(previous);
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let cost = cost_so_far[&cell];
+        // Skip stale heap entries left behind when a cheaper route was found
+        if estimate > cost + octile_distance(cell, goal) + f32::EPSILON {
+            continue;
+        }
+
+        for (next, step_cost) in grid.neighbors(cell, class) {
+            if !allowed(next) {
+                continue;
+            }
+            let new_cost = cost + step_cost;
+            if cost_so_far.get(&next).is_none_or(|&old| new_cost < old) {
+                cost_so_far.insert(next, new_cost);
+                came_from.insert(next, cell);
+                open.push(OpenCell { estimate: new_cost + octile_distance(next, goal), cell: next });
+            }
+        }
+    }
+    None
+}
+
+/// Get the total cost of following a path cell by cell
+pub fn path_cost(grid: &NavGrid, path: &[IVec2], class: MovementClass) -> Option<f32> {
+    path.windows(2).try_fold(0.0, |total, step| {
+        grid.neighbors(step[0], class)
+            .find(|&(cell, _)| cell == step[1])
+            .map(|(_, cost)| total + cost)
+    })
+}
+
+/// Turn a cell path into world waypoints, skipping cells that can be reached in a straight line
+///
+/// A shortcut is only taken if it crosses no terrain more expensive than the stretch of path it
+/// replaces, so smoothing never drags a unit through forest the search went around.
+/// The start cell is not included.
+pub fn smooth_path(grid: &NavGrid, path: &[IVec2], class: MovementClass) -> Vec<Vec2> {
+    let mut waypoints = Vec::new();
+    if path.len() < 2 {
+        return path.iter().map(|&cell| grid.cell_to_world(cell)).collect();
+    }
+
+    let cost = |cell| grid.cost(cell, class).unwrap_or(f32::MAX);
+    let mut anchor = 0;
+    let mut max_cost = cost(path[0]);
+    for i in 1..path.len() {
+        let stretch_cost = max_cost.max(cost(path[i]));
+        if i - anchor > 1 && !grid.line_of_sight(path[anchor], path[i], class, stretch_cost) {
+            anchor = i - 1;
+            waypoints.push(grid.cell_to_world(path[anchor]));
+            max_cost = cost(path[anchor]).max(cost(path[i]));
+        } else {
+            max_cost = stretch_cost;
+        }
+    }
+    waypoints.push(grid.cell_to_world(path[path.len() - 1]));
+    waypoints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{generate_terrain, TerrainType, WorldConfig};
+    use std::collections::VecDeque;
+
+    fn generated_grid(seed: u32) -> NavGrid {
+        let config = WorldConfig { seed, ..default() };
+        NavGrid::from_terrain(&config, &generate_terrain(&config))
+    }
+
+    /// Pick a walkable cell and the reachable cell furthest from it in BFS steps
+    fn distant_connected_cells(grid: &NavGrid) -> Option<(IVec2, IVec2)> {
+        let start = (0..grid.height())
+            .flat_map(|y| (0..grid.width()).map(move |x| IVec2::new(x, y)))
+            .find(|&cell| grid.is_passable(cell, MovementClass::Ground) && grid.neighbors(cell, MovementClass::Ground).count() >= 3)?;
+        let mut seen = HashMap::from([(start, 0)]);
+        let mut queue = VecDeque::from([start]);
+        let mut furthest = start;
+        while let Some(cell) = queue.pop_front() {
+            for (next, _) in grid.neighbors(cell, MovementClass::Ground) {
+                if !seen.contains_key(&next) {
+                    seen.insert(next, seen[&cell] + 1);
+                    if seen[&next] > seen[&furthest] {
+                        furthest = next;
+                    }
+                    queue.push_back(next);
+                }
+            }
+        }
+        (furthest != start).then_some((start, furthest))
+    }
+
+    /// Reference Dijkstra search returning only the cheapest cost
+    fn dijkstra_cost(grid: &NavGrid, start: IVec2, goal: IVec2) -> Option<f32> {
+        let mut open = BinaryHeap::from([OpenCell { estimate: 0.0, cell: start }]);
+        let mut best = HashMap::from([(start, 0.0)]);
+        while let Some(OpenCell { estimate, cell }) = open.pop() {
+            if cell == goal {
+                return Some(estimate);
+            }
+            if estimate > best[&cell] {
+                continue;
+            }
+            for (next, step) in grid.neighbors(cell, MovementClass::Ground) {
+                let cost = estimate + step;
+                if best.get(&next).is_none_or(|&old| cost < old) {
+                    best.insert(next, cost);
+                    open.push(OpenCell { estimate: cost, cell: next });
+                }
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_paths_on_generated_maps_are_valid_and_optimal() {
+        for seed in [1, 7, 42, 1234] {
+            let grid = generated_grid(seed);
+            let (start, goal) = distant_connected_cells(&grid).expect("map has no walkable region");
+
+            let path = find_path(&grid, start, goal, MovementClass::Ground).expect("no path found");
+            assert_eq!(path.first(), Some(&start));
+            assert_eq!(path.last(), Some(&goal));
+            for cell in &path {
+                assert!(grid.is_passable(*cell, MovementClass::Ground), "seed {}: path crosses {:?}", seed, grid.terrain(*cell));
+            }
+
+            let cost = path_cost(&grid, &path, MovementClass::Ground).expect("path has an invalid step");
+            let optimal = dijkstra_cost(&grid, start, goal).unwrap();
+            assert!((cost - optimal).abs() < 1e-3, "seed {}: cost {} but optimal is {}", seed, cost, optimal);
+
+            // Every smoothed leg must be walkable in a straight line
+            let waypoints = smooth_path(&grid, &path, MovementClass::Ground);
+            assert!(waypoints.len() <= path.len());
+            let mut from = start;
+            for waypoint in waypoints {
+                let to = grid.world_to_cell(waypoint);
+                assert!(grid.line_of_sight(from, to, MovementClass::Ground, f32::MAX), "seed {}: shortcut through an obstacle", seed);
+                from = to;
+            }
+        }
+    }
+
+    #[test]
+    fn test_water_blocks_ground_but_not_hover() {
+        let mut grid = NavGrid::new(10, 10, Vec2::ZERO, 1.0);
+        for y in 0..10 {
+            grid.set_terrain(IVec2::new(5, y), TerrainType::Water);
+        }
+        let (start, goal) = (IVec2::new(0, 5), IVec2::new(9, 5));
+        assert!(find_path(&grid, start, goal, MovementClass::Ground).is_none());
+        assert!(find_path(&grid, start, goal, MovementClass::Hover).is_some());
+
+        // Ground units detour around slow forest when it is cheaper
+        let mut grid = NavGrid::new(10, 10, Vec2::ZERO, 1.0);
+        for y in 2..10 {
+            for x in 3..7 {
+                grid.set_terrain(IVec2::new(x, y), TerrainType::Forest);
+            }
+        }
+        let path = find_path(&grid, IVec2::new(0, 3), IVec2::new(9, 3), MovementClass::Ground).unwrap();
+        assert!(path.iter().all(|&cell| grid.terrain(cell) != Some(TerrainType::Forest)));
+    }
+}
@@ -0,0 +1,222 @@
+//! Flow fields for moving large groups to a shared goal
+//!
+//! One search from the goal gives every cell its cheapest cost to reach it, so any number of
+//! units can head for the same point by stepping downhill, with no per-unit search.
+
+use bevy::prelude::*;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+use super::astar::OpenCell;
+use super::{MovementClass, NavGrid, NavGridChanged, NEIGHBOR_OFFSETS};
+
+/// Groups at least this large move with a flow field rather than individual paths
+pub const FLOW_FIELD_MIN_GROUP: usize = 8;
+
+/// Cost to reach one goal from every cell of the grid
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowField {
+    goal: IVec2,
+    class: MovementClass,
+    width: i32,
+    costs: Vec<f32>,
+}
+
+impl FlowField {
+    /// Build a field by searching outwards from the goal
+    pub fn build(grid: &NavGrid, goal: IVec2, class: MovementClass) -> Self {
+        let width = grid.width();
+        let mut field = Self {
+            goal,
+            class,
+            width,
+            costs: vec![f32::INFINITY; (width * grid.height()).max(0) as usize],
+        };
+        if !grid.is_passable(goal, class) {
+            return field;
+        }
+
+        // Step costs are symmetric, so searching from the goal gives the cost to reach it
+        let goal_index = field.index(goal);
+        field.costs[goal_index] = 0.0;
+        let mut open = BinaryHeap::from([OpenCell { estimate: 0.0, cell: goal }]);
+        while let Some(OpenCell { estimate: cost, cell }) = open.pop() {
+            if cost > field.costs[field.index(cell)] {
+                continue;
+            }
+            for (next, step) in grid.neighbors(cell, class) {
+                let index = field.index(next);
+                if cost + step < field.costs[index] {
+                    field.costs[index] = cost + step;
+                    open.push(OpenCell { estimate: cost + step, cell: next });
+                }
+            }
+        }
+        field
+    }
+
+    fn index(&self, cell: IVec2) -> usize {
+        (cell.y * self.width + cell.x) as usize
+    }
+
+    fn in_bounds(&self, cell: IVec2) -> bool {
+        cell.x >= 0 && cell.y >= 0 && cell.x < self.width && (self.index(cell)) < self.costs.len()
+    }
+
+    /// Get the goal cell
+    pub fn goal(&self) -> IVec2 {
+        self.goal
+    }
+
+    /// Get the movement class the field was built for
+    pub fn class(&self) -> MovementClass {
+        self.class
+    }
+
+    /// Get the cost to reach the goal from a cell, or `None` if it cannot
+    pub fn cost(&self, cell: IVec2) -> Option<f32> {
+        if !self.in_bounds(cell) {
+            return None;
+        }
+        let cost = self.costs[self.index(cell)];
+        cost.is_finite().then_some(cost)
+    }
+
+    /// Get the neighbouring cell to step to from `cell`, or `None` at the goal or if unreachable
+    pub fn next_cell(&self, grid: &NavGrid, cell: IVec2) -> Option<IVec2> {
+        let here = self.cost(cell)?;
+        grid.neighbors(cell, self.class)
+            .filter_map(|(next, step)| self.cost(next).map(|cost| (next, cost + step)))
+            .filter(|&(_, total)| total <= here + 1e-3)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(next, _)| next)
+            .filter(|_| cell != self.goal)
+    }
+
+    /// Get the direction to move from a world position, or `None` at the goal or if unreachable
+    pub fn direction(&self, grid: &NavGrid, position: Vec2) -> Option<Vec2> {
+        let next = self.next_cell(grid, grid.world_to_cell(position))?;
+        (grid.cell_to_world(next) - position).try_normalize()
+    }
+
+    /// Check whether changes to these cells could alter the field
+    pub fn is_affected_by(&self, cells: &[IVec2]) -> bool {
+        // A change matters if the cell was reachable or borders a reachable cell
+        cells.iter().any(|&cell| {
+            self.cost(cell).is_some()
+                || NEIGHBOR_OFFSETS.iter().any(|&offset| self.cost(cell + offset).is_some())
+        })
+    }
+}
+
+/// Component for units steering along a shared flow field
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct FlowFieldGoal {
+    /// Where the group is heading
+    pub goal: Vec2,
+    /// How the unit moves
+    pub class: MovementClass,
+}
+
+/// Resource holding the flow fields currently in use
+#[derive(Resource, Debug, Clone, Default)]
+pub struct FlowFields {
+    fields: HashMap<(IVec2, MovementClass), Arc<FlowField>>,
+}
+
+impl FlowFields {
+    /// Get the field towards a goal cell, if one has been built
+    pub fn get(&self, goal: IVec2, class: MovementClass) -> Option<Arc<FlowField>> {
+        self.fields.get(&(goal, class)).cloned()
+    }
+
+    /// Get the field towards a goal cell, building it if needed
+    pub fn get_or_build(&mut self, grid: &NavGrid, goal: IVec2, class: MovementClass) -> Arc<FlowField> {
+        self.fields.entry((goal, class))
+            .or_insert_with(|| Arc::new(FlowField::build(grid, goal, class)))
+            .clone()
+    }
+
+    /// Rebuild only the fields affected by changed cells
+    /// Returns how many fields were rebuilt
+    pub fn invalidate(&mut self, grid: &NavGrid, cells: &[IVec2]) -> usize {
+        let mut rebuilt = 0;
+        for ((goal, class), field) in self.fields.iter_mut() {
+            if field.is_affected_by(cells) {
+                *field = Arc::new(FlowField::build(grid, *goal, *class));
+                rebuilt += 1;
+            }
+        }
+        rebuilt
+    }
+
+    /// Get the number of fields held
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Check whether no fields are held
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// System to build fields for new group goals, drop unused ones and repair changed ones
+pub fn update_flow_fields(
+    grid: Res<NavGrid>,
+    mut fields: ResMut<FlowFields>,
+    goals: Query<&FlowFieldGoal>,
+    mut changed_events: EventReader<NavGridChanged>,
+) {
+    let in_use: HashSet<(IVec2, MovementClass)> = goals.iter()
+        .map(|goal| (grid.world_to_cell(goal.goal), goal.class))
+        .collect();
+    fields.fields.retain(|key, _| in_use.contains(key));
+
+    let cells: Vec<IVec2> = changed_events.read().flat_map(|event| event.cells.iter().copied()).collect();
+    if !cells.is_empty() {
+        fields.invalidate(&grid, &cells);
+    }
+
+    for (goal, class) in in_use {
+        fields.get_or_build(&grid, goal, class);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::TerrainType;
+
+    #[test]
+    fn test_flow_field_leads_to_goal_and_updates() {
+        let mut grid = NavGrid::new(20, 20, Vec2::ZERO, 1.0);
+        for y in 0..15 {
+            grid.set_terrain(IVec2::new(10, y), TerrainType::Mountains);
+        }
+        let goal = IVec2::new(18, 2);
+        let mut fields = FlowFields::default();
+        let field = fields.get_or_build(&grid, goal, MovementClass::Ground);
+
+        // Walking downhill from any reachable cell arrives at the goal
+        let mut cell = IVec2::new(1, 1);
+        for _ in 0..100 {
+            match field.next_cell(&grid, cell) {
+                Some(next) => cell = next,
+                None => break,
+            }
+        }
+        assert_eq!(cell, goal);
+
+        // Closing the gap only rebuilds the affected field, which then reports the start as cut off
+        let mut changed = Vec::new();
+        for y in 15..20 {
+            grid.set_terrain(IVec2::new(10, y), TerrainType::Water);
+            changed.push(IVec2::new(10, y));
+        }
+        assert_eq!(fields.invalidate(&grid, &changed), 1);
+        let field = fields.get(goal, MovementClass::Ground).unwrap();
+        assert_eq!(field.cost(IVec2::new(1, 1)), None);
+        assert!(field.cost(IVec2::new(15, 15)).is_some());
+    }
+}
@@ -0,0 +1,394 @@
+//! Navigation grid built from terrain tiles and blockers
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use crate::world::{TerrainType, TerrainTile, WorldConfig, ResourceNodeMarker};
+use super::NavGridChanged;
+
+/// The eight neighbouring cell offsets, orthogonal first
+pub const NEIGHBOR_OFFSETS: [IVec2; 8] = [
+    IVec2::new(1, 0),
+    IVec2::new(-1, 0),
+    IVec2::new(0, 1),
+    IVec2::new(0, -1),
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 1),
+    IVec2::new(-1, -1),
+];
+
+/// How a unit moves, which decides the terrain it can cross
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Reflect)]
+pub enum MovementClass {
+    /// Wheeled, tracked and walking units
+    #[default]
+    Ground,
+    /// Hovercraft that can also cross water
+    Hover,
+    /// Aircraft that ignore terrain and blockers
+    Air,
+}
+
+impl MovementClass {
+    /// Get every movement class
+    pub fn all() -> [MovementClass; 3] {
+        [MovementClass::Ground, MovementClass::Hover, MovementClass::Air]
+    }
+}
+
+/// Get the cost of entering a tile of this terrain, or `None` if it cannot be crossed
+pub fn terrain_cost(terrain_type: TerrainType, class: MovementClass) -> Option<f32> {
+    match class {
+        MovementClass::Air => Some(1.0),
+        MovementClass::Ground | MovementClass::Hover => match terrain_type {
+            TerrainType::Grassland => Some(1.0),
+            TerrainType::Quarry => Some(1.25),
+            TerrainType::Desert => Some(1.5),
+            TerrainType::Forest => Some(2.0),
+            TerrainType::Water if class == MovementClass::Hover => Some(1.0),
+            TerrainType::Water | TerrainType::Mountains => None,
+        },
+    }
+}
+
+/// Component for entities that block ground movement through the cells they cover
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct NavBlocker {
+    /// Half the size of the blocked area in meters
+    pub half_extents: Vec2,
+}
+
+/// Resource holding the terrain and blockers of every cell, one cell per terrain tile
+#[derive(Resource, Debug, Clone, Default)]
+pub struct NavGrid {
+    width: i32,
+    height: i32,
+    origin: Vec2,
+    cell_size: f32,
+    terrain: Vec<TerrainType>,
+    blockers: Vec<u16>,
+    blocker_cells: HashMap<Entity, Vec<IVec2>>,
+    revision: u64,
+}
+
+impl NavGrid {
+    /// Create a grid of open grassland whose cell (0, 0) is centred on `origin`
+    pub fn new(width: u32, height: u32, origin: Vec2, cell_size: f32) -> Self {
+        let len = (width * height) as usize;
+        Self {
+            width: width as i32,
+            height: height as i32,
+            origin,
+            cell_size,
+            terrain: vec![TerrainType::Grassland; len],
+            blockers: vec![0; len],
+            blocker_cells: HashMap::new(),
+            revision: 0,
+        }
+    }
+
+    /// Create an empty grid matching the tile layout of a world
+    pub fn from_config(config: &WorldConfig) -> Self {
+        Self::new(
+            config.width_tiles(),
+            config.height_tiles(),
+            Vec2::splat(config.border_width),
+            config.tile_size,
+        )
+    }
+
+    /// Create a grid from generated terrain, indexed `[y][x]`
+    pub fn from_terrain(config: &WorldConfig, terrain: &[Vec<TerrainType>]) -> Self {
+        let mut grid = Self::from_config(config);
+        for (y, row) in terrain.iter().enumerate() {
+            for (x, &terrain_type) in row.iter().enumerate() {
+                grid.set_terrain(IVec2::new(x as i32, y as i32), terrain_type);
+            }
+        }
+        grid
+    }
+
+    /// Check whether the grid was built for this world layout
+    pub fn matches(&self, config: &WorldConfig) -> bool {
+        self.width == config.width_tiles() as i32
+            && self.height == config.height_tiles() as i32
+            && self.origin == Vec2::splat(config.border_width)
+            && self.cell_size == config.tile_size
+    }
+
+    /// Get the width in cells
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    /// Get the height in cells
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Get the size of a cell in meters
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    /// Get a counter that increases every time the grid changes
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Check whether a cell lies on the grid
+    pub fn in_bounds(&self, cell: IVec2) -> bool {
+        cell.x >= 0 && cell.y >= 0 && cell.x < self.width && cell.y < self.height
+    }
+
+    fn index(&self, cell: IVec2) -> usize {
+        (cell.y * self.width + cell.x) as usize
+    }
+
+    /// Get the terrain of a cell
+    pub fn terrain(&self, cell: IVec2) -> Option<TerrainType> {
+        self.in_bounds(cell).then(|| self.terrain[self.index(cell)])
+    }
+
+    /// Change the terrain of a cell, returning whether anything changed
+    pub fn set_terrain(&mut self, cell: IVec2, terrain_type: TerrainType) -> bool {
+        if !self.in_bounds(cell) {
+            return false;
+        }
+        let index = self.index(cell);
+        if self.terrain[index] == terrain_type {
+            return false;
+        }
+        self.terrain[index] = terrain_type;
+        self.revision += 1;
+        true
+    }
+
+    /// Check whether a blocker covers a cell
+    pub fn is_blocked(&self, cell: IVec2) -> bool {
+        self.in_bounds(cell) && self.blockers[self.index(cell)] > 0
+    }
+
+    /// Record the cells covered by a blocker, replacing any it covered before
+    /// Returns the cells whose blocked state changed
+    pub fn set_blocker(&mut self, entity: Entity, cells: Vec<IVec2>) -> Vec<IVec2> {
+        if self.blocker_cells.get(&entity) == Some(&cells) {
+            return Vec::new();
+        }
+        let mut affected = self.blocker_cells.get(&entity).cloned().unwrap_or_default();
+        affected.extend(cells.iter().copied());
+        affected.retain(|&cell| self.in_bounds(cell));
+        affected.sort_by_key(|cell| (cell.y, cell.x));
+        affected.dedup();
+        let before: Vec<bool> = affected.iter().map(|&cell| self.is_blocked(cell)).collect();
+
+        self.remove_blocker(entity);
+        for &cell in &cells {
+            if self.in_bounds(cell) {
+                let index = self.index(cell);
+                self.blockers[index] += 1;
+            }
+        }
+        self.blocker_cells.insert(entity, cells);
+        self.revision += 1;
+
+        affected.into_iter()
+            .zip(before)
+            .filter(|&(cell, was_blocked)| self.is_blocked(cell) != was_blocked)
+            .map(|(cell, _)| cell)
+            .collect()
+    }
+
+    /// Forget a blocker, returning the cells that became free
+    pub fn remove_blocker(&mut self, entity: Entity) -> Vec<IVec2> {
+        let Some(cells) = self.blocker_cells.remove(&entity) else {
+            return Vec::new();
+        };
+        let mut freed = Vec::new();
+        for cell in cells {
+            if self.in_bounds(cell) {
+                let index = self.index(cell);
+                self.blockers[index] = self.blockers[index].saturating_sub(1);
+                if self.blockers[index] == 0 {
+                    freed.push(cell);
+                }
+            }
+        }
+        self.revision += 1;
+        freed
+    }
+
+    /// Get the cost of entering a cell, or `None` if it cannot be entered
+    pub fn cost(&self, cell: IVec2, class: MovementClass) -> Option<f32> {
+        let terrain_type = self.terrain(cell)?;
+        if class != MovementClass::Air && self.is_blocked(cell) {
+            return None;
+        }
+        terrain_cost(terrain_type, class)
+    }
+
+    /// Check whether a cell can be entered
+    pub fn is_passable(&self, cell: IVec2, class: MovementClass) -> bool {
+        self.cost(cell, class).is_some()
+    }
+
+    /// Convert a world position to the cell containing it
+    pub fn world_to_cell(&self, position: Vec2) -> IVec2 {
+        ((position - self.origin) / self.cell_size).round().as_ivec2()
+    }
+
+    /// Convert a cell to the world position of its centre
+    pub fn cell_to_world(&self, cell: IVec2) -> Vec2 {
+        self.origin + cell.as_vec2() * self.cell_size
+    }
+
+    /// Get every cell overlapping a rectangle
+    pub fn cells_in_rect(&self, center: Vec2, half_extents: Vec2) -> Vec<IVec2> {
+        let min = self.world_to_cell(center - half_extents);
+        let max = self.world_to_cell(center + half_extents);
+        (min.y..=max.y)
+            .flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+            .filter(|&cell| self.in_bounds(cell))
+            .collect()
+    }
+
+    /// Get the passable neighbours of a cell with the cost of stepping to each
+    /// Diagonal steps may not cut the corners of impassable cells
+    pub fn neighbors(&self, cell: IVec2, class: MovementClass) -> impl Iterator<Item = (IVec2, f32)> + '_ {
+        let here = self.cost(cell, class);
+        NEIGHBOR_OFFSETS.iter().filter_map(move |&offset| {
+            let here = here?;
+            let next = cell + offset;
+            let there = self.cost(next, class)?;
+            let diagonal = offset.x != 0 && offset.y != 0;
+            if diagonal
+                && (!self.is_passable(cell + IVec2::new(offset.x, 0), class)
+                    || !self.is_passable(cell + IVec2::new(0, offset.y), class))
+            {
+                return None;
+            }
+            let length = if diagonal { std::f32::consts::SQRT_2 } else { 1.0 };
+            Some((next, length * (here + there) / 2.0))
+        })
+    }
+
+    /// Find the passable cell closest to `cell` within `max_radius` cells
+    pub fn nearest_passable(&self, cell: IVec2, class: MovementClass, max_radius: i32) -> Option<IVec2> {
+        if self.is_passable(cell, class) {
+            return Some(cell);
+        }
+        (1..=max_radius).find_map(|radius| {
+            (-radius..=radius)
+                .flat_map(|dy| (-radius..=radius).map(move |dx| IVec2::new(dx, dy)))
+                .filter(|offset| offset.x.abs() == radius || offset.y.abs() == radius)
+                .map(|offset| cell + offset)
+                .filter(|&candidate| self.is_passable(candidate, class))
+                .min_by_key(|&candidate| (candidate - cell).length_squared())
+        })
+    }
+
+    /// Check whether a straight line between two cells only crosses passable cells
+    /// costing no more than `max_cost`
+    pub fn line_of_sight(&self, from: IVec2, to: IVec2, class: MovementClass, max_cost: f32) -> bool {
+        let clear = |cell| self.cost(cell, class).is_some_and(|cost| cost <= max_cost);
+        if !clear(from) {
+            return false;
+        }
+
+        let delta = to - from;
+        let steps = delta.abs();
+        let sign = delta.signum();
+        let mut cell = from;
+        let (mut ix, mut iy) = (0, 0);
+        while ix < steps.x || iy < steps.y {
+            let decision = (1 + 2 * ix) * steps.y - (1 + 2 * iy) * steps.x;
+            if decision == 0 {
+                // The line passes exactly through a corner, so both side cells must be clear
+                if !clear(cell + IVec2::new(sign.x, 0)) || !clear(cell + IVec2::new(0, sign.y)) {
+                    return false;
+                }
+                cell += sign;
+                ix += 1;
+                iy += 1;
+            } else if decision < 0 {
+                cell.x += sign.x;
+                ix += 1;
+            } else {
+                cell.y += sign.y;
+                iy += 1;
+            }
+            if !clear(cell) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// System to copy terrain changes into the navigation grid
+pub fn sync_nav_terrain(
+    config: Res<WorldConfig>,
+    mut grid: ResMut<NavGrid>,
+    tiles: Query<(&TerrainTile, &Transform), Changed<TerrainTile>>,
+    mut changed_events: EventWriter<NavGridChanged>,
+) {
+    if !grid.matches(&config) {
+        *grid = NavGrid::from_config(&config);
+    }
+
+    let mut changed = Vec::new();
+    for (tile, transform) in tiles.iter() {
+        let cell = grid.world_to_cell(transform.translation.truncate());
+        if grid.set_terrain(cell, tile.terrain_type) {
+            changed.push(cell);
+        }
+    }
+    if !changed.is_empty() {
+        changed_events.send(NavGridChanged { cells: changed });
+    }
+}
+
+/// System to make resource nodes block movement through their cell
+pub fn add_resource_node_blockers(
+    mut commands: Commands,
+    config: Res<WorldConfig>,
+    nodes: Query<Entity, (Added<ResourceNodeMarker>, Without<NavBlocker>)>,
+) {
+    for entity in nodes.iter() {
+        commands.entity(entity).insert(NavBlocker {
+            half_extents: Vec2::splat(config.tile_size * 0.25),
+        });
+    }
+}
+
+/// Blockers that were added, resized or moved
+type MovedBlockers<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static NavBlocker, &'static GlobalTransform),
+    Or<(Changed<NavBlocker>, Changed<GlobalTransform>)>,
+>;
+
+/// System to keep the cells covered by blockers up to date
+pub fn sync_nav_blockers(
+    mut grid: ResMut<NavGrid>,
+    blockers: MovedBlockers,
+    mut removed: RemovedComponents<NavBlocker>,
+    mut changed_events: EventWriter<NavGridChanged>,
+) {
+    let mut changed = Vec::new();
+    for entity in removed.read() {
+        changed.extend(grid.remove_blocker(entity));
+    }
+    for (entity, blocker, transform) in blockers.iter() {
+        let cells = grid.cells_in_rect(transform.translation().truncate(), blocker.half_extents);
+        changed.extend(grid.set_blocker(entity, cells));
+    }
+    if !changed.is_empty() {
+        changed.sort_by_key(|cell| (cell.y, cell.x));
+        changed.dedup();
+        changed_events.send(NavGridChanged { cells: changed });
+    }
+}
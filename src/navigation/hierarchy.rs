@@ -0,0 +1,429 @@
+//! Hierarchical pathfinding (HPA*) over clusters of the navigation grid
+//!
+//! The grid is split into square clusters. Wherever two neighbouring clusters share an open
+//! stretch of border, transition cells are placed on either side, and the cheapest cost
+//! between every pair of transitions inside a cluster is precomputed. Long queries search
+//! this small abstract graph, then refine within the corridor of clusters it passes through.
+
+use bevy::prelude::*;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use super::astar::OpenCell;
+use super::{find_path_within, octile_distance, MovementClass, NavGrid};
+
+/// Width and height of a cluster in cells
+pub const CLUSTER_SIZE: i32 = 10;
+
+/// Open border stretches longer than this get a transition at each end instead of the middle
+const MAX_ENTRANCE_WIDTH: i32 = 6;
+
+/// Border directions owned by each cluster: towards its right and upper neighbours
+const BORDERS: [IVec2; 2] = [IVec2::new(1, 0), IVec2::new(0, 1)];
+
+/// Cheapest costs from `start` to every cell accepted by `allowed`
+fn costs_within(
+    grid: &NavGrid,
+    start: IVec2,
+    class: MovementClass,
+    allowed: impl Fn(IVec2) -> bool,
+) -> HashMap<IVec2, f32> {
+    let mut costs = HashMap::new();
+    if !grid.is_passable(start, class) {
+        return costs;
+    }
+    let mut open = BinaryHeap::from([OpenCell { estimate: 0.0, cell: start }]);
+    costs.insert(start, 0.0);
+    while let Some(OpenCell { estimate: cost, cell }) = open.pop() {
+        if cost > costs[&cell] {
+            continue;
+        }
+        for (next, step) in grid.neighbors(cell, class) {
+            if !allowed(next) {
+                continue;
+            }
+            let new_cost = cost + step;
+            if costs.get(&next).is_none_or(|&old| new_cost < old) {
+                costs.insert(next, new_cost);
+                open.push(OpenCell { estimate: new_cost, cell: next });
+            }
+        }
+    }
+    costs
+}
+
+/// Abstract graph of cluster transitions for one movement class
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ClusterGraph {
+    class: MovementClass,
+    size: IVec2,
+    /// Transition cell pairs on the border between a cluster and its right or upper neighbour
+    entrances: HashMap<(IVec2, IVec2), Vec<(IVec2, IVec2)>>,
+    /// Cheapest cost between each pair of transitions inside a cluster
+    intra: HashMap<IVec2, HashMap<IVec2, Vec<(IVec2, f32)>>>,
+}
+
+impl ClusterGraph {
+    /// Build the graph for the whole grid
+    pub fn build(grid: &NavGrid, class: MovementClass) -> Self {
+        let mut graph = Self {
+            class,
+            size: IVec2::new(grid.width(), grid.height()),
+            ..default()
+        };
+        let clusters: Vec<IVec2> = graph.clusters().collect();
+        for &cluster in &clusters {
+            graph.update_entrances(grid, cluster);
+        }
+        for &cluster in &clusters {
+            graph.update_intra(grid, cluster);
+        }
+        graph
+    }
+
+    /// Check whether the graph was built for a grid of this size
+    pub fn matches(&self, grid: &NavGrid) -> bool {
+        self.size == IVec2::new(grid.width(), grid.height())
+    }
+
+    /// Get the cluster containing a cell
+    pub fn cluster_of(cell: IVec2) -> IVec2 {
+        cell.div_euclid(IVec2::splat(CLUSTER_SIZE))
+    }
+
+    fn cluster_count(&self) -> IVec2 {
+        (self.size + IVec2::splat(CLUSTER_SIZE - 1)) / CLUSTER_SIZE
+    }
+
+    fn clusters(&self) -> impl Iterator<Item = IVec2> {
+        let count = self.cluster_count();
+        (0..count.y).flat_map(move |y| (0..count.x).map(move |x| IVec2::new(x, y)))
+    }
+
+    fn has_cluster(&self, cluster: IVec2) -> bool {
+        let count = self.cluster_count();
+        cluster.x >= 0 && cluster.y >= 0 && cluster.x < count.x && cluster.y < count.y
+    }
+
+    /// Get the first cell of a cluster and the cell one past its last
+    fn bounds(&self, cluster: IVec2) -> (IVec2, IVec2) {
+        let min = cluster * CLUSTER_SIZE;
+        (min, (min + IVec2::splat(CLUSTER_SIZE)).min(self.size))
+    }
+
+    fn contains(&self, cluster: IVec2, cell: IVec2) -> bool {
+        let (min, max) = self.bounds(cluster);
+        cell.cmpge(min).all() && cell.cmplt(max).all()
+    }
+
+    /// Recompute the transitions on the right and upper borders of a cluster
+    fn update_entrances(&mut self, grid: &NavGrid, cluster: IVec2) {
+        let (min, max) = self.bounds(cluster);
+        for direction in BORDERS {
+            let neighbor = cluster + direction;
+            if !self.has_cluster(neighbor) {
+                continue;
+            }
+            // Walk along the shared border, collecting stretches open on both sides
+            let (along, start, length) = if direction.x != 0 {
+                (IVec2::Y, IVec2::new(max.x - 1, min.y), max.y - min.y)
+            } else {
+                (IVec2::X, IVec2::new(min.x, max.y - 1), max.x - min.x)
+            };
+            let open = |i: i32| {
+                let inside = start + along * i;
+                grid.is_passable(inside, self.class) && grid.is_passable(inside + direction, self.class)
+            };
+
+            let mut transitions = Vec::new();
+            let mut i = 0;
+            while i < length {
+                if !open(i) {
+                    i += 1;
+                    continue;
+                }
+                let run_start = i;
+                while i < length && open(i) {
+                    i += 1;
+                }
+                let run_end = i - 1;
+                let picks = if run_end - run_start + 1 > MAX_ENTRANCE_WIDTH {
+                    vec![run_start, run_end]
+                } else {
+                    vec![(run_start + run_end) / 2]
+                };
+                for pick in picks {
+                    let inside = start + along * pick;
+                    transitions.push((inside, inside + direction));
+                }
+            }
+            self.entrances.insert((cluster, neighbor), transitions);
+        }
+    }
+
+    /// Get the transition cells inside a cluster
+    fn transitions(&self, cluster: IVec2) -> Vec<IVec2> {
+        let mut cells = Vec::new();
+        for direction in BORDERS {
+            if let Some(pairs) = self.entrances.get(&(cluster, cluster + direction)) {
+                cells.extend(pairs.iter().map(|&(inside, _)| inside));
+            }
+            if let Some(pairs) = self.entrances.get(&(cluster - direction, cluster)) {
+                cells.extend(pairs.iter().map(|&(_, inside)| inside));
+            }
+        }
+        cells.sort_by_key(|cell| (cell.y, cell.x));
+        cells.dedup();
+        cells
+    }
+
+    /// Get the cells across a border that a transition connects to
+    fn partners(&self, cell: IVec2) -> Vec<IVec2> {
+        let cluster = Self::cluster_of(cell);
+        let mut partners = Vec::new();
+        for direction in BORDERS {
+            if let Some(pairs) = self.entrances.get(&(cluster, cluster + direction)) {
+                partners.extend(pairs.iter().filter(|pair| pair.0 == cell).map(|pair| pair.1));
+            }
+            if let Some(pairs) = self.entrances.get(&(cluster - direction, cluster)) {
+                partners.extend(pairs.iter().filter(|pair| pair.1 == cell).map(|pair| pair.0));
+            }
+        }
+        partners
+    }
+
+    /// Recompute the costs between the transitions of a cluster
+    fn update_intra(&mut self, grid: &NavGrid, cluster: IVec2) {
+        let transitions = self.transitions(cluster);
+        let mut edges: HashMap<IVec2, Vec<(IVec2, f32)>> = HashMap::new();
+        for &from in &transitions {
+            let costs = costs_within(grid, from, self.class, |cell| self.contains(cluster, cell));
+            let reachable = transitions.iter()
+                .filter(|&&to| to != from)
+                .filter_map(|&to| costs.get(&to).map(|&cost| (to, cost)))
+                .collect();
+            edges.insert(from, reachable);
+        }
+        self.intra.insert(cluster, edges);
+    }
+
+    /// Update the graph after the given cells changed
+    pub fn invalidate(&mut self, grid: &NavGrid, cells: &[IVec2]) {
+        let dirty: HashSet<IVec2> = cells.iter().map(|&cell| Self::cluster_of(cell)).collect();
+
+        // A cluster's transitions depend on its own borders and those its neighbours own
+        let mut touched = HashSet::new();
+        for &cluster in &dirty {
+            for offset in [IVec2::ZERO, IVec2::NEG_X, IVec2::NEG_Y] {
+                let owner = cluster + offset;
+                if self.has_cluster(owner) {
+                    self.update_entrances(grid, owner);
+                }
+            }
+            for offset in [IVec2::ZERO, IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                if self.has_cluster(cluster + offset) {
+                    touched.insert(cluster + offset);
+                }
+            }
+        }
+        for cluster in touched {
+            self.update_intra(grid, cluster);
+        }
+    }
+
+    /// Find a path between two cells through the abstract graph, refined to individual cells
+    pub fn find_path(&self, grid: &NavGrid, start: IVec2, goal: IVec2) -> Option<Vec<IVec2>> {
+        let class = self.class;
+        if !grid.is_passable(start, class) || !grid.is_passable(goal, class) {
+            return None;
+        }
+        let start_cluster = Self::cluster_of(start);
+        let goal_cluster = Self::cluster_of(goal);
+        if start_cluster == goal_cluster {
+            let local = find_path_within(grid, start, goal, class, |cell| self.contains(start_cluster, cell));
+            if local.is_some() {
+                return local;
+            }
+        }
+
+        // Temporarily link the start and goal to the transitions of their clusters
+        let start_costs = costs_within(grid, start, class, |cell| self.contains(start_cluster, cell));
+        let goal_costs = costs_within(grid, goal, class, |cell| self.contains(goal_cluster, cell));
+        let start_links: Vec<(IVec2, f32)> = self.transitions(start_cluster).into_iter()
+            .filter_map(|cell| start_costs.get(&cell).map(|&cost| (cell, cost)))
+            .collect();
+
+        let edges = |node: IVec2| -> Vec<(IVec2, f32)> {
+            if node == start {
+                return start_links.clone();
+            }
+            let cluster = Self::cluster_of(node);
+            let mut edges: Vec<(IVec2, f32)> = self.intra.get(&cluster)
+                .and_then(|cluster_edges| cluster_edges.get(&node))
+                .cloned()
+                .unwrap_or_default();
+            for partner in self.partners(node) {
+                if let Some((_, cost)) = grid.neighbors(node, class).find(|&(cell, _)| cell == partner) {
+                    edges.push((partner, cost));
+                }
+            }
+            if cluster == goal_cluster {
+                if let Some(&cost) = goal_costs.get(&node) {
+                    edges.push((goal, cost));
+                }
+            }
+            edges
+        };
+
+        let abstract_path = search(start, goal, edges)?;
+
+        // Refine with a cell search confined to the clusters the abstract path passes through,
+        // which straightens out the detours forced by fixed transition cells
+        let corridor: HashSet<IVec2> = abstract_path.iter().map(|&node| Self::cluster_of(node)).collect();
+        find_path_within(grid, start, goal, class, |cell| corridor.contains(&Self::cluster_of(cell)))
+    }
+}
+
+/// A* over an implicit graph given by an edge function
+fn search(start: IVec2, goal: IVec2, edges: impl Fn(IVec2) -> Vec<(IVec2, f32)>) -> Option<Vec<IVec2>> {
+    let mut open = BinaryHeap::from([OpenCell { estimate: octile_distance(start, goal), cell: start }]);
+    let mut came_from: HashMap<IVec2, IVec2> = HashMap::new();
+    let mut cost_so_far = HashMap::from([(start, 0.0f32)]);
+    let mut closed = HashSet::new();
+    while let Some(OpenCell { cell: node, .. }) = open.pop() {
+        if node == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(&previous) = came_from.get(&current) {
+                path.push(previous);
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if !closed.insert(node) {
+            continue;
+        }
+        let cost = cost_so_far[&node];
+        for (next, step) in edges(node) {
+            let new_cost = cost + step;
+            if cost_so_far.get(&next).is_none_or(|&old| new_cost < old) {
+                cost_so_far.insert(next, new_cost);
+                came_from.insert(next, node);
+                open.push(OpenCell { estimate: new_cost + octile_distance(next, goal), cell: next });
+            }
+        }
+    }
+    None
+}
+
+/// Resource holding a cluster graph for every movement class
+#[derive(Resource, Debug, Clone, Default)]
+pub struct NavHierarchy {
+    graphs: HashMap<MovementClass, ClusterGraph>,
+    revision: u64,
+}
+
+impl NavHierarchy {
+    /// Rebuild every graph from scratch
+    pub fn rebuild(&mut self, grid: &NavGrid) {
+        self.graphs = MovementClass::all()
+            .into_iter()
+            .map(|class| (class, ClusterGraph::build(grid, class)))
+            .collect();
+        self.revision += 1;
+    }
+
+    /// Update only the clusters around changed cells
+    pub fn invalidate(&mut self, grid: &NavGrid, cells: &[IVec2]) {
+        if self.graphs.values().any(|graph| !graph.matches(grid)) || self.graphs.is_empty() {
+            self.rebuild(grid);
+            return;
+        }
+        for graph in self.graphs.values_mut() {
+            graph.invalidate(grid, cells);
+        }
+        self.revision += 1;
+    }
+
+    /// Get a counter that increases every time the graphs change
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Get the graph for a movement class
+    pub fn graph(&self, class: MovementClass) -> Option<&ClusterGraph> {
+        self.graphs.get(&class)
+    }
+
+    /// Find a path through the hierarchy
+    pub fn find_path(&self, grid: &NavGrid, start: IVec2, goal: IVec2, class: MovementClass) -> Option<Vec<IVec2>> {
+        self.graph(class)?.find_path(grid, start, goal)
+    }
+}
+
+/// System to keep the cluster graphs in step with the navigation grid
+pub fn update_nav_hierarchy(
+    grid: Res<NavGrid>,
+    mut hierarchy: ResMut<NavHierarchy>,
+    mut changed_events: EventReader<super::NavGridChanged>,
+) {
+    let cells: Vec<IVec2> = changed_events.read().flat_map(|event| event.cells.iter().copied()).collect();
+    if !cells.is_empty() {
+        hierarchy.invalidate(&grid, &cells);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::navigation::{find_path, path_cost};
+    use crate::world::{generate_terrain, TerrainType, WorldConfig};
+
+    fn generated_grid(seed: u32) -> NavGrid {
+        let config = WorldConfig { seed, ..default() };
+        NavGrid::from_terrain(&config, &generate_terrain(&config))
+    }
+
+    #[test]
+    fn test_hierarchical_paths_match_reachability() {
+        let grid = generated_grid(42);
+        let graph = ClusterGraph::build(&grid, MovementClass::Ground);
+        let cells: Vec<IVec2> = (0..grid.height())
+            .flat_map(|y| (0..grid.width()).map(move |x| IVec2::new(x, y)))
+            .filter(|&cell| grid.is_passable(cell, MovementClass::Ground))
+            .step_by(37)
+            .collect();
+
+        for &start in &cells {
+            for &goal in cells.iter().rev().step_by(5) {
+                let direct = find_path(&grid, start, goal, MovementClass::Ground);
+                let hierarchical = graph.find_path(&grid, start, goal);
+                assert_eq!(direct.is_some(), hierarchical.is_some(), "{:?} -> {:?}", start, goal);
+                if let (Some(direct), Some(path)) = (direct, hierarchical) {
+                    assert_eq!((path[0], *path.last().unwrap()), (start, goal));
+                    let cost = path_cost(&grid, &path, MovementClass::Ground).expect("invalid step");
+                    // Only long queries use the hierarchy, and those should stay close to optimal
+                    let optimal = path_cost(&grid, &direct, MovementClass::Ground).unwrap();
+                    if octile_distance(start, goal) > (CLUSTER_SIZE * 2) as f32 {
+                        assert!(cost <= optimal * 1.3, "{} is far worse than {}", cost, optimal);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_incremental_update_matches_rebuild() {
+        let mut grid = generated_grid(7);
+        let mut graph = ClusterGraph::build(&grid, MovementClass::Ground);
+
+        let mut changed = Vec::new();
+        for y in 5..25 {
+            let cell = IVec2::new(19, y);
+            if grid.set_terrain(cell, TerrainType::Water) {
+                changed.push(cell);
+            }
+        }
+        graph.invalidate(&grid, &changed);
+        assert_eq!(graph, ClusterGraph::build(&grid, MovementClass::Ground));
+    }
+}
@@ -0,0 +1,66 @@
+//! Navigation grid and pathfinding for units
+//!
+//! The grid mirrors the terrain tiles, one cell per tile, with resource nodes and buildings
+//! blocking the cells they cover. Paths are searched with A* on background tasks, smoothed
+//! and cached until the grid changes. Long paths go through a cluster hierarchy, and large
+//! groups heading to the same place share a flow field.
+
+mod astar;
+mod flow_field;
+mod grid;
+mod hierarchy;
+mod requests;
+
+pub use astar::*;
+pub use flow_field::*;
+pub use grid::*;
+pub use hierarchy::*;
+pub use requests::*;
+
+use bevy::prelude::*;
+
+/// Cells of the navigation grid changed terrain or blocked state
+#[derive(Event, Debug, Clone)]
+pub struct NavGridChanged {
+    /// Every cell that changed
+    pub cells: Vec<IVec2>,
+}
+
+/// Plugin for the navigation grid and path requests
+pub struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<MovementClass>()
+            .register_type::<NavBlocker>()
+            .register_type::<NavPath>()
+            .register_type::<FlowFieldGoal>()
+            .init_resource::<NavGrid>()
+            .init_resource::<PathCache>()
+            .init_resource::<PendingPaths>()
+            .init_resource::<NavHierarchy>()
+            .init_resource::<FlowFields>()
+            .add_event::<NavGridChanged>()
+            .add_event::<PathRequest>()
+            .add_event::<PathReady>()
+            .add_event::<PathFailed>()
+            .add_systems(Update, (
+                sync_nav_terrain,
+                add_resource_node_blockers,
+                sync_nav_blockers.after(sync_nav_terrain),
+                invalidate_path_cache
+                    .after(sync_nav_terrain)
+                    .after(sync_nav_blockers),
+                update_nav_hierarchy
+                    .after(sync_nav_terrain)
+                    .after(sync_nav_blockers),
+                update_flow_fields
+                    .after(sync_nav_terrain)
+                    .after(sync_nav_blockers),
+                start_path_requests
+                    .after(invalidate_path_cache)
+                    .after(update_nav_hierarchy),
+                poll_path_requests.after(start_path_requests),
+            ));
+    }
+}
@@ -0,0 +1,370 @@
+//! Asynchronous path requests and the path cache
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use super::{
+    find_path, octile_distance, smooth_path, MovementClass, NavGrid, NavGridChanged, NavHierarchy,
+    CLUSTER_SIZE,
+};
+
+/// Cells searched around an impassable goal for somewhere to stop instead
+const GOAL_SEARCH_RADIUS: i32 = 8;
+
+/// Paths longer than this many cells are searched through the cluster hierarchy
+const HIERARCHICAL_DISTANCE: f32 = (CLUSTER_SIZE * 2) as f32;
+
+/// Request for a path to be computed in the background
+#[derive(Event, Debug, Clone)]
+pub struct PathRequest {
+    /// Entity that will receive the path
+    pub requester: Entity,
+    /// Where the path starts
+    pub start: Vec2,
+    /// Where the path should end
+    pub goal: Vec2,
+    /// How the requester moves
+    pub class: MovementClass,
+}
+
+/// A requested path was found and inserted as a `NavPath`
+#[derive(Event, Debug, Clone)]
+pub struct PathReady {
+    /// Entity that requested the path
+    pub requester: Entity,
+}
+
+/// No path exists for a request
+#[derive(Event, Debug, Clone)]
+pub struct PathFailed {
+    /// Entity that requested the path
+    pub requester: Entity,
+}
+
+/// Component holding the waypoints an entity is following
+#[derive(Component, Debug, Clone, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct NavPath {
+    /// World positions to visit in order
+    pub waypoints: Vec<Vec2>,
+    /// Index of the waypoint currently being approached
+    pub next: usize,
+}
+
+impl NavPath {
+    /// Create a path through the given waypoints
+    pub fn new(waypoints: Vec<Vec2>) -> Self {
+        Self { waypoints, next: 0 }
+    }
+
+    /// Get the waypoint currently being approached
+    pub fn current(&self) -> Option<Vec2> {
+        self.waypoints.get(self.next).copied()
+    }
+
+    /// Move on to the next waypoint
+    pub fn advance(&mut self) {
+        self.next = (self.next + 1).min(self.waypoints.len());
+    }
+
+    /// Check whether every waypoint has been reached
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.waypoints.len()
+    }
+}
+
+type PathKey = (IVec2, IVec2, MovementClass);
+
+/// Resource caching recently computed paths between cells
+#[derive(Resource, Debug, Clone)]
+pub struct PathCache {
+    /// Maximum number of paths kept
+    pub capacity: usize,
+    paths: HashMap<PathKey, Arc<Vec<Vec2>>>,
+    order: VecDeque<PathKey>,
+}
+
+impl Default for PathCache {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            paths: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+}
+
+impl PathCache {
+    /// Get a cached path between two cells
+    pub fn get(&self, start: IVec2, goal: IVec2, class: MovementClass) -> Option<Arc<Vec<Vec2>>> {
+        self.paths.get(&(start, goal, class)).cloned()
+    }
+
+    /// Store a path, evicting the oldest entry if the cache is full
+    pub fn insert(&mut self, start: IVec2, goal: IVec2, class: MovementClass, waypoints: Arc<Vec<Vec2>>) {
+        let key = (start, goal, class);
+        if self.paths.insert(key, waypoints).is_none() {
+            self.order.push_back(key);
+        }
+        while self.paths.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else { break };
+            self.paths.remove(&oldest);
+        }
+    }
+
+    /// Get the number of cached paths
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Check whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Forget every cached path
+    pub fn clear(&mut self) {
+        self.paths.clear();
+        self.order.clear();
+    }
+}
+
+struct PendingPath {
+    request: PathRequest,
+    key: PathKey,
+    /// Grid revision the search ran against
+    revision: u64,
+    task: Task<Option<Vec<Vec2>>>,
+}
+
+/// Resource holding path searches running in the background
+#[derive(Resource, Default)]
+pub struct PendingPaths {
+    pending: Vec<PendingPath>,
+}
+
+impl PendingPaths {
+    /// Get the number of searches still running
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Check whether no searches are running
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Replace the final waypoint with the exact goal when the goal cell itself was reached
+fn finish_at_goal(grid_goal: IVec2, key: &PathKey, goal: Vec2, waypoints: &[Vec2]) -> Vec<Vec2> {
+    let mut waypoints = waypoints.to_vec();
+    if key.1 == grid_goal {
+        if let Some(last) = waypoints.last_mut() {
+            *last = goal;
+        }
+    }
+    waypoints
+}
+
+/// The navigation grid and hierarchy, with the copy of them shared by background searches
+#[derive(SystemParam)]
+pub struct NavSnapshot<'w, 's> {
+    grid: Res<'w, NavGrid>,
+    hierarchy: Res<'w, NavHierarchy>,
+    shared: Local<'s, Option<(Arc<NavGrid>, Arc<NavHierarchy>)>>,
+}
+
+impl NavSnapshot<'_, '_> {
+    /// Get the copy searches share, taking a new one once the grid or hierarchy changed
+    fn shared(&mut self) -> (Arc<NavGrid>, Arc<NavHierarchy>) {
+        let stale = self.shared.as_ref().is_none_or(|(grid_copy, hierarchy_copy)| {
+            grid_copy.revision() != self.grid.revision() || hierarchy_copy.revision() != self.hierarchy.revision()
+        });
+        if stale {
+            *self.shared = Some((Arc::new(self.grid.clone()), Arc::new(self.hierarchy.clone())));
+        }
+        self.shared.clone().unwrap()
+    }
+}
+
+/// Writers announcing whether path requests succeeded
+#[derive(SystemParam)]
+pub struct PathOutcomes<'w> {
+    ready: EventWriter<'w, PathReady>,
+    failed: EventWriter<'w, PathFailed>,
+}
+
+/// System to answer path requests from the cache or start background searches
+pub fn start_path_requests(
+    mut commands: Commands,
+    mut nav: NavSnapshot,
+    mut requests: EventReader<PathRequest>,
+    cache: Res<PathCache>,
+    mut pending: ResMut<PendingPaths>,
+    mut outcomes: PathOutcomes,
+) {
+    for request in requests.read() {
+        // A new request replaces any search still running for the same entity
+        pending.pending.retain(|path| path.request.requester != request.requester);
+
+        let start = nav.grid.world_to_cell(request.start);
+        let goal_cell = nav.grid.world_to_cell(request.goal);
+        let (Some(start), Some(goal)) = (
+            nav.grid.nearest_passable(start, request.class, 1),
+            nav.grid.nearest_passable(goal_cell, request.class, GOAL_SEARCH_RADIUS),
+        ) else {
+            outcomes.failed.send(PathFailed { requester: request.requester });
+            continue;
+        };
+        let key = (start, goal, request.class);
+
+        if let Some(waypoints) = cache.get(start, goal, request.class) {
+            if let Some(mut entity) = commands.get_entity(request.requester) {
+                entity.insert(NavPath::new(finish_at_goal(goal_cell, &key, request.goal, &waypoints)));
+                outcomes.ready.send(PathReady { requester: request.requester });
+            }
+            continue;
+        }
+
+        // Searches share one copy of the grid and hierarchy until they change
+        let revision = nav.grid.revision();
+        let (grid, hierarchy) = nav.shared();
+        let class = request.class;
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let path = if octile_distance(start, goal) > HIERARCHICAL_DISTANCE && hierarchy.graph(class).is_some() {
+                hierarchy.find_path(&grid, start, goal, class)
+            } else {
+                find_path(&grid, start, goal, class)
+            };
+            path.map(|path| smooth_path(&grid, &path, class))
+        });
+        pending.pending.push(PendingPath {
+            request: request.clone(),
+            key,
+            revision,
+            task,
+        });
+    }
+}
+
+/// System to collect finished background searches, searching again when the grid changed
+/// while they ran
+pub fn poll_path_requests(
+    mut commands: Commands,
+    grid: Res<NavGrid>,
+    mut pending: ResMut<PendingPaths>,
+    mut cache: ResMut<PathCache>,
+    mut request_events: EventWriter<PathRequest>,
+    mut outcomes: PathOutcomes,
+) {
+    let mut still_running = Vec::new();
+    for mut path in pending.pending.drain(..) {
+        let Some(result) = block_on(future::poll_once(&mut path.task)) else {
+            still_running.push(path);
+            continue;
+        };
+        let requester = path.request.requester;
+        let Some(mut entity) = commands.get_entity(requester) else {
+            continue;
+        };
+        // The result may cross new obstacles, and the cache was already cleared of such paths
+        if path.revision != grid.revision() {
+            request_events.send(path.request);
+            continue;
+        }
+
+        match result {
+            Some(waypoints) => {
+                let (start, goal, class) = path.key;
+                let goal_cell = grid.world_to_cell(path.request.goal);
+                entity.insert(NavPath::new(finish_at_goal(goal_cell, &path.key, path.request.goal, &waypoints)));
+                cache.insert(start, goal, class, Arc::new(waypoints));
+                outcomes.ready.send(PathReady { requester });
+            }
+            None => {
+                entity.remove::<NavPath>();
+                outcomes.failed.send(PathFailed { requester });
+            }
+        }
+    }
+    pending.pending = still_running;
+}
+
+/// System to drop cached paths once the grid changes
+pub fn invalidate_path_cache(
+    mut changed_events: EventReader<NavGridChanged>,
+    mut cache: ResMut<PathCache>,
+) {
+    if changed_events.read().count() > 0 {
+        cache.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::TaskPool;
+    use crate::world::{generate_terrain, TerrainType, WorldConfig};
+
+    #[test]
+    fn test_searches_outdated_by_grid_changes_are_repeated() {
+        AsyncComputeTaskPool::get_or_init(TaskPool::default);
+        let config = WorldConfig { seed: 42, ..default() };
+        let grid = NavGrid::from_terrain(&config, &generate_terrain(&config));
+        let cells: Vec<IVec2> = (0..grid.height())
+            .flat_map(|y| (0..grid.width()).map(move |x| IVec2::new(x, y)))
+            .filter(|&cell| grid.is_passable(cell, MovementClass::Ground))
+            .collect();
+        let (start, goal) = (cells[0], cells[cells.len() / 2]);
+
+        let mut world = World::new();
+        let requester = world.spawn_empty().id();
+        let request = PathRequest {
+            requester,
+            start: grid.cell_to_world(start),
+            goal: grid.cell_to_world(goal),
+            class: MovementClass::Ground,
+        };
+        world.insert_resource(grid);
+        world.init_resource::<NavHierarchy>();
+        world.init_resource::<PathCache>();
+        world.init_resource::<PendingPaths>();
+        world.init_resource::<Events<PathRequest>>();
+        world.init_resource::<Events<PathReady>>();
+        world.init_resource::<Events<PathFailed>>();
+        let mut start_schedule = Schedule::default();
+        start_schedule.add_systems(start_path_requests);
+        let mut poll_schedule = Schedule::default();
+        poll_schedule.add_systems(poll_path_requests);
+        let mut finish = |world: &mut World| {
+            for _ in 0..10_000 {
+                poll_schedule.run(world);
+                if world.resource::<PendingPaths>().is_empty() {
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            panic!("the search never finished");
+        };
+
+        // A search that finishes after the grid changed is neither used nor cached, but repeated
+        world.send_event(request);
+        start_schedule.run(&mut world);
+        world.resource_mut::<Events<PathRequest>>().clear();
+        let mut grid = world.resource_mut::<NavGrid>();
+        let corner = IVec2::new(grid.width() - 1, grid.height() - 1);
+        assert!(grid.set_terrain(corner, TerrainType::Water) || grid.set_terrain(corner, TerrainType::Grassland));
+        finish(&mut world);
+        assert!(world.resource::<PathCache>().is_empty());
+        assert!(world.get::<NavPath>(requester).is_none());
+        assert_eq!(world.resource::<Events<PathRequest>>().len(), 1);
+
+        // The repeated search sees the new grid and is cached as usual
+        start_schedule.run(&mut world);
+        finish(&mut world);
+        assert_eq!(world.resource::<PathCache>().len(), 1);
+        assert!(world.get::<NavPath>(requester).is_some());
+    }
+}
@@ -0,0 +1,109 @@
+//! Player identity and entity ownership
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::campaign_menu::Faction;
+use crate::state::GameState;
+
+/// Identifier for a player taking part in a match
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize, Reflect)]
+#[reflect(Debug, PartialEq, Hash)]
+pub struct PlayerId(pub u8);
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Player {}", self.0 + 1)
+    }
+}
+
+/// Component marking which player owns an entity
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Reflect)]
+#[reflect(Component)]
+pub struct Owner(pub PlayerId);
+
+/// Resource identifying the player controlled on this machine
+#[derive(Resource, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Resource)]
+pub struct LocalPlayer(pub PlayerId);
+
+/// Resource recording which players are allied with each other
+#[derive(Resource, Debug, Clone, Default)]
+pub struct Alliances {
+    pairs: HashSet<(PlayerId, PlayerId)>,
+}
+
+impl Alliances {
+    fn key(a: PlayerId, b: PlayerId) -> (PlayerId, PlayerId) {
+        (a.min(b), a.max(b))
+    }
+
+    /// Form an alliance between two players
+    pub fn ally(&mut self, a: PlayerId, b: PlayerId) {
+        if a != b {
+            self.pairs.insert(Self::key(a, b));
+        }
+    }
+
+    /// Dissolve the alliance between two players
+    pub fn break_alliance(&mut self, a: PlayerId, b: PlayerId) {
+        self.pairs.remove(&Self::key(a, b));
+    }
+
+    /// Check whether two different players are allied
+    pub fn are_allied(&self, a: PlayerId, b: PlayerId) -> bool {
+        self.pairs.contains(&Self::key(a, b))
+    }
+}
+
+/// Resource recording which faction each player plays
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PlayerFactions {
+    factions: HashMap<PlayerId, Faction>,
+}
+
+impl PlayerFactions {
+    /// Get a player's faction, the default faction if none was chosen
+    pub fn get(&self, player: PlayerId) -> Faction {
+        self.factions.get(&player).copied().unwrap_or_default()
+    }
+
+    /// Choose a player's faction
+    pub fn set(&mut self, player: PlayerId, faction: Faction) {
+        self.factions.insert(player, faction);
+    }
+}
+
+/// Resource holding the faction the local player picked for their next game
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+#[reflect(Resource)]
+pub struct ChosenFaction(pub Faction);
+
+/// System to give every player of a new game their faction
+pub fn assign_player_factions(
+    local_player: Res<LocalPlayer>,
+    chosen: Res<ChosenFaction>,
+    mut factions: ResMut<PlayerFactions>,
+) {
+    *factions = PlayerFactions::default();
+    factions.set(local_player.0, chosen.0);
+    info!("{} plays the {}", local_player.0, chosen.0.name());
+}
+
+/// Plugin for player identity and ownership
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PlayerId>()
+            .register_type::<Owner>()
+            .register_type::<LocalPlayer>()
+            .init_resource::<LocalPlayer>()
+            .init_resource::<Alliances>()
+            .register_type::<ChosenFaction>()
+            .init_resource::<PlayerFactions>()
+            .init_resource::<ChosenFaction>()
+            .add_systems(OnEnter(GameState::InGame { is_paused: false }), assign_player_factions);
+    }
+}
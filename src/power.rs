@@ -0,0 +1,486 @@
+//! Power grid simulation for buildings
+//!
+//! Producers, consumers and pylons that are close enough, or attached to one another as
+//! children, form a grid. Each grid shares its supply between its consumers; when demand
+//! exceeds supply every consumer on the grid receives the same reduced fraction.
+//! Connections are only worked out again when a power building is added, removed, moved,
+//! reattached or changes owner.
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+use crate::player::{Owner, PlayerId};
+use crate::resources::{Processor, RecipeBook, update_processors};
+use crate::units::CommandHotkeys;
+
+/// Component for buildings that generate power
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct PowerProducer {
+    /// Power generated per second
+    pub output: f32,
+    /// Whether the producer is currently generating
+    pub enabled: bool,
+}
+
+impl PowerProducer {
+    /// Create an enabled producer
+    pub fn new(output: f32) -> Self {
+        Self { output, enabled: true }
+    }
+}
+
+impl Default for PowerProducer {
+    fn default() -> Self {
+        Self::new(50.0)
+    }
+}
+
+/// Component for buildings that draw power
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct PowerConsumer {
+    /// Power required per second at full operation
+    pub demand: f32,
+    /// Fraction of the demand the grid is supplying (0.0 to 1.0)
+    pub satisfaction: f32,
+}
+
+impl PowerConsumer {
+    /// Create a consumer with the given demand
+    pub fn new(demand: f32) -> Self {
+        Self { demand, satisfaction: 0.0 }
+    }
+}
+
+impl Default for PowerConsumer {
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+/// Component for pylons that extend a grid to everything within range
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct PowerPylon {
+    /// Connection range in meters
+    pub range: f32,
+}
+
+impl Default for PowerPylon {
+    fn default() -> Self {
+        Self { range: 60.0 }
+    }
+}
+
+/// A connected set of power buildings
+#[derive(Debug, Clone, Default)]
+pub struct PowerGrid {
+    /// Player owning the grid, if any
+    pub owner: Option<PlayerId>,
+    /// Total power generated per second
+    pub supply: f32,
+    /// Total power requested per second
+    pub demand: f32,
+    /// Fraction of demand met (0.0 to 1.0)
+    pub satisfaction: f32,
+    /// Entities connected to this grid
+    pub members: Vec<Entity>,
+    /// Connections between members, for display
+    pub links: Vec<(Entity, Entity)>,
+}
+
+impl PowerGrid {
+    /// Check whether the grid cannot meet its demand
+    pub fn is_brownout(&self) -> bool {
+        self.demand > 0.0 && self.satisfaction < 1.0
+    }
+}
+
+/// Resource holding the grids found on the last update
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PowerGrids {
+    grids: Vec<PowerGrid>,
+    membership: HashMap<Entity, usize>,
+}
+
+impl PowerGrids {
+    /// Get the grid an entity is connected to
+    pub fn grid_of(&self, entity: Entity) -> Option<&PowerGrid> {
+        self.membership.get(&entity).map(|&index| &self.grids[index])
+    }
+
+    /// Iterate over all grids
+    pub fn iter(&self) -> impl Iterator<Item = &PowerGrid> {
+        self.grids.iter()
+    }
+}
+
+/// Resource controlling the power overlay
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PowerOverlay {
+    /// Whether grids are drawn
+    pub enabled: bool,
+}
+
+/// Disjoint sets over grid member indices
+struct UnionFind {
+    parents: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        Self { parents: (0..len).collect() }
+    }
+
+    fn find(&mut self, index: usize) -> usize {
+        let mut root = index;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        let mut current = index;
+        while self.parents[current] != root {
+            let next = self.parents[current];
+            self.parents[current] = root;
+            current = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents[a] = b;
+        }
+    }
+}
+
+/// System to set processor power demand from the recipe they are running
+pub fn update_processor_power_demand(
+    recipes: Res<RecipeBook>,
+    mut processors: Query<(&Processor, &mut PowerConsumer)>,
+) {
+    for (processor, mut consumer) in processors.iter_mut() {
+        let demand = processor.recipe.as_deref()
+            .and_then(|id| recipes.get(id))
+            .filter(|_| processor.cycle_active)
+            .map_or(0.0, |recipe| recipe.power);
+        if consumer.demand != demand {
+            consumer.demand = demand;
+        }
+    }
+}
+
+/// Buildings taking part in a power grid
+type PowerBuilding = Or<(With<PowerProducer>, With<PowerConsumer>, With<PowerPylon>)>;
+
+/// Power buildings with everything needed to connect them and share out power
+type GridMembers<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform,
+        Option<&'static Owner>,
+        Option<&'static Parent>,
+        Option<&'static PowerProducer>,
+        Option<&'static mut PowerConsumer>,
+        Option<&'static PowerPylon>,
+    ),
+    PowerBuilding,
+>;
+
+/// Power buildings that moved, were reattached or changed hands since the last update
+type RewiredMembers<'w, 's> = Query<
+    'w,
+    's,
+    (),
+    (
+        PowerBuilding,
+        Or<(Changed<GlobalTransform>, Changed<Parent>, Changed<Owner>, Changed<PowerPylon>)>,
+    ),
+>;
+
+/// System to give processors a power connection, so powered recipes only run on a grid
+pub fn add_processor_power_consumers(
+    mut commands: Commands,
+    processors: Query<Entity, (With<Processor>, Without<PowerConsumer>)>,
+) {
+    for entity in processors.iter() {
+        commands.entity(entity).insert(PowerConsumer::default());
+    }
+}
+
+/// System to share out power, reconnecting the grids only when their buildings change
+pub fn update_power_grids(
+    mut grids: ResMut<PowerGrids>,
+    mut members: GridMembers,
+    rewired: RewiredMembers,
+    mut removed_producers: RemovedComponents<PowerProducer>,
+    mut removed_consumers: RemovedComponents<PowerConsumer>,
+    mut removed_pylons: RemovedComponents<PowerPylon>,
+    mut removed_parents: RemovedComponents<Parent>,
+) {
+    // Every reader is drained so the same removals are not seen again next frame
+    let removed = removed_producers.read().count()
+        + removed_consumers.read().count()
+        + removed_pylons.read().count()
+        + removed_parents.read().count();
+    let added = members.iter().any(|(entity, ..)| grids.grid_of(entity).is_none());
+    if removed > 0 || added || !rewired.is_empty() {
+        let (new_grids, membership) = connect_grids(&members);
+        grids.grids = new_grids;
+        grids.membership = membership;
+    }
+
+    for grid in grids.grids.iter_mut() {
+        grid.supply = 0.0;
+        grid.demand = 0.0;
+        for &member in &grid.members {
+            let Ok((_, _, _, _, producer, consumer, _)) = members.get(member) else { continue };
+            grid.supply += producer.filter(|p| p.enabled).map_or(0.0, |p| p.output.max(0.0));
+            grid.demand += consumer.map_or(0.0, |c| c.demand.max(0.0));
+        }
+        grid.satisfaction = if grid.demand > 0.0 {
+            (grid.supply / grid.demand).min(1.0)
+        } else if grid.supply > 0.0 {
+            1.0
+        } else {
+            0.0
+        };
+    }
+
+    for (entity, _, _, _, _, consumer, _) in members.iter_mut() {
+        if let Some(mut consumer) = consumer {
+            let satisfaction = grids.grid_of(entity).map_or(0.0, |grid| grid.satisfaction);
+            if consumer.satisfaction != satisfaction {
+                consumer.satisfaction = satisfaction;
+            }
+        }
+    }
+}
+
+/// Group power buildings into grids by parentage and pylon range
+fn connect_grids(members: &GridMembers) -> (Vec<PowerGrid>, HashMap<Entity, usize>) {
+    struct Member {
+        entity: Entity,
+        position: Vec2,
+        owner: Option<PlayerId>,
+        parent: Option<Entity>,
+        range: Option<f32>,
+    }
+
+    let nodes: Vec<Member> = members.iter()
+        .map(|(entity, transform, owner, parent, _, _, pylon)| Member {
+            entity,
+            position: transform.translation().truncate(),
+            owner: owner.map(|owner| owner.0),
+            parent: parent.map(|parent| parent.get()),
+            range: pylon.map(|pylon| pylon.range),
+        })
+        .collect();
+    let index_of: HashMap<Entity, usize> = nodes.iter()
+        .enumerate()
+        .map(|(index, node)| (node.entity, index))
+        .collect();
+
+    let mut sets = UnionFind::new(nodes.len());
+    let mut links = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        // Buildings attached to a powered base share its grid
+        if let Some(&parent) = node.parent.as_ref().and_then(|parent| index_of.get(parent)) {
+            if nodes[parent].owner == node.owner {
+                sets.union(i, parent);
+                links.push((parent, i));
+            }
+        }
+
+        // Pylons reach everything of the same owner within range
+        let Some(range) = node.range else { continue };
+        for (j, other) in nodes.iter().enumerate() {
+            if i == j || other.owner != node.owner {
+                continue;
+            }
+            // Pylon pairs are linked once, by whichever reaches further
+            let reach = match other.range {
+                Some(other_range) if other_range > range || (other_range == range && j < i) => continue,
+                _ => range,
+            };
+            if node.position.distance_squared(other.position) <= reach * reach {
+                sets.union(i, j);
+                links.push((i, j));
+            }
+        }
+    }
+
+    let mut grid_of_root: HashMap<usize, usize> = HashMap::new();
+    let mut grids: Vec<PowerGrid> = Vec::new();
+    let mut membership = HashMap::with_capacity(nodes.len());
+    for (i, node) in nodes.iter().enumerate() {
+        let root = sets.find(i);
+        let grid_index = *grid_of_root.entry(root).or_insert_with(|| {
+            grids.push(PowerGrid { owner: node.owner, ..default() });
+            grids.len() - 1
+        });
+        grids[grid_index].members.push(node.entity);
+        membership.insert(node.entity, grid_index);
+    }
+    for (a, b) in links {
+        let grid_index = membership[&nodes[a].entity];
+        grids[grid_index].links.push((nodes[a].entity, nodes[b].entity));
+    }
+
+    (grids, membership)
+}
+
+/// System to pass grid satisfaction on to processors, slowing production during brownouts
+pub fn apply_power_to_processors(
+    mut processors: Query<(&mut Processor, &PowerConsumer), Changed<PowerConsumer>>,
+) {
+    for (mut processor, consumer) in processors.iter_mut() {
+        processor.power_satisfaction = consumer.satisfaction;
+    }
+}
+
+/// System to toggle the power overlay with its hotkey
+pub fn toggle_power_overlay(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    hotkeys: Res<CommandHotkeys>,
+    mut overlay: ResMut<PowerOverlay>,
+) {
+    if keyboard_input.just_pressed(hotkeys.power_overlay) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+/// System to draw grids coloured by how well they are supplied
+pub fn draw_power_overlay(
+    overlay: Res<PowerOverlay>,
+    grids: Res<PowerGrids>,
+    pylons: Query<(&PowerPylon, &GlobalTransform)>,
+    transforms: Query<&GlobalTransform>,
+    mut gizmos: Gizmos,
+) {
+    if !overlay.enabled {
+        return;
+    }
+    let position = |entity: Entity| transforms.get(entity).ok().map(|t| t.translation().truncate());
+
+    for grid in grids.iter() {
+        // Green when fully supplied, through orange in a brownout, to red when unpowered
+        let color = if grid.satisfaction >= 1.0 {
+            Color::srgb(0.3, 0.9, 0.3)
+        } else if grid.satisfaction > 0.0 {
+            Color::srgb(1.0, 0.4 + 0.4 * grid.satisfaction, 0.1)
+        } else {
+            Color::srgb(0.9, 0.2, 0.2)
+        };
+        for &(a, b) in &grid.links {
+            if let (Some(a), Some(b)) = (position(a), position(b)) {
+                gizmos.line_2d(a, b, color);
+            }
+        }
+        for &member in &grid.members {
+            if let Some(member_pos) = position(member) {
+                gizmos.circle_2d(member_pos, 4.0, color);
+            }
+        }
+    }
+
+    for (pylon, transform) in pylons.iter() {
+        gizmos.circle_2d(transform.translation().truncate(), pylon.range, Color::srgba(0.4, 0.7, 1.0, 0.25));
+    }
+}
+
+/// Plugin for power generation and distribution
+pub struct PowerPlugin;
+
+impl Plugin for PowerPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PowerProducer>()
+            .register_type::<PowerConsumer>()
+            .register_type::<PowerPylon>()
+            .init_resource::<PowerGrids>()
+            .init_resource::<PowerOverlay>()
+            .add_systems(Update, (
+                add_processor_power_consumers,
+                update_processor_power_demand,
+                update_power_grids.after(update_processor_power_demand),
+                apply_power_to_processors
+                    .after(update_power_grids)
+                    .before(update_processors),
+                toggle_power_overlay,
+                draw_power_overlay
+                    .after(toggle_power_overlay)
+                    .after(update_power_grids),
+            ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_brownout_shares_supply() {
+        let mut world = World::new();
+        world.init_resource::<PowerGrids>();
+
+        world.spawn((PowerProducer::new(30.0), GlobalTransform::from_xyz(0.0, 0.0, 0.0)));
+        world.spawn((PowerPylon { range: 50.0 }, GlobalTransform::from_xyz(20.0, 0.0, 0.0)));
+        let near = world.spawn((PowerConsumer::new(40.0), GlobalTransform::from_xyz(60.0, 0.0, 0.0))).id();
+        let attached = world.spawn((PowerConsumer::new(20.0), GlobalTransform::from_xyz(500.0, 0.0, 0.0))).id();
+        world.entity_mut(near).add_child(attached);
+        let isolated = world.spawn((PowerConsumer::new(10.0), GlobalTransform::from_xyz(900.0, 0.0, 0.0))).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(update_power_grids);
+        schedule.run(&mut world);
+
+        let satisfaction = |entity| world.get::<PowerConsumer>(entity).unwrap().satisfaction;
+        assert_eq!(satisfaction(near), 0.5);
+        assert_eq!(satisfaction(attached), 0.5);
+        assert_eq!(satisfaction(isolated), 0.0);
+        assert!(world.resource::<PowerGrids>().grid_of(near).unwrap().is_brownout());
+    }
+
+    #[test]
+    fn test_grids_rewire_on_change() {
+        let mut world = World::new();
+        world.init_resource::<PowerGrids>();
+
+        let producer = world.spawn((PowerProducer::new(30.0), GlobalTransform::from_xyz(0.0, 0.0, 0.0))).id();
+        world.spawn((PowerPylon { range: 50.0 }, GlobalTransform::from_xyz(20.0, 0.0, 0.0)));
+        let consumer = world.spawn((PowerConsumer::new(40.0), GlobalTransform::from_xyz(60.0, 0.0, 0.0))).id();
+        let processor = world.spawn((Processor::new("smelt_iron"), GlobalTransform::from_xyz(40.0, 0.0, 0.0))).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((add_processor_power_consumers, update_power_grids).chain());
+        schedule.run(&mut world);
+
+        // Processors join the grid through a consumer of their own
+        schedule.run(&mut world);
+        assert!(world.get::<PowerConsumer>(processor).is_some());
+        let satisfaction = |world: &World| world.get::<PowerConsumer>(consumer).unwrap().satisfaction;
+        assert_eq!(satisfaction(&world), 0.75);
+
+        // Demand changes are shared out without rewiring
+        world.get_mut::<PowerConsumer>(consumer).unwrap().demand = 15.0;
+        schedule.run(&mut world);
+        assert_eq!(satisfaction(&world), 1.0);
+
+        // Moving out of range disconnects
+        *world.get_mut::<GlobalTransform>(consumer).unwrap() = GlobalTransform::from_xyz(600.0, 0.0, 0.0);
+        schedule.run(&mut world);
+        assert_eq!(satisfaction(&world), 0.0);
+
+        *world.get_mut::<GlobalTransform>(consumer).unwrap() = GlobalTransform::from_xyz(60.0, 0.0, 0.0);
+        schedule.run(&mut world);
+        assert_eq!(satisfaction(&world), 1.0);
+
+        // Losing the producer leaves the grid unpowered
+        world.despawn(producer);
+        schedule.run(&mut world);
+        assert_eq!(satisfaction(&world), 0.0);
+        assert!(world.resource::<PowerGrids>().grid_of(producer).is_none());
+    }
+}
@@ -0,0 +1,669 @@
+//! Training units and researching upgrades at buildings
+//!
+//! Every building that can make something has a `ProductionQueue`. Its cost is taken from
+//! the player's storages when an item is queued and given back in full if the item is
+//! cancelled, so a running queue never stalls for lack of resources. Only the item at the
+//! front makes progress. Finished units walk out towards the building's rally point, and
+//! units wait at the front of the queue while their owner has no supply to spare. Upgrades
+//! are technologies, researched at buildings that allow it; see `technology`.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use thiserror::Error;
+use crate::camera_controls::GameCursor;
+use crate::campaign_menu::Faction;
+use crate::defs::{BuildingDef, TechnologyDefs, UnitDef, UnitDefs};
+use crate::mobile_base::MobileBase;
+use crate::navigation::NavBlocker;
+use crate::player::{LocalPlayer, Owner, PlayerFactions, PlayerId};
+use crate::resources::{ResourceCost, SpendError, Treasury};
+use crate::state::GameState;
+use crate::supply::{Supply, SupplyCost, SupplyError};
+use crate::technology::{check_unlocked, LockedError, Technologies};
+use crate::units::{
+    issue_player_commands, Command, CommandHotkeys, CommandInput, Movement, OrderQueue, Selectable, Selected,
+};
+
+/// Most items a single building can have queued
+pub const MAX_QUEUE_LENGTH: usize = 5;
+
+/// Something a building can produce
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
+pub enum ProductionItem {
+    /// A unit, by the id of its definition
+    Unit(String),
+    /// An upgrade, by the id of the technology it researches
+    Upgrade(String),
+}
+
+/// An item waiting in or being worked on by a production queue
+#[derive(Debug, Clone, PartialEq, Reflect)]
+pub struct QueuedItem {
+    /// What is being produced
+    pub item: ProductionItem,
+    /// Resources paid when the item was queued, refunded if it is cancelled
+    pub cost: ResourceCost,
+    /// Seconds the item takes at normal speed
+    pub build_time: f32,
+    /// Fraction of the item done (0.0 to 1.0)
+    pub progress: f32,
+}
+
+/// Reasons the item at the front of a queue is not making progress
+#[derive(Error, Debug, Clone, PartialEq, Reflect)]
+pub enum ProductionBlocked {
+    /// The owner has no room for the unit
+    #[error(transparent)]
+    Supply(#[from] SupplyError),
+}
+
+/// Component for buildings that train units or research upgrades
+#[derive(Component, Debug, Clone, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct ProductionQueue {
+    /// Ids of the units the building can train
+    pub options: Vec<String>,
+    /// Whether technologies can be researched at the building
+    pub research: bool,
+    /// Where finished units head, if anywhere
+    pub rally_point: Option<Vec2>,
+    /// Why the front item is not making progress, if it is not
+    pub blocked: Option<ProductionBlocked>,
+    items: VecDeque<QueuedItem>,
+}
+
+impl ProductionQueue {
+    /// Create an empty queue for a building that can train the given units
+    pub fn new(options: Vec<String>) -> Self {
+        Self { options, ..default() }
+    }
+
+    /// Create the queue for a building of the given definition, if it can produce anything
+    pub fn for_building(def: &BuildingDef) -> Option<Self> {
+        (!def.produces.is_empty() || def.research).then(|| Self { research: def.research, ..Self::new(def.produces.clone()) })
+    }
+
+    /// Get the item being worked on
+    pub fn current(&self) -> Option<&QueuedItem> {
+        self.items.front()
+    }
+
+    /// Iterate over every item, current first
+    pub fn iter(&self) -> impl Iterator<Item = &QueuedItem> + '_ {
+        self.items.iter()
+    }
+
+    /// Get the number of queued items
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Check whether nothing is queued
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Check whether no more items can be queued
+    pub fn is_full(&self) -> bool {
+        self.items.len() >= MAX_QUEUE_LENGTH
+    }
+
+    /// Add an already paid-for item to the back of the queue
+    pub fn push(&mut self, item: ProductionItem, cost: ResourceCost, build_time: f32) -> Result<(), ProductionError> {
+        if self.is_full() {
+            return Err(ProductionError::QueueFull);
+        }
+        self.items.push_back(QueuedItem { item, cost, build_time, progress: 0.0 });
+        Ok(())
+    }
+
+    /// Take an item out of the queue, returning it so its cost can be refunded
+    pub fn remove(&mut self, index: usize) -> Result<QueuedItem, ProductionError> {
+        self.items.remove(index).ok_or(ProductionError::NoSuchItem(index))
+    }
+
+    /// Move an item to another place in the queue; an item moved off the front keeps its progress
+    pub fn reorder(&mut self, from: usize, to: usize) -> Result<(), ProductionError> {
+        if to >= self.items.len() {
+            return Err(ProductionError::NoSuchItem(to));
+        }
+        let item = self.remove(from)?;
+        self.items.insert(to, item);
+        Ok(())
+    }
+}
+
+/// Request to pay for an item and add it to a building's queue
+#[derive(Event, Debug, Clone)]
+pub struct EnqueueProduction {
+    /// Player paying for the item, who must own the building
+    pub player: PlayerId,
+    /// The producing building
+    pub building: Entity,
+    /// What to produce
+    pub item: ProductionItem,
+}
+
+/// Request to take an item out of a building's queue and get its cost back
+#[derive(Event, Debug, Clone)]
+pub struct CancelProduction {
+    /// Player giving the order, who must own the building
+    pub player: PlayerId,
+    /// The producing building
+    pub building: Entity,
+    /// Index of the item, 0 being the one worked on
+    pub index: usize,
+}
+
+/// Request to move an item to another place in a building's queue
+#[derive(Event, Debug, Clone)]
+pub struct ReorderProduction {
+    /// Player giving the order, who must own the building
+    pub player: PlayerId,
+    /// The producing building
+    pub building: Entity,
+    /// Index the item is at
+    pub from: usize,
+    /// Index the item should end up at
+    pub to: usize,
+}
+
+/// Request to change where a building's finished units head
+#[derive(Event, Debug, Clone)]
+pub struct SetRallyPoint {
+    /// Player giving the order, who must own the building
+    pub player: PlayerId,
+    /// The producing building
+    pub building: Entity,
+    /// The new rally point, or `None` to clear it
+    pub point: Option<Vec2>,
+}
+
+/// A building finished an item
+#[derive(Event, Debug, Clone)]
+pub struct ProductionCompleted {
+    /// Owner of the building
+    pub player: PlayerId,
+    /// The producing building
+    pub building: Entity,
+    /// What was produced
+    pub item: ProductionItem,
+    /// The new unit, if the item was a unit
+    pub unit: Option<Entity>,
+}
+
+/// Reasons a production request is refused
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ProductionError {
+    /// The entity has no production queue
+    #[error("{0:?} cannot produce anything")]
+    NotAProducer(Entity),
+
+    /// The player does not own the building
+    #[error("{player} does not own building {building:?}")]
+    NotOwner {
+        /// The commanding player
+        player: PlayerId,
+        /// The building
+        building: Entity,
+    },
+
+    /// No unit definition has the id
+    #[error("Unknown unit '{0}'")]
+    UnknownUnit(String),
+
+    /// No technology has the id
+    #[error("Unknown technology '{0}'")]
+    UnknownUpgrade(String),
+
+    /// The player has researched or queued the technology already
+    #[error("Technology '{0}' is already researched or queued")]
+    AlreadyResearched(String),
+
+    /// The player has not unlocked the item
+    #[error(transparent)]
+    Locked(#[from] LockedError),
+
+    /// The building cannot produce the item
+    #[error("The building cannot produce {0:?}")]
+    CannotProduce(ProductionItem),
+
+    /// The queue already holds as many items as it can
+    #[error("The production queue is full")]
+    QueueFull,
+
+    /// The queue has no item at the index
+    #[error("The production queue has no item {0}")]
+    NoSuchItem(usize),
+
+    /// The player cannot pay for the item
+    #[error("Cannot afford item: {0}")]
+    CannotAfford(#[from] SpendError),
+}
+
+/// Get a player's production queue, checking that they own the building
+fn producer_mut<'a>(
+    player: PlayerId,
+    building: Entity,
+    producer: Option<(Mut<'a, ProductionQueue>, &Owner)>,
+) -> Result<Mut<'a, ProductionQueue>, ProductionError> {
+    let (queue, owner) = producer.ok_or(ProductionError::NotAProducer(building))?;
+    if owner.0 != player {
+        return Err(ProductionError::NotOwner { player, building });
+    }
+    Ok(queue)
+}
+
+/// Component recording which definition a unit was built from
+#[derive(Component, Debug, Clone, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub struct Unit {
+    /// Id of the unit definition
+    pub def: String,
+}
+
+/// Spawn a freshly built unit for a player at `position`
+pub fn spawn_unit(commands: &mut Commands, def: &UnitDef, owner: PlayerId, position: Vec2) -> Entity {
+    let mut unit = commands.spawn((
+        Name::new(def.name.clone()),
+        Unit { def: def.id.clone() },
+        def.movement(),
+        def.health(),
+        def.armor,
+        SupplyCost(def.supply),
+        Owner(owner),
+        Selectable::new(def.id.clone(), def.radius),
+        TransformBundle::from_transform(Transform::from_translation(position.extend(1.0))),
+    ));
+    if let Some(weapon) = def.weapon() {
+        unit.insert(weapon);
+    }
+    if let Some(builder) = &def.builder {
+        unit.insert(builder.clone());
+    }
+    if let Some(gatherer) = &def.gatherer {
+        unit.insert(gatherer.gatherer());
+    }
+    unit.id()
+}
+
+/// Technology definitions with every player's research and faction, for deciding what they
+/// may produce
+#[derive(SystemParam)]
+pub struct ResearchState<'w> {
+    defs: Res<'w, TechnologyDefs>,
+    technologies: Res<'w, Technologies>,
+    player_factions: Res<'w, PlayerFactions>,
+}
+
+impl ResearchState<'_> {
+    /// Check that a player may produce something limited to `factions` and requiring `requires`
+    fn check_unlocked(&self, player: PlayerId, factions: &[Faction], requires: &[String]) -> Result<(), LockedError> {
+        check_unlocked(&self.technologies, &self.player_factions, player, factions, requires)
+    }
+}
+
+/// System to pay for requested items and add them to their buildings' queues
+pub fn enqueue_production(
+    unit_defs: Res<UnitDefs>,
+    research: ResearchState,
+    mut treasury: Treasury,
+    mut requests: EventReader<EnqueueProduction>,
+    mut producers: Query<(&mut ProductionQueue, &Owner)>,
+) {
+    for request in requests.read() {
+        // Research queued elsewhere counts too, so nobody pays for a technology twice
+        let queued_elsewhere = |id: &str| producers.iter().any(|(queue, owner)| {
+            owner.0 == request.player
+                && queue.iter().any(|queued| matches!(&queued.item, ProductionItem::Upgrade(other) if other == id))
+        });
+        let already_researched = match &request.item {
+            ProductionItem::Upgrade(id) => {
+                research.technologies.is_researched(request.player, id) || queued_elsewhere(id)
+            }
+            ProductionItem::Unit(_) => false,
+        };
+        let result = (|| {
+            let mut queue = producer_mut(request.player, request.building, producers.get_mut(request.building).ok())?;
+            let (cost, build_time) = match &request.item {
+                ProductionItem::Unit(id) => {
+                    if !queue.options.contains(id) {
+                        return Err(ProductionError::CannotProduce(request.item.clone()));
+                    }
+                    let def = unit_defs.get(id).ok_or_else(|| ProductionError::UnknownUnit(id.clone()))?;
+                    research.check_unlocked(request.player, &def.factions, &def.requires)?;
+                    (def.cost.clone(), def.build_time)
+                }
+                ProductionItem::Upgrade(id) => {
+                    if !queue.research {
+                        return Err(ProductionError::CannotProduce(request.item.clone()));
+                    }
+                    let def = research.defs.get(id).ok_or_else(|| ProductionError::UnknownUpgrade(id.clone()))?;
+                    research.check_unlocked(request.player, &def.factions, &def.requires)?;
+                    if already_researched {
+                        return Err(ProductionError::AlreadyResearched(id.clone()));
+                    }
+                    (def.cost.clone(), def.research_time)
+                }
+            };
+            if queue.is_full() {
+                return Err(ProductionError::QueueFull);
+            }
+            treasury.try_spend(request.player, &cost)?;
+            queue.push(request.item.clone(), cost, build_time)
+        })();
+        if let Err(err) = result {
+            warn!("{} cannot queue {:?}: {}", request.player, request.item, err);
+        }
+    }
+}
+
+/// System to take cancelled items out of their queues, refunding their full cost
+pub fn cancel_production(
+    mut treasury: Treasury,
+    mut requests: EventReader<CancelProduction>,
+    mut producers: Query<(&mut ProductionQueue, &Owner)>,
+) {
+    for request in requests.read() {
+        let result = producer_mut(request.player, request.building, producers.get_mut(request.building).ok())
+            .and_then(|mut queue| queue.remove(request.index));
+        match result {
+            Ok(item) => {
+                treasury.refund(request.player, &item.cost);
+            }
+            Err(err) => warn!("{} cannot cancel production: {}", request.player, err),
+        }
+    }
+}
+
+/// System to move items within their queues
+pub fn reorder_production(
+    mut requests: EventReader<ReorderProduction>,
+    mut producers: Query<(&mut ProductionQueue, &Owner)>,
+) {
+    for request in requests.read() {
+        let result = producer_mut(request.player, request.building, producers.get_mut(request.building).ok())
+            .and_then(|mut queue| queue.reorder(request.from, request.to));
+        if let Err(err) = result {
+            warn!("{} cannot reorder production: {}", request.player, err);
+        }
+    }
+}
+
+/// System to apply requested rally points
+pub fn set_rally_points(
+    mut requests: EventReader<SetRallyPoint>,
+    mut producers: Query<(&mut ProductionQueue, &Owner)>,
+) {
+    for request in requests.read() {
+        match producer_mut(request.player, request.building, producers.get_mut(request.building).ok()) {
+            Ok(mut queue) => queue.rally_point = request.point,
+            Err(err) => warn!("{} cannot set a rally point: {}", request.player, err),
+        }
+    }
+}
+
+/// Buildings with a production queue, with what is needed to place what they finish
+type Producers<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut ProductionQueue,
+        &'static Owner,
+        &'static Selectable,
+        &'static GlobalTransform,
+        Option<&'static NavBlocker>,
+        Option<&'static MobileBase>,
+    ),
+>;
+
+/// Selected buildings that can be given a rally point; mobile bases move on a right-click instead
+type RallyingProducers<'w, 's> =
+    Query<'w, 's, (Entity, &'static Owner), (With<ProductionQueue>, With<Selected>, Without<Movement>)>;
+
+/// System to work on the front item of every queue and hand out what is finished
+pub fn advance_production(
+    mut commands: Commands,
+    time: Res<Time>,
+    unit_defs: Res<UnitDefs>,
+    mut supply: ResMut<Supply>,
+    mut treasury: Treasury,
+    mut producers: Producers,
+    mut completed_events: EventWriter<ProductionCompleted>,
+) {
+    for (building, mut queue, owner, selectable, transform, blocker, base) in producers.iter_mut() {
+        let Some(current) = queue.current() else {
+            queue.blocked = None;
+            continue;
+        };
+        let cost = match &current.item {
+            ProductionItem::Unit(id) => unit_defs.get(id).map_or(0, |def| def.supply),
+            ProductionItem::Upgrade(_) => 0,
+        };
+        queue.blocked = supply.check(owner.0, cost).err().map(ProductionBlocked::from);
+        if queue.blocked.is_some() {
+            continue;
+        }
+
+        // Production modules on a mobile base speed up everything it makes
+        let speed = 1.0 + base.map_or(0.0, |base| base.effects.production);
+        let item = queue.items.front_mut().expect("the queue has a current item");
+        item.progress = (item.progress + speed * time.delta_seconds() / item.build_time).min(1.0);
+        if item.progress < 1.0 {
+            continue;
+        }
+
+        let item = queue.items.pop_front().expect("the queue has a current item");
+        let unit = match &item.item {
+            ProductionItem::Unit(id) => {
+                let Some(def) = unit_defs.get(id) else {
+                    // The definition went away in a reload, so the item can never be finished
+                    warn!("Unknown unit '{}' finished at {:?}; refunding it", id, building);
+                    treasury.refund(owner.0, &item.cost);
+                    continue;
+                };
+                let centre = transform.translation().truncate();
+                let direction = queue.rally_point
+                    .and_then(|point| (point - centre).try_normalize())
+                    .unwrap_or(Vec2::NEG_Y);
+                // Units walk out past the corner of the footprint, so they never start inside it
+                let reach = blocker.map_or(selectable.radius, |blocker| blocker.half_extents.length());
+                let position = centre + direction * (reach + def.radius);
+                let unit = spawn_unit(&mut commands, def, owner.0, position);
+                if let Some(target) = queue.rally_point {
+                    let mut orders = OrderQueue::default();
+                    orders.push(Command::Move { target }, false);
+                    commands.entity(unit).insert(orders);
+                }
+                supply.use_supply(owner.0, def.supply);
+                Some(unit)
+            }
+            ProductionItem::Upgrade(_) => None,
+        };
+        completed_events.send(ProductionCompleted { player: owner.0, building, item: item.item, unit });
+    }
+}
+
+/// System to queue the first unit each of the local player's selected buildings can train,
+/// or at research buildings the first technology the player can research next
+pub fn train_selected_units(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    hotkeys: Res<CommandHotkeys>,
+    local_player: Res<LocalPlayer>,
+    research: ResearchState,
+    producers: Query<(Entity, &ProductionQueue, &Owner), With<Selected>>,
+    mut enqueue_events: EventWriter<EnqueueProduction>,
+) {
+    if !keyboard_input.just_pressed(hotkeys.train) {
+        return;
+    }
+    let player = local_player.0;
+    let mut queued: Vec<String> = producers.iter()
+        .filter(|(_, _, owner)| owner.0 == player)
+        .flat_map(|(_, queue, _)| queue.iter())
+        .filter_map(|queued| match &queued.item {
+            ProductionItem::Upgrade(id) => Some(id.clone()),
+            ProductionItem::Unit(_) => None,
+        })
+        .collect();
+    for (building, queue, owner) in producers.iter() {
+        if owner.0 != player {
+            continue;
+        }
+        let item = if queue.research {
+            let mut available: Vec<_> = research.defs.iter()
+                .filter(|def| !research.technologies.is_researched(player, &def.id) && !queued.contains(&def.id))
+                .filter(|def| research.check_unlocked(player, &def.factions, &def.requires).is_ok())
+                .collect();
+            available.sort_by(|a, b| a.level.cmp(&b.level).then_with(|| a.id.cmp(&b.id)));
+            available.first().map(|def| {
+                queued.push(def.id.clone());
+                ProductionItem::Upgrade(def.id.clone())
+            })
+        } else {
+            queue.options.first().map(|id| ProductionItem::Unit(id.clone()))
+        };
+        if let Some(item) = item {
+            enqueue_events.send(EnqueueProduction { player, building, item });
+        }
+    }
+}
+
+/// System to set the rally point of the local player's selected buildings with a right-click
+pub fn set_rally_points_on_click(
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    game_cursor: GameCursor,
+    local_player: Res<LocalPlayer>,
+    command_input: Res<CommandInput>,
+    producers: RallyingProducers,
+    mut rally_events: EventWriter<SetRallyPoint>,
+) {
+    if !mouse_buttons.just_pressed(MouseButton::Right) || command_input.pending.is_some() {
+        return;
+    }
+    let Some(point) = game_cursor.world_position() else { return };
+    for (building, owner) in producers.iter() {
+        if owner.0 == local_player.0 {
+            rally_events.send(SetRallyPoint { player: local_player.0, building, point: Some(point) });
+        }
+    }
+}
+
+/// System to draw the rally points of selected buildings
+pub fn draw_rally_points(
+    producers: Query<(&ProductionQueue, &GlobalTransform), With<Selected>>,
+    mut gizmos: Gizmos,
+) {
+    let color = Color::srgb(0.4, 1.0, 0.6);
+    for (queue, transform) in producers.iter() {
+        let Some(point) = queue.rally_point else { continue };
+        gizmos.line_2d(transform.translation().truncate(), point, color);
+        gizmos.circle_2d(point, 2.0, color);
+    }
+}
+
+/// Plugin for production queues
+pub struct ProductionPlugin;
+
+impl Plugin for ProductionPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<ProductionQueue>()
+            .add_event::<EnqueueProduction>()
+            .add_event::<CancelProduction>()
+            .add_event::<ReorderProduction>()
+            .add_event::<SetRallyPoint>()
+            .add_event::<ProductionCompleted>()
+            .add_systems(Update, (
+                train_selected_units,
+                set_rally_points_on_click.before(issue_player_commands),
+                draw_rally_points,
+            ).run_if(in_state(GameState::InGame { is_paused: false })))
+            .add_systems(Update, (
+                enqueue_production.after(train_selected_units),
+                cancel_production,
+                reorder_production,
+                set_rally_points.after(set_rally_points_on_click),
+                advance_production,
+            ).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defs::Definition;
+    use crate::resources::{spawn_stocked_storage, step_seconds, treasury_test_world, ResourceStorage, ResourceType};
+
+    #[test]
+    fn test_queue_reserves_costs_and_waits_for_supply() {
+        let mut world = treasury_test_world();
+        world.insert_resource(UnitDefs::load_from_file(UnitDef::FILE).expect("unit definitions load"));
+        world.insert_resource(TechnologyDefs::from_defs("technologies.ron", Vec::new()).unwrap());
+        world.init_resource::<Technologies>();
+        world.init_resource::<PlayerFactions>();
+        let player = PlayerId(0);
+        let mut supply = Supply::default();
+        supply.provide(player, 1);
+        world.insert_resource(supply);
+        world.init_resource::<Events<EnqueueProduction>>();
+        world.init_resource::<Events<CancelProduction>>();
+        world.init_resource::<Events<ReorderProduction>>();
+        world.init_resource::<Events<SetRallyPoint>>();
+        world.init_resource::<Events<ProductionCompleted>>();
+        let storage = spawn_stocked_storage(&mut world, player, &[(ResourceType::Wood, 500), (ResourceType::Iron, 100)]);
+        let building = world.spawn((
+            ProductionQueue::new(vec!["gatherer".to_string(), "engineer".to_string()]),
+            Owner(player),
+            Selectable::new("barracks", 20.0),
+            NavBlocker { half_extents: Vec2::splat(20.0) },
+            GlobalTransform::from_translation(Vec3::new(100.0, 100.0, 1.0)),
+        )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((enqueue_production, cancel_production, reorder_production, set_rally_points, advance_production).chain());
+        let wood = |world: &World| world.get::<ResourceStorage>(storage).unwrap().get_amount(ResourceType::Wood).to_f64();
+        let queue = |world: &World| world.get::<ProductionQueue>(building).unwrap().clone();
+
+        // Queueing pays up front, refusing what the building cannot make or what does not fit
+        let unit = |id: &str| ProductionItem::Unit(id.to_string());
+        for item in [unit("gatherer"), unit("engineer"), unit("tank"), unit("gatherer"), unit("gatherer"), unit("gatherer"), unit("gatherer")] {
+            world.send_event(EnqueueProduction { player, building, item });
+        }
+        world.send_event(SetRallyPoint { player, building, point: Some(Vec2::new(200.0, 100.0)) });
+        step_seconds(&mut world, &mut schedule, 1);
+        assert_eq!(queue(&world).len(), MAX_QUEUE_LENGTH);
+        assert!((wood(&world) - (500.0 - 75.0 - 50.0 * 4.0)).abs() < 1e-3);
+
+        // Cancelling refunds in full and reordering keeps the front item's progress
+        world.send_event(CancelProduction { player, building, index: 4 });
+        world.send_event(ReorderProduction { player, building, from: 0, to: 1 });
+        step_seconds(&mut world, &mut schedule, 1);
+        assert!((wood(&world) - (500.0 - 75.0 - 50.0 * 3.0)).abs() < 1e-3);
+        let items: Vec<QueuedItem> = queue(&world).iter().cloned().collect();
+        assert_eq!(items[0].item, unit("engineer"));
+        assert!((items[0].progress - 1.0 / 15.0).abs() < 1e-4);
+        assert!((items[1].progress - 1.0 / 10.0).abs() < 1e-4);
+
+        // The finished unit walks out towards the rally point, then running out of supply holds the queue
+        step_seconds(&mut world, &mut schedule, 15);
+        assert_eq!(world.resource::<Events<ProductionCompleted>>().len(), 1);
+        let engineer = world.query_filtered::<Entity, With<Movement>>().single(&world);
+        let transform = world.get::<Transform>(engineer).unwrap();
+        assert!((transform.translation.truncate() - Vec2::new(100.0 + 20.0 * 2.0_f32.sqrt() + 1.5, 100.0)).length() < 1e-3);
+        assert_eq!(
+            world.get::<OrderQueue>(engineer).unwrap().current(),
+            Some(&Command::Move { target: Vec2::new(200.0, 100.0) })
+        );
+        step_seconds(&mut world, &mut schedule, 20);
+        let queue = queue(&world);
+        assert_eq!(queue.len(), 3);
+        assert_eq!(
+            queue.blocked,
+            Some(ProductionBlocked::Supply(SupplyError::NotEnoughSupply { used: 1, provided: 1, cost: 1 }))
+        );
+        assert!((queue.current().unwrap().progress - 0.1).abs() < 1e-4);
+    }
+}
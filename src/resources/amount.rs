@@ -0,0 +1,174 @@
+//! Fixed-point resource amounts
+//!
+//! Resources are counted in millionths of a unit so that small per-frame deltas add up
+//! exactly instead of being truncated or drifting like repeated `f32` additions do.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
+
+/// An exact, non-negative amount of a resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Reflect)]
+#[reflect(Debug, PartialEq, Hash, Default)]
+pub struct ResourceAmount(u64);
+
+impl ResourceAmount {
+    /// Number of fixed-point steps in one whole unit
+    pub const SCALE: u64 = 1_000_000;
+
+    /// No resources
+    pub const ZERO: Self = Self(0);
+
+    /// The largest representable amount
+    pub const MAX: Self = Self(u64::MAX);
+
+    /// Create an amount from whole units
+    pub const fn from_units(units: u32) -> Self {
+        Self(units as u64 * Self::SCALE)
+    }
+
+    /// Create an amount from raw fixed-point steps
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    /// Create an amount from a fractional number of units, rounding to the nearest step
+    ///
+    /// Negative and non-finite values become zero.
+    pub fn from_f32(units: f32) -> Self {
+        Self::from_f64(units as f64)
+    }
+
+    /// Create an amount from a fractional number of units, rounding to the nearest step
+    pub fn from_f64(units: f64) -> Self {
+        if units.is_finite() && units > 0.0 {
+            Self((units * Self::SCALE as f64).round() as u64)
+        } else {
+            Self::ZERO
+        }
+    }
+
+    /// Get the raw fixed-point value
+    pub const fn raw(self) -> u64 {
+        self.0
+    }
+
+    /// Get the amount as a floating point number of units (for display and rates only)
+    pub fn to_f32(self) -> f32 {
+        self.to_f64() as f32
+    }
+
+    /// Get the amount as a floating point number of units (for display and rates only)
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / Self::SCALE as f64
+    }
+
+    /// Get the number of whole units, rounding down
+    pub fn whole_units(self) -> u32 {
+        (self.0 / Self::SCALE).min(u32::MAX as u64) as u32
+    }
+
+    /// Check whether the amount is zero
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Subtract, stopping at zero
+    pub const fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    /// Add, stopping at [`ResourceAmount::MAX`]
+    pub const fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    /// Subtract, returning `None` if the result would be negative
+    pub const fn checked_sub(self, other: Self) -> Option<Self> {
+        match self.0.checked_sub(other.0) {
+            Some(raw) => Some(Self(raw)),
+            None => None,
+        }
+    }
+
+    /// Scale the amount by a factor, rounding to the nearest step
+    pub fn scale(self, factor: f64) -> Self {
+        Self::from_f64(self.to_f64() * factor)
+    }
+}
+
+impl Add for ResourceAmount {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.saturating_add(other)
+    }
+}
+
+impl AddAssign for ResourceAmount {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for ResourceAmount {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.saturating_sub(other)
+    }
+}
+
+impl SubAssign for ResourceAmount {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl Mul<u32> for ResourceAmount {
+    type Output = Self;
+
+    fn mul(self, factor: u32) -> Self {
+        Self(self.0.saturating_mul(factor as u64))
+    }
+}
+
+impl Sum for ResourceAmount {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl From<u32> for ResourceAmount {
+    fn from(units: u32) -> Self {
+        Self::from_units(units)
+    }
+}
+
+impl fmt::Display for ResourceAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match f.precision() {
+            Some(precision) => write!(f, "{:.*}", precision, self.to_f64()),
+            None => write!(f, "{}", self.whole_units()),
+        }
+    }
+}
+
+// Data files write amounts as plain numbers of units
+impl Serialize for ResourceAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_f64())
+    }
+}
+
+impl<'de> Deserialize<'de> for ResourceAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let units = f64::deserialize(deserializer)?;
+        if units < 0.0 || !units.is_finite() {
+            return Err(serde::de::Error::custom(format!("invalid resource amount {}", units)));
+        }
+        Ok(Self::from_f64(units))
+    }
+}
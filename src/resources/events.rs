@@ -0,0 +1,127 @@
+//! Events emitted by the economy systems
+
+use bevy::prelude::*;
+use crate::player::PlayerId;
+use super::{ResourceType, ResourceAmount, ResourceCost, TradeSide, TransferReason, TradeError};
+
+/// A gatherer took resources from a node
+#[derive(Event, Debug, Clone)]
+pub struct ResourceGathered {
+    /// The gathering unit
+    pub gatherer: Entity,
+    /// The node that was harvested
+    pub node: Entity,
+    /// Owner of the gatherer, if any
+    pub player: Option<PlayerId>,
+    /// Resource gathered
+    pub resource: ResourceType,
+    /// Amount gathered
+    pub amount: ResourceAmount,
+}
+
+/// Resources were delivered into a storage
+#[derive(Event, Debug, Clone)]
+pub struct ResourceDeposited {
+    /// The storage receiving the resources
+    pub storage: Entity,
+    /// The entity that delivered them
+    pub source: Entity,
+    /// Owner of the storage, if any
+    pub player: Option<PlayerId>,
+    /// Resource deposited
+    pub resource: ResourceType,
+    /// Amount handed over, including any excess spilled or wasted
+    pub amount: ResourceAmount,
+    /// Amount that went straight into the storage
+    pub stored: ResourceAmount,
+}
+
+/// A player paid a cost from their treasury
+#[derive(Event, Debug, Clone)]
+pub struct ResourceSpent {
+    /// The paying player
+    pub player: PlayerId,
+    /// Everything withdrawn
+    pub cost: ResourceCost,
+}
+
+/// Resources were returned to a player's treasury
+#[derive(Event, Debug, Clone)]
+pub struct ResourceRefunded {
+    /// The player receiving the refund
+    pub player: PlayerId,
+    /// Everything that made it back into storage
+    pub amounts: ResourceCost,
+}
+
+/// A resource node ran out of resources
+#[derive(Event, Debug, Clone)]
+pub struct NodeDepleted {
+    /// The depleted node
+    pub node: Entity,
+    /// Resource the node provided
+    pub resource: ResourceType,
+}
+
+/// A storage reached its capacity for a resource
+#[derive(Event, Debug, Clone)]
+pub struct StorageFull {
+    /// The full storage
+    pub storage: Entity,
+    /// Owner of the storage, if any
+    pub player: Option<PlayerId>,
+    /// Resource that is full
+    pub resource: ResourceType,
+}
+
+/// A player bought or sold resources on the market
+#[derive(Event, Debug, Clone)]
+pub struct MarketTrade {
+    /// The trading player
+    pub player: PlayerId,
+    /// Whether the player bought or sold
+    pub side: TradeSide,
+    /// Resource traded
+    pub resource: ResourceType,
+    /// Amount of the resource that changed hands
+    pub amount: ResourceAmount,
+    /// Credits paid or received
+    pub credits: ResourceAmount,
+}
+
+/// Resources moved directly from one player to another
+#[derive(Event, Debug, Clone)]
+pub struct ResourceTransferred {
+    /// The giving player
+    pub from: PlayerId,
+    /// The receiving player
+    pub to: PlayerId,
+    /// Everything that arrived
+    pub amounts: ResourceCost,
+    /// Why the resources moved
+    pub reason: TransferReason,
+}
+
+/// A market order, transfer or tribute payment could not be carried out
+#[derive(Event, Debug, Clone)]
+pub struct TradeRejected {
+    /// The player whose request failed
+    pub player: PlayerId,
+    /// Why it failed
+    pub error: TradeError,
+}
+
+/// Resources were carried from one storage to another by the logistics network
+#[derive(Event, Debug, Clone)]
+pub struct ResourceHauled {
+    /// Storage the resources came from
+    pub from: Entity,
+    /// Storage the resources arrived at
+    pub to: Entity,
+    /// The hauler or link that moved them
+    pub carrier: Entity,
+    /// Resource moved
+    pub resource: ResourceType,
+    /// Amount delivered
+    pub amount: ResourceAmount,
+}
@@ -0,0 +1,278 @@
+//! Per-player record of resource income and expenses over time
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::player::PlayerId;
+use super::{
+    ResourceType, ResourceAmount, ResourceDeposited, ResourceGathered, ResourceRefunded,
+    ResourceSpent, ResourceTransferred, MarketTrade, TradeSide,
+};
+
+const STATS_DIR: &str = "./statistics";
+
+/// Income and expenses recorded during one time bucket
+#[derive(Debug, Clone, Default)]
+pub struct LedgerBucket {
+    /// Resources gathered from nodes
+    pub gathered: HashMap<ResourceType, ResourceAmount>,
+    /// Resources delivered into the player's storages, bought or received from other players
+    pub income: HashMap<ResourceType, ResourceAmount>,
+    /// Resources returned to the player's storages, e.g. from cancelled production
+    pub refunded: HashMap<ResourceType, ResourceAmount>,
+    /// Resources the player spent
+    pub expense: HashMap<ResourceType, ResourceAmount>,
+}
+
+impl LedgerBucket {
+    fn get(map: &HashMap<ResourceType, ResourceAmount>, resource_type: ResourceType) -> ResourceAmount {
+        map.get(&resource_type).copied().unwrap_or_default()
+    }
+}
+
+/// Resource recording every player's economy as a time series, for statistics and balancing
+#[derive(Resource, Debug, Clone)]
+pub struct EconomyLedger {
+    /// Length of each time bucket in seconds
+    pub bucket_seconds: f32,
+    players: HashMap<PlayerId, BTreeMap<u32, LedgerBucket>>,
+}
+
+impl Default for EconomyLedger {
+    fn default() -> Self {
+        Self {
+            bucket_seconds: 10.0,
+            players: HashMap::new(),
+        }
+    }
+}
+
+impl EconomyLedger {
+    fn bucket_mut(&mut self, player: PlayerId, time: f32) -> &mut LedgerBucket {
+        let index = (time / self.bucket_seconds.max(f32::EPSILON)).floor().max(0.0) as u32;
+        self.players.entry(player).or_default().entry(index).or_default()
+    }
+
+    /// Record resources gathered from a node
+    pub fn record_gathered(&mut self, player: PlayerId, resource_type: ResourceType, amount: ResourceAmount, time: f32) {
+        *self.bucket_mut(player, time).gathered.entry(resource_type).or_default() += amount;
+    }
+
+    /// Record resources delivered into a player's storages
+    pub fn record_income(&mut self, player: PlayerId, resource_type: ResourceType, amount: ResourceAmount, time: f32) {
+        *self.bucket_mut(player, time).income.entry(resource_type).or_default() += amount;
+    }
+
+    /// Record resources returned to a player's storages
+    pub fn record_refund(&mut self, player: PlayerId, resource_type: ResourceType, amount: ResourceAmount, time: f32) {
+        *self.bucket_mut(player, time).refunded.entry(resource_type).or_default() += amount;
+    }
+
+    /// Record resources leaving a player's storages
+    pub fn record_expense(&mut self, player: PlayerId, resource_type: ResourceType, amount: ResourceAmount, time: f32) {
+        *self.bucket_mut(player, time).expense.entry(resource_type).or_default() += amount;
+    }
+
+    /// Get every player with recorded activity
+    pub fn players(&self) -> impl Iterator<Item = PlayerId> + '_ {
+        self.players.keys().copied()
+    }
+
+    /// Get a player's buckets in time order, with each bucket's start time in seconds
+    pub fn series(&self, player: PlayerId) -> impl Iterator<Item = (f32, &LedgerBucket)> + '_ {
+        let bucket_seconds = self.bucket_seconds;
+        self.players.get(&player)
+            .into_iter()
+            .flat_map(|buckets| buckets.iter())
+            .map(move |(&index, bucket)| (index as f32 * bucket_seconds, bucket))
+    }
+
+    /// Get a player's total income and expense of a resource over the whole game, not counting refunds
+    pub fn totals(&self, player: PlayerId, resource_type: ResourceType) -> (ResourceAmount, ResourceAmount) {
+        self.series(player).fold((ResourceAmount::ZERO, ResourceAmount::ZERO), |(income, expense), (_, bucket)| {
+            (
+                income + LedgerBucket::get(&bucket.income, resource_type),
+                expense + LedgerBucket::get(&bucket.expense, resource_type),
+            )
+        })
+    }
+
+    /// Clear all recorded data
+    pub fn clear(&mut self) {
+        self.players.clear();
+    }
+
+    /// Write the ledger as CSV with one row per player, bucket and resource
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "player,time,resource,gathered,income,refunded,expense")?;
+        let mut players: Vec<_> = self.players().collect();
+        players.sort();
+        for player in players {
+            for (time, bucket) in self.series(player) {
+                for resource_type in ResourceType::all() {
+                    let gathered = LedgerBucket::get(&bucket.gathered, resource_type);
+                    let income = LedgerBucket::get(&bucket.income, resource_type);
+                    let refunded = LedgerBucket::get(&bucket.refunded, resource_type);
+                    let expense = LedgerBucket::get(&bucket.expense, resource_type);
+                    if gathered.is_zero() && income.is_zero() && refunded.is_zero() && expense.is_zero() {
+                        continue;
+                    }
+                    writeln!(
+                        writer,
+                        "{},{:.1},{},{:.3},{:.3},{:.3},{:.3}",
+                        player.0, time, resource_type, gathered, income, refunded, expense,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Export the ledger to a CSV file in the statistics directory
+    /// Returns the path of the written file
+    pub fn export_csv(&self, filename: &str) -> io::Result<PathBuf> {
+        if !Path::new(STATS_DIR).exists() {
+            fs::create_dir_all(STATS_DIR)?;
+        }
+
+        let path = Path::new(STATS_DIR).join(format!("{}.csv", filename));
+        let file = fs::File::create(&path)?;
+        self.write_csv(io::BufWriter::new(file))?;
+        Ok(path)
+    }
+}
+
+/// Events that bring resources into a player's storages
+#[derive(SystemParam)]
+pub struct IncomeEvents<'w, 's> {
+    gathered: EventReader<'w, 's, ResourceGathered>,
+    deposited: EventReader<'w, 's, ResourceDeposited>,
+    trades: EventReader<'w, 's, MarketTrade>,
+    transferred: EventReader<'w, 's, ResourceTransferred>,
+}
+
+/// System to record economy events in the ledger
+pub fn record_economy_events(
+    time: Res<Time>,
+    mut ledger: ResMut<EconomyLedger>,
+    mut income: IncomeEvents,
+    mut spent: EventReader<ResourceSpent>,
+    mut refunded: EventReader<ResourceRefunded>,
+) {
+    let now = time.elapsed_seconds();
+
+    for event in income.gathered.read() {
+        if let Some(player) = event.player {
+            ledger.record_gathered(player, event.resource, event.amount, now);
+        }
+    }
+    for event in income.deposited.read() {
+        if let Some(player) = event.player {
+            ledger.record_income(player, event.resource, event.stored, now);
+        }
+    }
+    for event in income.trades.read() {
+        if event.side == TradeSide::Buy {
+            ledger.record_income(event.player, event.resource, event.amount, now);
+        }
+    }
+    for event in income.transferred.read() {
+        for (resource_type, amount) in event.amounts.iter() {
+            ledger.record_income(event.to, resource_type, amount, now);
+        }
+    }
+    for event in refunded.read() {
+        for (resource_type, amount) in event.amounts.iter() {
+            ledger.record_refund(event.player, resource_type, amount, now);
+        }
+    }
+    for event in spent.read() {
+        for (resource_type, amount) in event.cost.iter() {
+            ledger.record_expense(event.player, resource_type, amount, now);
+        }
+    }
+}
+
+/// System to clear the ledger when a new game starts
+pub fn reset_economy_ledger(mut ledger: ResMut<EconomyLedger>) {
+    ledger.clear();
+}
+
+/// System to export the ledger when the game ends or the app quits mid-game
+pub fn export_economy_ledger(ledger: Res<EconomyLedger>) {
+    if ledger.players.is_empty() {
+        return;
+    }
+    let filename = format!("economy_{}", chrono::Local::now().format("%Y%m%d_%H%M%S"));
+    match ledger.export_csv(&filename) {
+        Ok(path) => info!("Exported economy statistics to {}", path.display()),
+        Err(err) => warn!("Failed to export economy statistics: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::ResourceCost;
+
+    #[test]
+    fn test_ledger_separates_income_and_refunds() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<EconomyLedger>();
+        world.init_resource::<Events<ResourceGathered>>();
+        world.init_resource::<Events<ResourceDeposited>>();
+        world.init_resource::<Events<ResourceSpent>>();
+        world.init_resource::<Events<ResourceRefunded>>();
+        world.init_resource::<Events<MarketTrade>>();
+        world.init_resource::<Events<ResourceTransferred>>();
+
+        let player = PlayerId(0);
+        let storage = world.spawn_empty().id();
+        // Only part of the delivery fit; the rest was wasted
+        world.send_event(ResourceDeposited {
+            storage,
+            source: storage,
+            player: Some(player),
+            resource: ResourceType::Wood,
+            amount: ResourceAmount::from_units(10),
+            stored: ResourceAmount::from_units(4),
+        });
+        world.send_event(ResourceRefunded {
+            player,
+            amounts: ResourceCost::new().with(ResourceType::Wood, 3),
+        });
+        world.send_event(ResourceSpent {
+            player,
+            cost: ResourceCost::new().with(ResourceType::Wood, 2),
+        });
+        // Purchases count as income, not as refunds
+        world.send_event(MarketTrade {
+            player,
+            side: TradeSide::Buy,
+            resource: ResourceType::Wood,
+            amount: ResourceAmount::from_units(5),
+            credits: ResourceAmount::from_units(50),
+        });
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(record_economy_events);
+        schedule.run(&mut world);
+
+        let ledger = world.resource::<EconomyLedger>();
+        assert_eq!(
+            ledger.totals(player, ResourceType::Wood),
+            (ResourceAmount::from_units(9), ResourceAmount::from_units(2))
+        );
+
+        let mut csv = Vec::new();
+        ledger.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "player,time,resource,gathered,income,refunded,expense\n0,0.0,Wood,0.000,9.000,3.000,2.000\n"
+        );
+    }
+}
@@ -0,0 +1,583 @@
+//! Logistics network moving resources between a player's storages
+//!
+//! Storages declare per-resource supply and demand thresholds. Idle haulers are dispatched
+//! to carry surplus to storages that are short, while conveyor and rail links move
+//! resources continuously between the two storages they connect.
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+use crate::player::Owner;
+use crate::units::CommandHotkeys;
+use super::{ResourceType, ResourceAmount, ResourceStorage, ResourceHauled};
+
+/// Distance at which a hauler can load from or unload into a storage
+const HAUL_RANGE: f32 = 5.0;
+
+/// Seconds over which route throughput is averaged
+const THROUGHPUT_WINDOW: f32 = 30.0;
+
+/// Component declaring what a storage offers to and wants from the logistics network
+#[derive(Component, Debug, Clone, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct LogisticsThresholds {
+    /// Amounts above which a resource is offered to other storages
+    pub supply_above: HashMap<ResourceType, ResourceAmount>,
+    /// Amounts below which a resource is requested from other storages
+    pub demand_below: HashMap<ResourceType, ResourceAmount>,
+}
+
+impl LogisticsThresholds {
+    /// Create thresholds that neither supply nor demand anything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offer a resource to the network once the storage holds more than `above`
+    pub fn supply(mut self, resource_type: ResourceType, above: impl Into<ResourceAmount>) -> Self {
+        self.supply_above.insert(resource_type, above.into());
+        self
+    }
+
+    /// Request a resource from the network while the storage holds less than `below`
+    pub fn demand(mut self, resource_type: ResourceType, below: impl Into<ResourceAmount>) -> Self {
+        self.demand_below.insert(resource_type, below.into());
+        self
+    }
+
+    /// Get how much of a resource the storage can give away
+    pub fn surplus(&self, storage: &ResourceStorage, resource_type: ResourceType) -> ResourceAmount {
+        self.supply_above.get(&resource_type)
+            .map(|&above| storage.get_amount(resource_type).saturating_sub(above))
+            .unwrap_or_default()
+    }
+
+    /// Get how much of a resource the storage wants and has room for
+    pub fn shortfall(&self, storage: &ResourceStorage, resource_type: ResourceType) -> ResourceAmount {
+        self.demand_below.get(&resource_type)
+            .map(|&below| {
+                below.saturating_sub(storage.get_amount(resource_type))
+                    .min(storage.get_remaining_capacity(resource_type))
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Stage of a hauling job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum HaulStage {
+    /// Travelling to the supplying storage
+    Pickup,
+    /// Carrying cargo to the receiving storage
+    Dropoff,
+}
+
+/// A delivery assigned to a hauler
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct HaulJob {
+    /// Resource being moved
+    pub resource: ResourceType,
+    /// Storage the resource is collected from
+    pub from: Entity,
+    /// Storage the resource is delivered to
+    pub to: Entity,
+    /// Amount reserved for this trip
+    pub amount: ResourceAmount,
+    /// Where the hauler is in the trip
+    pub stage: HaulStage,
+}
+
+/// Component for units that carry resources between storages
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Hauler {
+    /// Maximum amount carried per trip
+    pub capacity: ResourceAmount,
+    /// Travel speed in meters per second
+    pub speed: f32,
+    /// Resources currently loaded
+    pub cargo: Option<(ResourceType, ResourceAmount)>,
+    /// Current assignment, if any
+    pub job: Option<HaulJob>,
+    /// Storages that took none of the current cargo, skipped when choosing where to unload
+    pub refused: Vec<Entity>,
+}
+
+impl Default for Hauler {
+    fn default() -> Self {
+        Self {
+            capacity: ResourceAmount::from_units(20),
+            speed: 30.0,
+            cargo: None,
+            job: None,
+            refused: Vec::new(),
+        }
+    }
+}
+
+/// Kind of fixed link between two storages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+pub enum LinkKind {
+    /// Short-range belt
+    #[default]
+    Conveyor,
+    /// Long-range railway
+    Rail,
+}
+
+/// Component for a conveyor or rail link moving resources between two storages
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct LogisticsLink {
+    /// One end of the link
+    pub a: Entity,
+    /// The other end of the link
+    pub b: Entity,
+    /// Kind of link
+    pub kind: LinkKind,
+    /// Units moved per second, shared between all resources
+    pub throughput: f32,
+}
+
+impl LogisticsLink {
+    /// Create a link between two storages
+    pub fn new(kind: LinkKind, a: Entity, b: Entity) -> Self {
+        let throughput = match kind {
+            LinkKind::Conveyor => 2.0,
+            LinkKind::Rail => 8.0,
+        };
+        Self { a, b, kind, throughput }
+    }
+}
+
+/// Measured flow along one route of the network
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RouteThroughput {
+    /// Average units per second over recent time
+    pub rate: f32,
+    /// Total units moved since the route was first used
+    pub total: ResourceAmount,
+}
+
+/// Resource tracking throughput between pairs of storages
+#[derive(Resource, Debug, Clone, Default)]
+pub struct LogisticsStats {
+    routes: HashMap<(Entity, Entity), RouteThroughput>,
+}
+
+impl LogisticsStats {
+    /// Get the throughput from one storage to another
+    pub fn route(&self, from: Entity, to: Entity) -> Option<&RouteThroughput> {
+        self.routes.get(&(from, to))
+    }
+
+    /// Iterate over every route with its endpoints
+    pub fn iter(&self) -> impl Iterator<Item = ((Entity, Entity), &RouteThroughput)> {
+        self.routes.iter().map(|(&route, throughput)| (route, throughput))
+    }
+}
+
+/// Resource controlling the logistics overlay
+#[derive(Resource, Debug, Clone, Default)]
+pub struct LogisticsOverlay {
+    /// Whether routes and haulers are drawn
+    pub enabled: bool,
+}
+
+/// Storages haulers can be sent between
+type HaulStorages<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static ResourceStorage, &'static Transform, Option<&'static LogisticsThresholds>, Option<&'static Owner>),
+    Without<Hauler>,
+>;
+
+/// System to assign jobs to idle haulers
+pub fn dispatch_haulers(
+    mut haulers: Query<(&mut Hauler, &Transform, Option<&Owner>)>,
+    storages: HaulStorages,
+) {
+    // Amounts already on their way, so two haulers are not sent for the same shortfall
+    let mut incoming: HashMap<(Entity, ResourceType), ResourceAmount> = HashMap::new();
+    let mut outgoing: HashMap<(Entity, ResourceType), ResourceAmount> = HashMap::new();
+    for (hauler, _, _) in haulers.iter() {
+        if let Some(job) = hauler.job {
+            *incoming.entry((job.to, job.resource)).or_default() += job.amount;
+            if job.stage == HaulStage::Pickup {
+                *outgoing.entry((job.from, job.resource)).or_default() += job.amount;
+            }
+        }
+    }
+
+    for (mut hauler, hauler_transform, hauler_owner) in haulers.iter_mut() {
+        if hauler.job.is_some() {
+            continue;
+        }
+        let position = hauler_transform.translation.truncate();
+        let same_owner = |owner: Option<&Owner>| owner == hauler_owner;
+
+        // Haulers left holding cargo unload it first, preferring storages that asked for it,
+        // then storages that keep it rather than spilling or wasting it
+        if let Some((resource_type, amount)) = hauler.cargo {
+            let target = storages.iter()
+                .filter(|(entity, storage, _, _, owner)| {
+                    same_owner(*owner)
+                        && !hauler.refused.contains(entity)
+                        && !storage.get_deposit_room(resource_type, amount).is_zero()
+                })
+                .min_by(|a, b| {
+                    let wants = |thresholds: Option<&LogisticsThresholds>, storage| {
+                        thresholds.is_some_and(|t| !t.shortfall(storage, resource_type).is_zero())
+                    };
+                    let keeps = |storage: &ResourceStorage| !storage.get_remaining_capacity(resource_type).is_zero();
+                    wants(b.3, b.1).cmp(&wants(a.3, a.1))
+                        .then_with(|| keeps(b.1).cmp(&keeps(a.1)))
+                        .then_with(|| {
+                            a.2.translation.truncate().distance_squared(position)
+                                .total_cmp(&b.2.translation.truncate().distance_squared(position))
+                        })
+                });
+            match target {
+                Some((entity, ..)) => {
+                    hauler.job = Some(HaulJob {
+                        resource: resource_type,
+                        from: entity,
+                        to: entity,
+                        amount,
+                        stage: HaulStage::Dropoff,
+                    });
+                }
+                // Give refused storages another chance once nothing else is left
+                None => hauler.refused.clear(),
+            }
+            continue;
+        }
+
+        // Serve the largest outstanding shortfall from the closest supplier
+        let mut best: Option<(HaulJob, ResourceAmount, f32)> = None;
+        for (consumer, storage, consumer_transform, thresholds, owner) in storages.iter() {
+            let Some(thresholds) = thresholds else { continue };
+            if !same_owner(owner) {
+                continue;
+            }
+            for &resource_type in thresholds.demand_below.keys() {
+                let shortfall = thresholds.shortfall(storage, resource_type)
+                    .saturating_sub(incoming.get(&(consumer, resource_type)).copied().unwrap_or_default());
+                if shortfall.is_zero() {
+                    continue;
+                }
+                let consumer_pos = consumer_transform.translation.truncate();
+
+                for (supplier, supply, supplier_transform, supplier_thresholds, supplier_owner) in storages.iter() {
+                    let Some(supplier_thresholds) = supplier_thresholds else { continue };
+                    if supplier == consumer || !same_owner(supplier_owner) {
+                        continue;
+                    }
+                    let surplus = supplier_thresholds.surplus(supply, resource_type)
+                        .saturating_sub(outgoing.get(&(supplier, resource_type)).copied().unwrap_or_default());
+                    if surplus.is_zero() {
+                        continue;
+                    }
+                    let supplier_pos = supplier_transform.translation.truncate();
+                    let distance = position.distance(supplier_pos) + supplier_pos.distance(consumer_pos);
+                    let better = best.as_ref().is_none_or(|(_, best_shortfall, best_distance)| {
+                        shortfall > *best_shortfall || (shortfall == *best_shortfall && distance < *best_distance)
+                    });
+                    if better {
+                        let job = HaulJob {
+                            resource: resource_type,
+                            from: supplier,
+                            to: consumer,
+                            amount: shortfall.min(surplus).min(hauler.capacity),
+                            stage: HaulStage::Pickup,
+                        };
+                        best = Some((job, shortfall, distance));
+                    }
+                }
+            }
+        }
+
+        if let Some((job, _, _)) = best {
+            *incoming.entry((job.to, job.resource)).or_default() += job.amount;
+            *outgoing.entry((job.from, job.resource)).or_default() += job.amount;
+            hauler.job = Some(job);
+        }
+    }
+}
+
+/// System to move haulers along their jobs and load or unload cargo
+pub fn update_haulers(
+    time: Res<Time>,
+    mut haulers: Query<(Entity, &mut Hauler, &mut Transform)>,
+    mut storages: Query<(&mut ResourceStorage, &Transform), Without<Hauler>>,
+    mut hauled_events: EventWriter<ResourceHauled>,
+) {
+    for (hauler_entity, mut hauler, mut transform) in haulers.iter_mut() {
+        let Some(job) = hauler.job else { continue };
+        let target = match job.stage {
+            HaulStage::Pickup => job.from,
+            HaulStage::Dropoff => job.to,
+        };
+        let Ok((mut storage, storage_transform)) = storages.get_mut(target) else {
+            // The storage is gone; keep any cargo and wait for a new job
+            hauler.job = None;
+            continue;
+        };
+
+        let to_target = storage_transform.translation.truncate() - transform.translation.truncate();
+        let distance = to_target.length();
+        if distance > HAUL_RANGE {
+            let step = (hauler.speed * time.delta_seconds()).min(distance - HAUL_RANGE);
+            let offset = to_target / distance * step;
+            transform.translation += offset.extend(0.0);
+            continue;
+        }
+
+        match job.stage {
+            HaulStage::Pickup => {
+                let taken = storage.remove_resource(job.resource, job.amount.min(hauler.capacity));
+                if taken.is_zero() {
+                    hauler.job = None;
+                } else {
+                    hauler.cargo = Some((job.resource, taken));
+                    hauler.refused.clear();
+                    hauler.job = Some(HaulJob { amount: taken, stage: HaulStage::Dropoff, ..job });
+                }
+            }
+            HaulStage::Dropoff => {
+                if let Some((resource_type, amount)) = hauler.cargo {
+                    let delivered = storage.deposit(resource_type, amount);
+                    let remaining = amount - delivered;
+                    hauler.cargo = (!remaining.is_zero()).then_some((resource_type, remaining));
+                    if delivered.is_zero() {
+                        hauler.refused.push(job.to);
+                    } else if remaining.is_zero() {
+                        hauler.refused.clear();
+                    }
+                    if !delivered.is_zero() && job.from != job.to {
+                        hauled_events.send(ResourceHauled {
+                            from: job.from,
+                            to: job.to,
+                            carrier: hauler_entity,
+                            resource: resource_type,
+                            amount: delivered,
+                        });
+                    }
+                }
+                hauler.job = None;
+            }
+        }
+    }
+}
+
+/// System to move resources along conveyor and rail links
+pub fn run_logistics_links(
+    time: Res<Time>,
+    links: Query<(Entity, &LogisticsLink)>,
+    mut storages: Query<(&mut ResourceStorage, &LogisticsThresholds, Option<&Owner>)>,
+    mut hauled_events: EventWriter<ResourceHauled>,
+) {
+    for (link_entity, link) in links.iter() {
+        let Ok([(mut a, a_thresholds, a_owner), (mut b, b_thresholds, b_owner)]) =
+            storages.get_many_mut([link.a, link.b])
+        else {
+            continue;
+        };
+        if a_owner != b_owner {
+            continue;
+        }
+
+        let mut budget = ResourceAmount::from_f32(link.throughput * time.delta_seconds());
+        for resource_type in ResourceType::all() {
+            if budget.is_zero() {
+                break;
+            }
+            let forward = a_thresholds.surplus(&a, resource_type).min(b_thresholds.shortfall(&b, resource_type));
+            let backward = b_thresholds.surplus(&b, resource_type).min(a_thresholds.shortfall(&a, resource_type));
+            let (amount, from, to, source, dest) = if !forward.is_zero() {
+                (forward, link.a, link.b, &mut a, &mut b)
+            } else if !backward.is_zero() {
+                (backward, link.b, link.a, &mut b, &mut a)
+            } else {
+                continue;
+            };
+
+            let moved = source.remove_resource(resource_type, amount.min(budget));
+            let delivered = dest.add_resource(resource_type, moved);
+            // Return anything the destination could not take
+            source.add_resource(resource_type, moved - delivered);
+            budget -= delivered;
+            if !delivered.is_zero() {
+                hauled_events.send(ResourceHauled {
+                    from,
+                    to,
+                    carrier: link_entity,
+                    resource: resource_type,
+                    amount: delivered,
+                });
+            }
+        }
+    }
+}
+
+/// System to update route throughput from hauling events
+pub fn update_logistics_stats(
+    time: Res<Time>,
+    mut stats: ResMut<LogisticsStats>,
+    mut hauled_events: EventReader<ResourceHauled>,
+) {
+    let delta = time.delta_seconds();
+    if delta <= 0.0 {
+        return;
+    }
+
+    let mut moved: HashMap<(Entity, Entity), ResourceAmount> = HashMap::new();
+    for event in hauled_events.read() {
+        *moved.entry((event.from, event.to)).or_default() += event.amount;
+    }
+    for (&route, &amount) in moved.iter() {
+        stats.routes.entry(route).or_default().total += amount;
+    }
+
+    // Exponential moving average of units per second
+    let blend = 1.0 - (-delta / THROUGHPUT_WINDOW).exp();
+    stats.routes.retain(|route, throughput| {
+        let instant = moved.get(route).map_or(0.0, |amount| amount.to_f32() / delta);
+        throughput.rate += (instant - throughput.rate) * blend;
+        throughput.rate > 0.001 || moved.contains_key(route)
+    });
+}
+
+/// System to toggle the logistics overlay with its hotkey
+pub fn toggle_logistics_overlay(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    hotkeys: Res<CommandHotkeys>,
+    mut overlay: ResMut<LogisticsOverlay>,
+) {
+    if keyboard_input.just_pressed(hotkeys.logistics_overlay) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+/// System to draw routes, links and haulers when the overlay is enabled
+pub fn draw_logistics_overlay(
+    overlay: Res<LogisticsOverlay>,
+    stats: Res<LogisticsStats>,
+    links: Query<&LogisticsLink>,
+    haulers: Query<(&Hauler, &Transform)>,
+    transforms: Query<&Transform, Without<Hauler>>,
+    mut gizmos: Gizmos,
+) {
+    if !overlay.enabled {
+        return;
+    }
+    let position = |entity: Entity| transforms.get(entity).ok().map(|t| t.translation.truncate());
+
+    for link in links.iter() {
+        if let (Some(a), Some(b)) = (position(link.a), position(link.b)) {
+            let color = match link.kind {
+                LinkKind::Conveyor => Color::srgb(0.5, 0.5, 0.5),
+                LinkKind::Rail => Color::srgb(0.7, 0.6, 0.4),
+            };
+            gizmos.line_2d(a, b, color);
+        }
+    }
+
+    // Routes brighten from dim green to yellow as throughput approaches one unit per second
+    for ((from, to), throughput) in stats.iter() {
+        if let (Some(a), Some(b)) = (position(from), position(to)) {
+            let load = throughput.rate.clamp(0.0, 1.0);
+            gizmos.arrow_2d(a, b, Color::srgb(0.2 + 0.8 * load, 0.6 + 0.4 * load, 0.2));
+        }
+    }
+
+    for (hauler, transform) in haulers.iter() {
+        let hauler_pos = transform.translation.truncate();
+        let color = if hauler.cargo.is_some() {
+            Color::srgb(0.9, 0.8, 0.2)
+        } else {
+            Color::srgb(0.4, 0.7, 0.9)
+        };
+        gizmos.circle_2d(hauler_pos, 3.0, color);
+        if let Some(job) = hauler.job {
+            let target = match job.stage {
+                HaulStage::Pickup => job.from,
+                HaulStage::Dropoff => job.to,
+            };
+            if let Some(target_pos) = position(target) {
+                gizmos.line_2d(hauler_pos, target_pos, color.with_alpha(0.4));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::OverflowPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn test_haulers_balance_thresholds() {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.init_resource::<Events<ResourceHauled>>();
+
+        let mut supply = ResourceStorage::new();
+        supply.add_resource(ResourceType::Iron, ResourceAmount::from_units(100));
+        let supplier = world.spawn((
+            supply,
+            LogisticsThresholds::new().supply(ResourceType::Iron, 20),
+            Transform::from_xyz(0.0, 0.0, 0.0),
+        )).id();
+        let consumer = world.spawn((
+            ResourceStorage::new(),
+            LogisticsThresholds::new().demand(ResourceType::Iron, 50),
+            Transform::from_xyz(100.0, 0.0, 0.0),
+        )).id();
+        world.spawn((Hauler::default(), Transform::from_xyz(0.0, 0.0, 0.0)));
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((dispatch_haulers, update_haulers).chain());
+        for _ in 0..60 * 60 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs(1) / 60);
+            schedule.run(&mut world);
+        }
+
+        let amount = |entity| world.get::<ResourceStorage>(entity).unwrap().get_amount(ResourceType::Iron);
+        assert_eq!(amount(consumer), ResourceAmount::from_units(50));
+        assert_eq!(amount(supplier), ResourceAmount::from_units(50));
+    }
+
+    #[test]
+    fn test_haulers_unload_into_full_waste_storage() {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.init_resource::<Events<ResourceHauled>>();
+
+        let mut full = ResourceStorage::empty().with_overflow_policy(OverflowPolicy::Waste);
+        full.set_capacity(ResourceType::Iron, ResourceAmount::from_units(50));
+        full.add_resource(ResourceType::Iron, ResourceAmount::from_units(50));
+        let dump = world.spawn((full, Transform::from_xyz(20.0, 0.0, 0.0))).id();
+        let hauler = world.spawn((
+            Hauler {
+                cargo: Some((ResourceType::Iron, ResourceAmount::from_units(10))),
+                ..default()
+            },
+            Transform::from_xyz(0.0, 0.0, 0.0),
+        )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((dispatch_haulers, update_haulers).chain());
+        for _ in 0..60 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs(1) / 60);
+            schedule.run(&mut world);
+        }
+
+        let hauler = world.get::<Hauler>(hauler).unwrap();
+        assert_eq!(hauler.cargo, None);
+        assert_eq!(hauler.job, None);
+        let dump = world.get::<ResourceStorage>(dump).unwrap();
+        assert_eq!(dump.get_amount(ResourceType::Iron), ResourceAmount::from_units(50));
+        assert_eq!(dump.wasted[&ResourceType::Iron], ResourceAmount::from_units(10));
+    }
+}
@@ -0,0 +1,330 @@
+//! Resource market where players trade resources for credits
+//!
+//! Prices follow supply and demand: selling a resource pushes its price down, buying pushes it
+//! up, and the pressure fades over time so prices drift back towards their base value.
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use thiserror::Error;
+use crate::player::PlayerId;
+use super::{ResourceType, ResourceAmount, ResourceCost, SpendError, Treasury, MarketTrade, TradeRejected};
+
+/// Which side of a market trade a player is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
+pub enum TradeSide {
+    /// The player receives resources and pays credits
+    Buy,
+    /// The player gives resources and receives credits
+    Sell,
+}
+
+/// Errors from market orders, transfers and tribute payments
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TradeError {
+    /// The player does not have the resources to give
+    #[error(transparent)]
+    Spend(#[from] SpendError),
+
+    /// The player cannot pay for a purchase
+    #[error("Not enough credits: need {required:.1}, have {available:.1}")]
+    InsufficientCredits {
+        /// Credits the purchase costs
+        required: ResourceAmount,
+        /// Credits the player has
+        available: ResourceAmount,
+    },
+
+    /// The player has no room to store a purchase
+    #[error("No storage space for {0}")]
+    NoCapacity(ResourceType),
+
+    /// Direct transfers are only allowed between allies
+    #[error("{from} is not allied with {to}")]
+    NotAllied {
+        /// The giving player
+        from: PlayerId,
+        /// The receiving player
+        to: PlayerId,
+    },
+
+    /// The order or transfer contained nothing
+    #[error("Nothing to trade")]
+    Empty,
+}
+
+/// Request for a player to buy or sell a resource on the market
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct MarketOrder {
+    /// The trading player
+    pub player: PlayerId,
+    /// Whether to buy or sell
+    pub side: TradeSide,
+    /// Resource to trade
+    pub resource: ResourceType,
+    /// Amount of the resource; purchases are reduced to whatever the player can store
+    pub amount: ResourceAmount,
+}
+
+/// Market state for one resource
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct MarketPrice {
+    /// Price per unit in credits when supply and demand are balanced
+    pub base: f32,
+    /// Net units sold to the market, fading back towards zero over time
+    pub pressure: f32,
+}
+
+/// Resource holding market prices and every player's credits
+#[derive(Resource, Debug, Clone)]
+pub struct Market {
+    prices: HashMap<ResourceType, MarketPrice>,
+    credits: HashMap<PlayerId, ResourceAmount>,
+    /// Net units sold that halve a price (or bought that double it)
+    pub depth: f32,
+    /// Fraction of the price the market keeps between buying and selling
+    pub spread: f32,
+    /// Seconds for supply pressure to fall by half
+    pub recovery_half_life: f32,
+    /// Lowest and highest price as multiples of the base price
+    pub price_bounds: (f32, f32),
+}
+
+impl Default for Market {
+    fn default() -> Self {
+        let prices = ResourceType::all()
+            .map(|resource_type| {
+                let base = match resource_type {
+                    ResourceType::Wood => 1.0,
+                    ResourceType::Stone => 1.5,
+                    ResourceType::Iron => 3.0,
+                    ResourceType::Copper => 3.0,
+                    ResourceType::Alloy => 10.0,
+                    ResourceType::Refined => 12.0,
+                };
+                (resource_type, MarketPrice { base, pressure: 0.0 })
+            })
+            .collect();
+
+        Self {
+            prices,
+            credits: HashMap::new(),
+            depth: 500.0,
+            spread: 0.1,
+            recovery_half_life: 120.0,
+            price_bounds: (0.25, 4.0),
+        }
+    }
+}
+
+impl Market {
+    fn price_at(&self, resource_type: ResourceType, pressure: f32) -> f32 {
+        let Some(price) = self.prices.get(&resource_type) else {
+            return 0.0;
+        };
+        let factor = 2f32.powf(-pressure / self.depth.max(f32::EPSILON));
+        price.base * factor.clamp(self.price_bounds.0, self.price_bounds.1)
+    }
+
+    fn pressure(&self, resource_type: ResourceType) -> f32 {
+        self.prices.get(&resource_type).map_or(0.0, |price| price.pressure)
+    }
+
+    /// Get the current mid-market price per unit of a resource
+    pub fn price(&self, resource_type: ResourceType) -> f32 {
+        self.price_at(resource_type, self.pressure(resource_type))
+    }
+
+    /// Get the state of a resource's market
+    pub fn get(&self, resource_type: ResourceType) -> Option<&MarketPrice> {
+        self.prices.get(&resource_type)
+    }
+
+    /// Get the credits a trade of this size would cost or earn, including its own price impact
+    pub fn quote(&self, side: TradeSide, resource_type: ResourceType, amount: ResourceAmount) -> ResourceAmount {
+        let units = amount.to_f32();
+        let pressure = self.pressure(resource_type);
+        // Price the whole lot at the midpoint of the impact it causes
+        let (midpoint, spread) = match side {
+            TradeSide::Buy => (pressure - units / 2.0, 1.0 + self.spread / 2.0),
+            TradeSide::Sell => (pressure + units / 2.0, 1.0 - self.spread / 2.0),
+        };
+        amount.scale((self.price_at(resource_type, midpoint) * spread) as f64)
+    }
+
+    /// Get a player's credits
+    pub fn credits(&self, player: PlayerId) -> ResourceAmount {
+        self.credits.get(&player).copied().unwrap_or_default()
+    }
+
+    /// Give credits to a player
+    pub fn add_credits(&mut self, player: PlayerId, amount: ResourceAmount) {
+        *self.credits.entry(player).or_default() += amount;
+    }
+
+    /// Take credits from a player, or nothing if they cannot pay all of it
+    pub fn spend_credits(&mut self, player: PlayerId, amount: ResourceAmount) -> Result<(), TradeError> {
+        let available = self.credits(player);
+        if available < amount {
+            return Err(TradeError::InsufficientCredits { required: amount, available });
+        }
+        self.credits.insert(player, available - amount);
+        Ok(())
+    }
+
+    /// Record a completed trade's effect on supply and demand
+    pub fn record_trade(&mut self, side: TradeSide, resource_type: ResourceType, amount: ResourceAmount) {
+        if let Some(price) = self.prices.get_mut(&resource_type) {
+            match side {
+                TradeSide::Buy => price.pressure -= amount.to_f32(),
+                TradeSide::Sell => price.pressure += amount.to_f32(),
+            }
+        }
+    }
+
+    /// Let supply pressure fade over `delta` seconds
+    pub fn recover(&mut self, delta: f32) {
+        let decay = 0.5f32.powf(delta / self.recovery_half_life.max(f32::EPSILON));
+        for price in self.prices.values_mut() {
+            price.pressure *= decay;
+        }
+    }
+
+    /// Clear all prices and credits back to their starting state
+    pub fn reset(&mut self) {
+        for price in self.prices.values_mut() {
+            price.pressure = 0.0;
+        }
+        self.credits.clear();
+    }
+
+    /// Carry out a market order against a player's treasury
+    pub fn execute(&mut self, treasury: &mut Treasury, order: &MarketOrder) -> Result<MarketTrade, TradeError> {
+        let player = order.player;
+        let resource_type = order.resource;
+
+        let (amount, credits) = match order.side {
+            TradeSide::Sell => {
+                if order.amount.is_zero() {
+                    return Err(TradeError::Empty);
+                }
+                treasury.try_spend(player, &ResourceCost::new().with(resource_type, order.amount))?;
+                let credits = self.quote(TradeSide::Sell, resource_type, order.amount);
+                self.add_credits(player, credits);
+                (order.amount, credits)
+            }
+            TradeSide::Buy => {
+                if order.amount.is_zero() {
+                    return Err(TradeError::Empty);
+                }
+                let amount = order.amount.min(treasury.room(player, resource_type));
+                if amount.is_zero() {
+                    return Err(TradeError::NoCapacity(resource_type));
+                }
+                let required = self.quote(TradeSide::Buy, resource_type, amount);
+                let available = self.credits(player);
+                if available < required {
+                    return Err(TradeError::InsufficientCredits { required, available });
+                }
+                // Only charge for what actually fit in the player's storages
+                let leftover = treasury.deposit(player, &ResourceCost::new().with(resource_type, amount));
+                let stored = amount - leftover.get(resource_type);
+                let credits = self.quote(TradeSide::Buy, resource_type, stored);
+                self.spend_credits(player, credits)?;
+                (stored, credits)
+            }
+        };
+
+        self.record_trade(order.side, resource_type, amount);
+        Ok(MarketTrade { player, side: order.side, resource: resource_type, amount, credits })
+    }
+}
+
+/// System to let market prices drift back towards their base values
+pub fn update_market_prices(time: Res<Time>, mut market: ResMut<Market>) {
+    market.recover(time.delta_seconds());
+}
+
+/// System to carry out market orders
+pub fn process_market_orders(
+    mut orders: EventReader<MarketOrder>,
+    mut market: ResMut<Market>,
+    mut treasury: Treasury,
+    mut trades: EventWriter<MarketTrade>,
+    mut rejected: EventWriter<TradeRejected>,
+) {
+    for order in orders.read() {
+        match market.execute(&mut treasury, order) {
+            Ok(trade) => {
+                trades.send(trade);
+            }
+            Err(error) => {
+                rejected.send(TradeRejected { player: order.player, error });
+            }
+        }
+    }
+}
+
+/// System to reset the market when a new game starts
+pub fn reset_market(mut market: ResMut<Market>) {
+    market.reset();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::SystemState;
+    use crate::player::Owner;
+    use crate::resources::{ResourceRefunded, ResourceSpent, ResourceStorage};
+
+    #[test]
+    fn test_prices_follow_supply_and_recover() {
+        let mut market = Market::default();
+        let base = market.price(ResourceType::Iron);
+        let lot = ResourceAmount::from_units(100);
+
+        // Buying back what was just sold costs more than the sale earned
+        let earned = market.quote(TradeSide::Sell, ResourceType::Iron, lot);
+        market.record_trade(TradeSide::Sell, ResourceType::Iron, lot);
+        assert!(market.price(ResourceType::Iron) < base);
+        assert!(market.quote(TradeSide::Buy, ResourceType::Iron, lot) > earned);
+
+        market.recover(market.recovery_half_life * 10.0);
+        assert!((market.price(ResourceType::Iron) - base).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_purchases_are_limited_to_storage_room() {
+        let mut world = World::new();
+        world.init_resource::<Events<ResourceSpent>>();
+        world.init_resource::<Events<ResourceRefunded>>();
+        let player = PlayerId(0);
+        let mut storage = ResourceStorage::new();
+        let capacity = storage.get_capacity(ResourceType::Iron);
+        storage.add_resource(ResourceType::Iron, capacity - ResourceAmount::from_units(10));
+        world.spawn((Owner(player), storage));
+
+        let mut market = Market::default();
+        market.add_credits(player, ResourceAmount::from_units(10_000));
+        let order = MarketOrder {
+            player,
+            side: TradeSide::Buy,
+            resource: ResourceType::Iron,
+            amount: ResourceAmount::from_units(50),
+        };
+
+        let mut state: SystemState<Treasury> = SystemState::new(&mut world);
+        let mut treasury = state.get_mut(&mut world);
+        let expected = market.quote(TradeSide::Buy, ResourceType::Iron, ResourceAmount::from_units(10));
+        let trade = market.execute(&mut treasury, &order).unwrap();
+        assert_eq!(trade.amount, ResourceAmount::from_units(10));
+        assert_eq!(trade.credits, expected);
+        assert_eq!(market.credits(player), ResourceAmount::from_units(10_000) - expected);
+        assert_eq!(treasury.balance(player, ResourceType::Iron), capacity);
+
+        assert!(matches!(market.execute(&mut treasury, &order), Err(TradeError::NoCapacity(ResourceType::Iron))));
+
+        // Purchases are income reported by the trade, not refunds
+        assert!(world.resource::<Events<ResourceRefunded>>().is_empty());
+    }
+}
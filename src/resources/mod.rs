@@ -1,14 +1,35 @@
 //! Resource system for StrategyForge
 //! Handles all resource-related functionality including resource types, storage, and gathering
 
+use bevy::app::AppExit;
 use bevy::prelude::*;
+use crate::defs::load_defs;
+use crate::state::GameState;
+use crate::world::regenerate_nodes;
+use crate::world::render::scale_node_visuals;
 
+mod amount;
+mod events;
+mod ledger;
+mod logistics;
+mod market;
 mod nodes;
+mod processing;
 mod storage;
+mod trade;
+mod treasury;
 pub mod types;
 
+pub use amount::*;
+pub use events::*;
+pub use ledger::*;
+pub use logistics::*;
+pub use market::*;
 pub use nodes::*;
+pub use processing::*;
 pub use storage::*;
+pub use trade::*;
+pub use treasury::*;
 pub use types::*;
 
 /// Plugin for the resource system
@@ -21,14 +42,80 @@ impl Plugin for ResourcePlugin {
             .register_type::<ResourceType>()
             .register_type::<ResourceNode>()
             .register_type::<ResourceStorage>()
-            .register_type::<ResourceGatherer>();
+            .register_type::<StorageModule>()
+            .register_type::<ResourceGatherer>()
+            .register_type::<Processor>()
+            .register_type::<LogisticsThresholds>()
+            .register_type::<Hauler>()
+            .register_type::<LogisticsLink>()
+            .register_type::<TradeSide>()
+            .register_type::<TransferReason>();
         
         // Add resource systems
-        app.add_systems(Update, (
-            update_resource_nodes,
-            handle_resource_gathering,
-            handle_resource_delivery,
-        ));
+        app.add_event::<ResourceGathered>()
+            .add_event::<ResourceDeposited>()
+            .add_event::<ResourceSpent>()
+            .add_event::<ResourceRefunded>()
+            .add_event::<NodeDepleted>()
+            .add_event::<StorageFull>()
+            .add_event::<MarketOrder>()
+            .add_event::<MarketTrade>()
+            .add_event::<TransferRequest>()
+            .add_event::<ResourceTransferred>()
+            .add_event::<TradeRejected>()
+            .add_event::<ResourceHauled>();
+        
+        app.init_resource::<TreasurySummary>()
+            .init_resource::<EconomyLedger>()
+            .init_resource::<Market>()
+            .init_resource::<Tributes>()
+            .init_resource::<LogisticsStats>()
+            .init_resource::<LogisticsOverlay>()
+            .init_resource::<RecipeBook>()
+            .add_systems(Startup, load_defs::<Recipe>)
+            .add_systems(OnEnter(GameState::InGame { is_paused: false }), (
+                reset_economy_ledger,
+                reset_market,
+                reset_tributes,
+            ))
+            .add_systems(OnExit(GameState::InGame { is_paused: false }), export_economy_ledger)
+            .add_systems(Last, export_economy_ledger
+                .run_if(on_event::<AppExit>())
+                .run_if(in_state(GameState::InGame { is_paused: false })))
+            .add_systems(Update, (
+                regenerate_nodes::<ResourceNode>,
+                handle_resource_gathering,
+                handle_node_depletion::<ResourceNode>.after(handle_resource_gathering),
+                handle_node_depletion::<crate::world::ResourceNode>.after(handle_resource_gathering),
+                scale_node_visuals::<ResourceNode>,
+                handle_resource_delivery,
+                update_processors,
+                apply_storage_modules,
+                spill_storage_overflow.after(handle_resource_delivery),
+                update_market_prices,
+                process_market_orders,
+                process_transfer_requests,
+                collect_tributes.after(handle_resource_delivery),
+                update_treasury_summary
+                    .after(handle_resource_delivery)
+                    .after(update_processors)
+                    .after(spill_storage_overflow)
+                    .after(process_market_orders)
+                    .after(process_transfer_requests)
+                    .after(collect_tributes),
+                record_economy_events,
+            ))
+            .add_systems(Update, (
+                dispatch_haulers,
+                update_haulers.after(dispatch_haulers),
+                run_logistics_links,
+                update_logistics_stats.after(update_haulers).after(run_logistics_links),
+                toggle_logistics_overlay,
+                draw_logistics_overlay.after(toggle_logistics_overlay),
+            ));
+
+        #[cfg(debug_assertions)]
+        app.add_systems(Update, crate::defs::hot_reload_defs::<Recipe>);
     }
 }
 
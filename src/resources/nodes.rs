@@ -1,7 +1,12 @@
 //! Resource node spawning and management
 
 use bevy::prelude::*;
-use crate::resources::{ResourceType, ResourceStorage, ResourceNode};
+use crate::player::Owner;
+use crate::world::{DepletionBehavior, NodeLifecycle, TerrainTile, WorldConfig, regenerate_nodes};
+use crate::resources::{
+    ResourceType, ResourceAmount, ResourceStorage, ResourceNode, ResourceGathered, ResourceDeposited,
+    NodeDepleted, StorageFull,
+};
 
 /// Component for entities that can gather resources
 #[derive(Component, Debug, Clone, Reflect)]
@@ -11,16 +16,25 @@ pub struct ResourceGatherer {
     pub can_gather: Vec<ResourceType>,
     
     /// Current resource being carried (if any)
-    pub carrying: Option<(ResourceType, f32)>,
+    pub carrying: Option<(ResourceType, ResourceAmount)>,
     
     /// Maximum amount of resources that can be carried
-    pub carry_capacity: f32,
+    pub carry_capacity: ResourceAmount,
     
     /// Gather rate (resources per second)
     pub gather_rate: f32,
+
+    /// Gather rate before research, which `gather_rate` is recalculated from
+    pub base_gather_rate: f32,
     
     /// Distance at which the unit can gather resources
     pub gather_range: f32,
+
+    /// Node the unit was ordered to work; unassigned gatherers use whichever node is closest
+    pub assigned_node: Option<Entity>,
+
+    /// Whether the unit is carrying a load back to storage
+    pub returning: bool,
 }
 
 impl Default for ResourceGatherer {
@@ -28,75 +42,131 @@ impl Default for ResourceGatherer {
         Self {
             can_gather: vec![ResourceType::Wood, ResourceType::Stone],
             carrying: None,
-            carry_capacity: 10.0,
+            carry_capacity: ResourceAmount::from_units(10),
             gather_rate: 1.0,
+            base_gather_rate: 1.0,
             gather_range: 2.0,
+            assigned_node: None,
+            returning: false,
         }
     }
 }
 
-/// System to update resource nodes (regeneration, etc.)
-pub fn update_resource_nodes(
-    time: Res<Time>,
-    mut query: Query<&mut ResourceNode>,
+/// System to remove or transform nodes that have been exhausted for good
+pub fn handle_node_depletion<T: NodeLifecycle>(
+    mut commands: Commands,
+    mut depleted_events: EventReader<NodeDepleted>,
+    nodes: Query<(&T, &Transform)>,
+    mut tiles: Query<(&mut TerrainTile, &Transform), Without<T>>,
+    config: Res<WorldConfig>,
 ) {
-    for mut node in query.iter_mut() {
-        // Regenerate resources if applicable
-        if node.can_regenerate && node.amount < node.max_amount {
-            node.amount = (node.amount + node.regen_rate * time.delta_seconds())
-                .min(node.max_amount);
+    for event in depleted_events.read() {
+        let Ok((node, node_transform)) = nodes.get(event.node) else {
+            continue;
+        };
+        // Nodes that can grow back stay where they are
+        if !node.is_exhausted() {
+            continue;
         }
+        
+        match node.on_depleted() {
+            DepletionBehavior::Remain => continue,
+            DepletionBehavior::Despawn => {}
+            DepletionBehavior::ConvertTerrain(terrain_type) => {
+                let position = node_transform.translation.truncate();
+                let half_tile = config.tile_size / 2.0;
+                let tile = tiles.iter_mut()
+                    .filter(|(_, tile_transform)| {
+                        let offset = (tile_transform.translation.truncate() - position).abs();
+                        offset.x <= half_tile && offset.y <= half_tile
+                    })
+                    .min_by(|(_, a), (_, b)| {
+                        a.translation.truncate().distance_squared(position)
+                            .total_cmp(&b.translation.truncate().distance_squared(position))
+                    });
+                if let Some((mut tile, _)) = tile {
+                    tile.terrain_type = terrain_type;
+                }
+            }
+        }
+        commands.entity(event.node).despawn_recursive();
     }
 }
 
+/// Range at which gatherers can deliver resources to a storage
+pub const DELIVERY_RANGE: f32 = 5.0;
+
 /// System to handle resource gathering
 pub fn handle_resource_gathering(
     time: Res<Time>,
-    mut gatherers: Query<(&mut ResourceGatherer, &Transform)>,
-    mut nodes: Query<(&mut ResourceNode, &Transform)>,
+    mut gatherers: Query<(Entity, &mut ResourceGatherer, &Transform, Option<&Owner>)>,
+    mut nodes: Query<(Entity, &mut ResourceNode, &Transform)>,
+    mut gathered_events: EventWriter<ResourceGathered>,
+    mut depleted_events: EventWriter<NodeDepleted>,
 ) {
-    for (mut gatherer, gatherer_transform) in gatherers.iter_mut() {
+    for (gatherer_entity, mut gatherer, gatherer_transform, owner) in gatherers.iter_mut() {
         // Skip if already carrying maximum capacity
-        if let Some((_, amount)) = gatherer.carrying {
-            if amount >= gatherer.carry_capacity {
-                continue;
-            }
+        let carried = gatherer.carrying.map(|(_, amount)| amount).unwrap_or_default();
+        if carried >= gatherer.carry_capacity {
+            continue;
         }
+        let carried_type = gatherer.carrying.map(|(res_type, _)| res_type);
         
         // Find the closest resource node of a type this gatherer can gather
         let gatherer_pos = gatherer_transform.translation;
         let mut best_node = None;
         let mut closest_distance = f32::MAX;
         
-        for (node, node_transform) in nodes.iter_mut() {
+        for (node_entity, node, node_transform) in nodes.iter_mut() {
             // Check if this node has resources and is of a type we can gather
             if node.amount <= 0.0 || !gatherer.can_gather.contains(&node.resource_type) {
                 continue;
             }
             
+            // Gatherers sent to a node stick to it
+            if gatherer.assigned_node.is_some_and(|assigned| assigned != node_entity) {
+                continue;
+            }
+            
+            // Don't mix resource types in a single load
+            if carried_type.is_some_and(|res_type| res_type != node.resource_type) {
+                continue;
+            }
+            
             // Calculate distance to node
             let distance = node_transform.translation.distance_squared(gatherer_pos);
             if distance < closest_distance {
                 closest_distance = distance;
-                best_node = Some((node, node_transform));
+                best_node = Some((node_entity, node));
             }
         }
         
         // If we found a node in range, gather from it
-        if let Some((mut node, _)) = best_node {
+        if let Some((node_entity, mut node)) = best_node {
             let gather_range_sq = gatherer.gather_range * gatherer.gather_range;
             if closest_distance <= gather_range_sq {
-                // Calculate how much to gather this frame
-                let gather_amount = gatherer.gather_rate * time.delta_seconds();
-                let gathered = node.gather(gather_amount);
+                // Calculate how much to gather this frame, without overfilling the load
+                let room = (gatherer.carry_capacity - carried).to_f32();
+                let gather_amount = (gatherer.gather_rate * time.delta_seconds()).min(room);
+                let gathered = ResourceAmount::from_f32(node.gather(gather_amount));
                 
                 // Add to carried resources
-                if let Some((res_type, ref mut amount)) = &mut gatherer.carrying {
-                    if *res_type == node.resource_type {
-                        *amount += gathered;
+                gatherer.carrying = Some((node.resource_type, carried + gathered));
+                
+                if !gathered.is_zero() {
+                    gathered_events.send(ResourceGathered {
+                        gatherer: gatherer_entity,
+                        node: node_entity,
+                        player: owner.map(|owner| owner.0),
+                        resource: node.resource_type,
+                        amount: gathered,
+                    });
+                    if node.is_depleted() {
+                        depleted_events.send(NodeDepleted {
+                            node: node_entity,
+                            resource: node.resource_type,
+                        });
                     }
-                } else {
-                    gatherer.carrying = Some((node.resource_type, gathered));
                 }
             }
         }
@@ -105,14 +175,15 @@ pub fn handle_resource_gathering(
 
 /// System to handle resource delivery to storage
 pub fn handle_resource_delivery(
-    mut gatherers: Query<(&mut ResourceGatherer, &Transform)>,
-    mut storages: Query<(&mut ResourceStorage, &Transform)>,
+    mut gatherers: Query<(Entity, &mut ResourceGatherer, &Transform, Option<&Owner>)>,
+    mut storages: Query<(Entity, &mut ResourceStorage, &Transform, Option<&Owner>)>,
     time: Res<Time>,
+    mut deposited_events: EventWriter<ResourceDeposited>,
+    mut full_events: EventWriter<StorageFull>,
 ) {
-    const DELIVERY_RANGE: f32 = 5.0; // Range at which resources can be delivered
     const DELIVERY_RATE: f32 = 10.0; // Resources per second
     
-    for (mut gatherer, gatherer_transform) in gatherers.iter_mut() {
+    for (gatherer_entity, mut gatherer, gatherer_transform, gatherer_owner) in gatherers.iter_mut() {
         // Skip if not carrying anything
         let (res_type, ref mut amount) = match &mut gatherer.carrying {
             Some(carrying) => carrying,
@@ -124,30 +195,57 @@ pub fn handle_resource_delivery(
         let mut best_storage = None;
         let mut closest_distance = f32::MAX;
         
-        for (storage, storage_transform) in storages.iter_mut() {
+        for (storage_entity, storage, storage_transform, storage_owner) in storages.iter_mut() {
+            // Only deliver to storages belonging to the gatherer's player
+            if gatherer_owner.is_some() && gatherer_owner != storage_owner {
+                continue;
+            }
+            
             // Check if this storage can accept the resource
-            if storage.get_remaining_capacity(*res_type) > 0 {
+            if storage.accepts(*res_type) {
                 let distance = storage_transform.translation.distance_squared(gatherer_pos);
                 if distance < closest_distance {
                     closest_distance = distance;
-                    best_storage = Some(storage);
+                    best_storage = Some((storage_entity, storage, storage_owner));
                 }
             }
         }
         
         // If we found a storage in range, deliver resources
-        if let Some(mut storage) = best_storage {
+        if let Some((storage_entity, mut storage, storage_owner)) = best_storage {
             let delivery_range_sq = DELIVERY_RANGE * DELIVERY_RANGE;
             if closest_distance <= delivery_range_sq {
                 // Calculate how much to deliver this frame
-                let deliver_amount = (DELIVERY_RATE * time.delta_seconds()).min(*amount);
+                let deliver_amount = ResourceAmount::from_f32(DELIVERY_RATE * time.delta_seconds()).min(*amount);
                 
                 // Try to add to storage
-                let delivered = storage.add_resource(*res_type, deliver_amount as u32) as f32;
+                let had_room = !storage.get_remaining_capacity(*res_type).is_zero();
+                let before = storage.get_amount(*res_type);
+                let delivered = storage.deposit(*res_type, deliver_amount);
+                let stored = storage.get_amount(*res_type) - before;
                 *amount -= delivered;
                 
+                let player = storage_owner.map(|owner| owner.0);
+                if !delivered.is_zero() {
+                    deposited_events.send(ResourceDeposited {
+                        storage: storage_entity,
+                        source: gatherer_entity,
+                        player,
+                        resource: *res_type,
+                        amount: delivered,
+                        stored,
+                    });
+                }
+                if had_room && storage.get_remaining_capacity(*res_type).is_zero() {
+                    full_events.send(StorageFull {
+                        storage: storage_entity,
+                        player,
+                        resource: *res_type,
+                    });
+                }
+                
                 // If we delivered everything, clear the carried resource
-                if *amount <= 0.0 {
+                if amount.is_zero() {
                     gatherer.carrying = None;
                 }
             }
@@ -163,9 +261,69 @@ impl Plugin for ResourcePlugin {
         app
             .register_type::<ResourceGatherer>()
             .add_systems(Update, (
-                update_resource_nodes,
+                regenerate_nodes::<ResourceNode>,
                 handle_resource_gathering,
                 handle_resource_delivery,
             ));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Run gathering and delivery for `seconds` of game time at a fixed frame rate
+    /// and return how much ended up in storage
+    fn simulate_economy(fps: u32, seconds: u32) -> ResourceAmount {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.init_resource::<Events<ResourceGathered>>();
+        world.init_resource::<Events<ResourceDeposited>>();
+        world.init_resource::<Events<NodeDepleted>>();
+        world.init_resource::<Events<StorageFull>>();
+
+        world.spawn((
+            ResourceGatherer::default(),
+            Transform::from_xyz(0.0, 0.0, 0.0),
+        ));
+        world.spawn((
+            ResourceNode::new(ResourceType::Wood, 1000.0),
+            Transform::from_xyz(1.0, 0.0, 0.0),
+        ));
+        let storage = world.spawn((
+            ResourceStorage::new(),
+            Transform::from_xyz(-1.0, 0.0, 0.0),
+        )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((handle_resource_gathering, handle_resource_delivery).chain());
+
+        let frame = Duration::from_secs(1) / fps;
+        for _ in 0..fps * seconds {
+            world.resource_mut::<Time>().advance_by(frame);
+            schedule.run(&mut world);
+        }
+
+        world.get::<ResourceStorage>(storage).unwrap().get_amount(ResourceType::Wood)
+    }
+
+    #[test]
+    fn test_small_deliveries_are_not_truncated() {
+        // At 1000 FPS each frame delivers far less than one unit
+        let delivered = simulate_economy(1000, 2);
+        assert!(delivered.to_f32() > 1.9, "delivered only {}", delivered.to_f32());
+    }
+
+    #[test]
+    fn test_gathering_is_frame_rate_independent() {
+        let expected = ResourceAmount::from_units(10).to_f64();
+        for fps in [20, 60, 144, 1000] {
+            let delivered = simulate_economy(fps, 10).to_f64();
+            assert!(
+                (delivered - expected).abs() < 0.01,
+                "{} FPS delivered {} instead of {}", fps, delivered, expected,
+            );
+        }
+    }
+}
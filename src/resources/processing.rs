@@ -0,0 +1,398 @@
+//! Production chains that refine primary resources into advanced materials
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fmt;
+use crate::data_file::parse_builtin;
+use crate::defs::{check_non_negative, check_positive, DefRegistry, Definition, FieldError};
+use super::{ResourceType, ResourceAmount, ResourceStorage};
+
+/// A single resource amount used as a recipe input or output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Reflect)]
+pub struct RecipeItem {
+    /// Resource consumed or produced
+    pub resource: ResourceType,
+    /// Amount per production cycle
+    pub amount: u32,
+}
+
+impl RecipeItem {
+    /// Get the amount per cycle as an exact resource amount
+    pub fn quantity(&self) -> ResourceAmount {
+        ResourceAmount::from_units(self.amount)
+    }
+}
+
+/// A data-driven production recipe
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+pub struct Recipe {
+    /// Unique identifier used by processors to reference this recipe
+    pub id: String,
+    /// Human-readable name
+    pub name: String,
+    /// Resources consumed at the start of each cycle
+    pub inputs: Vec<RecipeItem>,
+    /// Resources produced at the end of each cycle
+    pub outputs: Vec<RecipeItem>,
+    /// Duration of one cycle in seconds
+    pub duration: f32,
+    /// Power required while the cycle is running (0.0 for unpowered recipes)
+    #[serde(default)]
+    pub power: f32,
+}
+
+impl Definition for Recipe {
+    const KIND: &'static str = "recipe";
+    const FILE: &'static str = "assets/data/recipes.ron";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn validate(&self) -> Result<(), FieldError> {
+        check_positive("duration", self.duration)?;
+        check_non_negative("power", self.power)
+    }
+
+    fn builtin() -> Vec<Self> {
+        parse_builtin(Self::FILE, include_str!("../../assets/data/recipes.ron"))
+    }
+}
+
+/// Resource holding every recipe known to the game
+pub type RecipeBook = DefRegistry<Recipe>;
+
+/// Reason a processor cannot make progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum StallReason {
+    /// The selected recipe does not exist in the recipe book
+    UnknownRecipe,
+    /// The processor has no storage to pull inputs from
+    NoStorage,
+    /// Not enough of an input resource is available
+    MissingInput(ResourceType),
+    /// There is no room to store an output resource
+    OutputFull(ResourceType),
+    /// The processor is not receiving any power
+    NoPower,
+}
+
+impl fmt::Display for StallReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StallReason::UnknownRecipe => write!(f, "Unknown recipe"),
+            StallReason::NoStorage => write!(f, "No storage connected"),
+            StallReason::MissingInput(resource) => write!(f, "Missing input: {}", resource),
+            StallReason::OutputFull(resource) => write!(f, "Output full: {}", resource),
+            StallReason::NoPower => write!(f, "No power"),
+        }
+    }
+}
+
+/// Current state of a processor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+pub enum ProductionStatus {
+    /// No recipe selected
+    #[default]
+    Idle,
+    /// A cycle is in progress
+    Working,
+    /// Production is blocked
+    Stalled(StallReason),
+}
+
+/// Component for buildings that turn input resources into output resources
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Processor {
+    /// Id of the recipe being produced
+    pub recipe: Option<String>,
+
+    /// Storage used for inputs and outputs (defaults to this entity's own storage)
+    pub storage: Option<Entity>,
+
+    /// Inputs pulled from storage and waiting to be consumed
+    pub input_buffer: HashMap<ResourceType, ResourceAmount>,
+
+    /// Finished outputs waiting to be moved into storage
+    pub output_buffer: HashMap<ResourceType, ResourceAmount>,
+
+    /// Number of cycles worth of inputs and outputs the buffers can hold
+    pub buffer_cycles: u32,
+
+    /// Progress of the current cycle (0.0 to 1.0)
+    pub progress: f32,
+
+    /// Whether inputs for the current cycle have been consumed
+    pub cycle_active: bool,
+
+    /// Inputs consumed by the current cycle, put back in the input buffer if it is abandoned
+    pub cycle_inputs: HashMap<ResourceType, ResourceAmount>,
+
+    /// Fraction of the required power currently supplied (0.0 to 1.0), taken from the
+    /// building's `PowerConsumer`; powered recipes stay stalled until a grid supplies it
+    pub power_satisfaction: f32,
+
+    /// Current production status, for display in the UI
+    pub status: ProductionStatus,
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Self {
+            recipe: None,
+            storage: None,
+            input_buffer: HashMap::new(),
+            output_buffer: HashMap::new(),
+            buffer_cycles: 2,
+            progress: 0.0,
+            cycle_active: false,
+            cycle_inputs: HashMap::new(),
+            power_satisfaction: 0.0,
+            status: ProductionStatus::Idle,
+        }
+    }
+}
+
+impl Processor {
+    /// Create a processor producing the given recipe
+    pub fn new(recipe: impl Into<String>) -> Self {
+        Self {
+            recipe: Some(recipe.into()),
+            ..default()
+        }
+    }
+
+    /// Use a different entity's storage for inputs and outputs
+    pub fn with_storage(mut self, storage: Entity) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Change the active recipe, abandoning any cycle in progress
+    ///
+    /// Inputs the abandoned cycle consumed go back into the input buffer, and buffered inputs
+    /// the new recipe does not use are returned to storage on the next update.
+    pub fn set_recipe(&mut self, recipe: Option<String>) {
+        if self.recipe != recipe {
+            self.recipe = recipe;
+            self.progress = 0.0;
+            self.cycle_active = false;
+            for (resource_type, amount) in self.cycle_inputs.drain() {
+                *self.input_buffer.entry(resource_type).or_default() += amount;
+            }
+        }
+    }
+
+    /// Get the amount of a resource in the input buffer
+    pub fn buffered_input(&self, resource_type: ResourceType) -> ResourceAmount {
+        self.input_buffer.get(&resource_type).copied().unwrap_or_default()
+    }
+
+    /// Get the amount of a resource in the output buffer
+    pub fn buffered_output(&self, resource_type: ResourceType) -> ResourceAmount {
+        self.output_buffer.get(&resource_type).copied().unwrap_or_default()
+    }
+
+    /// Move finished outputs into storage
+    fn flush_outputs(&mut self, storage: &mut ResourceStorage) {
+        for (&resource_type, amount) in self.output_buffer.iter_mut() {
+            if !amount.is_zero() {
+                *amount -= storage.add_resource(resource_type, *amount);
+            }
+        }
+    }
+
+    /// Move buffered inputs the recipe does not use back into storage
+    fn return_unused_inputs(&mut self, recipe: Option<&Recipe>, storage: &mut ResourceStorage) {
+        let used = |resource_type| {
+            recipe.is_some_and(|recipe| recipe.inputs.iter().any(|input| input.resource == resource_type))
+        };
+        for (&resource_type, amount) in self.input_buffer.iter_mut() {
+            if !amount.is_zero() && !used(resource_type) {
+                *amount -= storage.add_resource(resource_type, *amount);
+            }
+        }
+    }
+
+    /// Top up the input buffer from storage
+    fn refill_inputs(&mut self, recipe: &Recipe, storage: &mut ResourceStorage) {
+        for input in &recipe.inputs {
+            let target = input.quantity() * self.buffer_cycles.max(1);
+            let buffered = self.buffered_input(input.resource);
+            if buffered < target {
+                let taken = storage.remove_resource(input.resource, target - buffered);
+                *self.input_buffer.entry(input.resource).or_default() += taken;
+            }
+        }
+    }
+
+    /// Try to consume inputs for a new cycle
+    fn start_cycle(&mut self, recipe: &Recipe) -> Result<(), StallReason> {
+        for output in &recipe.outputs {
+            let limit = output.quantity() * self.buffer_cycles.max(1);
+            if self.buffered_output(output.resource) + output.quantity() > limit {
+                return Err(StallReason::OutputFull(output.resource));
+            }
+        }
+        for input in &recipe.inputs {
+            if self.buffered_input(input.resource) < input.quantity() {
+                return Err(StallReason::MissingInput(input.resource));
+            }
+        }
+        for input in &recipe.inputs {
+            if let Some(amount) = self.input_buffer.get_mut(&input.resource) {
+                *amount -= input.quantity();
+            }
+            *self.cycle_inputs.entry(input.resource).or_default() += input.quantity();
+        }
+        self.cycle_active = true;
+        self.progress = 0.0;
+        Ok(())
+    }
+
+    /// Move the produced outputs of a finished cycle into the output buffer
+    fn finish_cycle(&mut self, recipe: &Recipe) {
+        for output in &recipe.outputs {
+            *self.output_buffer.entry(output.resource).or_default() += output.quantity();
+        }
+        self.cycle_inputs.clear();
+        self.cycle_active = false;
+        self.progress = 0.0;
+    }
+}
+
+/// System to advance production on all processors
+pub fn update_processors(
+    time: Res<Time>,
+    recipes: Res<RecipeBook>,
+    mut processors: Query<(Entity, &mut Processor)>,
+    mut storages: Query<&mut ResourceStorage>,
+) {
+    for (entity, mut processor) in processors.iter_mut() {
+        let storage_entity = processor.storage.unwrap_or(entity);
+        let Some(recipe_id) = processor.recipe.clone() else {
+            if let Ok(mut storage) = storages.get_mut(storage_entity) {
+                processor.return_unused_inputs(None, &mut storage);
+            }
+            processor.status = ProductionStatus::Idle;
+            continue;
+        };
+        let Some(recipe) = recipes.get(&recipe_id) else {
+            processor.status = ProductionStatus::Stalled(StallReason::UnknownRecipe);
+            continue;
+        };
+        let Ok(mut storage) = storages.get_mut(storage_entity) else {
+            processor.status = ProductionStatus::Stalled(StallReason::NoStorage);
+            continue;
+        };
+
+        processor.flush_outputs(&mut storage);
+        processor.return_unused_inputs(Some(recipe), &mut storage);
+        processor.refill_inputs(recipe, &mut storage);
+
+        if !processor.cycle_active {
+            if let Err(reason) = processor.start_cycle(recipe) {
+                processor.status = ProductionStatus::Stalled(reason);
+                continue;
+            }
+        }
+
+        let power_factor = if recipe.power > 0.0 {
+            processor.power_satisfaction.clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        if power_factor <= 0.0 {
+            processor.status = ProductionStatus::Stalled(StallReason::NoPower);
+            continue;
+        }
+
+        processor.status = ProductionStatus::Working;
+        processor.progress += time.delta_seconds() * power_factor / recipe.duration;
+        if processor.progress >= 1.0 {
+            processor.finish_cycle(recipe);
+            processor.flush_outputs(&mut storage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_processors_follow_recipes_and_stall() {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.insert_resource(RecipeBook::from_defs("recipes.ron", Recipe::builtin()).unwrap());
+
+        // Enough ore for one alloy cycle, run at half power from a separate storage
+        let mut ore = ResourceStorage::new();
+        ore.add_resource(ResourceType::Iron, ResourceAmount::from_units(3));
+        ore.add_resource(ResourceType::Copper, ResourceAmount::from_units(2));
+        let store = world.spawn(ore).id();
+        let mut half_powered = Processor::new("alloy").with_storage(store);
+        half_powered.power_satisfaction = 0.5;
+        let smelter = world.spawn(half_powered).id();
+
+        let mut stocked = ResourceStorage::new();
+        stocked.add_resource(ResourceType::Iron, ResourceAmount::from_units(10));
+        stocked.add_resource(ResourceType::Copper, ResourceAmount::from_units(10));
+        let unpowered = world.spawn((Processor::new("alloy"), stocked)).id();
+        let unknown = world.spawn((Processor::new("missing"), ResourceStorage::new())).id();
+        let detached = world.spawn(Processor::new("alloy")).id();
+        let idle = world.spawn((Processor::default(), ResourceStorage::new())).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(update_processors);
+        let mut run_for = |world: &mut World, steps: u32| {
+            for _ in 0..steps {
+                world.resource_mut::<Time>().advance_by(Duration::from_millis(500));
+                schedule.run(world);
+            }
+        };
+        let status = |world: &World, entity| world.get::<Processor>(entity).unwrap().status;
+        let stored = |world: &World, resource| world.get::<ResourceStorage>(store).unwrap().get_amount(resource);
+
+        // Inputs are taken when the cycle starts and progress runs at the power fraction
+        run_for(&mut world, 10);
+        let processor = world.get::<Processor>(smelter).unwrap();
+        assert_eq!(processor.status, ProductionStatus::Working);
+        assert!((processor.progress - 0.5).abs() < 1e-3, "progress {}", processor.progress);
+        assert_eq!(processor.buffered_input(ResourceType::Iron), ResourceAmount::from_units(1));
+        assert!(stored(&world, ResourceType::Iron).is_zero());
+        assert!(stored(&world, ResourceType::Alloy).is_zero());
+
+        // The finished alloy reaches storage and the next cycle waits for iron
+        run_for(&mut world, 15);
+        assert_eq!(stored(&world, ResourceType::Alloy), ResourceAmount::from_units(1));
+        assert_eq!(status(&world, smelter), ProductionStatus::Stalled(StallReason::MissingInput(ResourceType::Iron)));
+
+        assert_eq!(status(&world, unpowered), ProductionStatus::Stalled(StallReason::NoPower));
+        assert_eq!(status(&world, unknown), ProductionStatus::Stalled(StallReason::UnknownRecipe));
+        assert_eq!(status(&world, detached), ProductionStatus::Stalled(StallReason::NoStorage));
+        assert_eq!(status(&world, idle), ProductionStatus::Idle);
+
+        // Switching recipe abandons the cycle in progress and hands its inputs back
+        let mut processor = world.get_mut::<Processor>(unpowered).unwrap();
+        assert!(processor.cycle_active);
+        processor.set_recipe(Some("refined".to_string()));
+        assert!(!processor.cycle_active);
+        assert_eq!(processor.progress, 0.0);
+        assert_eq!(processor.buffered_input(ResourceType::Iron), ResourceAmount::from_units(6));
+        assert_eq!(processor.buffered_input(ResourceType::Copper), ResourceAmount::from_units(3));
+
+        // The old recipe's inputs go back to storage instead of staying stranded in the buffer
+        run_for(&mut world, 1);
+        let processor = world.get::<Processor>(unpowered).unwrap();
+        assert!(processor.buffered_input(ResourceType::Iron).is_zero());
+        assert!(processor.buffered_input(ResourceType::Copper).is_zero());
+        let storage = world.get::<ResourceStorage>(unpowered).unwrap();
+        assert_eq!(storage.get_amount(ResourceType::Iron), ResourceAmount::from_units(10));
+        assert_eq!(storage.get_amount(ResourceType::Copper), ResourceAmount::from_units(10));
+        assert_eq!(processor.status, ProductionStatus::Stalled(StallReason::MissingInput(ResourceType::Alloy)));
+    }
+}
@@ -2,91 +2,515 @@
 
 use bevy::prelude::*;
 use std::collections::HashMap;
-use super::ResourceType;
+use crate::player::Owner;
+use super::{ResourceType, ResourceAmount};
 
-/// Component for entities that can store resources
+/// Share of a storage's capacity for a resource that can wait to spill over at once
+const SPILL_BUFFER_FRACTION: f64 = 0.25;
+
+/// What a storage does with resources delivered beyond its capacity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+pub enum OverflowPolicy {
+    /// Refuse the excess; the deliverer keeps it
+    #[default]
+    Reject,
+    /// Accept the excess and pass it on to another storage with free space, refusing
+    /// deliveries once too much is waiting to be passed on
+    Spill,
+    /// Accept the excess and discard it
+    Waste,
+}
+
+/// Where a capacity modifier comes from
+#[derive(Debug, Clone, PartialEq, Eq, Reflect)]
+pub enum ModifierSource {
+    /// A storage module attached to the storage entity
+    Module(Entity),
+    /// A researched technology, identified by its id
+    Technology(String),
+}
+
+/// A bonus applied on top of a storage's base capacity
+///
+/// Flat bonuses are summed and added to the base capacity first, then the sum of all
+/// percentage bonuses is applied to the result.
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct CapacityBonus {
+    /// Resource affected, or `None` for every resource
+    pub resource: Option<ResourceType>,
+    /// Flat capacity added
+    pub flat: u32,
+    /// Fractional capacity increase (0.25 = +25%)
+    pub percent: f32,
+}
+
+impl CapacityBonus {
+    /// Check whether this bonus affects a resource type
+    pub fn applies_to(&self, resource_type: ResourceType) -> bool {
+        self.resource.is_none_or(|resource| resource == resource_type)
+    }
+}
+
+/// A capacity bonus together with where it came from
+#[derive(Debug, Clone, PartialEq, Reflect)]
+pub struct CapacityModifier {
+    /// Origin of the bonus, used to replace or remove it later
+    pub source: ModifierSource,
+    /// The bonus itself
+    pub bonus: CapacityBonus,
+}
+
+/// Component for storage modules that raise the capacity of the storage they are attached to
+///
+/// Modules are attached by making them children of the storage entity.
 #[derive(Component, Debug, Clone, Default, Reflect)]
 #[reflect(Component, Default)]
+pub struct StorageModule {
+    /// Capacity bonuses provided by this module
+    pub bonuses: Vec<CapacityBonus>,
+}
+
+impl StorageModule {
+    /// Create a module that adds a flat amount of capacity for every resource
+    pub fn flat(amount: u32) -> Self {
+        Self {
+            bonuses: vec![CapacityBonus { resource: None, flat: amount, percent: 0.0 }],
+        }
+    }
+}
+
+/// Component for entities that can store resources
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
 pub struct ResourceStorage {
-    capacities: HashMap<ResourceType, u32>,
-    amounts: HashMap<ResourceType, u32>,
+    base_capacities: HashMap<ResourceType, ResourceAmount>,
+    capacities: HashMap<ResourceType, ResourceAmount>,
+    amounts: HashMap<ResourceType, ResourceAmount>,
+    modifiers: Vec<CapacityModifier>,
+    /// Behaviour when more is delivered than fits
+    pub overflow_policy: OverflowPolicy,
+    /// Excess accepted under [`OverflowPolicy::Spill`] that still needs a new home
+    pub pending_spill: HashMap<ResourceType, ResourceAmount>,
+    /// Total amount discarded under [`OverflowPolicy::Waste`]
+    pub wasted: HashMap<ResourceType, ResourceAmount>,
+}
+
+impl Default for ResourceStorage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ResourceStorage {
     /// Create a new empty storage with default capacities
     pub fn new() -> Self {
+        let base_capacities: HashMap<_, _> = ResourceType::all()
+            .map(|resource_type| (resource_type, ResourceAmount::from_units(resource_type.default_capacity())))
+            .collect();
         Self {
-            capacities: HashMap::new(),
+            capacities: base_capacities.clone(),
+            base_capacities,
             amounts: HashMap::new(),
+            modifiers: Vec::new(),
+            overflow_policy: OverflowPolicy::default(),
+            pending_spill: HashMap::new(),
+            wasted: HashMap::new(),
+        }
+    }
+
+    /// Create a new empty storage with no capacity for any resource
+    pub fn empty() -> Self {
+        Self {
+            base_capacities: HashMap::new(),
+            capacities: HashMap::new(),
+            ..Self::new()
+        }
+    }
+
+    /// Set the overflow behaviour of this storage
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Set the base capacity for a specific resource type
+    pub fn set_capacity(&mut self, resource_type: ResourceType, capacity: ResourceAmount) {
+        self.base_capacities.insert(resource_type, capacity);
+        self.recalculate_capacity(resource_type);
+    }
+
+    /// Get the base capacity for a resource type, before modifiers
+    pub fn get_base_capacity(&self, resource_type: ResourceType) -> ResourceAmount {
+        self.base_capacities.get(&resource_type).copied().unwrap_or_default()
+    }
+
+    /// Add a capacity modifier, replacing any existing one with the same source and resource
+    pub fn add_modifier(&mut self, modifier: CapacityModifier) {
+        self.modifiers.retain(|m| !(m.source == modifier.source && m.bonus.resource == modifier.bonus.resource));
+        self.modifiers.push(modifier);
+        self.recalculate_capacities();
+    }
+
+    /// Remove every modifier that came from the given source
+    pub fn remove_modifiers_from(&mut self, source: &ModifierSource) {
+        let before = self.modifiers.len();
+        self.modifiers.retain(|m| &m.source != source);
+        if self.modifiers.len() != before {
+            self.recalculate_capacities();
         }
     }
-    
-    /// Set the capacity for a specific resource type
-    pub fn set_capacity(&mut self, resource_type: ResourceType, capacity: u32) {
+
+    /// Get all active capacity modifiers
+    pub fn modifiers(&self) -> &[CapacityModifier] {
+        &self.modifiers
+    }
+
+    fn recalculate_capacities(&mut self) {
+        let resource_types: Vec<_> = self.base_capacities.keys().copied().collect();
+        for resource_type in resource_types {
+            self.recalculate_capacity(resource_type);
+        }
+    }
+
+    fn recalculate_capacity(&mut self, resource_type: ResourceType) {
+        let (flat, percent) = self.modifiers.iter()
+            .map(|m| m.bonus)
+            .filter(|bonus| bonus.applies_to(resource_type))
+            .fold((0u32, 0.0f32), |(flat, percent), bonus| (flat.saturating_add(bonus.flat), percent + bonus.percent));
+        let base = self.get_base_capacity(resource_type) + ResourceAmount::from_units(flat);
+        let capacity = base.scale((1.0 + percent as f64).max(0.0));
         self.capacities.insert(resource_type, capacity);
-        // Ensure amount doesn't exceed new capacity
-        if let Some(amount) = self.amounts.get_mut(&resource_type) {
-            *amount = (*amount).min(capacity);
+
+        // Whatever no longer fits is handled like an oversized delivery; rejected excess
+        // stays stored until it is spent, since there is nobody to hand it back to
+        let Some(amount) = self.amounts.get_mut(&resource_type) else { return };
+        let excess = amount.saturating_sub(capacity);
+        if excess.is_zero() {
+            return;
+        }
+        match self.overflow_policy {
+            OverflowPolicy::Reject => {}
+            OverflowPolicy::Spill => {
+                *amount = capacity;
+                *self.pending_spill.entry(resource_type).or_default() += excess;
+            }
+            OverflowPolicy::Waste => {
+                *amount = capacity;
+                *self.wasted.entry(resource_type).or_default() += excess;
+            }
         }
     }
-    
+
     /// Get the current amount of a resource
-    pub fn get_amount(&self, resource_type: ResourceType) -> u32 {
-        *self.amounts.get(&resource_type).unwrap_or(&0)
+    pub fn get_amount(&self, resource_type: ResourceType) -> ResourceAmount {
+        self.amounts.get(&resource_type).copied().unwrap_or_default()
     }
-    
-    /// Get the capacity for a resource type
-    pub fn get_capacity(&self, resource_type: ResourceType) -> u32 {
-        *self.capacities.get(&resource_type).unwrap_or(&0)
+
+    /// Get the capacity for a resource type, including modifiers
+    pub fn get_capacity(&self, resource_type: ResourceType) -> ResourceAmount {
+        self.capacities.get(&resource_type).copied().unwrap_or_default()
     }
-    
+
     /// Get the remaining capacity for a resource type
-    pub fn get_remaining_capacity(&self, resource_type: ResourceType) -> u32 {
+    pub fn get_remaining_capacity(&self, resource_type: ResourceType) -> ResourceAmount {
         self.get_capacity(resource_type).saturating_sub(self.get_amount(resource_type))
     }
-    
-    /// Add resources of a specific type
+
+    /// Get how much more of a resource can be accepted to spill over later
+    pub fn get_spill_room(&self, resource_type: ResourceType) -> ResourceAmount {
+        let buffer = self.get_capacity(resource_type).scale(SPILL_BUFFER_FRACTION);
+        buffer.saturating_sub(self.pending_spill.get(&resource_type).copied().unwrap_or_default())
+    }
+
+    /// Get how much of a delivery would be handed over under the overflow policy
+    pub fn get_deposit_room(&self, resource_type: ResourceType, amount: ResourceAmount) -> ResourceAmount {
+        let remaining = self.get_remaining_capacity(resource_type);
+        match self.overflow_policy {
+            OverflowPolicy::Reject => amount.min(remaining),
+            OverflowPolicy::Spill => amount.min(remaining.saturating_add(self.get_spill_room(resource_type))),
+            OverflowPolicy::Waste if self.get_capacity(resource_type).is_zero() => ResourceAmount::ZERO,
+            OverflowPolicy::Waste => amount,
+        }
+    }
+
+    /// Check whether a delivery of this resource would be accepted under the overflow policy
+    pub fn accepts(&self, resource_type: ResourceType) -> bool {
+        !self.get_deposit_room(resource_type, ResourceAmount::MAX).is_zero()
+    }
+
+    /// Add resources of a specific type, rejecting anything beyond capacity
     /// Returns the amount that was actually added
-    pub fn add_resource(&mut self, resource_type: ResourceType, amount: u32) -> u32 {
-        let capacity = self.get_capacity(resource_type);
-        let current = self.get_amount(resource_type);
-        let add_amount = amount.min(capacity.saturating_sub(current));
-        
-        if add_amount > 0 {
-            *self.amounts.entry(resource_type).or_insert(0) += add_amount;
+    pub fn add_resource(&mut self, resource_type: ResourceType, amount: ResourceAmount) -> ResourceAmount {
+        let add_amount = amount.min(self.get_remaining_capacity(resource_type));
+
+        if !add_amount.is_zero() {
+            *self.amounts.entry(resource_type).or_default() += add_amount;
         }
-        
+
         add_amount
     }
-    
+
+    /// Deliver resources to this storage, handling any excess according to the overflow policy
+    /// Returns the amount the deliverer handed over
+    pub fn deposit(&mut self, resource_type: ResourceType, amount: ResourceAmount) -> ResourceAmount {
+        let stored = self.add_resource(resource_type, amount);
+        let excess = amount - stored;
+        if excess.is_zero() {
+            return amount;
+        }
+
+        match self.overflow_policy {
+            OverflowPolicy::Reject => stored,
+            OverflowPolicy::Spill => {
+                let spilled = excess.min(self.get_spill_room(resource_type));
+                *self.pending_spill.entry(resource_type).or_default() += spilled;
+                stored + spilled
+            }
+            OverflowPolicy::Waste => {
+                *self.wasted.entry(resource_type).or_default() += excess;
+                amount
+            }
+        }
+    }
+
     /// Remove resources of a specific type
     /// Returns the amount that was actually removed
-    pub fn remove_resource(&mut self, resource_type: ResourceType, amount: u32) -> u32 {
+    pub fn remove_resource(&mut self, resource_type: ResourceType, amount: ResourceAmount) -> ResourceAmount {
         let current = self.get_amount(resource_type);
         let remove_amount = amount.min(current);
-        
-        if remove_amount > 0 {
+
+        if !remove_amount.is_zero() {
             *self.amounts.get_mut(&resource_type).unwrap() -= remove_amount;
         }
-        
+
         remove_amount
     }
-    
+
     /// Check if there's enough of a resource
-    pub fn has_enough(&self, resource_type: ResourceType, amount: u32) -> bool {
+    pub fn has_enough(&self, resource_type: ResourceType, amount: ResourceAmount) -> bool {
         self.get_amount(resource_type) >= amount
     }
-    
+
     /// Check if the storage has any resources
     pub fn is_empty(&self) -> bool {
-        self.amounts.values().all(|&amount| amount == 0)
+        self.amounts.values().all(|amount| amount.is_zero())
     }
-    
+
     /// Get an iterator over all stored resources
-    pub fn iter(&self) -> impl Iterator<Item = (ResourceType, u32, u32)> + '_ {
+    pub fn iter(&self) -> impl Iterator<Item = (ResourceType, ResourceAmount, ResourceAmount)> + '_ {
         self.amounts.iter().map(move |(&resource_type, &amount)| {
             let capacity = self.get_capacity(resource_type);
             (resource_type, amount, capacity)
         })
     }
 }
+
+/// Storage modules that were added, changed or moved to another parent
+type ChangedModules<'w, 's> =
+    Query<'w, 's, (), (With<StorageModule>, Or<(Changed<StorageModule>, Changed<Parent>)>)>;
+
+/// System to apply capacity bonuses from storage modules to their parent storage
+pub fn apply_storage_modules(
+    changed_modules: ChangedModules,
+    mut removed_modules: RemovedComponents<StorageModule>,
+    modules: Query<(Entity, &StorageModule, &Parent)>,
+    mut storages: Query<(Entity, &mut ResourceStorage)>,
+) {
+    let removed = removed_modules.read().count() > 0;
+    if changed_modules.is_empty() && !removed {
+        return;
+    }
+
+    // Rebuild module modifiers from scratch so detached and despawned modules are dropped
+    let mut module_modifiers: HashMap<Entity, Vec<CapacityModifier>> = HashMap::new();
+    for (module_entity, module, parent) in modules.iter() {
+        module_modifiers.entry(parent.get()).or_default().extend(
+            module.bonuses.iter().map(|&bonus| CapacityModifier {
+                source: ModifierSource::Module(module_entity),
+                bonus,
+            }),
+        );
+    }
+
+    for (entity, mut storage) in storages.iter_mut() {
+        let new_modifiers = module_modifiers.remove(&entity).unwrap_or_default();
+        let had_modules = storage.modifiers.iter().any(|m| matches!(m.source, ModifierSource::Module(_)));
+        if !had_modules && new_modifiers.is_empty() {
+            continue;
+        }
+        storage.modifiers.retain(|m| !matches!(m.source, ModifierSource::Module(_)));
+        storage.modifiers.extend(new_modifiers);
+        storage.recalculate_capacities();
+    }
+}
+
+/// System to move spilled resources into the owner's nearest other storage with free space
+pub fn spill_storage_overflow(
+    mut storages: Query<(Entity, &mut ResourceStorage, &Transform, Option<&Owner>)>,
+) {
+    let spilling: Vec<(Entity, Option<Owner>, Vec3, ResourceType, ResourceAmount)> = storages.iter()
+        .flat_map(|(entity, storage, transform, owner)| {
+            let owner = owner.copied();
+            storage.pending_spill.iter()
+                .filter(|(_, amount)| !amount.is_zero())
+                .map(move |(&resource_type, &amount)| (entity, owner, transform.translation, resource_type, amount))
+        })
+        .collect();
+
+    for (source, source_owner, position, resource_type, amount) in spilling {
+        let target = storages.iter()
+            .filter(|(entity, storage, _, owner)| {
+                *entity != source
+                    && owner.copied() == source_owner
+                    && !storage.get_remaining_capacity(resource_type).is_zero()
+            })
+            .min_by(|(_, _, a, _), (_, _, b, _)| {
+                a.translation.distance_squared(position)
+                    .total_cmp(&b.translation.distance_squared(position))
+            })
+            .map(|(entity, ..)| entity);
+
+        let Some(target) = target else {
+            continue;
+        };
+
+        let moved = match storages.get_mut(target) {
+            Ok((_, mut storage, ..)) => storage.add_resource(resource_type, amount),
+            Err(_) => ResourceAmount::ZERO,
+        };
+        if let Ok((_, mut storage, ..)) = storages.get_mut(source) {
+            if let Some(pending) = storage.pending_spill.get_mut(&resource_type) {
+                *pending -= moved;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::PlayerId;
+
+    #[test]
+    fn test_overflow_policies() {
+        let wood = ResourceType::Wood;
+        let units = ResourceAmount::from_units;
+        let storage_with = |policy: OverflowPolicy| {
+            let mut storage = ResourceStorage::empty().with_overflow_policy(policy);
+            storage.set_capacity(wood, units(100));
+            storage.add_resource(wood, units(90));
+            storage
+        };
+
+        // Reject hands back whatever does not fit
+        let mut reject = storage_with(OverflowPolicy::Reject);
+        assert_eq!(reject.deposit(wood, units(30)), units(10));
+        assert!(!reject.accepts(wood));
+
+        // Waste takes everything and throws the excess away
+        let mut waste = storage_with(OverflowPolicy::Waste);
+        assert_eq!(waste.deposit(wood, units(30)), units(30));
+        assert_eq!(waste.wasted[&wood], units(20));
+        assert!(waste.accepts(wood));
+
+        // Spill only takes what its buffer can hold until the excess finds a new home
+        let mut spill = storage_with(OverflowPolicy::Spill);
+        assert_eq!(spill.deposit(wood, units(30)), units(30));
+        assert_eq!(spill.deposit(wood, units(10)), units(5));
+        assert_eq!(spill.pending_spill[&wood], units(25));
+        assert!(!spill.accepts(wood));
+        assert_eq!(spill.deposit(wood, units(10)), ResourceAmount::ZERO);
+
+        let mut world = World::new();
+        let source = world.spawn((spill, Transform::default())).id();
+        let mut neighbour = ResourceStorage::empty();
+        neighbour.set_capacity(wood, units(15));
+        let neighbour = world.spawn((neighbour, Transform::from_xyz(10.0, 0.0, 0.0))).id();
+        let mut schedule = Schedule::default();
+        schedule.add_systems(spill_storage_overflow);
+        schedule.run(&mut world);
+        assert_eq!(world.get::<ResourceStorage>(neighbour).unwrap().get_amount(wood), units(15));
+        let spill = world.get::<ResourceStorage>(source).unwrap();
+        assert_eq!(spill.pending_spill[&wood], units(10));
+        assert!(spill.accepts(wood));
+    }
+
+    #[test]
+    fn test_spill_stays_with_owner() {
+        let wood = ResourceType::Wood;
+        let units = ResourceAmount::from_units;
+        let storage_with = |capacity: u32, amount: u32| {
+            let mut storage = ResourceStorage::empty().with_overflow_policy(OverflowPolicy::Spill);
+            storage.set_capacity(wood, units(capacity));
+            storage.add_resource(wood, units(amount));
+            storage
+        };
+
+        let mut world = World::new();
+        let (player, opponent) = (PlayerId(0), PlayerId(1));
+        let mut spill = storage_with(100, 100);
+        assert_eq!(spill.deposit(wood, units(20)), units(20));
+        let source = world.spawn((Owner(player), spill, Transform::default())).id();
+        let theirs = world.spawn((Owner(opponent), storage_with(100, 0), Transform::from_xyz(5.0, 0.0, 0.0))).id();
+        let ours = world.spawn((Owner(player), storage_with(100, 0), Transform::from_xyz(50.0, 0.0, 0.0))).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(spill_storage_overflow);
+        schedule.run(&mut world);
+
+        let amount = |entity| world.get::<ResourceStorage>(entity).unwrap().get_amount(wood);
+        assert_eq!(amount(theirs), ResourceAmount::ZERO);
+        assert_eq!(amount(ours), units(20));
+        assert_eq!(world.get::<ResourceStorage>(source).unwrap().pending_spill[&wood], ResourceAmount::ZERO);
+    }
+
+    #[test]
+    fn test_capacity_drop_follows_overflow_policy() {
+        let wood = ResourceType::Wood;
+        let units = ResourceAmount::from_units;
+        let storage_with = |policy: OverflowPolicy| {
+            let mut storage = ResourceStorage::empty().with_overflow_policy(policy);
+            storage.set_capacity(wood, units(100));
+            storage
+        };
+
+        let mut world = World::new();
+        let player = PlayerId(0);
+        let mut full = storage_with(OverflowPolicy::Spill);
+        full.add_modifier(CapacityModifier {
+            source: ModifierSource::Technology("silos".to_string()),
+            bonus: CapacityBonus { resource: None, flat: 50, percent: 0.0 },
+        });
+        full.add_resource(wood, units(150));
+        let source = world.spawn((Owner(player), full, Transform::default())).id();
+        let module = world.spawn(StorageModule::flat(40)).set_parent(source).id();
+        let ours = world.spawn((Owner(player), storage_with(OverflowPolicy::Reject), Transform::from_xyz(20.0, 0.0, 0.0))).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((apply_storage_modules, spill_storage_overflow).chain());
+        schedule.run(&mut world);
+        world.get_mut::<ResourceStorage>(source).unwrap().add_resource(wood, units(40));
+        assert_eq!(world.get::<ResourceStorage>(source).unwrap().get_amount(wood), units(190));
+
+        // Detaching the module drops the capacity back to 150 and the excess moves next door
+        world.entity_mut(module).remove::<StorageModule>();
+        schedule.run(&mut world);
+        let amount = |world: &World, entity| world.get::<ResourceStorage>(entity).unwrap().get_amount(wood);
+        assert_eq!(amount(&world, source), units(150));
+        assert_eq!(amount(&world, ours), units(40));
+        assert_eq!(world.get::<ResourceStorage>(source).unwrap().pending_spill[&wood], ResourceAmount::ZERO);
+
+        // Waste throws the excess away, Reject keeps it until it is spent
+        let silos = ModifierSource::Technology("silos".to_string());
+        let mut waste = world.get::<ResourceStorage>(source).unwrap().clone().with_overflow_policy(OverflowPolicy::Waste);
+        waste.remove_modifiers_from(&silos);
+        assert_eq!(waste.get_amount(wood), units(100));
+        assert_eq!(waste.wasted[&wood], units(50));
+        let mut reject = world.get::<ResourceStorage>(source).unwrap().clone().with_overflow_policy(OverflowPolicy::Reject);
+        reject.remove_modifiers_from(&silos);
+        assert_eq!(reject.get_amount(wood), units(150));
+        assert_eq!(reject.deposit(wood, units(10)), ResourceAmount::ZERO);
+        assert_eq!(reject.remove_resource(wood, units(60)), units(60));
+        assert_eq!(reject.get_amount(wood), units(90));
+    }
+}
@@ -0,0 +1,268 @@
+//! Direct resource transfers between players, tribute and tax
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::player::{Alliances, PlayerId};
+use super::{
+    ResourceCost, ResourceDeposited, ResourceTransferred, TradeError, TradeRejected, Treasury,
+};
+
+/// Seconds between settlements of income tax
+pub const TAX_SETTLE_SECONDS: f32 = 10.0;
+
+/// Why resources moved from one player to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
+pub enum TransferReason {
+    /// A player sent resources to an ally
+    Gift,
+    /// A fixed tribute payment
+    Tribute,
+    /// A share of the payer's income
+    Tax,
+}
+
+/// Request for a player to send resources to an ally
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct TransferRequest {
+    /// The giving player
+    pub from: PlayerId,
+    /// The receiving player
+    pub to: PlayerId,
+    /// Resources to send
+    pub amounts: ResourceCost,
+}
+
+/// What a tribute agreement requires the payer to hand over
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TributeTerms {
+    /// Pay a fixed amount every `interval` seconds
+    Periodic {
+        /// Resources paid each time
+        amounts: ResourceCost,
+        /// Seconds between payments
+        interval: f32,
+    },
+    /// Pay a fraction of every delivery into the payer's storages
+    Income {
+        /// Fraction of income owed (0.0 to 1.0)
+        rate: f32,
+    },
+}
+
+/// An ongoing obligation for one player to pay another
+#[derive(Debug, Clone)]
+pub struct TributeAgreement {
+    /// The paying player
+    pub payer: PlayerId,
+    /// The receiving player
+    pub recipient: PlayerId,
+    /// What is owed
+    pub terms: TributeTerms,
+    owed: ResourceCost,
+    timer: f32,
+}
+
+impl TributeAgreement {
+    fn interval(&self) -> f32 {
+        match &self.terms {
+            TributeTerms::Periodic { interval, .. } => *interval,
+            TributeTerms::Income { .. } => TAX_SETTLE_SECONDS,
+        }
+    }
+}
+
+/// Resource holding every active tribute and tax agreement
+#[derive(Resource, Debug, Clone, Default)]
+pub struct Tributes {
+    agreements: Vec<TributeAgreement>,
+}
+
+impl Tributes {
+    /// Start a new agreement
+    pub fn add(&mut self, payer: PlayerId, recipient: PlayerId, terms: TributeTerms) {
+        self.agreements.push(TributeAgreement {
+            payer,
+            recipient,
+            terms,
+            owed: ResourceCost::new(),
+            timer: 0.0,
+        });
+    }
+
+    /// End every agreement between a payer and a recipient
+    pub fn remove(&mut self, payer: PlayerId, recipient: PlayerId) {
+        self.agreements.retain(|agreement| agreement.payer != payer || agreement.recipient != recipient);
+    }
+
+    /// Iterate over the active agreements
+    pub fn iter(&self) -> impl Iterator<Item = &TributeAgreement> {
+        self.agreements.iter()
+    }
+
+    /// End all agreements
+    pub fn clear(&mut self) {
+        self.agreements.clear();
+    }
+}
+
+/// System to carry out transfers between allies
+pub fn process_transfer_requests(
+    mut requests: EventReader<TransferRequest>,
+    alliances: Res<Alliances>,
+    mut treasury: Treasury,
+    mut transferred: EventWriter<ResourceTransferred>,
+    mut rejected: EventWriter<TradeRejected>,
+) {
+    for request in requests.read() {
+        let result = if request.amounts.is_empty() {
+            Err(TradeError::Empty)
+        } else if !alliances.are_allied(request.from, request.to) {
+            Err(TradeError::NotAllied { from: request.from, to: request.to })
+        } else {
+            treasury.transfer(request.from, request.to, &request.amounts).map_err(TradeError::from)
+        };
+
+        match result {
+            Ok(amounts) => {
+                if !amounts.is_empty() {
+                    transferred.send(ResourceTransferred {
+                        from: request.from,
+                        to: request.to,
+                        amounts,
+                        reason: TransferReason::Gift,
+                    });
+                }
+            }
+            Err(error) => {
+                rejected.send(TradeRejected { player: request.from, error });
+            }
+        }
+    }
+}
+
+/// System to accrue income tax and pay tributes when they fall due
+pub fn collect_tributes(
+    time: Res<Time>,
+    mut tributes: ResMut<Tributes>,
+    mut deposited: EventReader<ResourceDeposited>,
+    mut treasury: Treasury,
+    mut transferred: EventWriter<ResourceTransferred>,
+    mut rejected: EventWriter<TradeRejected>,
+) {
+    let delta = time.delta_seconds();
+    let deposits: Vec<_> = deposited.read().collect();
+
+    for agreement in tributes.agreements.iter_mut() {
+        if let TributeTerms::Income { rate } = agreement.terms {
+            for event in deposits.iter().filter(|event| event.player == Some(agreement.payer)) {
+                agreement.owed = std::mem::take(&mut agreement.owed)
+                    .with(event.resource, event.stored.scale(rate.clamp(0.0, 1.0) as f64));
+            }
+        }
+
+        agreement.timer += delta;
+        if agreement.timer < agreement.interval() {
+            continue;
+        }
+        agreement.timer = 0.0;
+
+        let (amounts, reason) = match &agreement.terms {
+            TributeTerms::Periodic { amounts, .. } => (amounts.clone(), TransferReason::Tribute),
+            TributeTerms::Income { .. } => {
+                // Tax is capped at what the payer still holds; anything already spent is forgiven
+                let mut due = ResourceCost::new();
+                for (resource_type, owed) in std::mem::take(&mut agreement.owed).iter() {
+                    due = due.with(resource_type, owed.min(treasury.balance(agreement.payer, resource_type)));
+                }
+                (due, TransferReason::Tax)
+            }
+        };
+        if amounts.is_empty() {
+            continue;
+        }
+
+        match treasury.transfer(agreement.payer, agreement.recipient, &amounts) {
+            Ok(amounts) => {
+                if !amounts.is_empty() {
+                    transferred.send(ResourceTransferred {
+                        from: agreement.payer,
+                        to: agreement.recipient,
+                        amounts,
+                        reason,
+                    });
+                }
+            }
+            Err(error) => {
+                rejected.send(TradeRejected { player: agreement.payer, error: error.into() });
+            }
+        }
+    }
+}
+
+/// System to end all tribute agreements when a new game starts
+pub fn reset_tributes(mut tributes: ResMut<Tributes>) {
+    tributes.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::player::Owner;
+    use crate::resources::{ResourceAmount, ResourceRefunded, ResourceSpent, ResourceStorage, ResourceType};
+
+    #[test]
+    fn test_gifts_and_income_tax() {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.init_resource::<Alliances>();
+        world.init_resource::<Tributes>();
+        world.init_resource::<Events<TransferRequest>>();
+        world.init_resource::<Events<ResourceDeposited>>();
+        world.init_resource::<Events<ResourceTransferred>>();
+        world.init_resource::<Events<TradeRejected>>();
+        world.init_resource::<Events<ResourceSpent>>();
+        world.init_resource::<Events<ResourceRefunded>>();
+
+        let (payer, recipient, stranger) = (PlayerId(0), PlayerId(1), PlayerId(2));
+        let mut stocked = ResourceStorage::new();
+        stocked.add_resource(ResourceType::Wood, ResourceAmount::from_units(100));
+        let payer_storage = world.spawn((Owner(payer), stocked)).id();
+        let recipient_storage = world.spawn((Owner(recipient), ResourceStorage::new())).id();
+        world.spawn((Owner(stranger), ResourceStorage::new()));
+        world.resource_mut::<Alliances>().ally(payer, recipient);
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((process_transfer_requests, collect_tributes).chain());
+
+        // Gifts only go to allies
+        let gift = ResourceCost::new().with(ResourceType::Wood, 30);
+        world.send_event(TransferRequest { from: payer, to: recipient, amounts: gift.clone() });
+        world.send_event(TransferRequest { from: payer, to: stranger, amounts: gift });
+        schedule.run(&mut world);
+        let wood = |world: &World, entity| world.get::<ResourceStorage>(entity).unwrap().get_amount(ResourceType::Wood);
+        assert_eq!(wood(&world, payer_storage), ResourceAmount::from_units(70));
+        assert_eq!(wood(&world, recipient_storage), ResourceAmount::from_units(30));
+        let rejected: Vec<_> = world.resource_mut::<Events<TradeRejected>>().drain().collect();
+        assert!(matches!(rejected.as_slice(), [TradeRejected { error: TradeError::NotAllied { .. }, .. }]));
+
+        // Tax is owed on what was stored, not on excess that was wasted
+        world.resource_mut::<Tributes>().add(payer, recipient, TributeTerms::Income { rate: 0.5 });
+        world.send_event(ResourceDeposited {
+            storage: payer_storage,
+            source: payer_storage,
+            player: Some(payer),
+            resource: ResourceType::Wood,
+            amount: ResourceAmount::from_units(40),
+            stored: ResourceAmount::from_units(10),
+        });
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(TAX_SETTLE_SECONDS));
+        schedule.run(&mut world);
+        assert_eq!(wood(&world, payer_storage), ResourceAmount::from_units(65));
+        assert_eq!(wood(&world, recipient_storage), ResourceAmount::from_units(35));
+        let taxes: Vec<_> = world.resource_mut::<Events<ResourceTransferred>>().drain()
+            .filter(|transfer| transfer.reason == TransferReason::Tax)
+            .collect();
+        assert_eq!(taxes.len(), 1);
+    }
+}
@@ -0,0 +1,351 @@
+//! Per-player treasury spanning all of a player's storages
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use thiserror::Error;
+use crate::player::{Owner, PlayerId};
+use super::{ResourceType, ResourceAmount, ResourceStorage, ResourceSpent, ResourceRefunded};
+
+/// A bundle of resource amounts, used for costs, refunds and income
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, Reflect)]
+#[serde(transparent)]
+pub struct ResourceCost(pub HashMap<ResourceType, ResourceAmount>);
+
+impl ResourceCost {
+    /// Create an empty cost
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an amount of a resource to the cost
+    pub fn with(mut self, resource_type: ResourceType, amount: impl Into<ResourceAmount>) -> Self {
+        *self.0.entry(resource_type).or_default() += amount.into();
+        self
+    }
+
+    /// Get the amount of a resource in this cost
+    pub fn get(&self, resource_type: ResourceType) -> ResourceAmount {
+        self.0.get(&resource_type).copied().unwrap_or_default()
+    }
+
+    /// Check whether the cost contains nothing
+    pub fn is_empty(&self) -> bool {
+        self.0.values().all(|amount| amount.is_zero())
+    }
+
+    /// Iterate over the non-zero amounts in this cost
+    pub fn iter(&self) -> impl Iterator<Item = (ResourceType, ResourceAmount)> + '_ {
+        self.0.iter()
+            .filter(|(_, amount)| !amount.is_zero())
+            .map(|(&resource_type, &amount)| (resource_type, amount))
+    }
+
+    /// Scale every amount by a factor, e.g. for partial refunds
+    pub fn scaled(&self, factor: f64) -> Self {
+        Self(self.0.iter().map(|(&resource_type, &amount)| (resource_type, amount.scale(factor))).collect())
+    }
+}
+
+/// Errors returned when a treasury cannot cover a cost
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SpendError {
+    /// The player does not have enough of a resource
+    #[error("Not enough {resource}: need {required:.1}, have {available:.1}")]
+    Insufficient {
+        /// Resource that is short
+        resource: ResourceType,
+        /// Amount the cost requires
+        required: ResourceAmount,
+        /// Amount the player has across all storages
+        available: ResourceAmount,
+    },
+}
+
+/// Access to each player's combined resources across all the storages they own
+///
+/// Spending is atomic: either the whole cost is withdrawn or nothing is.
+#[derive(SystemParam)]
+pub struct Treasury<'w, 's> {
+    storages: Query<'w, 's, (&'static Owner, &'static mut ResourceStorage)>,
+    spent_events: EventWriter<'w, ResourceSpent>,
+    refunded_events: EventWriter<'w, ResourceRefunded>,
+}
+
+impl<'w, 's> Treasury<'w, 's> {
+    /// Get a player's total amount of a resource
+    pub fn balance(&self, player: PlayerId, resource_type: ResourceType) -> ResourceAmount {
+        self.storages.iter()
+            .filter(|(owner, _)| owner.0 == player)
+            .map(|(_, storage)| storage.get_amount(resource_type))
+            .sum()
+    }
+
+    /// Get a player's total storage capacity for a resource
+    pub fn capacity(&self, player: PlayerId, resource_type: ResourceType) -> ResourceAmount {
+        self.storages.iter()
+            .filter(|(owner, _)| owner.0 == player)
+            .map(|(_, storage)| storage.get_capacity(resource_type))
+            .sum()
+    }
+
+    /// Get a player's total amount of every resource
+    pub fn balances(&self, player: PlayerId) -> ResourceCost {
+        let mut totals = ResourceCost::new();
+        for (owner, storage) in self.storages.iter() {
+            if owner.0 == player {
+                for (resource_type, amount, _) in storage.iter() {
+                    totals = totals.with(resource_type, amount);
+                }
+            }
+        }
+        totals
+    }
+
+    /// Check whether a player can pay a cost
+    pub fn can_afford(&self, player: PlayerId, cost: &ResourceCost) -> Result<(), SpendError> {
+        for (resource_type, required) in cost.iter() {
+            let available = self.balance(player, resource_type);
+            if available < required {
+                return Err(SpendError::Insufficient { resource: resource_type, required, available });
+            }
+        }
+        Ok(())
+    }
+
+    /// Withdraw a cost from a player's storages, or nothing if they cannot pay all of it
+    pub fn try_spend(&mut self, player: PlayerId, cost: &ResourceCost) -> Result<(), SpendError> {
+        self.can_afford(player, cost)?;
+
+        for (resource_type, required) in cost.iter() {
+            let mut remaining = required;
+            for (owner, mut storage) in self.storages.iter_mut() {
+                if remaining.is_zero() {
+                    break;
+                }
+                if owner.0 == player {
+                    remaining -= storage.remove_resource(resource_type, remaining);
+                }
+            }
+        }
+        if !cost.is_empty() {
+            self.spent_events.send(ResourceSpent { player, cost: cost.clone() });
+        }
+        Ok(())
+    }
+
+    /// Return resources to a player's storages
+    /// Returns whatever did not fit anywhere
+    pub fn refund(&mut self, player: PlayerId, amounts: &ResourceCost) -> ResourceCost {
+        let (returned, leftover) = self.store(player, amounts);
+        if !returned.is_empty() {
+            self.refunded_events.send(ResourceRefunded { player, amounts: returned });
+        }
+        leftover
+    }
+
+    /// Add newly acquired resources, e.g. a market purchase, to a player's storages
+    /// The caller reports them as income through its own event; returns whatever did not fit anywhere
+    pub fn deposit(&mut self, player: PlayerId, amounts: &ResourceCost) -> ResourceCost {
+        self.store(player, amounts).1
+    }
+
+    /// Fill a player's storages in turn, returning what was stored and what was left over
+    fn store(&mut self, player: PlayerId, amounts: &ResourceCost) -> (ResourceCost, ResourceCost) {
+        let mut stored = ResourceCost::new();
+        let mut leftover = ResourceCost::new();
+        for (resource_type, amount) in amounts.iter() {
+            let mut remaining = amount;
+            for (owner, mut storage) in self.storages.iter_mut() {
+                if remaining.is_zero() {
+                    break;
+                }
+                if owner.0 == player {
+                    remaining -= storage.add_resource(resource_type, remaining);
+                }
+            }
+            stored = stored.with(resource_type, amount - remaining);
+            if !remaining.is_zero() {
+                leftover = leftover.with(resource_type, remaining);
+            }
+        }
+        (stored, leftover)
+    }
+
+    /// Get how much more of a resource a player's storages can hold
+    pub fn room(&self, player: PlayerId, resource_type: ResourceType) -> ResourceAmount {
+        self.storages.iter()
+            .filter(|(owner, _)| owner.0 == player)
+            .map(|(_, storage)| storage.get_remaining_capacity(resource_type))
+            .sum()
+    }
+
+    /// Move resources from one player's storages to another's
+    /// Only what the recipient has room for is taken from the payer; returns what was delivered
+    pub fn transfer(&mut self, from: PlayerId, to: PlayerId, amounts: &ResourceCost) -> Result<ResourceCost, SpendError> {
+        self.can_afford(from, amounts)?;
+
+        let mut deliverable = ResourceCost::new();
+        for (resource_type, amount) in amounts.iter() {
+            deliverable = deliverable.with(resource_type, amount.min(self.room(to, resource_type)));
+        }
+        self.try_spend(from, &deliverable)?;
+        let undelivered = self.deposit(to, &deliverable);
+        debug_assert!(undelivered.is_empty(), "recipient room shrank during a transfer");
+        Ok(deliverable)
+    }
+}
+
+/// Create a world with a clock and the events a [`Treasury`] sends, for testing systems that spend
+#[cfg(test)]
+pub(crate) fn treasury_test_world() -> World {
+    let mut world = World::new();
+    world.insert_resource(Time::<()>::default());
+    world.init_resource::<Events<ResourceSpent>>();
+    world.init_resource::<Events<ResourceRefunded>>();
+    world
+}
+
+/// Spawn a storage owned by a player holding whole units of each listed resource
+#[cfg(test)]
+pub(crate) fn spawn_stocked_storage(world: &mut World, player: PlayerId, stock: &[(ResourceType, u32)]) -> Entity {
+    let mut storage = ResourceStorage::new();
+    for &(resource_type, units) in stock {
+        storage.add_resource(resource_type, ResourceAmount::from_units(units));
+    }
+    world.spawn((Owner(player), storage)).id()
+}
+
+/// Advance the clock in tenth-of-a-second steps, running the schedule after each one
+#[cfg(test)]
+pub(crate) fn step_seconds(world: &mut World, schedule: &mut Schedule, seconds: u32) {
+    for _ in 0..seconds * 10 {
+        world.resource_mut::<Time>().advance_by(std::time::Duration::from_millis(100));
+        schedule.run(world);
+    }
+}
+
+/// Resource holding each player's totals as of the last update, for display
+#[derive(Resource, Debug, Clone, Default)]
+pub struct TreasurySummary {
+    players: HashMap<PlayerId, ResourceCost>,
+}
+
+impl TreasurySummary {
+    /// Get a player's totals
+    pub fn get(&self, player: PlayerId) -> Option<&ResourceCost> {
+        self.players.get(&player)
+    }
+}
+
+/// System to refresh the treasury summary from all owned storages
+pub fn update_treasury_summary(
+    storages: Query<(&Owner, &ResourceStorage)>,
+    mut summary: ResMut<TreasurySummary>,
+) {
+    summary.players.clear();
+    for (owner, storage) in storages.iter() {
+        let totals = summary.players.entry(owner.0).or_default();
+        for (resource_type, amount, _) in storage.iter() {
+            *totals.0.entry(resource_type).or_default() += amount;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::SystemState;
+
+    #[test]
+    fn test_try_spend_is_atomic() {
+        let mut world = treasury_test_world();
+        let player = PlayerId(0);
+        spawn_stocked_storage(&mut world, player, &[(ResourceType::Wood, 100)]);
+        spawn_stocked_storage(&mut world, player, &[(ResourceType::Stone, 10)]);
+
+        let mut state: SystemState<Treasury> = SystemState::new(&mut world);
+        let mut treasury = state.get_mut(&mut world);
+
+        let cost = ResourceCost::new()
+            .with(ResourceType::Wood, 50)
+            .with(ResourceType::Stone, 20);
+        assert!(matches!(
+            treasury.try_spend(player, &cost),
+            Err(SpendError::Insufficient { resource: ResourceType::Stone, .. })
+        ));
+        assert_eq!(treasury.balance(player, ResourceType::Wood), ResourceAmount::from_units(100));
+        assert_eq!(treasury.balance(player, ResourceType::Stone), ResourceAmount::from_units(10));
+        assert!(world.resource::<Events<ResourceSpent>>().is_empty());
+    }
+
+    #[test]
+    fn test_spend_spans_storages_and_refunds() {
+        let mut world = treasury_test_world();
+        let player = PlayerId(0);
+        let other = PlayerId(1);
+        spawn_stocked_storage(&mut world, player, &[(ResourceType::Wood, 30)]);
+        spawn_stocked_storage(&mut world, player, &[(ResourceType::Wood, 30)]);
+        spawn_stocked_storage(&mut world, other, &[(ResourceType::Wood, 500)]);
+
+        let mut state: SystemState<Treasury> = SystemState::new(&mut world);
+        let mut treasury = state.get_mut(&mut world);
+
+        let cost = ResourceCost::new().with(ResourceType::Wood, 50);
+        assert!(treasury.try_spend(player, &cost).is_ok());
+        assert_eq!(treasury.balance(player, ResourceType::Wood), ResourceAmount::from_units(10));
+        assert_eq!(treasury.balance(other, ResourceType::Wood), ResourceAmount::from_units(500));
+
+        let leftover = treasury.refund(player, &cost);
+        assert!(leftover.is_empty());
+        assert_eq!(treasury.balance(player, ResourceType::Wood), ResourceAmount::from_units(60));
+    }
+
+    #[test]
+    fn test_fractional_amounts_are_exact() {
+        let mut world = treasury_test_world();
+        let player = PlayerId(0);
+        world.spawn((Owner(player), ResourceStorage::new()));
+
+        let mut state: SystemState<Treasury> = SystemState::new(&mut world);
+        let mut treasury = state.get_mut(&mut world);
+
+        let tenth = ResourceCost::new().with(ResourceType::Iron, ResourceAmount::from_f32(0.1));
+        for _ in 0..10 {
+            treasury.refund(player, &tenth);
+        }
+        assert_eq!(treasury.balance(player, ResourceType::Iron), ResourceAmount::from_units(1));
+    }
+
+    #[test]
+    fn test_transfer_only_takes_what_fits() {
+        let mut world = treasury_test_world();
+        let payer = PlayerId(0);
+        let recipient = PlayerId(1);
+        let mut full = ResourceStorage::new();
+        let capacity = full.get_capacity(ResourceType::Wood);
+        full.add_resource(ResourceType::Wood, capacity);
+        world.spawn((Owner(payer), full.clone()));
+        world.spawn((Owner(recipient), full));
+        let mut nearly_full = ResourceStorage::new();
+        nearly_full.add_resource(ResourceType::Wood, capacity - ResourceAmount::from_units(5));
+        world.spawn((Owner(recipient), nearly_full));
+
+        let mut state: SystemState<Treasury> = SystemState::new(&mut world);
+        let mut treasury = state.get_mut(&mut world);
+        let payer_before = treasury.balance(payer, ResourceType::Wood);
+        let recipient_before = treasury.balance(recipient, ResourceType::Wood);
+
+        let gift = ResourceCost::new().with(ResourceType::Wood, 20);
+        let delivered = treasury.transfer(payer, recipient, &gift).unwrap();
+        assert_eq!(delivered.get(ResourceType::Wood), ResourceAmount::from_units(5));
+        assert_eq!(treasury.balance(payer, ResourceType::Wood), payer_before - ResourceAmount::from_units(5));
+        assert_eq!(treasury.balance(recipient, ResourceType::Wood), recipient_before + ResourceAmount::from_units(5));
+
+        // Nothing moves once the recipient is full, and nothing is lost from the payer
+        let delivered = treasury.transfer(payer, recipient, &gift).unwrap();
+        assert!(delivered.is_empty());
+        assert_eq!(treasury.balance(payer, ResourceType::Wood), payer_before - ResourceAmount::from_units(5));
+    }
+}
@@ -3,6 +3,7 @@
 use bevy::prelude::*;
 use serde::{Serialize, Deserialize};
 use std::fmt;
+use crate::world::{DepletionBehavior, NodeLifecycle, RegenerationProfile, TerrainType};
 
 /// Primary resource types in the game
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect, Default)]
@@ -29,6 +30,18 @@ pub enum ResourceType {
 }
 
 impl ResourceType {
+    /// Iterate over every resource type
+    pub fn all() -> impl Iterator<Item = ResourceType> {
+        [
+            ResourceType::Wood,
+            ResourceType::Stone,
+            ResourceType::Iron,
+            ResourceType::Copper,
+            ResourceType::Alloy,
+            ResourceType::Refined,
+        ].iter().copied()
+    }
+
     /// Get the default storage capacity for this resource type
     pub fn default_capacity(&self) -> u32 {
         match self {
@@ -41,6 +54,26 @@ impl ResourceType {
         }
     }
     
+    /// Get how nodes of this resource type regenerate by default
+    pub fn default_regeneration(&self) -> RegenerationProfile {
+        match self {
+            // Trees regrow slowly, but a clear-cut forest is gone for good
+            ResourceType::Wood => RegenerationProfile::Logistic { rate: 0.02 },
+            _ => RegenerationProfile::None,
+        }
+    }
+    
+    /// Get what happens to exhausted nodes of this resource type by default
+    pub fn default_depletion(&self) -> DepletionBehavior {
+        match self {
+            ResourceType::Wood => DepletionBehavior::ConvertTerrain(TerrainType::Grassland),
+            ResourceType::Stone | ResourceType::Iron | ResourceType::Copper => {
+                DepletionBehavior::ConvertTerrain(TerrainType::Quarry)
+            }
+            ResourceType::Alloy | ResourceType::Refined => DepletionBehavior::Despawn,
+        }
+    }
+    
     /// Get the base gather rate for this resource type (per second)
     pub fn base_gather_rate(&self) -> f32 {
         match self {
@@ -80,29 +113,42 @@ pub struct ResourceNode {
     /// Maximum capacity of this node
     pub max_amount: f32,
     
-    /// Whether this node can regenerate over time
-    pub can_regenerate: bool,
+    /// How the node regenerates over time
+    pub regeneration: RegenerationProfile,
     
-    /// Rate at which the node regenerates (per second)
-    pub regen_rate: f32,
+    /// What happens once the node is exhausted and cannot recover
+    pub on_depleted: DepletionBehavior,
+    
+    /// Seconds the node has been fully depleted, if it is
+    pub depleted_for: Option<f32>,
 }
 
 impl ResourceNode {
-    /// Create a new resource node
-    pub fn new(resource_type: ResourceType, amount: f32, can_regenerate: bool) -> Self {
+    /// Create a new resource node using the default lifecycle for its resource type
+    pub fn new(resource_type: ResourceType, amount: f32) -> Self {
         let max_amount = amount;
         Self {
             resource_type,
             amount,
             max_amount,
-            can_regenerate,
-            regen_rate: match resource_type {
-                ResourceType::Wood => 0.1,  // Trees regrow slowly
-                _ => 0.0,  // Other resources don't regenerate by default
-            },
+            regeneration: resource_type.default_regeneration(),
+            on_depleted: resource_type.default_depletion(),
+            depleted_for: None,
         }
     }
     
+    /// Set how the node regenerates
+    pub fn with_regeneration(mut self, regeneration: RegenerationProfile) -> Self {
+        self.regeneration = regeneration;
+        self
+    }
+    
+    /// Set what happens when the node is exhausted
+    pub fn with_depletion(mut self, on_depleted: DepletionBehavior) -> Self {
+        self.on_depleted = on_depleted;
+        self
+    }
+    
     /// Extract resources from the node
     /// Returns the amount actually gathered
     pub fn gather(&mut self, amount: f32) -> f32 {
@@ -116,3 +162,26 @@ impl ResourceNode {
         self.amount <= 0.0
     }
 }
+
+impl NodeLifecycle for ResourceNode {
+    fn amounts(&self) -> (f32, f32) {
+        (self.amount, self.max_amount)
+    }
+
+    fn regeneration(&self) -> RegenerationProfile {
+        self.regeneration
+    }
+
+    fn on_depleted(&self) -> DepletionBehavior {
+        self.on_depleted
+    }
+
+    fn depleted_for(&self) -> Option<f32> {
+        self.depleted_for
+    }
+
+    fn set_progress(&mut self, amount: f32, depleted_for: Option<f32>) {
+        self.amount = amount;
+        self.depleted_for = depleted_for;
+    }
+}
@@ -38,3 +38,15 @@ pub enum GameState {
     /// Credits screen
     Credits,
 }
+
+/// Resource holding the kind of game being played
+#[derive(Resource, Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Reflect)]
+#[reflect(Resource)]
+pub enum GameMode {
+    /// A battle against AI or other players
+    #[default]
+    Skirmish,
+
+    /// A story mission
+    Campaign,
+}
@@ -0,0 +1,230 @@
+//! Supply, which limits how many units each player can field
+//!
+//! Every unit takes up some of its owner's supply, while finished buildings and the modules
+//! attached to mobile bases provide it. However much supply a player builds, they can never
+//! field more than the hard limit of the game mode. Production waits with the next unit at
+//! the front of the queue until there is room for it.
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+use thiserror::Error;
+use crate::buildings::Building;
+use crate::defs::BuildingDefs;
+use crate::mobile_base::MobileBase;
+use crate::player::{Owner, PlayerId};
+use crate::production::advance_production;
+use crate::state::GameMode;
+
+/// Default hard limit on supply in skirmishes
+const DEFAULT_SKIRMISH_LIMIT: u32 = 200;
+
+/// Default hard limit on supply in campaign missions
+const DEFAULT_CAMPAIGN_LIMIT: u32 = 150;
+
+/// Component for units that take up their owner's supply
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct SupplyCost(pub u32);
+
+/// Resource holding the most supply a player may use in each game mode
+#[derive(Resource, Debug, Clone, PartialEq, Reflect)]
+#[reflect(Resource)]
+pub struct SupplyLimits {
+    /// Limit in skirmishes
+    pub skirmish: u32,
+    /// Limit in campaign missions
+    pub campaign: u32,
+}
+
+impl Default for SupplyLimits {
+    fn default() -> Self {
+        Self {
+            skirmish: DEFAULT_SKIRMISH_LIMIT,
+            campaign: DEFAULT_CAMPAIGN_LIMIT,
+        }
+    }
+}
+
+impl SupplyLimits {
+    /// Get the limit for a game mode
+    pub fn limit(&self, mode: GameMode) -> u32 {
+        match mode {
+            GameMode::Skirmish => self.skirmish,
+            GameMode::Campaign => self.campaign,
+        }
+    }
+}
+
+/// Supply a player uses and has available
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+pub struct PlayerSupply {
+    /// Supply taken up by the player's units
+    pub used: u32,
+    /// Supply provided by the player's buildings and modules
+    pub provided: u32,
+}
+
+/// Reasons a player has no room for another unit
+#[derive(Error, Debug, Clone, PartialEq, Eq, Reflect)]
+pub enum SupplyError {
+    /// The player's buildings do not provide enough supply
+    #[error("Not enough supply ({used}/{provided}, the unit needs {cost}); build more supply")]
+    NotEnoughSupply {
+        /// Supply the player uses
+        used: u32,
+        /// Supply the player's buildings provide
+        provided: u32,
+        /// Supply the unit needs
+        cost: u32,
+    },
+
+    /// The player is at the hard limit of the game mode
+    #[error("Supply limit of {limit} reached ({used} used, the unit needs {cost})")]
+    LimitReached {
+        /// Supply the player uses
+        used: u32,
+        /// Hard limit of the game mode
+        limit: u32,
+        /// Supply the unit needs
+        cost: u32,
+    },
+}
+
+/// Resource holding every player's supply, recounted each frame
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct Supply {
+    limit: u32,
+    players: HashMap<PlayerId, PlayerSupply>,
+}
+
+impl Default for Supply {
+    fn default() -> Self {
+        Self::new(DEFAULT_SKIRMISH_LIMIT)
+    }
+}
+
+impl Supply {
+    /// Create an empty count under a hard limit
+    pub fn new(limit: u32) -> Self {
+        Self { limit, players: HashMap::new() }
+    }
+
+    /// Get the hard limit of the game mode
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// Get a player's supply
+    pub fn get(&self, player: PlayerId) -> PlayerSupply {
+        self.players.get(&player).copied().unwrap_or_default()
+    }
+
+    /// Get the most supply a player can use right now
+    pub fn cap(&self, player: PlayerId) -> u32 {
+        self.get(player).provided.min(self.limit)
+    }
+
+    /// Check whether a player has room for a unit taking up `cost` supply
+    pub fn check(&self, player: PlayerId, cost: u32) -> Result<(), SupplyError> {
+        let PlayerSupply { used, provided } = self.get(player);
+        if cost == 0 || used + cost <= self.cap(player) {
+            return Ok(());
+        }
+        if provided < self.limit {
+            Err(SupplyError::NotEnoughSupply { used, provided, cost })
+        } else {
+            Err(SupplyError::LimitReached { used, limit: self.limit, cost })
+        }
+    }
+
+    /// Add supply a player's buildings provide
+    pub fn provide(&mut self, player: PlayerId, amount: u32) {
+        self.players.entry(player).or_default().provided += amount;
+    }
+
+    /// Take up supply for a new unit, so later units in the same frame see it
+    pub fn use_supply(&mut self, player: PlayerId, cost: u32) {
+        self.players.entry(player).or_default().used += cost;
+    }
+}
+
+/// System to recount every player's supply from their units, buildings and modules
+pub fn update_supply(
+    mode: Res<GameMode>,
+    limits: Res<SupplyLimits>,
+    defs: Res<BuildingDefs>,
+    mut supply: ResMut<Supply>,
+    units: Query<(&SupplyCost, &Owner)>,
+    buildings: Query<(&Building, &Owner, Option<&MobileBase>)>,
+) {
+    let mut counted = Supply::new(limits.limit(*mode));
+    for (cost, owner) in units.iter() {
+        counted.use_supply(owner.0, cost.0);
+    }
+    for (building, owner, base) in buildings.iter() {
+        let own = defs.get(&building.def).map_or(0, |def| def.supply);
+        counted.provide(owner.0, own + base.map_or(0, |base| base.effects.supply));
+    }
+    // Only touch the resource when something changed, so readers can rely on change detection
+    if *supply != counted {
+        *supply = counted;
+    }
+}
+
+/// Plugin for supply
+pub struct SupplyPlugin;
+
+impl Plugin for SupplyPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SupplyCost>()
+            .register_type::<SupplyLimits>()
+            .init_resource::<SupplyLimits>()
+            .init_resource::<Supply>()
+            .add_systems(Update, update_supply.before(advance_production));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defs::{BuildingDef, Definition};
+    use crate::mobile_base::ModuleEffects;
+
+    #[test]
+    fn test_supply_counts_providers_and_respects_mode_limit() {
+        let mut world = World::new();
+        world.insert_resource(GameMode::Campaign);
+        world.insert_resource(SupplyLimits { skirmish: 200, campaign: 30 });
+        let defs = BuildingDefs::load_from_file(BuildingDef::FILE).expect("building definitions load");
+        let base_def = defs.get("mobile_base").unwrap().clone();
+        world.insert_resource(defs);
+        world.init_resource::<Supply>();
+        let player = PlayerId(0);
+        let other = PlayerId(1);
+
+        world.spawn((Building { def: "barracks".to_string() }, Owner(player)));
+        world.spawn((Building { def: "research_lab".to_string() }, Owner(player)));
+        world.spawn((Building { def: "supply_depot".to_string() }, Owner(other)));
+        let mut base = MobileBase::new(&base_def);
+        base.effects = ModuleEffects { supply: 15, ..default() };
+        world.spawn((Building { def: base_def.id.clone() }, base, Owner(player)));
+        for _ in 0..9 {
+            world.spawn((SupplyCost(3), Owner(player)));
+        }
+        world.spawn((SupplyCost(2), Owner(other)));
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(update_supply);
+        schedule.run(&mut world);
+
+        // Buildings and attached modules add up, but the campaign limit caps them
+        let supply = world.resource::<Supply>();
+        assert_eq!(supply.get(player), PlayerSupply { used: 27, provided: 45 });
+        assert_eq!(supply.cap(player), 30);
+        assert_eq!(supply.check(player, 3), Ok(()));
+        assert_eq!(supply.check(player, 4), Err(SupplyError::LimitReached { used: 27, limit: 30, cost: 4 }));
+        assert_eq!(supply.get(other), PlayerSupply { used: 2, provided: 15 });
+        assert_eq!(supply.check(other, 14), Err(SupplyError::NotEnoughSupply { used: 2, provided: 15, cost: 14 }));
+        assert_eq!(supply.check(PlayerId(2), 0), Ok(()));
+    }
+}
@@ -0,0 +1,440 @@
+//! Researching technologies and applying what they do
+//!
+//! Technologies are researched as upgrades in the production queues of research buildings,
+//! paid for up front like any other item. Each player's researched technologies add up to a
+//! set of stat modifiers, applied on top of the definition values of everything the player
+//! owns: units and buildings get them as they appear, and everything is recalculated when a
+//! player finishes research. Storage bonuses go through each storage's capacity modifiers, so
+//! they stack with storage modules the usual way.
+//!
+//! Units, buildings and technologies can name technologies they require and the factions
+//! allowed to have them; `check_unlocked` is the one place both are checked.
+
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+use crate::buildings::{Builder, Building};
+use crate::campaign_menu::Faction;
+use crate::combat::{Armor, Health, Weapon};
+use crate::defs::{BuildingDefs, ModifierTarget, Stat, StatModifier, TechnologyDefs, UnitDefs};
+use crate::mobile_base::{apply_module_effects, MobileBase};
+use crate::player::{Owner, PlayerFactions, PlayerId};
+use crate::production::{advance_production, ProductionCompleted, ProductionItem, Unit};
+use crate::resources::{CapacityBonus, CapacityModifier, ModifierSource, ResourceGatherer, ResourceStorage};
+use crate::state::GameState;
+use crate::units::Movement;
+
+/// Resource recording which technologies each player has researched
+#[derive(Resource, Debug, Clone, Default)]
+pub struct Technologies {
+    researched: HashMap<PlayerId, HashSet<String>>,
+}
+
+impl Technologies {
+    /// Check whether a player has researched a technology
+    pub fn is_researched(&self, player: PlayerId, id: &str) -> bool {
+        self.researched.get(&player).is_some_and(|researched| researched.contains(id))
+    }
+
+    /// Iterate over the ids of every technology a player has researched
+    pub fn researched(&self, player: PlayerId) -> impl Iterator<Item = &str> + '_ {
+        self.researched.get(&player).into_iter().flatten().map(String::as_str)
+    }
+
+    /// Record a finished technology, returning whether the player did not have it yet
+    pub fn complete(&mut self, player: PlayerId, id: impl Into<String>) -> bool {
+        self.researched.entry(player).or_default().insert(id.into())
+    }
+}
+
+/// A player finished researching a technology
+#[derive(Event, Debug, Clone)]
+pub struct TechnologyResearched {
+    /// The researching player
+    pub player: PlayerId,
+    /// Id of the technology
+    pub technology: String,
+}
+
+/// Reasons a player cannot build or research something yet
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum LockedError {
+    /// The player's faction cannot have it at all
+    #[error("Not available to the {}", .0.name())]
+    WrongFaction(Faction),
+
+    /// A technology it requires has not been researched
+    #[error("Requires technology '{0}'")]
+    MissingTechnology(String),
+}
+
+/// Check that a player's faction is among `factions` (empty for every faction) and that they
+/// have researched every technology in `requires`
+pub fn check_unlocked(
+    technologies: &Technologies,
+    player_factions: &PlayerFactions,
+    player: PlayerId,
+    factions: &[Faction],
+    requires: &[String],
+) -> Result<(), LockedError> {
+    let faction = player_factions.get(player);
+    if !factions.is_empty() && !factions.contains(&faction) {
+        return Err(LockedError::WrongFaction(faction));
+    }
+    match requires.iter().find(|id| !technologies.is_researched(player, id)) {
+        Some(missing) => Err(LockedError::MissingTechnology(missing.clone())),
+        None => Ok(()),
+    }
+}
+
+/// What a stat modifier is being applied to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subject<'a> {
+    /// A unit, by the id of its definition
+    Unit(&'a str),
+    /// A building, by the id of its definition
+    Building(&'a str),
+}
+
+impl Subject<'_> {
+    /// Check whether a modifier aimed at `target` applies
+    fn is_targeted_by(&self, target: &ModifierTarget) -> bool {
+        match (target, self) {
+            (ModifierTarget::Units, Subject::Unit(_)) | (ModifierTarget::Buildings, Subject::Building(_)) => true,
+            (ModifierTarget::Unit(id), Subject::Unit(def)) | (ModifierTarget::Building(id), Subject::Building(def)) => id == def,
+            _ => false,
+        }
+    }
+}
+
+/// Resource holding the stat modifiers each player's research adds up to
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PlayerModifiers {
+    /// Every modifier of each player, with the id of the technology it comes from
+    players: HashMap<PlayerId, Vec<(String, StatModifier)>>,
+}
+
+impl PlayerModifiers {
+    /// Apply a player's modifiers for a stat of a subject to its base value
+    pub fn apply(&self, player: PlayerId, stat: Stat, subject: Subject, base: f32) -> f32 {
+        let (flat, percent) = self.players.get(&player).into_iter().flatten()
+            .map(|(_, modifier)| modifier)
+            .filter(|modifier| modifier.stat == stat && subject.is_targeted_by(&modifier.target))
+            .fold((0.0, 0.0), |(flat, percent), modifier| (flat + modifier.flat, percent + modifier.percent));
+        ((base + flat) * (1.0 + percent)).max(0.0)
+    }
+
+    /// Get the storage capacity bonus of each of a player's technologies that has one
+    pub fn storage_bonuses(&self, player: PlayerId) -> Vec<CapacityModifier> {
+        let mut bonuses: Vec<CapacityModifier> = Vec::new();
+        let storage = self.players.get(&player).into_iter().flatten()
+            .filter(|(_, modifier)| modifier.stat == Stat::Storage);
+        for (technology, modifier) in storage {
+            let source = ModifierSource::Technology(technology.clone());
+            let flat = modifier.flat.max(0.0).round() as u32;
+            // A storage keeps one modifier per source, so effects of the same technology are summed
+            match bonuses.iter_mut().find(|bonus| bonus.source == source) {
+                Some(existing) => {
+                    existing.bonus.flat += flat;
+                    existing.bonus.percent += modifier.percent;
+                }
+                None => bonuses.push(CapacityModifier {
+                    source,
+                    bonus: CapacityBonus { resource: None, flat, percent: modifier.percent },
+                }),
+            }
+        }
+        bonuses
+    }
+}
+
+/// System to record technologies finished in production queues
+pub fn record_research(
+    mut technologies: ResMut<Technologies>,
+    mut completed_events: EventReader<ProductionCompleted>,
+    mut researched_events: EventWriter<TechnologyResearched>,
+) {
+    for event in completed_events.read() {
+        let ProductionItem::Upgrade(id) = &event.item else { continue };
+        if technologies.complete(event.player, id.clone()) {
+            info!("{} researched '{}'", event.player, id);
+            researched_events.send(TechnologyResearched { player: event.player, technology: id.clone() });
+        }
+    }
+}
+
+/// System to gather the effects of each player's researched technologies
+pub fn update_player_modifiers(
+    defs: Res<TechnologyDefs>,
+    technologies: Res<Technologies>,
+    mut modifiers: ResMut<PlayerModifiers>,
+) {
+    if !defs.is_changed() && !technologies.is_changed() {
+        return;
+    }
+    modifiers.players.clear();
+    for (&player, researched) in technologies.researched.iter() {
+        let mut ids: Vec<&String> = researched.iter().collect();
+        ids.sort_unstable();
+        let effects = ids.into_iter()
+            .filter_map(|id| defs.get(id))
+            .flat_map(|def| def.effects.iter().map(|effect| (def.id.clone(), effect.clone())))
+            .collect();
+        modifiers.players.insert(player, effects);
+    }
+}
+
+/// Units with every stat research can modify
+type ModifiableUnits<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Ref<'static, Unit>,
+        &'static Owner,
+        &'static mut Health,
+        &'static mut Armor,
+        &'static mut Movement,
+        Option<&'static mut Weapon>,
+        Option<&'static mut Builder>,
+    ),
+>;
+
+/// System to apply research to the stats of new units, or of every unit when research finishes
+pub fn apply_unit_modifiers(
+    modifiers: Res<PlayerModifiers>,
+    defs: Res<UnitDefs>,
+    mut units: ModifiableUnits,
+    mut gatherers: Query<(&mut ResourceGatherer, &Owner, Option<&Unit>)>,
+) {
+    let all = modifiers.is_changed() || defs.is_changed();
+    for (unit, owner, mut health, mut armor, mut movement, weapon, builder) in units.iter_mut() {
+        if !all && !unit.is_added() {
+            continue;
+        }
+        let Some(def) = defs.get(&unit.def) else { continue };
+        let apply = |stat: Stat, base: f32| modifiers.apply(owner.0, stat, Subject::Unit(&def.id), base);
+        health.set_max(apply(Stat::MaxHealth, def.health));
+        armor.value = apply(Stat::Armor, def.armor.value);
+        movement.max_speed = apply(Stat::Speed, def.speed);
+        if let (Some(mut weapon), Some(weapon_def)) = (weapon, &def.weapon) {
+            weapon.damage = apply(Stat::Damage, weapon_def.damage);
+            weapon.range = apply(Stat::Range, weapon_def.range);
+        }
+        if let (Some(mut builder), Some(builder_def)) = (builder, &def.builder) {
+            builder.rate = apply(Stat::BuildRate, builder_def.rate);
+            builder.repair_rate = apply(Stat::BuildRate, builder_def.repair_rate);
+        }
+    }
+
+    for (mut gatherer, owner, unit) in gatherers.iter_mut() {
+        if !all && !gatherer.is_added() {
+            continue;
+        }
+        let subject = Subject::Unit(unit.map_or("", |unit| unit.def.as_str()));
+        gatherer.gather_rate = modifiers.apply(owner.0, Stat::GatherRate, subject, gatherer.base_gather_rate);
+    }
+}
+
+/// Buildings other than mobile bases with every stat research can modify
+type ModifiableBuildings<'w, 's> = Query<
+    'w,
+    's,
+    (Ref<'static, Building>, &'static Owner, &'static mut Health, &'static mut Armor, Option<&'static mut Weapon>),
+    Without<MobileBase>,
+>;
+
+/// System to apply research to the stats of new buildings, or of every building when research
+/// finishes; mobile bases get theirs along with their module effects
+pub fn apply_building_modifiers(
+    modifiers: Res<PlayerModifiers>,
+    defs: Res<BuildingDefs>,
+    mut buildings: ModifiableBuildings,
+) {
+    let all = modifiers.is_changed() || defs.is_changed();
+    for (building, owner, mut health, mut armor, weapon) in buildings.iter_mut() {
+        if !all && !building.is_added() {
+            continue;
+        }
+        let Some(def) = defs.get(&building.def) else { continue };
+        let apply = |stat: Stat, base: f32| modifiers.apply(owner.0, stat, Subject::Building(&def.id), base);
+        health.set_max(apply(Stat::MaxHealth, def.health));
+        armor.value = apply(Stat::Armor, def.armor.value);
+        if let (Some(mut weapon), Some(weapon_def)) = (weapon, &def.weapon) {
+            weapon.damage = apply(Stat::Damage, weapon_def.damage);
+            weapon.range = apply(Stat::Range, weapon_def.range);
+        }
+    }
+}
+
+/// System to keep the technology capacity modifiers of every storage in line with its owner's research
+pub fn apply_storage_modifiers(
+    modifiers: Res<PlayerModifiers>,
+    mut storages: Query<(&mut ResourceStorage, &Owner)>,
+) {
+    for (mut storage, owner) in storages.iter_mut() {
+        if !modifiers.is_changed() && !storage.is_added() {
+            continue;
+        }
+        let wanted = modifiers.storage_bonuses(owner.0);
+        let current: Vec<CapacityModifier> = storage.modifiers().iter()
+            .filter(|modifier| matches!(modifier.source, ModifierSource::Technology(_)))
+            .cloned()
+            .collect();
+        if current.len() == wanted.len() && wanted.iter().all(|modifier| current.contains(modifier)) {
+            continue;
+        }
+        for modifier in current {
+            storage.remove_modifiers_from(&modifier.source);
+        }
+        for modifier in wanted {
+            storage.add_modifier(modifier);
+        }
+    }
+}
+
+/// System to forget every player's research when a game ends
+pub fn reset_research(
+    mut technologies: ResMut<Technologies>,
+    mut modifiers: ResMut<PlayerModifiers>,
+) {
+    *technologies = Technologies::default();
+    *modifiers = PlayerModifiers::default();
+}
+
+/// Plugin for technologies and their effects
+pub struct TechnologyPlugin;
+
+impl Plugin for TechnologyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Technologies>()
+            .init_resource::<PlayerModifiers>()
+            .add_event::<TechnologyResearched>()
+            .add_systems(Update, (
+                record_research.after(advance_production),
+                update_player_modifiers.before(apply_module_effects),
+                (apply_unit_modifiers, apply_building_modifiers, apply_storage_modifiers),
+            ).chain())
+            .add_systems(OnExit(GameState::InGame { is_paused: false }), reset_research);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defs::{DefLoadError, Definition, TechnologyDef, UnitDef};
+    use crate::navigation::NavBlocker;
+    use crate::production::{enqueue_production, EnqueueProduction, ProductionError, ProductionQueue};
+    use crate::resources::{spawn_stocked_storage, step_seconds, treasury_test_world, ResourceType};
+    use crate::supply::Supply;
+    use crate::units::Selectable;
+
+    #[test]
+    fn test_research_unlocks_and_modifies_stats() {
+        // Requirements must name known technologies and never lead back to themselves
+        let defs = TechnologyDefs::load_from_file(TechnologyDef::FILE).expect("technology definitions load");
+        assert_eq!(TechnologyDefs::builtin().len(), defs.len());
+        let mut first = defs.get("industrial_mining").unwrap().clone();
+        let mut second = defs.get("automated_mining").unwrap().clone();
+        first.requires = vec![second.id.clone()];
+        let err = TechnologyDefs::from_defs("technologies.ron", vec![first.clone(), second.clone()]).unwrap_err();
+        assert!(matches!(&err, DefLoadError::InvalidField { field, .. } if field == "requires[0]"), "{}", err);
+        first.requires.clear();
+        second.requires = vec!["perpetual_motion".to_string()];
+        let err = TechnologyDefs::from_defs("technologies.ron", vec![first, second]).unwrap_err();
+        assert!(err.to_string().contains("perpetual_motion"), "{}", err);
+
+        let mut world = treasury_test_world();
+        world.insert_resource(defs);
+        world.insert_resource(UnitDefs::load_from_file(UnitDef::FILE).expect("unit definitions load"));
+        world.insert_resource(BuildingDefs::load_from_file(crate::defs::BuildingDef::FILE).expect("building definitions load"));
+        world.init_resource::<Technologies>();
+        world.init_resource::<PlayerModifiers>();
+        world.init_resource::<PlayerFactions>();
+        let mut supply = Supply::default();
+        supply.provide(PlayerId(0), 10);
+        world.insert_resource(supply);
+        world.init_resource::<Events<EnqueueProduction>>();
+        world.init_resource::<Events<ProductionCompleted>>();
+        world.init_resource::<Events<TechnologyResearched>>();
+        let player = PlayerId(0);
+        let storage = spawn_stocked_storage(&mut world, player, &[
+            (ResourceType::Wood, 500),
+            (ResourceType::Stone, 300),
+            (ResourceType::Iron, 200),
+            (ResourceType::Copper, 200),
+        ]);
+        let lab = world.spawn((
+            ProductionQueue::for_building(world.resource::<BuildingDefs>().get("research_lab").unwrap()).unwrap(),
+            Building { def: "research_lab".to_string() },
+            Health::new(600.0),
+            Armor::default(),
+            Owner(player),
+            Selectable::new("research_lab", 20.0),
+            NavBlocker { half_extents: Vec2::splat(20.0) },
+            GlobalTransform::default(),
+        )).id();
+        let barracks = world.spawn((
+            ProductionQueue::new(vec!["gatherer".to_string()]),
+            Owner(player),
+            Selectable::new("barracks", 15.0),
+            GlobalTransform::default(),
+        )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((
+            enqueue_production,
+            advance_production,
+            record_research,
+            update_player_modifiers,
+            (apply_unit_modifiers, apply_building_modifiers, apply_storage_modifiers),
+        ).chain());
+        let queued = |world: &World| world.get::<ProductionQueue>(lab).unwrap().len();
+        let upgrade = |id: &str| ProductionItem::Upgrade(id.to_string());
+
+        // Research needs its prerequisites, and each technology can only be researched once
+        assert_eq!(
+            check_unlocked(world.resource(), world.resource(), player, &[], &["industrial_mining".to_string()]),
+            Err(LockedError::MissingTechnology("industrial_mining".to_string()))
+        );
+        world.send_event(EnqueueProduction { player, building: barracks, item: ProductionItem::Unit("gatherer".to_string()) });
+        world.send_event(EnqueueProduction { player, building: lab, item: upgrade("automated_mining") });
+        world.send_event(EnqueueProduction { player, building: lab, item: upgrade("industrial_mining") });
+        world.send_event(EnqueueProduction { player, building: lab, item: upgrade("industrial_mining") });
+        world.send_event(EnqueueProduction { player, building: lab, item: upgrade("matter_compression") });
+        step_seconds(&mut world, &mut schedule, 11);
+        assert_eq!(queued(&world), 1);
+        // Units trained before the research is done gather at their definition's rate
+        let gatherer = world.query_filtered::<Entity, With<Unit>>().single(&world);
+        assert_eq!(world.get::<ResourceGatherer>(gatherer).unwrap().gather_rate, 1.0);
+        assert_eq!(
+            check_unlocked(world.resource(), world.resource(), player, &[Faction::VoidHarbingers], &[]),
+            Err(LockedError::WrongFaction(Faction::Mechanists))
+        );
+        assert!(ProductionError::from(LockedError::WrongFaction(Faction::Mechanists)).to_string().contains("Mechanists"));
+
+        // Finishing research applies its effects to what the player already has
+        step_seconds(&mut world, &mut schedule, 35);
+        assert!(world.resource::<Technologies>().is_researched(player, "industrial_mining"));
+        assert_eq!(world.resource::<Events<TechnologyResearched>>().len(), 1);
+        assert!((world.get::<ResourceGatherer>(gatherer).unwrap().gather_rate - 1.15).abs() < 1e-4);
+        world.send_event(EnqueueProduction { player, building: lab, item: upgrade("reinforced_foundations") });
+        step_seconds(&mut world, &mut schedule, 61);
+        assert!((world.get::<Health>(lab).unwrap().max - 600.0 * 1.25).abs() < 1e-3);
+        assert!((world.get::<Health>(lab).unwrap().current - 600.0 * 1.25).abs() < 1e-3);
+        assert!((world.get::<Builder>(gatherer).unwrap().rate - 1.1).abs() < 1e-4);
+
+        // Storage bonuses go through the storage's own modifiers
+        world.resource_mut::<PlayerFactions>().set(player, Faction::VoidHarbingers);
+        world.resource_mut::<Technologies>().complete(player, "matter_compression");
+        step_seconds(&mut world, &mut schedule, 1);
+        let capacity = world.get::<ResourceStorage>(storage).unwrap().get_capacity(ResourceType::Wood).to_f64();
+        assert!((capacity - 1000.0 * 1.4).abs() < 1e-3);
+
+        // Research does not carry over into the next game
+        let mut reset = Schedule::default();
+        reset.add_systems(reset_research);
+        reset.run(&mut world);
+        assert_eq!(world.resource::<Technologies>().researched(player).count(), 0);
+        assert_eq!(world.resource::<PlayerModifiers>().storage_bonuses(player), Vec::new());
+    }
+}
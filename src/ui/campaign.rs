@@ -7,7 +7,7 @@ use bevy::{
     },
 };
 use bevy::prelude::in_state;
-use crate::state::GameState;
+use crate::state::{GameMode, GameState};
 use super::button_effect_system;
 use super::components::*;
 use super::components::ScrollableList;
@@ -358,6 +358,7 @@ fn handle_campaign_button_interactions(
     start_button_query: Query<&StartCampaignButton>,
     mission_button_query: Query<&MissionButton>,
     mut campaign_state: ResMut<CampaignState>,
+    mut game_mode: ResMut<GameMode>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
     for (interaction, entity, mut bg_color, effect) in interaction_query.iter_mut() {
@@ -385,6 +386,7 @@ fn handle_campaign_button_interactions(
         if start_button_query.get(entity).is_ok() {
             if let Some(_mission_id) = campaign_state.selected_mission {
                 // Start the selected mission
+                *game_mode = GameMode::Campaign;
                 next_state.set(GameState::InGame { is_paused: false });
             }
             continue;
@@ -11,6 +11,11 @@ pub struct MainMenuMarker;
 #[reflect(Component)]
 pub struct NewGameButton;
 
+/// Button cycling through the factions the player can pick
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct FactionButton;
+
 #[derive(Component, Debug, Clone, Reflect)]
 #[reflect(Component)]
 pub struct LoadGameButton;
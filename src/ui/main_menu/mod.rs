@@ -16,7 +16,7 @@ impl Plugin for MainMenuPlugin {
             .add_systems(OnEnter(GameState::MainMenu), systems::setup_main_menu)
             .add_systems(
                 Update,
-                systems::handle_menu_button_interactions
+                (systems::handle_menu_button_interactions, systems::handle_faction_button)
                     .run_if(in_state(GameState::MainMenu)),
             )
             .add_systems(OnExit(GameState::MainMenu), systems::cleanup_menu::<components::MainMenuMarker>);
@@ -7,21 +7,29 @@ use bevy::{
         Val, UiRect, Style, FlexDirection, JustifyContent, AlignItems, 
     },
 };
-use crate::state::GameState;
+use crate::campaign_menu::Faction;
+use crate::player::ChosenFaction;
+use crate::state::{GameMode, GameState};
 use crate::ui::components::ButtonHoverEffect;
 use crate::ui::theme::Theme;
 use crate::ui::theme::ButtonTheme;
 
 // Import button components from the main menu components module
 use super::components::{
-    NewGameButton, LoadGameButton, MultiplayerButton, 
+    NewGameButton, FactionButton, LoadGameButton, MultiplayerButton, 
     SettingsButton, CreditsButton, ExitButton, MainMenuMarker
 };
 
+// Get the text shown on the faction button
+fn faction_button_text(faction: Faction) -> String {
+    format!("FACTION: {}", faction.name().to_uppercase())
+}
+
 pub fn setup_main_menu(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     theme: Res<Theme>,
+    chosen_faction: Res<ChosenFaction>,
 ) {
     // UI camera is already set up by the CameraPlugin
     
@@ -119,6 +127,15 @@ pub fn setup_main_menu(
                                 NewGameButton,
                             );
 
+                            // Faction button, cycling through the factions on click
+                            spawn_menu_button(
+                                parent,
+                                &asset_server,
+                                &theme,
+                                &faction_button_text(chosen_faction.0),
+                                FactionButton,
+                            );
+
                             // Load Game button
                             spawn_menu_button(
                                 parent,
@@ -260,6 +277,7 @@ pub fn handle_menu_button_interactions(
         Option<&ExitButton>,
     ), (Changed<Interaction>, With<Button>)>,
     mut next_state: ResMut<NextState<GameState>>,
+    mut game_mode: ResMut<GameMode>,
     mut app_exit_events: EventWriter<AppExit>,
 ) {
     for (interaction, mut bg_color, effect, new_game, load_game, multiplayer, settings, credits, exit) in interaction_query.iter_mut() {
@@ -277,6 +295,7 @@ pub fn handle_menu_button_interactions(
         
         // Handle button actions based on button type
         if new_game.is_some() {
+            *game_mode = GameMode::Skirmish;
             next_state.set(GameState::InGame { is_paused: false });
         } else if load_game.is_some() {
             println!("Load game clicked");
@@ -293,6 +312,31 @@ pub fn handle_menu_button_interactions(
     }
 }
 
+/// Faction buttons that were just pressed or hovered, with their text
+type FactionButtons<'w, 's> =
+    Query<'w, 's, (&'static Interaction, &'static Children), (Changed<Interaction>, With<FactionButton>)>;
+
+/// System to pick the next faction when the faction button is pressed
+pub fn handle_faction_button(
+    button_query: FactionButtons,
+    mut text_query: Query<&mut Text>,
+    mut chosen_faction: ResMut<ChosenFaction>,
+) {
+    for (interaction, children) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let factions: Vec<Faction> = Faction::iter().collect();
+        let index = factions.iter().position(|&faction| faction == chosen_faction.0).unwrap_or(0);
+        chosen_faction.0 = factions[(index + 1) % factions.len()];
+        for &child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(child) {
+                text.sections[0].value = faction_button_text(chosen_faction.0);
+            }
+        }
+    }
+}
+
 pub fn cleanup_menu<T: Component>(
     mut commands: Commands,
     query: Query<Entity, With<T>>,
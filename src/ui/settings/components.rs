@@ -1,4 +1,6 @@
 use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::units::{CommandHotkeys, ControlGroupBindings, CONTROL_GROUP_COUNT};
 
 // Marker component for the settings menu
 #[derive(Component, Debug, Default)]
@@ -92,11 +94,142 @@ impl Default for VideoSettingControl {
 pub struct SettingsState {
     pub current_tab: u8,
     pub video_settings: VideoSettings,
+    pub control_settings: ControlSettings,
+    /// Binding waiting for the player to press its new key
+    pub rebinding: Option<KeyBinding>,
     // TODO: Add other settings state fields here as needed
 }
 
+// Control settings, copied to the in-game bindings when applied
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlSettings {
+    pub control_groups: ControlGroupBindings,
+    pub commands: CommandHotkeys,
+}
+
+/// Keys held as modifiers or used to cancel, which can't be bound
+pub const RESERVED_KEYS: [KeyCode; 5] = [
+    KeyCode::Escape,
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+];
+
+impl ControlSettings {
+    /// Bind a key, giving the binding's old key to whichever binding already used it
+    ///
+    /// Returns the binding that was swapped, if any
+    pub fn rebind(&mut self, binding: KeyBinding, key: KeyCode) -> Option<KeyBinding> {
+        let old_key = binding.key(self);
+        let conflict = KeyBinding::all().find(|&other| other != binding && other.key(self) == key);
+        *binding.key_mut(self) = key;
+        if let Some(other) = conflict {
+            *other.key_mut(self) = old_key;
+        }
+        conflict
+    }
+}
+
+// A rebindable key, on the button that changes it
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyBinding {
+    ControlGroup(usize),
+    CycleFocus,
+    AttackMove,
+    Patrol,
+    Stop,
+    Hold,
+    CycleFormation,
+    CycleStance,
+    Build,
+    CancelConstruction,
+    Train,
+    LogisticsOverlay,
+    PowerOverlay,
+}
+
+impl KeyBinding {
+    /// Every rebindable key, in display order
+    pub fn all() -> impl Iterator<Item = KeyBinding> {
+        (0..CONTROL_GROUP_COUNT).map(KeyBinding::ControlGroup).chain([
+            KeyBinding::CycleFocus,
+            KeyBinding::AttackMove,
+            KeyBinding::Patrol,
+            KeyBinding::Stop,
+            KeyBinding::Hold,
+            KeyBinding::CycleFormation,
+            KeyBinding::CycleStance,
+            KeyBinding::Build,
+            KeyBinding::CancelConstruction,
+            KeyBinding::Train,
+            KeyBinding::LogisticsOverlay,
+            KeyBinding::PowerOverlay,
+        ])
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            KeyBinding::ControlGroup(group) => format!("Control Group {}", group + 1),
+            KeyBinding::CycleFocus => "Cycle Selected Type".to_string(),
+            KeyBinding::AttackMove => "Attack Move".to_string(),
+            KeyBinding::Patrol => "Patrol".to_string(),
+            KeyBinding::Stop => "Stop".to_string(),
+            KeyBinding::Hold => "Hold Position".to_string(),
+            KeyBinding::CycleFormation => "Cycle Formation".to_string(),
+            KeyBinding::CycleStance => "Cycle Stance".to_string(),
+            KeyBinding::Build => "Place Building".to_string(),
+            KeyBinding::CancelConstruction => "Cancel Construction".to_string(),
+            KeyBinding::Train => "Train Unit".to_string(),
+            KeyBinding::LogisticsOverlay => "Logistics Overlay".to_string(),
+            KeyBinding::PowerOverlay => "Power Overlay".to_string(),
+        }
+    }
+
+    pub fn key_mut<'a>(&self, controls: &'a mut ControlSettings) -> &'a mut KeyCode {
+        match *self {
+            KeyBinding::ControlGroup(group) => &mut controls.control_groups.groups[group],
+            KeyBinding::CycleFocus => &mut controls.control_groups.cycle_focus,
+            KeyBinding::AttackMove => &mut controls.commands.attack_move,
+            KeyBinding::Patrol => &mut controls.commands.patrol,
+            KeyBinding::Stop => &mut controls.commands.stop,
+            KeyBinding::Hold => &mut controls.commands.hold,
+            KeyBinding::CycleFormation => &mut controls.commands.cycle_formation,
+            KeyBinding::CycleStance => &mut controls.commands.cycle_stance,
+            KeyBinding::Build => &mut controls.commands.build,
+            KeyBinding::CancelConstruction => &mut controls.commands.cancel_construction,
+            KeyBinding::Train => &mut controls.commands.train,
+            KeyBinding::LogisticsOverlay => &mut controls.commands.logistics_overlay,
+            KeyBinding::PowerOverlay => &mut controls.commands.power_overlay,
+        }
+    }
+
+    pub fn key(&self, controls: &ControlSettings) -> KeyCode {
+        match *self {
+            KeyBinding::ControlGroup(group) => controls.control_groups.groups[group],
+            KeyBinding::CycleFocus => controls.control_groups.cycle_focus,
+            KeyBinding::AttackMove => controls.commands.attack_move,
+            KeyBinding::Patrol => controls.commands.patrol,
+            KeyBinding::Stop => controls.commands.stop,
+            KeyBinding::Hold => controls.commands.hold,
+            KeyBinding::CycleFormation => controls.commands.cycle_formation,
+            KeyBinding::CycleStance => controls.commands.cycle_stance,
+            KeyBinding::Build => controls.commands.build,
+            KeyBinding::CancelConstruction => controls.commands.cancel_construction,
+            KeyBinding::Train => controls.commands.train,
+            KeyBinding::LogisticsOverlay => controls.commands.logistics_overlay,
+            KeyBinding::PowerOverlay => controls.commands.power_overlay,
+        }
+    }
+}
+
+// Marker for the text showing a binding's key
+#[derive(Component, Debug, Clone, Copy)]
+pub struct KeyBindingLabel(pub KeyBinding);
+
 // Video settings
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct VideoSettings {
     pub display_mode: DisplayMode,
     pub resolution: (u32, u32),
@@ -108,7 +241,7 @@ pub struct VideoSettings {
     pub ui_scale: f32,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect, Serialize, Deserialize)]
 #[reflect(Component)]
 pub enum DisplayMode {
     Windowed,
@@ -122,7 +255,7 @@ impl Default for DisplayMode {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect, Serialize, Deserialize)]
 #[reflect(Component)]
 pub enum GraphicsQuality {
     Low,
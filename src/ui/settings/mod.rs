@@ -2,6 +2,7 @@
 
 // Module declarations
 pub mod components;
+pub mod persistence;
 pub mod styles;
 mod systems;  // Don't export systems directly
 
@@ -13,8 +14,9 @@ pub use components::{
     SettingsState, SettingsTab, SettingsMenuMarker, SettingType, VideoSettingControl,
     VideoSettings, SettingControl, TabButton, TabContent, ApplyButton, ResetButton, BackButton,
     SettingsChangedEvent, ApplySettingsEvent, ResetSettingsEvent, BackToMenuEvent,
-    DisplayMode, GraphicsQuality
+    DisplayMode, GraphicsQuality, ControlSettings, KeyBinding, KeyBindingLabel, RESERVED_KEYS
 };
+pub use persistence::{SavedSettings, SaveSettingsError, SETTINGS_FILE};
 pub use styles::*;
 
 /// Setup function to be called by the main app's plugin
@@ -32,13 +34,19 @@ pub fn setup_settings_ui(app: &mut App) {
                 fps_limit: Some(60),
                 ui_scale: 1.0,
             },
+            control_settings: ControlSettings::default(),
+            rebinding: None,
         })
+        .add_systems(Startup, systems::load_saved_settings)
         .add_systems(OnEnter(GameState::Settings), systems::setup_settings_menu)
         .add_systems(
             Update,
             (
                 systems::handle_settings_button_interactions,
                 systems::handle_settings_changes,
+                systems::handle_key_binding_buttons,
+                systems::capture_key_binding,
+                systems::update_key_binding_labels,
             )
             .run_if(in_state(GameState::Settings)),
         )
@@ -0,0 +1,104 @@
+//! Keeping settings between sessions
+//!
+//! Applied settings are written to a RON file next to the game and read back on startup.
+
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+use crate::data_file::{read_data_file, DataFileError};
+use super::components::{ControlSettings, SettingsState, VideoSettings};
+
+/// File the applied settings are kept in
+pub const SETTINGS_FILE: &str = "./settings.ron";
+
+/// Errors that can occur when saving settings
+#[derive(Error, Debug)]
+pub enum SaveSettingsError {
+    /// The settings could not be written as RON
+    #[error("Failed to serialize settings: {0}")]
+    Serialize(#[from] ron::Error),
+
+    /// The file could not be written
+    #[error("Failed to write {0}: {1}")]
+    Io(String, #[source] std::io::Error),
+}
+
+/// Settings as written to the settings file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedSettings {
+    /// Display and rendering settings
+    pub video: VideoSettings,
+    /// Key bindings
+    #[serde(default)]
+    pub controls: ControlSettings,
+}
+
+impl SavedSettings {
+    /// Take the settings to save from the settings menu
+    pub fn from_state(state: &SettingsState) -> Self {
+        Self {
+            video: state.video_settings,
+            controls: state.control_settings.clone(),
+        }
+    }
+
+    /// Copy loaded settings into the settings menu
+    pub fn apply_to(&self, state: &mut SettingsState) {
+        state.video_settings = self.video;
+        state.control_settings = self.controls.clone();
+    }
+
+    /// Read settings, or `None` if nothing has been saved yet
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>, DataFileError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+        read_data_file(path).map(Some)
+    }
+
+    /// Write settings, replacing any saved before
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveSettingsError> {
+        let path = path.as_ref();
+        let serialized = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, serialized).map_err(|e| SaveSettingsError::Io(path.display().to_string(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::KeyCode;
+    use crate::ui::settings::KeyBinding;
+
+    #[test]
+    fn test_rebinding_swaps_and_round_trips() {
+        let mut state = SettingsState::default();
+
+        // Taking a key from another binding hands that binding the old key
+        let swapped = state.control_settings.rebind(KeyBinding::Stop, KeyCode::Digit1);
+        assert_eq!(swapped, Some(KeyBinding::ControlGroup(0)));
+        assert_eq!(KeyBinding::Stop.key(&state.control_settings), KeyCode::Digit1);
+        assert_eq!(KeyBinding::ControlGroup(0).key(&state.control_settings), KeyCode::KeyX);
+        assert_eq!(state.control_settings.rebind(KeyBinding::Hold, KeyCode::KeyJ), None);
+
+        let mut keys: Vec<KeyCode> = KeyBinding::all().map(|binding| binding.key(&state.control_settings)).collect();
+        let count = keys.len();
+        keys.sort_by_key(|key| format!("{:?}", key));
+        keys.dedup();
+        assert_eq!(keys.len(), count, "every binding keeps a key of its own");
+
+        let path = std::env::temp_dir().join(format!("strategyforge-settings-{}.ron", std::process::id()));
+        assert!(SavedSettings::load(&path).unwrap().is_none());
+        let saved = SavedSettings::from_state(&state);
+        saved.save(&path).unwrap();
+        let loaded = SavedSettings::load(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, saved);
+
+        let mut restored = SettingsState::default();
+        loaded.apply_to(&mut restored);
+        assert_eq!(restored.control_settings, state.control_settings);
+    }
+}
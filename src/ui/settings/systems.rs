@@ -3,8 +3,10 @@ use bevy::window::{PrimaryWindow, WindowMode};
 use crate::state::GameState;
 use super::{
     SettingsState, SettingControl, SettingType, VideoSettingControl,
-    TabButton, BackButton, ApplyButton, ResetButton, TabContent, SettingsMenuMarker
+    TabButton, BackButton, ApplyButton, ResetButton, TabContent, SettingsMenuMarker,
+    ControlSettings, KeyBinding, KeyBindingLabel, RESERVED_KEYS, SavedSettings, SETTINGS_FILE
 };
+use crate::units::{CommandHotkeys, ControlGroupBindings};
 use super::components::{DisplayMode, GraphicsQuality};
 use bevy::ui::BackgroundColor;
 use bevy::window::WindowResolution;
@@ -176,11 +178,18 @@ pub(crate) fn setup_settings_menu(
                             ..default()
                         })
                         .with_children(|tab_content| {
-                            // This will be populated based on the selected tab
-                            match settings_state.current_tab {
-                                0 => create_video_settings_tab(tab_content, &asset_server, &settings_state),
-                                _ => {}
-                            }
+                            // Tabs with content are built up front and shown or hidden by the tab buttons
+                            let tab_style = |tab: u8| Style {
+                                display: if settings_state.current_tab == tab { Display::Flex } else { Display::None },
+                                width: Val::Percent(100.0),
+                                ..default()
+                            };
+                            tab_content
+                                .spawn((NodeBundle { style: tab_style(0), ..default() }, TabContent::new(0)))
+                                .with_children(|tab| create_video_settings_tab(tab, &asset_server, &settings_state));
+                            tab_content
+                                .spawn((NodeBundle { style: tab_style(2), ..default() }, TabContent::new(2)))
+                                .with_children(|tab| create_controls_settings_tab(tab, &asset_server, &settings_state));
                         });
 
                     // Bottom action buttons with proper spacing
@@ -375,6 +384,85 @@ fn create_video_settings_tab(
 
 
 
+// Helper function to create controls settings tab
+fn create_controls_settings_tab(
+    parent: &mut ChildBuilder,
+    asset_server: &Res<AssetServer>,
+    settings_state: &SettingsState,
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                width: Val::Percent(100.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|content| {
+            for binding in KeyBinding::all() {
+                content
+                    .spawn((
+                        NodeBundle {
+                            style: Style {
+                                flex_direction: FlexDirection::Row,
+                                align_items: AlignItems::Center,
+                                justify_content: JustifyContent::SpaceBetween,
+                                width: Val::Percent(100.0),
+                                margin: UiRect::vertical(Val::Px(4.0)),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        SettingControl::new(SettingType::Controls),
+                    ))
+                    .with_children(|row| {
+                        // Label
+                        row.spawn(TextBundle::from_section(
+                            binding.label(),
+                            regular_text_style(asset_server, 18.0),
+                        ));
+
+                        // Button showing the bound key, click to rebind
+                        row.spawn((
+                            ButtonBundle {
+                                style: Style {
+                                    min_width: Val::Px(140.0),
+                                    padding: UiRect::axes(Val::Px(12.0), Val::Px(6.0)),
+                                    justify_content: JustifyContent::Center,
+                                    ..default()
+                                },
+                                background_color: BackgroundColor(BUTTON_COLOR),
+                                ..default()
+                            },
+                            binding,
+                        ))
+                        .with_children(|button| {
+                            button.spawn((
+                                TextBundle::from_section(
+                                    key_binding_text(binding, settings_state),
+                                    regular_text_style(asset_server, 16.0),
+                                ),
+                                KeyBindingLabel(binding),
+                            ));
+                        });
+                    });
+            }
+        });
+}
+
+// Get the text shown on a key binding button
+fn key_binding_text(binding: KeyBinding, settings_state: &SettingsState) -> String {
+    if settings_state.rebinding == Some(binding) {
+        return "Press a key...".to_string();
+    }
+    let name = format!("{:?}", binding.key(&settings_state.control_settings));
+    name.strip_prefix("Key")
+        .or_else(|| name.strip_prefix("Digit"))
+        .unwrap_or(&name)
+        .to_string()
+}
+
 // Handle button interactions
 pub(crate) fn handle_settings_button_interactions(
     interaction_query: Query<(&Interaction, Entity), (Changed<Interaction>, With<Button>)>,
@@ -382,9 +470,11 @@ pub(crate) fn handle_settings_button_interactions(
     back_button_query: Query<&BackButton>,
     apply_button_query: Query<&ApplyButton>,
     reset_button_query: Query<&ResetButton>,
-    mut tab_content_query: Query<(&TabContent, &mut Visibility)>,
+    mut tab_content_query: Query<(&TabContent, &mut Visibility, &mut Style)>,
     mut settings_state: ResMut<SettingsState>,
     mut game_state: ResMut<NextState<GameState>>,
+    mut control_group_bindings: ResMut<ControlGroupBindings>,
+    mut command_hotkeys: ResMut<CommandHotkeys>,
 ) {
     for (interaction, entity) in interaction_query.iter() {
         if *interaction == Interaction::Pressed {
@@ -393,12 +483,15 @@ pub(crate) fn handle_settings_button_interactions(
                 settings_state.current_tab = tab_button.tab;
                 
                 // Update tab content visibility
-                for (content, mut visibility) in tab_content_query.iter_mut() {
-                    *visibility = if content.tab == tab_button.tab {
+                for (content, mut visibility, mut style) in tab_content_query.iter_mut() {
+                    let is_selected = content.tab == tab_button.tab;
+                    *visibility = if is_selected {
                         Visibility::Visible
                     } else {
                         Visibility::Hidden
                     };
+                    // Hidden tabs must not take up space in the layout either
+                    style.display = if is_selected { Display::Flex } else { Display::None };
                 }
                 continue;
             }
@@ -411,14 +504,21 @@ pub(crate) fn handle_settings_button_interactions(
 
             // Handle apply button
             if apply_button_query.get(entity).is_ok() {
-                // TODO: Apply settings
-                info!("Settings applied");
+                // TODO: Apply video settings
+                *control_group_bindings = settings_state.control_settings.control_groups.clone();
+                *command_hotkeys = settings_state.control_settings.commands.clone();
+                match SavedSettings::from_state(&settings_state).save(SETTINGS_FILE) {
+                    Ok(()) => info!("Settings applied"),
+                    Err(err) => warn!("Settings applied but not saved: {}", err),
+                }
                 continue;
             }
 
             // Handle reset button
             if reset_button_query.get(entity).is_ok() {
-                // TODO: Reset settings to defaults
+                // TODO: Reset video settings to defaults
+                settings_state.control_settings = ControlSettings::default();
+                settings_state.rebinding = None;
                 info!("Settings reset to defaults");
                 continue;
             }
@@ -540,6 +640,87 @@ pub(crate) fn handle_settings_changes(
     }
 }
 
+// Key binding buttons that were just clicked or hovered
+type KeyBindingInteractions<'w, 's> =
+    Query<'w, 's, (&'static Interaction, &'static KeyBinding), (Changed<Interaction>, With<Button>)>;
+
+// Start rebinding a key when its button is clicked
+pub(crate) fn handle_key_binding_buttons(
+    interaction_query: KeyBindingInteractions,
+    mut button_query: Query<(&KeyBinding, &mut BackgroundColor)>,
+    mut settings_state: ResMut<SettingsState>,
+) {
+    for (interaction, binding) in interaction_query.iter() {
+        if *interaction == Interaction::Pressed {
+            settings_state.rebinding = Some(*binding);
+        }
+    }
+    if settings_state.is_changed() {
+        for (binding, mut background) in button_query.iter_mut() {
+            background.0 = if settings_state.rebinding == Some(*binding) {
+                BUTTON_SELECTED_COLOR
+            } else {
+                BUTTON_COLOR
+            };
+        }
+    }
+}
+
+// Assign the next key pressed to the binding being changed
+pub(crate) fn capture_key_binding(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut settings_state: ResMut<SettingsState>,
+) {
+    let Some(binding) = settings_state.rebinding else {
+        return;
+    };
+    let Some(&key) = keyboard_input.get_just_pressed().next() else {
+        return;
+    };
+    // Escape cancels without changing the binding; modifiers are ignored
+    if key == KeyCode::Escape {
+        settings_state.rebinding = None;
+        return;
+    }
+    if RESERVED_KEYS.contains(&key) {
+        return;
+    }
+    if let Some(swapped) = settings_state.control_settings.rebind(binding, key) {
+        info!("{} now uses the key {} had", swapped.label(), binding.label());
+    }
+    settings_state.rebinding = None;
+}
+
+// Restore the settings applied in an earlier session
+pub(crate) fn load_saved_settings(
+    mut settings_state: ResMut<SettingsState>,
+    mut control_group_bindings: ResMut<ControlGroupBindings>,
+    mut command_hotkeys: ResMut<CommandHotkeys>,
+) {
+    match SavedSettings::load(SETTINGS_FILE) {
+        Ok(Some(saved)) => {
+            saved.apply_to(&mut settings_state);
+            *control_group_bindings = saved.controls.control_groups;
+            *command_hotkeys = saved.controls.commands;
+        }
+        Ok(None) => {}
+        Err(err) => warn!("Using default settings: {}", err),
+    }
+}
+
+// Keep the key binding buttons showing the current keys
+pub(crate) fn update_key_binding_labels(
+    settings_state: Res<SettingsState>,
+    mut label_query: Query<(&KeyBindingLabel, &mut Text)>,
+) {
+    if !settings_state.is_changed() {
+        return;
+    }
+    for (label, mut text) in label_query.iter_mut() {
+        text.sections[0].value = key_binding_text(label.0, &settings_state);
+    }
+}
+
 pub fn cleanup_menu<T: Component>(
     mut commands: Commands,
     query: Query<Entity, With<T>>,
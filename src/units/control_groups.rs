@@ -0,0 +1,335 @@
+//! Control groups and selection hotkeys
+//!
+//! Ctrl plus a group key stores the selection, shift plus the key adds to the group and the
+//! key alone recalls it. Recalling the same group twice in quick succession centres the
+//! camera on it. Tab cycles which unit type within the selection has focus.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::camera_controls::GameCamera;
+use crate::player::{LocalPlayer, Owner};
+use super::{Selectable, Selected, SelectionChanged};
+
+/// Number of control groups
+pub const CONTROL_GROUP_COUNT: usize = 10;
+
+/// Resource holding the keys for control groups and selection
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlGroupBindings {
+    /// Key for each control group, in group order
+    pub groups: [KeyCode; CONTROL_GROUP_COUNT],
+    /// Cycles which unit type within the selection has focus
+    pub cycle_focus: KeyCode,
+    /// Seconds between two recalls of a group for the camera to jump to it
+    pub double_tap_seconds: f32,
+}
+
+impl Default for ControlGroupBindings {
+    fn default() -> Self {
+        Self {
+            groups: [
+                KeyCode::Digit1,
+                KeyCode::Digit2,
+                KeyCode::Digit3,
+                KeyCode::Digit4,
+                KeyCode::Digit5,
+                KeyCode::Digit6,
+                KeyCode::Digit7,
+                KeyCode::Digit8,
+                KeyCode::Digit9,
+                KeyCode::Digit0,
+            ],
+            cycle_focus: KeyCode::Tab,
+            double_tap_seconds: 0.3,
+        }
+    }
+}
+
+/// Resource holding the local player's control groups
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ControlGroups {
+    groups: [Vec<Entity>; CONTROL_GROUP_COUNT],
+    last_recall: Option<(usize, f64)>,
+}
+
+impl ControlGroups {
+    /// Get the entities in a group
+    pub fn get(&self, group: usize) -> &[Entity] {
+        self.groups.get(group).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Replace the entities in a group
+    pub fn set(&mut self, group: usize, mut entities: Vec<Entity>) {
+        if let Some(members) = self.groups.get_mut(group) {
+            entities.sort();
+            entities.dedup();
+            *members = entities;
+        }
+    }
+
+    /// Add entities to a group
+    pub fn add(&mut self, group: usize, entities: impl IntoIterator<Item = Entity>) {
+        if let Some(members) = self.groups.get(group) {
+            let mut merged = members.clone();
+            merged.extend(entities);
+            self.set(group, merged);
+        }
+    }
+
+    /// Remove an entity from every group
+    pub fn remove_entity(&mut self, entity: Entity) {
+        for members in &mut self.groups {
+            members.retain(|&member| member != entity);
+        }
+    }
+
+    /// Remove every group
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Resource holding which unit type within the selection has focus, for the HUD
+#[derive(Resource, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelectionFocus {
+    /// `Selectable::kind` of the focused units
+    pub kind: Option<String>,
+}
+
+/// The local player's selection and the selectable units control groups can hold
+#[derive(SystemParam)]
+pub struct GroupCandidates<'w, 's> {
+    local_player: Res<'w, LocalPlayer>,
+    selected: Query<'w, 's, (Entity, &'static Owner), With<Selected>>,
+    units: Query<'w, 's, (&'static Owner, &'static GlobalTransform, Has<Selected>), With<Selectable>>,
+}
+
+/// System to bind, extend and recall control groups
+pub fn handle_control_group_input(
+    mut commands: Commands,
+    time: Res<Time>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    bindings: Res<ControlGroupBindings>,
+    mut groups: ResMut<ControlGroups>,
+    candidates: GroupCandidates,
+    mut camera_query: Query<&mut GameCamera>,
+) {
+    let GroupCandidates { local_player, selected, units } = candidates;
+    let Some(group) = bindings.groups.iter().position(|&key| keyboard_input.just_pressed(key)) else { return };
+    let own_selection = || {
+        selected.iter()
+            .filter(|(_, owner)| owner.0 == local_player.0)
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>()
+    };
+
+    if keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        groups.set(group, own_selection());
+        groups.last_recall = None;
+        return;
+    }
+    if keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        groups.add(group, own_selection());
+        groups.last_recall = None;
+        return;
+    }
+
+    // Recall the group, replacing the selection
+    let members: Vec<Entity> = groups.get(group).iter()
+        .copied()
+        .filter(|&entity| units.get(entity).is_ok_and(|(owner, ..)| owner.0 == local_player.0))
+        .collect();
+    if members.is_empty() {
+        return;
+    }
+    for (entity, _) in selected.iter() {
+        if !members.contains(&entity) {
+            commands.entity(entity).remove::<Selected>();
+        }
+    }
+    for &entity in &members {
+        if units.get(entity).is_ok_and(|(.., is_selected)| !is_selected) {
+            commands.entity(entity).insert(Selected);
+        }
+    }
+
+    // A second recall in quick succession centres the camera on the group
+    let now = time.elapsed_seconds_f64();
+    let double_tap = groups.last_recall
+        .is_some_and(|(last, at)| last == group && now - at <= bindings.double_tap_seconds as f64);
+    groups.last_recall = Some((group, now));
+    if double_tap {
+        let positions: Vec<Vec2> = members.iter()
+            .filter_map(|&entity| units.get(entity).ok())
+            .map(|(_, transform, _)| transform.translation().truncate())
+            .collect();
+        let centre = positions.iter().sum::<Vec2>() / positions.len() as f32;
+        for mut camera in camera_query.iter_mut() {
+            camera.target_position.x = centre.x;
+            camera.target_position.y = centre.y;
+        }
+        groups.last_recall = None;
+    }
+}
+
+/// System to cycle the focused unit type within the selection
+pub fn cycle_selection_focus(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    bindings: Res<ControlGroupBindings>,
+    selected: Query<&Selectable, With<Selected>>,
+    mut focus: ResMut<SelectionFocus>,
+) {
+    if !keyboard_input.just_pressed(bindings.cycle_focus) {
+        return;
+    }
+    let mut kinds: Vec<&str> = selected.iter().map(|selectable| selectable.kind.as_str()).collect();
+    kinds.sort_unstable();
+    kinds.dedup();
+    let next = match focus.kind.as_deref().and_then(|kind| kinds.iter().position(|&other| other == kind)) {
+        Some(index) => kinds[(index + 1) % kinds.len()],
+        None => match kinds.first() {
+            Some(&kind) => kind,
+            None => return,
+        },
+    };
+    focus.kind = Some(next.to_string());
+}
+
+/// System to keep the focused unit type within the selection when it changes
+pub fn update_selection_focus(
+    mut changed_events: EventReader<SelectionChanged>,
+    selected: Query<&Selectable, With<Selected>>,
+    mut focus: ResMut<SelectionFocus>,
+) {
+    if changed_events.read().last().is_none() {
+        return;
+    }
+    let mut kinds: Vec<&str> = selected.iter().map(|selectable| selectable.kind.as_str()).collect();
+    kinds.sort_unstable();
+    let still_selected = focus.kind.as_deref().is_some_and(|kind| kinds.contains(&kind));
+    if !still_selected {
+        let first = kinds.first().map(|kind| kind.to_string());
+        focus.set_if_neq(SelectionFocus { kind: first });
+    }
+}
+
+/// System to drop despawned entities and entities that changed hands from control groups
+pub fn prune_control_groups(
+    mut groups: ResMut<ControlGroups>,
+    mut removed: RemovedComponents<Selectable>,
+    changed_owners: Query<(Entity, &Owner), Changed<Owner>>,
+    local_player: Res<LocalPlayer>,
+) {
+    for entity in removed.read() {
+        groups.remove_entity(entity);
+    }
+    for (entity, owner) in changed_owners.iter() {
+        if owner.0 != local_player.0 {
+            groups.remove_entity(entity);
+        }
+    }
+}
+
+/// System to clear control groups when leaving a game
+pub fn clear_control_groups(mut groups: ResMut<ControlGroups>, mut focus: ResMut<SelectionFocus>) {
+    groups.clear();
+    *focus = SelectionFocus::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::PlayerId;
+
+    #[test]
+    fn test_bind_and_recall_group() {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.insert_resource(ButtonInput::<KeyCode>::default());
+        world.insert_resource(LocalPlayer(PlayerId(0)));
+        world.init_resource::<ControlGroupBindings>();
+        world.init_resource::<ControlGroups>();
+        let unit = |world: &mut World, player: u8| {
+            world.spawn((Selectable::new("tank", 4.0), Owner(PlayerId(player)), GlobalTransform::default())).id()
+        };
+        let a = unit(&mut world, 0);
+        let b = unit(&mut world, 0);
+        let enemy = unit(&mut world, 1);
+        world.entity_mut(a).insert(Selected);
+        world.entity_mut(enemy).insert(Selected);
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(handle_control_group_input);
+        let press = |world: &mut World, keys: &[KeyCode]| {
+            let mut input = world.resource_mut::<ButtonInput<KeyCode>>();
+            input.reset_all();
+            for &key in keys {
+                input.press(key);
+            }
+        };
+
+        // Ctrl+1 binds only the local player's units
+        press(&mut world, &[KeyCode::ControlLeft, KeyCode::Digit1]);
+        schedule.run(&mut world);
+        assert_eq!(world.resource::<ControlGroups>().get(0), &[a]);
+
+        // Selecting something else and pressing 1 brings the group back
+        world.entity_mut(a).remove::<Selected>();
+        world.entity_mut(enemy).remove::<Selected>();
+        world.entity_mut(b).insert(Selected);
+        press(&mut world, &[KeyCode::Digit1]);
+        schedule.run(&mut world);
+        assert!(world.get::<Selected>(a).is_some());
+        assert!(world.get::<Selected>(b).is_none());
+    }
+
+    #[test]
+    fn test_double_tap_centres_camera() {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.insert_resource(ButtonInput::<KeyCode>::default());
+        world.insert_resource(LocalPlayer(PlayerId(0)));
+        world.init_resource::<ControlGroupBindings>();
+        world.init_resource::<ControlGroups>();
+        let camera = world.spawn(GameCamera {
+            target_zoom: 1.0,
+            zoom_speed: 1.0,
+            min_zoom: 0.5,
+            max_zoom: 2.0,
+            drag_start: None,
+            pan_speed: 1.0,
+            target_position: Vec3::new(0.0, 0.0, 5.0),
+        }).id();
+        let a = world.spawn((Selectable::new("tank", 4.0), Owner(PlayerId(0)), GlobalTransform::from_xyz(10.0, 0.0, 0.0))).id();
+        let b = world.spawn((Selectable::new("tank", 4.0), Owner(PlayerId(0)), GlobalTransform::from_xyz(30.0, 20.0, 0.0))).id();
+        world.resource_mut::<ControlGroups>().set(2, vec![a, b]);
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(handle_control_group_input);
+        let mut recall = |world: &mut World, after: f32| {
+            world.resource_mut::<Time>().advance_by(std::time::Duration::from_secs_f32(after));
+            let mut input = world.resource_mut::<ButtonInput<KeyCode>>();
+            input.reset_all();
+            input.press(KeyCode::Digit3);
+            schedule.run(world);
+        };
+        let target = |world: &World| world.get::<GameCamera>(camera).unwrap().target_position;
+
+        // Recalls too far apart only select
+        recall(&mut world, 1.0);
+        recall(&mut world, 0.5);
+        assert!(world.get::<Selected>(a).is_some() && world.get::<Selected>(b).is_some());
+        assert_eq!(target(&world), Vec3::new(0.0, 0.0, 5.0));
+
+        // A quick second recall centres on the group, keeping the camera height
+        recall(&mut world, 0.1);
+        assert_eq!(target(&world), Vec3::new(20.0, 10.0, 5.0));
+
+        // The double tap is used up, so a third quick recall starts over
+        world.get_mut::<GameCamera>(camera).unwrap().target_position = Vec3::ZERO;
+        recall(&mut world, 0.1);
+        assert_eq!(target(&world), Vec3::ZERO);
+    }
+}
@@ -0,0 +1,343 @@
+//! Unit formations
+//!
+//! A formation move spawns an anchor entity that follows the path to the target at the
+//! speed of the slowest member. Members steer towards their slot around the anchor instead
+//! of following paths of their own, so the group keeps its shape through turns. Members
+//! stay in formation, and keep its bonuses, until they are given another order.
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+use crate::data_file::{parse_builtin, read_data_file, DataFileError};
+use crate::navigation::{MovementClass, PathFailed};
+use super::{Destination, Movement, MovementFinished, SteeringIntent};
+
+const FORMATION_FILE: &str = "assets/data/formations.ron";
+
+/// How strongly members close the gap to their slot, per second
+const SLOT_GAIN: f32 = 2.0;
+
+/// Shape of a formation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
+pub enum FormationType {
+    /// Side by side across the direction of travel
+    Line,
+    /// Two abreast, one pair behind the other
+    Column,
+    /// A V with its tip pointing forwards
+    Wedge,
+    /// A square block
+    Box,
+}
+
+impl FormationType {
+    /// Get every formation type
+    pub fn all() -> [FormationType; 4] {
+        [FormationType::Line, FormationType::Column, FormationType::Wedge, FormationType::Box]
+    }
+
+    /// Get the slot offsets for `count` units, centred on the anchor
+    ///
+    /// Offsets are local to the formation: +X points in the direction of travel and +Y to
+    /// its left.
+    pub fn slot_offsets(&self, count: usize, spacing: f32) -> Vec<Vec2> {
+        let mut offsets: Vec<Vec2> = (0..count)
+            .map(|i| match self {
+                FormationType::Line => Vec2::new(0.0, i as f32),
+                FormationType::Column => Vec2::new(-((i / 2) as f32), (i % 2) as f32),
+                FormationType::Wedge => {
+                    let row = i.div_ceil(2);
+                    let side = if i % 2 == 1 { 1.0 } else { -1.0 };
+                    Vec2::new(-(row as f32), side * row as f32)
+                }
+                FormationType::Box => {
+                    let side = (count as f32).sqrt().ceil().max(1.0) as usize;
+                    Vec2::new(-((i / side) as f32), (i % side) as f32)
+                }
+            } * spacing)
+            .collect();
+        if count > 0 {
+            let centre = offsets.iter().sum::<Vec2>() / count as f32;
+            for offset in &mut offsets {
+                *offset -= centre;
+            }
+        }
+        offsets
+    }
+}
+
+/// Stat changes applied to units while they are in formation
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, Reflect)]
+#[serde(default)]
+pub struct FormationBonus {
+    /// Fractional change to damage dealt (0.1 = +10%)
+    pub damage: f32,
+    /// Flat armor added
+    pub armor: f32,
+    /// Fractional change to movement speed
+    pub speed: f32,
+}
+
+/// Definition of a formation type, loaded from data
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormationDef {
+    /// Formation type this defines
+    pub formation: FormationType,
+    /// Distance between neighbouring slots in meters
+    pub spacing: f32,
+    /// Bonus applied to members
+    #[serde(default)]
+    pub bonus: FormationBonus,
+}
+
+/// Errors that can occur when loading formation definitions
+#[derive(Error, Debug)]
+pub enum FormationLoadError {
+    /// The formation file could not be read or parsed
+    #[error(transparent)]
+    File(#[from] DataFileError),
+
+    /// A formation type is defined twice
+    #[error("Formation {0:?} is defined more than once")]
+    Duplicate(FormationType),
+
+    /// A formation has a non-positive spacing
+    #[error("Formation {0:?} must have a positive spacing")]
+    InvalidSpacing(FormationType),
+}
+
+/// Resource holding the definition of every formation type
+#[derive(Resource, Debug, Clone)]
+pub struct FormationDefs {
+    defs: HashMap<FormationType, FormationDef>,
+}
+
+impl Default for FormationDefs {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl FormationDefs {
+    /// Build formation definitions from a list, using the built-in ones for missing types
+    pub fn from_defs(defs: Vec<FormationDef>) -> Result<Self, FormationLoadError> {
+        let mut loaded = HashMap::new();
+        for def in defs {
+            if def.spacing <= 0.0 {
+                return Err(FormationLoadError::InvalidSpacing(def.formation));
+            }
+            if loaded.insert(def.formation, def.clone()).is_some() {
+                return Err(FormationLoadError::Duplicate(def.formation));
+            }
+        }
+        let mut result = Self::builtin();
+        result.defs.extend(loaded);
+        Ok(result)
+    }
+
+    /// Load formation definitions from a RON file containing a list of definitions
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self, FormationLoadError> {
+        Self::from_defs(read_data_file(path)?)
+    }
+
+    /// Built-in formations used when no data file is available
+    pub fn builtin() -> Self {
+        let defs: Vec<FormationDef> = parse_builtin(FORMATION_FILE, include_str!("../../assets/data/formations.ron"));
+        Self {
+            defs: defs.into_iter().map(|def| (def.formation, def)).collect(),
+        }
+    }
+
+    /// Get the definition of a formation type
+    pub fn get(&self, formation: FormationType) -> &FormationDef {
+        &self.defs[&formation]
+    }
+}
+
+/// Component for the invisible anchor a formation moves around
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct FormationAnchor {
+    /// Shape of the formation
+    pub formation: FormationType,
+}
+
+/// Component for units holding a slot in a formation
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct FormationMember {
+    /// The formation's anchor entity
+    pub anchor: Entity,
+    /// Slot position relative to the anchor, in the formation's local frame
+    pub offset: Vec2,
+    /// Bonus the member gets while in formation
+    pub bonus: FormationBonus,
+}
+
+/// A unit joining a formation move
+pub struct FormationRecruit<'a> {
+    /// The unit
+    pub entity: Entity,
+    /// Where it currently is
+    pub position: Vec2,
+    /// How it moves
+    pub movement: &'a Movement,
+}
+
+/// Spawn a formation anchor heading for `target` and assign every recruit a slot
+pub fn form_up(
+    commands: &mut Commands,
+    defs: &FormationDefs,
+    formation: FormationType,
+    target: Vec2,
+    recruits: &[FormationRecruit],
+) {
+    if recruits.is_empty() {
+        return;
+    }
+    let def = defs.get(formation);
+    let centre = recruits.iter().map(|recruit| recruit.position).sum::<Vec2>() / recruits.len() as f32;
+    let facing = (target - centre).try_normalize().unwrap_or(Vec2::X);
+
+    // The formation moves no faster and over no rougher ground than its slowest member
+    let slowest = recruits.iter()
+        .map(|recruit| recruit.movement)
+        .min_by(|a, b| a.max_speed.total_cmp(&b.max_speed))
+        .expect("recruits are not empty");
+    let class = [MovementClass::Ground, MovementClass::Hover, MovementClass::Air]
+        .into_iter()
+        .find(|&class| recruits.iter().any(|recruit| recruit.movement.class == class))
+        .unwrap_or_default();
+    let anchor = commands.spawn((
+        FormationAnchor { formation },
+        Movement {
+            max_speed: slowest.max_speed * (1.0 + def.bonus.speed).max(0.1),
+            acceleration: slowest.acceleration,
+            turn_rate: slowest.turn_rate,
+            class,
+            // Anchors have no footprint, so separation ignores them
+            radius: 0.0,
+            heading: facing.to_angle(),
+            ..default()
+        },
+        Destination(target),
+        TransformBundle::from_transform(Transform::from_translation(centre.extend(0.0))),
+    )).id();
+
+    // Give slots to units in order across and along the formation so paths do not cross
+    let offsets = formation.slot_offsets(recruits.len(), def.spacing);
+    let left = facing.perp();
+    let order_key = |local: Vec2| (-local.x, local.y);
+    let mut slots: Vec<Vec2> = offsets;
+    slots.sort_by(|a, b| order_key(*a).partial_cmp(&order_key(*b)).unwrap_or(std::cmp::Ordering::Equal));
+    let mut units: Vec<(Entity, Vec2)> = recruits.iter()
+        .map(|recruit| {
+            let relative = recruit.position - centre;
+            (recruit.entity, Vec2::new(relative.dot(facing), relative.dot(left)))
+        })
+        .collect();
+    units.sort_by(|a, b| {
+        order_key(a.1).partial_cmp(&order_key(b.1)).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(&b.0))
+    });
+    for ((entity, _), offset) in units.into_iter().zip(slots) {
+        commands.entity(entity).insert(FormationMember { anchor, offset, bonus: def.bonus });
+    }
+}
+
+/// System to steer formation members towards their slots around the anchor
+pub fn steer_formation_members(
+    anchors: Query<(&Movement, &Transform), With<FormationAnchor>>,
+    mut members: Query<(&FormationMember, &Movement, &Transform, &mut SteeringIntent), Without<FormationAnchor>>,
+) {
+    for (member, movement, transform, mut intent) in members.iter_mut() {
+        let Ok((anchor_movement, anchor_transform)) = anchors.get(member.anchor) else { continue };
+        let slot = anchor_transform.translation.truncate()
+            + Vec2::from_angle(anchor_movement.heading).rotate(member.offset);
+        let to_slot = slot - transform.translation.truncate();
+
+        let max_speed = movement.max_speed * (1.0 + member.bonus.speed).max(0.1);
+        intent.desired_velocity = if to_slot.length() <= movement.arrival_radius && anchor_movement.speed() < 0.1 {
+            Vec2::ZERO
+        } else {
+            (anchor_movement.velocity + to_slot * SLOT_GAIN).clamp_length_max(max_speed)
+        };
+    }
+}
+
+/// System to finish formation moves when the anchor arrives and disband empty formations
+pub fn update_formations(
+    mut commands: Commands,
+    mut finished_events: ParamSet<(EventReader<MovementFinished>, EventWriter<MovementFinished>)>,
+    mut failed_events: EventReader<PathFailed>,
+    anchors: Query<Entity, With<FormationAnchor>>,
+    members: Query<(Entity, &FormationMember)>,
+) {
+    // A formation that cannot find a way to its target stops where it is
+    let mut arrived: Vec<Entity> = finished_events.p0().read()
+        .map(|event| event.entity)
+        .chain(failed_events.read().map(|event| event.requester))
+        .filter(|&entity| anchors.contains(entity))
+        .collect();
+    arrived.sort();
+    arrived.dedup();
+
+    let mut member_counts: HashMap<Entity, usize> = HashMap::new();
+    for (entity, member) in members.iter() {
+        if !anchors.contains(member.anchor) {
+            commands.entity(entity).remove::<FormationMember>();
+            continue;
+        }
+        *member_counts.entry(member.anchor).or_default() += 1;
+        // Members finish their order with the formation but stay in it
+        if arrived.contains(&member.anchor) {
+            finished_events.p1().send(MovementFinished { entity });
+        }
+    }
+    for anchor in anchors.iter() {
+        if !member_counts.contains_key(&anchor) {
+            commands.entity(anchor).despawn_recursive();
+        }
+    }
+}
+
+/// System to load the formation definitions, falling back to the built-in ones
+pub fn load_formation_defs(mut commands: Commands) {
+    let defs = match FormationDefs::load_from_file(FORMATION_FILE) {
+        Ok(defs) => defs,
+        Err(err) => {
+            warn!("{}; using built-in formations", err);
+            FormationDefs::builtin()
+        }
+    };
+    commands.insert_resource(defs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_offsets_are_centred_and_spaced() {
+        let builtin = FormationDefs::builtin();
+        let loaded = FormationDefs::load_from_file(FORMATION_FILE).expect("formation definitions load");
+        for formation in FormationType::all() {
+            assert_eq!(builtin.get(formation), loaded.get(formation));
+        }
+
+        for formation in FormationType::all() {
+            for count in [1, 2, 5, 9, 12] {
+                let offsets = formation.slot_offsets(count, 4.0);
+                assert_eq!(offsets.len(), count);
+                let centre = offsets.iter().sum::<Vec2>() / count as f32;
+                assert!(centre.length() < 1e-3, "{:?} with {} units is centred on {}", formation, count, centre);
+                for (i, a) in offsets.iter().enumerate() {
+                    for b in &offsets[i + 1..] {
+                        assert!(a.distance(*b) >= 4.0 - 1e-3, "{:?} slots {} and {} overlap", formation, a, b);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Units and how they are controlled
+
+mod control_groups;
+mod formation;
+mod movement;
+mod orders;
+mod selection;
+
+pub use control_groups::*;
+pub use formation::*;
+pub use movement::*;
+pub use orders::*;
+pub use selection::*;
+
+use bevy::prelude::*;
+use crate::navigation::start_path_requests;
+use crate::state::GameState;
+
+/// Plugin for unit movement and control
+pub struct UnitsPlugin;
+
+impl Plugin for UnitsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Movement>()
+            .register_type::<Destination>()
+            .register_type::<SteeringIntent>()
+            .register_type::<Selectable>()
+            .register_type::<Selected>()
+            .register_type::<OrderQueue>()
+            .register_type::<FormationAnchor>()
+            .register_type::<FormationMember>()
+            .init_resource::<FormationDefs>()
+            .init_resource::<SelectionInput>()
+            .init_resource::<CommandHotkeys>()
+            .init_resource::<CommandInput>()
+            .init_resource::<ControlGroupBindings>()
+            .init_resource::<ControlGroups>()
+            .init_resource::<SelectionFocus>()
+            .add_event::<MovementFinished>()
+            .add_event::<SelectionChanged>()
+            .add_event::<IssueCommand>()
+            .add_systems(Startup, load_formation_defs)
+            .add_systems(Update, (
+                add_order_queues,
+                update_formations,
+                advance_orders,
+                process_issued_commands,
+                start_orders,
+                follow_order_targets,
+                run_gather_orders,
+            ).chain().before(request_destination_paths))
+            .add_systems(Update, request_destination_paths.before(start_path_requests))
+            .add_systems(FixedUpdate, (
+                add_steering_intents,
+                steer_units,
+                steer_formation_members,
+                separate_units,
+                integrate_movement,
+            ).chain())
+            .add_systems(Update, (
+                handle_selection_input,
+                deselect_lost_entities,
+                draw_selection,
+                issue_player_commands.before(process_issued_commands),
+                draw_order_waypoints,
+                handle_control_group_input,
+                cycle_selection_focus,
+                prune_control_groups,
+            ).run_if(in_state(GameState::InGame { is_paused: false })))
+            .add_systems(PostUpdate, (notify_selection_changed, update_selection_focus).chain())
+            .add_systems(OnExit(GameState::InGame { is_paused: false }), (clear_selection, clear_control_groups));
+    }
+}
@@ -0,0 +1,375 @@
+//! Unit movement: steering along paths and flow fields, separation and arrival
+//!
+//! Everything here runs on the fixed timestep and visits units in entity order, so the same
+//! orders always produce the same positions.
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+use crate::navigation::{FlowFieldGoal, FlowFields, MovementClass, NavGrid, NavPath, PathRequest};
+
+/// Weight of the push away from nearby units relative to the pull towards the destination
+const SEPARATION_WEIGHT: f32 = 1.5;
+
+/// Size of the buckets used to find nearby units, in meters
+const NEIGHBOR_CELL_SIZE: f32 = 8.0;
+
+/// Component for units that can move
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Movement {
+    /// Top speed on open ground in meters per second
+    pub max_speed: f32,
+    /// Acceleration and braking in meters per second squared
+    pub acceleration: f32,
+    /// Turn rate in radians per second
+    pub turn_rate: f32,
+    /// What terrain the unit can cross
+    pub class: MovementClass,
+    /// Radius of the unit's footprint in meters, used for separation
+    pub radius: f32,
+    /// Distance from the destination at which the unit counts as arrived
+    pub arrival_radius: f32,
+    /// Current velocity in meters per second
+    pub velocity: Vec2,
+    /// Direction the unit is facing in radians
+    pub heading: f32,
+}
+
+impl Default for Movement {
+    fn default() -> Self {
+        Self {
+            max_speed: 10.0,
+            acceleration: 20.0,
+            turn_rate: std::f32::consts::TAU,
+            class: MovementClass::Ground,
+            radius: 2.0,
+            arrival_radius: 1.0,
+            velocity: Vec2::ZERO,
+            heading: 0.0,
+        }
+    }
+}
+
+impl Movement {
+    /// Create movement for a unit with the given top speed and class
+    pub fn new(max_speed: f32, class: MovementClass) -> Self {
+        Self {
+            max_speed,
+            class,
+            ..default()
+        }
+    }
+
+    /// Get the current speed in meters per second
+    pub fn speed(&self) -> f32 {
+        self.velocity.length()
+    }
+}
+
+/// Component giving a unit somewhere to go; a path is requested whenever it changes
+#[derive(Component, Debug, Clone, Copy, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct Destination(pub Vec2);
+
+/// Desired velocity chosen by steering for the current tick
+#[derive(Component, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct SteeringIntent {
+    /// Velocity the unit is trying to reach
+    pub desired_velocity: Vec2,
+}
+
+/// A unit reached its destination and stopped
+#[derive(Event, Debug, Clone)]
+pub struct MovementFinished {
+    /// The unit that arrived
+    pub entity: Entity,
+}
+
+/// Velocity that heads for `target`, slowing down over the last stretch if `arrive` is set
+fn seek(position: Vec2, target: Vec2, movement: &Movement, speed_limit: f32, arrive: bool) -> Vec2 {
+    let offset = target - position;
+    let distance = offset.length();
+    if distance <= f32::EPSILON {
+        return Vec2::ZERO;
+    }
+    let mut speed = speed_limit;
+    if arrive {
+        // Brake so the unit can stop at the target with its acceleration
+        let braking_speed = (2.0 * movement.acceleration * (distance - movement.arrival_radius).max(0.0)).sqrt();
+        speed = speed.min(braking_speed);
+    }
+    offset / distance * speed
+}
+
+/// Top speed on the unit's current cell, slowed by rough terrain
+fn terrain_speed(grid: &NavGrid, position: Vec2, movement: &Movement) -> f32 {
+    let cost = grid.cost(grid.world_to_cell(position), movement.class).unwrap_or(1.0);
+    movement.max_speed / cost.max(1.0)
+}
+
+/// System to give newly spawned moving units somewhere to record their steering
+pub fn add_steering_intents(
+    mut commands: Commands,
+    units: Query<Entity, (With<Movement>, Without<SteeringIntent>)>,
+) {
+    for entity in units.iter() {
+        commands.entity(entity).insert(SteeringIntent::default());
+    }
+}
+
+/// Path-following units given a new destination
+type RedirectedUnits<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static Destination, &'static Movement, &'static Transform),
+    (Changed<Destination>, Without<FlowFieldGoal>),
+>;
+
+/// System to request paths for units whose destination changed
+pub fn request_destination_paths(
+    units: RedirectedUnits,
+    mut requests: EventWriter<PathRequest>,
+) {
+    for (entity, destination, movement, transform) in units.iter() {
+        requests.send(PathRequest {
+            requester: entity,
+            start: transform.translation.truncate(),
+            goal: destination.0,
+            class: movement.class,
+        });
+    }
+}
+
+/// Moving units with whatever they are steering along
+type SteeredUnits<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Movement,
+        &'static Transform,
+        &'static mut SteeringIntent,
+        Option<&'static mut NavPath>,
+        Option<&'static FlowFieldGoal>,
+    ),
+>;
+
+/// System to choose each unit's desired velocity from its path or flow field
+pub fn steer_units(
+    mut commands: Commands,
+    grid: Res<NavGrid>,
+    fields: Res<FlowFields>,
+    mut units: SteeredUnits,
+    mut finished_events: EventWriter<MovementFinished>,
+) {
+    let waypoint_radius = grid.cell_size() * 0.5;
+    for (entity, movement, transform, mut intent, path, flow_goal) in units.iter_mut() {
+        let position = transform.translation.truncate();
+        let speed_limit = terrain_speed(&grid, position, movement);
+
+        let mut arrived = false;
+        intent.desired_velocity = if let Some(goal) = flow_goal {
+            let goal_cell = grid.world_to_cell(goal.goal);
+            let distance = position.distance(goal.goal);
+            // Groups cannot all stand on the goal, so stopping within a cell of it counts
+            if distance <= movement.arrival_radius.max(grid.cell_size()) {
+                arrived = true;
+                Vec2::ZERO
+            } else if grid.world_to_cell(position) == goal_cell {
+                seek(position, goal.goal, movement, speed_limit, true)
+            } else {
+                match fields.get(goal_cell, goal.class).and_then(|field| field.direction(&grid, position)) {
+                    Some(direction) => direction * speed_limit,
+                    None => Vec2::ZERO,
+                }
+            }
+        } else if let Some(mut path) = path {
+            // Skip waypoints already reached, except the last which needs a proper arrival
+            while let Some(waypoint) = path.current() {
+                let is_last = path.next + 1 >= path.waypoints.len();
+                if is_last || position.distance(waypoint) > waypoint_radius {
+                    break;
+                }
+                path.advance();
+            }
+            match path.current() {
+                Some(waypoint) if path.next + 1 >= path.waypoints.len() => {
+                    if position.distance(waypoint) <= movement.arrival_radius {
+                        path.advance();
+                        arrived = true;
+                        Vec2::ZERO
+                    } else {
+                        seek(position, waypoint, movement, speed_limit, true)
+                    }
+                }
+                Some(waypoint) => seek(position, waypoint, movement, speed_limit, false),
+                None => {
+                    arrived = true;
+                    Vec2::ZERO
+                }
+            }
+        } else {
+            Vec2::ZERO
+        };
+
+        if arrived {
+            commands.entity(entity)
+                .remove::<NavPath>()
+                .remove::<FlowFieldGoal>()
+                .remove::<Destination>();
+            finished_events.send(MovementFinished { entity });
+        }
+    }
+}
+
+/// System to push overlapping units apart
+pub fn separate_units(
+    mut units: Query<(Entity, &Movement, &Transform, &mut SteeringIntent)>,
+) {
+    let mut snapshot: Vec<(Entity, Vec2, f32, MovementClass)> = units.iter()
+        .map(|(entity, movement, transform, _)| (entity, transform.translation.truncate(), movement.radius, movement.class))
+        .collect();
+    snapshot.sort_by_key(|&(entity, ..)| entity);
+
+    let bucket_of = |position: Vec2| (position / NEIGHBOR_CELL_SIZE).floor().as_ivec2();
+    let mut buckets: HashMap<IVec2, Vec<usize>> = HashMap::new();
+    for (index, &(_, position, _, _)) in snapshot.iter().enumerate() {
+        buckets.entry(bucket_of(position)).or_default().push(index);
+    }
+
+    for (index, &(entity, position, radius, class)) in snapshot.iter().enumerate() {
+        let mut push = Vec2::ZERO;
+        let bucket = bucket_of(position);
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let Some(neighbors) = buckets.get(&(bucket + IVec2::new(x, y))) else { continue };
+                for &other in neighbors {
+                    let (_, other_position, other_radius, other_class) = snapshot[other];
+                    // Aircraft and ground units do not get in each other's way
+                    if other == index || (other_class == MovementClass::Air) != (class == MovementClass::Air) {
+                        continue;
+                    }
+                    // Movers without a footprint, such as formation anchors, take up no space
+                    if radius <= 0.0 || other_radius <= 0.0 {
+                        continue;
+                    }
+                    let offset = position - other_position;
+                    let distance = offset.length();
+                    let min_distance = radius + other_radius;
+                    if distance >= min_distance {
+                        continue;
+                    }
+                    // Units on the same spot are split along a direction fixed by their order
+                    let direction = offset.try_normalize()
+                        .unwrap_or_else(|| Vec2::from_angle(index as f32 * 2.399_963));
+                    push += direction * (min_distance - distance) / min_distance;
+                }
+            }
+        }
+
+        if push != Vec2::ZERO {
+            if let Ok((_, movement, _, mut intent)) = units.get_mut(entity) {
+                let separation = push * movement.max_speed * SEPARATION_WEIGHT;
+                intent.desired_velocity = (intent.desired_velocity + separation).clamp_length_max(movement.max_speed);
+            }
+        }
+    }
+}
+
+/// System to turn, accelerate and move units towards their desired velocity
+pub fn integrate_movement(
+    time: Res<Time>,
+    grid: Res<NavGrid>,
+    mut units: Query<(&mut Movement, &SteeringIntent, &mut Transform)>,
+) {
+    let delta = time.delta_seconds();
+    for (mut movement, intent, mut transform) in units.iter_mut() {
+        let desired = intent.desired_velocity;
+
+        // Turn towards the desired direction no faster than the turn rate
+        if desired.length_squared() > f32::EPSILON {
+            let target_heading = desired.to_angle();
+            let difference = (target_heading - movement.heading + std::f32::consts::PI)
+                .rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI;
+            let max_turn = movement.turn_rate * delta;
+            movement.heading += difference.clamp(-max_turn, max_turn);
+        }
+
+        // Units only drive in the direction they face, easing off while turning sharply
+        let facing = Vec2::from_angle(movement.heading);
+        let alignment = if desired == Vec2::ZERO { 0.0 } else { facing.dot(desired.normalize()).max(0.0) };
+        let target_speed = desired.length() * alignment;
+        let speed = movement.speed();
+        let max_change = movement.acceleration * delta;
+        let new_speed = speed + (target_speed - speed).clamp(-max_change, max_change);
+        movement.velocity = facing * new_speed;
+
+        // Slide along obstacles instead of entering impassable cells
+        let position = transform.translation.truncate();
+        let step = movement.velocity * delta;
+        let passable = |point: Vec2| grid.width() == 0 || grid.is_passable(grid.world_to_cell(point), movement.class);
+        let moved = [step, Vec2::new(step.x, 0.0), Vec2::new(0.0, step.y)]
+            .into_iter()
+            .find(|&candidate| passable(position + candidate) || !passable(position))
+            .unwrap_or(Vec2::ZERO);
+        if moved != step {
+            movement.velocity = moved / delta.max(f32::EPSILON);
+        }
+
+        transform.translation += moved.extend(0.0);
+        transform.rotation = Quat::from_rotation_z(movement.heading);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn simulate() -> Vec<Vec3> {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.insert_resource(NavGrid::new(40, 40, Vec2::ZERO, 4.0));
+        world.init_resource::<FlowFields>();
+        world.init_resource::<Events<MovementFinished>>();
+
+        // Two units heading for the same point along crossing paths
+        let goal = Vec2::new(80.0, 80.0);
+        let units: Vec<Entity> = [Vec2::new(10.0, 80.0), Vec2::new(80.0, 10.0), Vec2::new(12.0, 82.0)]
+            .into_iter()
+            .map(|start| {
+                world.spawn((
+                    Movement::default(),
+                    SteeringIntent::default(),
+                    NavPath::new(vec![goal]),
+                    Transform::from_translation(start.extend(0.0)),
+                )).id()
+            })
+            .collect();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((steer_units, separate_units, integrate_movement).chain());
+        for _ in 0..64 * 20 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs(1) / 64);
+            schedule.run(&mut world);
+        }
+        units.iter().map(|&unit| world.get::<Transform>(unit).unwrap().translation).collect()
+    }
+
+    #[test]
+    fn test_movement_is_deterministic_and_arrives() {
+        let first = simulate();
+        assert_eq!(first, simulate());
+
+        // Units end up near the goal without stacking on top of each other
+        for position in &first {
+            assert!(position.truncate().distance(Vec2::new(80.0, 80.0)) < 8.0, "stopped at {}", position);
+        }
+        for (i, a) in first.iter().enumerate() {
+            for b in &first[i + 1..] {
+                assert!(a.distance(*b) > 2.0, "units overlap at {} and {}", a, b);
+            }
+        }
+    }
+}
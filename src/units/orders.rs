@@ -0,0 +1,836 @@
+//! Orders given to units and the command stream they arrive through
+//!
+//! Every order, whether from the mouse, a hotkey, the AI or a replay, is sent as an
+//! `IssueCommand` event. Nothing else touches a unit's `OrderQueue`, so recording or
+//! networking that one event stream is enough to reproduce a game.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, VecDeque};
+use crate::buildings::{Builder, Building, ConstructionSite};
+use crate::camera_controls::GameCursor;
+use crate::combat::{Engagement, Health, Stance, Weapon};
+use crate::navigation::{FlowFieldGoal, NavGrid, NavPath, PathFailed, FLOW_FIELD_MIN_GROUP};
+use crate::player::{Alliances, LocalPlayer, Owner, PlayerId};
+use crate::resources::{ResourceGatherer, ResourceNode, ResourceStorage, DELIVERY_RANGE};
+use super::{
+    form_up, Destination, FormationAnchor, FormationDefs, FormationMember, FormationRecruit, FormationType,
+    Movement, MovementFinished, Selectable, Selected,
+};
+
+/// Distance in meters within which a right-click lands on a resource node
+const NODE_CLICK_RADIUS: f32 = 6.0;
+
+/// An order for a unit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Reflect)]
+pub enum Command {
+    /// Move to a point, ignoring enemies on the way
+    Move { target: Vec2 },
+    /// Move to a point in formation with the other units given the same order
+    FormationMove { target: Vec2, formation: FormationType },
+    /// Move to a point, engaging enemies met on the way
+    AttackMove { target: Vec2 },
+    /// Chase and attack a specific entity
+    Attack { target: Entity },
+    /// Go to a resource node and gather from it
+    Gather { node: Entity },
+    /// Go to a construction site and work on it until it is finished
+    Construct { site: Entity },
+    /// Go to a damaged building and repair it until it is back to full health
+    Repair { target: Entity },
+    /// Walk back and forth between the point the order started at and a target
+    Patrol { target: Vec2 },
+    /// Drop every order and stop
+    Stop,
+    /// Stay put until given another order
+    HoldPosition,
+    /// Change how the units engage enemies, without touching their orders
+    SetStance { stance: Stance },
+}
+
+impl Command {
+    /// Get the fixed point this order heads for, if it has one
+    pub fn target_point(&self) -> Option<Vec2> {
+        match *self {
+            Command::Move { target }
+            | Command::FormationMove { target, .. }
+            | Command::AttackMove { target }
+            | Command::Patrol { target } => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Get the entity this order heads for, if it has one
+    pub fn target_entity(&self) -> Option<Entity> {
+        match *self {
+            Command::Attack { target } => Some(target),
+            Command::Gather { node } => Some(node),
+            Command::Construct { site } => Some(site),
+            Command::Repair { target } => Some(target),
+            _ => None,
+        }
+    }
+}
+
+/// Request for a player's units to carry out a command
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct IssueCommand {
+    /// The commanding player; units they do not own ignore the command
+    pub player: PlayerId,
+    /// Units receiving the command
+    pub units: Vec<Entity>,
+    /// What to do
+    pub command: Command,
+    /// Append to the units' orders instead of replacing them
+    pub queued: bool,
+}
+
+/// Component holding a unit's orders, the first being the one it is carrying out
+#[derive(Component, Debug, Clone, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct OrderQueue {
+    orders: VecDeque<Command>,
+    /// Whether the current order has been acted on yet
+    started: bool,
+    /// Where the unit was when the current order started
+    origin: Option<Vec2>,
+}
+
+impl OrderQueue {
+    /// Get the order being carried out
+    pub fn current(&self) -> Option<&Command> {
+        self.orders.front()
+    }
+
+    /// Iterate over every order, current first
+    pub fn iter(&self) -> impl Iterator<Item = &Command> + '_ {
+        self.orders.iter()
+    }
+
+    /// Check whether the unit has no orders
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    /// Add an order after the existing ones, or replace them all
+    pub fn push(&mut self, command: Command, queued: bool) {
+        if !queued {
+            self.orders.clear();
+            self.started = false;
+            self.origin = None;
+        }
+        self.orders.push_back(command);
+    }
+
+    /// Act on the current order again, e.g. after a fight interrupted it; the order keeps
+    /// where it started, so an interrupted patrol still returns to its first endpoint
+    pub fn restart_current(&mut self) {
+        self.started = false;
+    }
+
+    /// Finish the current order and move on to the next
+    pub fn complete_current(&mut self) {
+        self.orders.pop_front();
+        self.started = false;
+        self.origin = None;
+    }
+}
+
+/// Resource holding the keys for giving orders
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandHotkeys {
+    /// Arms an attack-move for the next right-click
+    pub attack_move: KeyCode,
+    /// Arms a patrol for the next right-click
+    pub patrol: KeyCode,
+    /// Stops the selected units
+    pub stop: KeyCode,
+    /// Makes the selected units hold position
+    pub hold: KeyCode,
+    /// Cycles the formation used for moves
+    pub cycle_formation: KeyCode,
+    /// Cycles the stance of the selected units
+    pub cycle_stance: KeyCode,
+    /// Cycles the building being placed
+    pub build: KeyCode,
+    /// Cancels the selected construction sites
+    pub cancel_construction: KeyCode,
+    /// Queues a unit at the selected buildings
+    pub train: KeyCode,
+    /// Toggles the logistics overlay
+    pub logistics_overlay: KeyCode,
+    /// Toggles the power overlay
+    pub power_overlay: KeyCode,
+}
+
+impl Default for CommandHotkeys {
+    fn default() -> Self {
+        // WASD belongs to the camera
+        Self {
+            attack_move: KeyCode::KeyF,
+            patrol: KeyCode::KeyR,
+            stop: KeyCode::KeyX,
+            hold: KeyCode::KeyH,
+            cycle_formation: KeyCode::KeyG,
+            cycle_stance: KeyCode::KeyV,
+            build: KeyCode::KeyB,
+            cancel_construction: KeyCode::Delete,
+            train: KeyCode::KeyT,
+            logistics_overlay: KeyCode::KeyL,
+            power_overlay: KeyCode::KeyP,
+        }
+    }
+}
+
+/// Targeted command waiting for the player to pick a point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingCommand {
+    /// The next right-click attack-moves
+    AttackMove,
+    /// The next right-click patrols
+    Patrol,
+}
+
+/// Resource tracking command input from the local player
+#[derive(Resource, Debug, Clone, Default)]
+pub struct CommandInput {
+    /// Command armed by a hotkey, replacing the smart right-click
+    pub pending: Option<PendingCommand>,
+    /// Formation groups move in, if any
+    pub formation: Option<FormationType>,
+}
+
+/// Moving units still without an order queue
+type UnqueuedUnits<'w, 's> =
+    Query<'w, 's, Entity, (With<Movement>, Without<OrderQueue>, Without<FormationAnchor>)>;
+
+/// Selected entities and what they can be ordered to do
+type SelectedUnits<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static Owner, Has<ResourceGatherer>, Option<&'static Stance>),
+    With<Selected>,
+>;
+
+/// Buildings and construction sites, for deciding whether builders can work on them
+type Structures<'w, 's> = Query<
+    'w,
+    's,
+    (Option<&'static ConstructionSite>, &'static Health),
+    Or<(With<ConstructionSite>, With<Building>)>,
+>;
+
+/// Mouse buttons, keys and the hotkeys bound to commands
+#[derive(SystemParam)]
+pub struct CommandControls<'w> {
+    mouse_buttons: Res<'w, ButtonInput<MouseButton>>,
+    keyboard_input: Res<'w, ButtonInput<KeyCode>>,
+    hotkeys: Res<'w, CommandHotkeys>,
+}
+
+/// Everything a smart right-click can land on
+#[derive(SystemParam)]
+pub struct ClickTargets<'w, 's> {
+    alliances: Res<'w, Alliances>,
+    selectables: Query<'w, 's, (Entity, &'static Selectable, &'static GlobalTransform, &'static Owner)>,
+    nodes: Query<'w, 's, (Entity, &'static GlobalTransform), With<ResourceNode>>,
+    structures: Structures<'w, 's>,
+    builders: Query<'w, 's, &'static Builder>,
+}
+
+/// System to give newly spawned moving units an order queue
+pub fn add_order_queues(
+    mut commands: Commands,
+    units: UnqueuedUnits,
+) {
+    for entity in units.iter() {
+        commands.entity(entity).insert(OrderQueue::default());
+    }
+}
+
+/// System to turn the local player's mouse and hotkey input into commands
+pub fn issue_player_commands(
+    controls: CommandControls,
+    game_cursor: GameCursor,
+    local_player: Res<LocalPlayer>,
+    mut input: ResMut<CommandInput>,
+    selected: SelectedUnits,
+    targets: ClickTargets,
+    mut command_events: EventWriter<IssueCommand>,
+) {
+    let CommandControls { mouse_buttons, keyboard_input, hotkeys } = controls;
+    let player = local_player.0;
+    let mut units: Vec<(Entity, bool)> = selected.iter()
+        .filter(|(_, owner, ..)| owner.0 == player)
+        .map(|(entity, _, gatherer, _)| (entity, gatherer))
+        .collect();
+    units.sort_by_key(|&(entity, _)| entity);
+    let queued = keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let mut issue = |units: Vec<Entity>, command: Command| {
+        if !units.is_empty() {
+            command_events.send(IssueCommand { player, units, command, queued });
+        }
+    };
+    let all_units = || units.iter().map(|&(entity, _)| entity).collect::<Vec<_>>();
+
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        input.pending = None;
+    }
+    if keyboard_input.just_pressed(hotkeys.attack_move) {
+        input.pending = Some(PendingCommand::AttackMove);
+    }
+    if keyboard_input.just_pressed(hotkeys.patrol) {
+        input.pending = Some(PendingCommand::Patrol);
+    }
+    if keyboard_input.just_pressed(hotkeys.stop) {
+        issue(all_units(), Command::Stop);
+    }
+    if keyboard_input.just_pressed(hotkeys.hold) {
+        issue(all_units(), Command::HoldPosition);
+    }
+    if keyboard_input.just_pressed(hotkeys.cycle_formation) {
+        let formations = FormationType::all();
+        input.formation = match input.formation.and_then(|current| formations.iter().position(|&f| f == current)) {
+            Some(index) => formations.get(index + 1).copied(),
+            None => Some(formations[0]),
+        };
+        info!("Formation: {:?}", input.formation);
+    }
+    if keyboard_input.just_pressed(hotkeys.cycle_stance) {
+        // Every selected unit moves on from the stance of the first one, so mixed selections line up
+        let current = units.first()
+            .and_then(|&(entity, _)| selected.get(entity).ok())
+            .and_then(|(.., stance)| stance.copied())
+            .unwrap_or_default();
+        let stance = current.next();
+        info!("Stance: {:?}", stance);
+        issue(all_units(), Command::SetStance { stance });
+    }
+
+    if !mouse_buttons.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let pending = input.pending.take();
+    let Some(point) = game_cursor.world_position() else { return };
+
+    match pending {
+        Some(PendingCommand::AttackMove) => return issue(all_units(), Command::AttackMove { target: point }),
+        Some(PendingCommand::Patrol) => return issue(all_units(), Command::Patrol { target: point }),
+        None => {}
+    }
+
+    // Smart right-click: attack enemies, gather from nodes, otherwise move
+    let enemy = targets.selectables.iter()
+        .filter(|(.., owner)| owner.0 != player && !targets.alliances.are_allied(owner.0, player))
+        .map(|(entity, selectable, transform, _)| {
+            (entity, selectable.radius, transform.translation().truncate().distance(point))
+        })
+        .filter(|&(_, radius, distance)| distance <= radius)
+        .min_by(|a, b| a.2.total_cmp(&b.2));
+    if let Some((target, ..)) = enemy {
+        return issue(all_units(), Command::Attack { target });
+    }
+
+    // Builders construct the player's sites and repair their damaged buildings
+    let structures = &targets.structures;
+    let structure = targets.selectables.iter()
+        .filter(|&(entity, .., owner)| owner.0 == player && structures.contains(entity))
+        .map(|(entity, selectable, transform, _)| {
+            (entity, selectable.radius, transform.translation().truncate().distance(point))
+        })
+        .filter(|&(_, radius, distance)| distance <= radius)
+        .min_by(|a, b| a.2.total_cmp(&b.2));
+    if let Some((Ok((site, health)), target)) = structure.map(|(target, ..)| (structures.get(target), target)) {
+        let command = match site {
+            Some(_) => Some(Command::Construct { site: target }),
+            None if health.current < health.max => Some(Command::Repair { target }),
+            None => None,
+        };
+        let can_help = |entity: Entity| targets.builders.get(entity).is_ok_and(|builder| match site {
+            Some(site) => builder.tier >= site.tier,
+            None => builder.repair_rate > 0.0,
+        });
+        let (helpers, others): (Vec<Entity>, Vec<Entity>) = all_units().into_iter().partition(|&entity| can_help(entity));
+        if let Some(command) = command.filter(|_| !helpers.is_empty()) {
+            issue(helpers, command);
+            issue(others, Command::Move { target: point });
+            return;
+        }
+    }
+
+    let node = targets.nodes.iter()
+        .map(|(entity, transform)| (entity, transform.translation().truncate().distance(point)))
+        .filter(|&(_, distance)| distance <= NODE_CLICK_RADIUS)
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+    if let Some((node, _)) = node {
+        let (gatherers, others): (Vec<_>, Vec<_>) = units.iter().partition(|(_, gatherer)| *gatherer);
+        issue(gatherers.into_iter().map(|(entity, _)| entity).collect(), Command::Gather { node });
+        issue(others.into_iter().map(|(entity, _)| entity).collect(), Command::Move { target: point });
+        return;
+    }
+
+    let command = match input.formation {
+        Some(formation) if units.len() > 1 => Command::FormationMove { target: point, formation },
+        _ => Command::Move { target: point },
+    };
+    issue(all_units(), command);
+}
+
+/// System to apply issued commands to the order queues of the commanding player's units
+pub fn process_issued_commands(
+    mut command_events: EventReader<IssueCommand>,
+    mut units: Query<(&Owner, &mut OrderQueue, Option<&mut Stance>)>,
+) {
+    for event in command_events.read() {
+        for &unit in &event.units {
+            let Ok((owner, mut queue, stance)) = units.get_mut(unit) else { continue };
+            if owner.0 != event.player {
+                warn!("Player {:?} cannot command unit {:?} owned by {:?}", event.player, unit, owner.0);
+                continue;
+            }
+            if let Command::SetStance { stance: new_stance } = event.command {
+                // Stances apply at once instead of waiting in the queue
+                if let Some(mut stance) = stance {
+                    *stance = new_stance;
+                }
+                continue;
+            }
+            queue.push(event.command, event.queued);
+        }
+    }
+}
+
+/// System to finish or repeat orders once units arrive or cannot find a way
+///
+/// Units fighting an enemy they found themselves are chasing it rather than following their
+/// order, so their arrivals and failed paths leave the order alone.
+pub fn advance_orders(
+    mut finished_events: EventReader<MovementFinished>,
+    mut failed_events: EventReader<PathFailed>,
+    mut units: Query<(&mut OrderQueue, Has<Engagement>)>,
+) {
+    for event in finished_events.read() {
+        let Ok((mut queue, engaged)) = units.get_mut(event.entity) else { continue };
+        if !queue.started || engaged {
+            continue;
+        }
+        match queue.current().copied() {
+            Some(Command::Patrol { target }) => {
+                // Turn around and head back to where this leg started
+                let origin = queue.origin.unwrap_or(target);
+                queue.orders[0] = Command::Patrol { target: origin };
+                queue.origin = Some(target);
+                queue.started = false;
+            }
+            Some(Command::Move { .. } | Command::FormationMove { .. } | Command::AttackMove { .. }) => {
+                queue.complete_current();
+            }
+            // Attacks, gathering and building work last until the target is gone or done, holds until replaced
+            _ => {}
+        }
+    }
+    for event in failed_events.read() {
+        if let Ok((mut queue, engaged)) = units.get_mut(event.requester) {
+            if queue.started && !engaged {
+                queue.complete_current();
+            }
+        }
+    }
+}
+
+/// System to start each unit's current order, sending groups moving to the same point along a shared flow
+/// field and units ordered into formation together as one formation
+pub fn start_orders(
+    mut commands: Commands,
+    formation_defs: Res<FormationDefs>,
+    mut units: Query<(Entity, &mut OrderQueue, &Movement, &Transform, Option<&mut Weapon>)>,
+    targets: Query<&GlobalTransform>,
+) {
+    let mut starting: Vec<Entity> = units.iter()
+        .filter(|(_, queue, ..)| !queue.started && !queue.is_empty())
+        .map(|(entity, ..)| entity)
+        .collect();
+    starting.sort();
+
+    // Count units heading for each point this tick
+    let mut group_sizes: HashMap<(u32, u32), usize> = HashMap::new();
+    for &entity in &starting {
+        if let Some(point) = units.get(entity).ok().and_then(|(_, queue, ..)| queue.current()?.target_point()) {
+            *group_sizes.entry((point.x.to_bits(), point.y.to_bits())).or_default() += 1;
+        }
+    }
+
+    let mut formations: Vec<((u32, u32, FormationType), Vec<Entity>)> = Vec::new();
+    for &entity in &starting {
+        if let Ok((_, queue, ..)) = units.get(entity) {
+            if let Some(&Command::FormationMove { target, formation }) = queue.current() {
+                let key = (target.x.to_bits(), target.y.to_bits(), formation);
+                match formations.iter_mut().find(|(other, _)| *other == key) {
+                    Some((_, members)) => members.push(entity),
+                    None => formations.push((key, vec![entity])),
+                }
+            }
+        }
+    }
+    for ((x, y, formation), members) in &formations {
+        let recruits: Vec<FormationRecruit> = members.iter()
+            .filter_map(|&entity| units.get(entity).ok())
+            .map(|(entity, _, movement, transform, _)| FormationRecruit {
+                entity,
+                position: transform.translation.truncate(),
+                movement,
+            })
+            .collect();
+        let target = Vec2::new(f32::from_bits(*x), f32::from_bits(*y));
+        form_up(&mut commands, &formation_defs, *formation, target, &recruits);
+    }
+
+    for entity in starting {
+        let Ok((_, mut queue, movement, transform, weapon)) = units.get_mut(entity) else { continue };
+        let Some(command) = queue.current().copied() else { continue };
+        queue.started = true;
+        queue.origin.get_or_insert(transform.translation.truncate());
+
+        // Only attack orders pick a target; anything else calls off the current attack
+        if let Some(mut weapon) = weapon {
+            weapon.target = match command {
+                Command::Attack { target } => Some(target),
+                _ => None,
+            };
+        }
+
+        // A new order takes the unit out of any formation, unless it is forming a new one
+        let mut unit = commands.entity(entity);
+        unit.remove::<(NavPath, FlowFieldGoal, Destination, Engagement)>();
+        if !matches!(command, Command::FormationMove { .. }) {
+            unit.remove::<FormationMember>();
+        }
+        match command {
+            Command::Move { target } | Command::AttackMove { target } | Command::Patrol { target } => {
+                let group = group_sizes.get(&(target.x.to_bits(), target.y.to_bits())).copied().unwrap_or(1);
+                if group >= FLOW_FIELD_MIN_GROUP {
+                    unit.insert(FlowFieldGoal { goal: target, class: movement.class });
+                } else {
+                    unit.insert(Destination(target));
+                }
+            }
+            Command::Attack { target: target_entity }
+            | Command::Gather { node: target_entity }
+            | Command::Construct { site: target_entity }
+            | Command::Repair { target: target_entity } => {
+                match targets.get(target_entity) {
+                    Ok(target) => {
+                        unit.insert(Destination(target.translation().truncate()));
+                    }
+                    Err(_) => queue.complete_current(),
+                }
+            }
+            Command::Stop | Command::SetStance { .. } => queue.complete_current(),
+            // Formations were formed above
+            Command::FormationMove { .. } | Command::HoldPosition => {}
+        }
+    }
+}
+
+/// Units working through their orders, with what they need to chase a target
+type ChasingUnits<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static mut OrderQueue, &'static Transform, Option<&'static Destination>, Option<&'static Weapon>),
+>;
+
+/// System to keep units chasing the entity their order targets, dropping the order once it is gone
+///
+/// Attackers stop as soon as the target is within range of their weapon.
+pub fn follow_order_targets(
+    mut commands: Commands,
+    grid: Res<NavGrid>,
+    mut units: ChasingUnits,
+    targets: Query<&GlobalTransform>,
+) {
+    let repath_distance = grid.cell_size().max(1.0);
+    for (entity, mut queue, transform, destination, weapon) in units.iter_mut() {
+        if !queue.started {
+            continue;
+        }
+        let Some(target_entity) = queue.current().and_then(Command::target_entity) else { continue };
+        // Gatherers come and go between their node and storage, which `run_gather_orders` steers
+        if matches!(queue.current(), Some(Command::Gather { .. })) {
+            continue;
+        }
+        match targets.get(target_entity) {
+            Ok(target) => {
+                // Attackers that already caught up set off again when the target moves away
+                let position = target.translation().truncate();
+                let is_attack = matches!(queue.current(), Some(Command::Attack { .. }));
+                let in_range = weapon.is_some_and(|weapon| {
+                    weapon.in_range(transform.translation.truncate().distance(position))
+                });
+                if is_attack && in_range {
+                    if destination.is_some() {
+                        commands.entity(entity).remove::<(NavPath, FlowFieldGoal, Destination)>();
+                    }
+                    continue;
+                }
+                let heading_for = match destination {
+                    Some(destination) => destination.0,
+                    None if is_attack => transform.translation.truncate(),
+                    None => continue,
+                };
+                if heading_for.distance(position) > repath_distance {
+                    commands.entity(entity).insert(Destination(position));
+                }
+            }
+            Err(_) => {
+                queue.complete_current();
+                commands.entity(entity).remove::<(NavPath, Destination)>();
+            }
+        }
+    }
+}
+
+/// Gatherers with the orders that send them to a node and where they are headed
+type GatherUnits<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut OrderQueue,
+        &'static mut ResourceGatherer,
+        &'static Transform,
+        Option<&'static Destination>,
+        Option<&'static Owner>,
+    ),
+>;
+
+/// System to walk gatherers between the node they were ordered to work and the closest storage
+///
+/// Gatherers fill up at the node, carry the load to a storage that takes it and head back, until
+/// the node is gone or runs dry; the order ends once the last load has been delivered.
+pub fn run_gather_orders(
+    mut commands: Commands,
+    mut units: GatherUnits,
+    nodes: Query<(&ResourceNode, &GlobalTransform)>,
+    storages: Query<(&ResourceStorage, &GlobalTransform, Option<&Owner>)>,
+) {
+    for (entity, mut queue, mut gatherer, transform, destination, owner) in units.iter_mut() {
+        let node = match queue.current() {
+            Some(&Command::Gather { node }) => node,
+            _ => {
+                if gatherer.assigned_node.is_some() {
+                    gatherer.assigned_node = None;
+                    gatherer.returning = false;
+                }
+                continue;
+            }
+        };
+        if !queue.started {
+            continue;
+        }
+        if gatherer.assigned_node != Some(node) {
+            gatherer.assigned_node = Some(node);
+        }
+
+        let position = transform.translation.truncate();
+        let node_position = nodes.get(node).ok()
+            .filter(|(node, _)| !node.is_depleted())
+            .map(|(_, node_transform)| node_transform.translation().truncate());
+        match gatherer.carrying {
+            Some((_, amount)) if amount >= gatherer.carry_capacity => gatherer.returning = true,
+            None if gatherer.returning => gatherer.returning = false,
+            _ => {}
+        }
+
+        let (target, reach) = match (gatherer.carrying, node_position) {
+            (Some((resource_type, _)), _) if gatherer.returning || node_position.is_none() => {
+                let storage = storages.iter()
+                    .filter(|(storage, _, storage_owner)| {
+                        (owner.is_none() || owner == *storage_owner) && storage.accepts(resource_type)
+                    })
+                    .map(|(_, storage_transform, _)| storage_transform.translation().truncate())
+                    .min_by(|a, b| a.distance_squared(position).total_cmp(&b.distance_squared(position)));
+                // With nowhere to take the load, wait where the unit is
+                let Some(storage) = storage else { continue };
+                (storage, DELIVERY_RANGE)
+            }
+            (_, Some(node_position)) => (node_position, gatherer.gather_range),
+            (_, None) => {
+                queue.complete_current();
+                gatherer.assigned_node = None;
+                gatherer.returning = false;
+                commands.entity(entity).remove::<(NavPath, Destination)>();
+                continue;
+            }
+        };
+        let heading_elsewhere = destination.is_none_or(|destination| destination.0.distance(target) > reach);
+        if position.distance(target) > reach && heading_elsewhere {
+            commands.entity(entity).insert(Destination(target));
+        }
+    }
+}
+
+/// System to draw the queued orders of the selected units as waypoint markers
+pub fn draw_order_waypoints(
+    local_player: Res<LocalPlayer>,
+    units: Query<(&OrderQueue, &Transform, &Owner), With<Selected>>,
+    targets: Query<&GlobalTransform>,
+    mut gizmos: Gizmos,
+) {
+    for (queue, transform, owner) in units.iter() {
+        if owner.0 != local_player.0 {
+            continue;
+        }
+        let mut from = transform.translation.truncate();
+        for command in queue.iter() {
+            let (point, color) = match *command {
+                Command::Move { target } | Command::FormationMove { target, .. } => (target, Color::srgb(0.3, 1.0, 0.4)),
+                Command::AttackMove { target } => (target, Color::srgb(1.0, 0.5, 0.2)),
+                Command::Patrol { target } => (target, Color::srgb(0.3, 0.7, 1.0)),
+                Command::Attack { target } => match targets.get(target) {
+                    Ok(target) => (target.translation().truncate(), Color::srgb(1.0, 0.2, 0.2)),
+                    Err(_) => continue,
+                },
+                Command::Gather { node } => match targets.get(node) {
+                    Ok(node) => (node.translation().truncate(), Color::srgb(1.0, 0.9, 0.3)),
+                    Err(_) => continue,
+                },
+                Command::Construct { site: target } | Command::Repair { target } => match targets.get(target) {
+                    Ok(target) => (target.translation().truncate(), Color::srgb(0.9, 0.6, 1.0)),
+                    Err(_) => continue,
+                },
+                Command::Stop | Command::HoldPosition | Command::SetStance { .. } => continue,
+            };
+            gizmos.line_2d(from, point, color.with_alpha(0.4));
+            gizmos.circle_2d(point, 1.5, color);
+            from = point;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shift_queue_and_patrol() {
+        let mut world = World::new();
+        world.init_resource::<Events<IssueCommand>>();
+        world.init_resource::<Events<MovementFinished>>();
+        world.init_resource::<Events<PathFailed>>();
+        world.init_resource::<FormationDefs>();
+        let player = PlayerId(0);
+        let unit = world.spawn((
+            Owner(player),
+            OrderQueue::default(),
+            Movement::default(),
+            Transform::default(),
+        )).id();
+        let enemy_unit = world.spawn((Owner(PlayerId(1)), OrderQueue::default())).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((advance_orders, process_issued_commands, start_orders).chain());
+
+        let send = |world: &mut World, units: Vec<Entity>, command: Command, queued: bool| {
+            world.send_event(IssueCommand { player, units, command, queued });
+        };
+        let a = Vec2::new(10.0, 0.0);
+        let b = Vec2::new(20.0, 0.0);
+        send(&mut world, vec![unit, enemy_unit], Command::Move { target: a }, false);
+        send(&mut world, vec![unit], Command::Patrol { target: b }, true);
+        schedule.run(&mut world);
+
+        assert_eq!(world.get::<Destination>(unit), Some(&Destination(a)));
+        assert!(world.get::<OrderQueue>(enemy_unit).unwrap().is_empty());
+
+        // Arriving finishes the move and starts the queued patrol
+        world.entity_mut(unit).insert(Transform::from_translation(a.extend(0.0)));
+        world.send_event(MovementFinished { entity: unit });
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Destination>(unit), Some(&Destination(b)));
+
+        // Reaching the end of the patrol turns the unit around
+        world.entity_mut(unit).insert(Transform::from_translation(b.extend(0.0)));
+        world.send_event(MovementFinished { entity: unit });
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Destination>(unit), Some(&Destination(a)));
+
+        // A fight on the way does not move the patrol's endpoints
+        world.entity_mut(unit).insert(Transform::from_xyz(15.0, 8.0, 0.0));
+        world.get_mut::<OrderQueue>(unit).unwrap().restart_current();
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Destination>(unit), Some(&Destination(a)));
+        world.entity_mut(unit).insert(Transform::from_translation(a.extend(0.0)));
+        world.send_event(MovementFinished { entity: unit });
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Destination>(unit), Some(&Destination(b)));
+
+        // A new order without shift replaces the patrol
+        send(&mut world, vec![unit], Command::Stop, false);
+        schedule.run(&mut world);
+        assert!(world.get::<Destination>(unit).is_none());
+        assert!(world.get::<OrderQueue>(unit).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_gather_orders_make_trips() {
+        use crate::resources::{handle_resource_gathering, NodeDepleted, ResourceAmount, ResourceGathered, ResourceType};
+        use std::time::Duration;
+
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.init_resource::<Events<IssueCommand>>();
+        world.init_resource::<Events<ResourceGathered>>();
+        world.init_resource::<Events<NodeDepleted>>();
+        world.init_resource::<FormationDefs>();
+        let player = PlayerId(0);
+        let storage = Vec2::ZERO;
+        let ordered = Vec2::new(20.5, 0.0);
+        let unit = world.spawn((
+            Owner(player),
+            OrderQueue::default(),
+            ResourceGatherer::default(),
+            Movement::default(),
+            Transform::default(),
+        )).id();
+        let node_at = |position: Vec2| (
+            ResourceNode::new(ResourceType::Wood, 100.0),
+            Transform::from_translation(position.extend(0.0)),
+            GlobalTransform::from_translation(position.extend(0.0)),
+        );
+        let node = world.spawn(node_at(ordered)).id();
+        let decoy = world.spawn(node_at(Vec2::new(19.0, 1.0))).id();
+        world.spawn((Owner(player), ResourceStorage::new(), GlobalTransform::from_translation(storage.extend(0.0))));
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((process_issued_commands, start_orders, run_gather_orders, handle_resource_gathering).chain());
+
+        world.send_event(IssueCommand { player, units: vec![unit], command: Command::Gather { node }, queued: false });
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Destination>(unit), Some(&Destination(ordered)));
+
+        // Gathering sticks to the ordered node even with a closer one in reach
+        world.entity_mut(unit).insert(Transform::from_xyz(19.0, 0.0, 0.0)).remove::<Destination>();
+        world.resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        schedule.run(&mut world);
+        assert!(world.get::<ResourceNode>(node).unwrap().amount < 100.0);
+        assert_eq!(world.get::<ResourceNode>(decoy).unwrap().amount, 100.0);
+        assert!(world.get::<Destination>(unit).is_none());
+
+        // A full load is carried to storage, and an empty gatherer heads back
+        world.get_mut::<ResourceGatherer>(unit).unwrap().carrying = Some((ResourceType::Wood, ResourceAmount::from_units(10)));
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Destination>(unit), Some(&Destination(storage)));
+        world.entity_mut(unit).insert(Transform::from_xyz(1.0, 0.0, 0.0)).remove::<Destination>();
+        world.get_mut::<ResourceGatherer>(unit).unwrap().carrying = None;
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Destination>(unit), Some(&Destination(ordered)));
+
+        // The order ends once the node is gone
+        world.despawn(node);
+        schedule.run(&mut world);
+        assert!(world.get::<OrderQueue>(unit).unwrap().is_empty());
+        assert_eq!(world.get::<ResourceGatherer>(unit).unwrap().assigned_node, None);
+    }
+}
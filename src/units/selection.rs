@@ -0,0 +1,302 @@
+//! Selecting units and buildings with the mouse
+//!
+//! Left click selects the entity under the cursor, dragging selects everything inside the
+//! rectangle, shift adds to the current selection and double-clicking selects every visible
+//! entity of the same kind. Only the local player's entities can be selected.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use crate::camera_controls::GameCursor;
+use crate::player::{LocalPlayer, Owner};
+use super::SelectionFocus;
+
+/// Cursor movement in pixels before a press counts as a drag rather than a click
+const DRAG_THRESHOLD: f32 = 5.0;
+
+/// Seconds between two clicks on the same entity for them to count as a double-click
+const DOUBLE_CLICK_SECONDS: f64 = 0.3;
+
+/// Component for entities the player can select
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Selectable {
+    /// Kind of entity, used to select all of a type at once
+    pub kind: String,
+    /// Radius in meters within which a click hits the entity
+    pub radius: f32,
+}
+
+impl Default for Selectable {
+    fn default() -> Self {
+        Self {
+            kind: String::new(),
+            radius: 4.0,
+        }
+    }
+}
+
+impl Selectable {
+    /// Create a selectable entity of the given kind
+    pub fn new(kind: impl Into<String>, radius: f32) -> Self {
+        Self { kind: kind.into(), radius }
+    }
+}
+
+/// Marker component for currently selected entities
+#[derive(Component, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct Selected;
+
+/// The set of selected entities changed
+#[derive(Event, Debug, Clone)]
+pub struct SelectionChanged {
+    /// Every entity now selected
+    pub selected: Vec<Entity>,
+}
+
+/// Resource tracking mouse interaction for selection
+#[derive(Resource, Debug, Clone, Default)]
+pub struct SelectionInput {
+    /// Screen position where the left button was pressed
+    pub drag_start: Option<Vec2>,
+    last_click: Option<(Entity, f64)>,
+}
+
+impl SelectionInput {
+    /// Get the screen-space rectangle being dragged, if the drag is past the threshold
+    pub fn drag_rect(&self, cursor: Vec2) -> Option<Rect> {
+        let start = self.drag_start?;
+        (start.distance(cursor) >= DRAG_THRESHOLD).then(|| Rect::from_corners(start, cursor))
+    }
+
+    /// Record a click on an entity, returning whether it completes a double-click
+    fn register_click(&mut self, entity: Entity, now: f64) -> bool {
+        let double_click = self.last_click
+            .is_some_and(|(last, at)| last == entity && now - at <= DOUBLE_CLICK_SECONDS);
+        // A double-click is used up rather than starting the next one
+        self.last_click = (!double_click).then_some((entity, now));
+        double_click
+    }
+}
+
+/// One of the local player's selectable entities and its position on the map
+type Candidate<'a> = (Entity, &'a Selectable, Vec2);
+
+/// Get the candidates inside a rectangle on the map, optionally only those of one kind
+fn candidates_in_rect(candidates: &[Candidate], rect: Rect, kind: Option<&str>) -> Vec<Entity> {
+    candidates.iter()
+        .filter(|(_, selectable, position)| {
+            rect.contains(*position) && kind.is_none_or(|kind| selectable.kind == kind)
+        })
+        .map(|(entity, ..)| *entity)
+        .collect()
+}
+
+/// Get the candidate under a point on the map, preferring the closest when several overlap
+fn candidate_at<'a>(candidates: &[Candidate<'a>], point: Vec2) -> Option<Candidate<'a>> {
+    candidates.iter()
+        .copied()
+        .filter(|(_, selectable, position)| position.distance(point) <= selectable.radius)
+        .min_by(|a, b| a.2.distance(point).total_cmp(&b.2.distance(point)))
+}
+
+/// Entities that can be selected, and which of them already are
+#[derive(SystemParam)]
+pub struct Selectables<'w, 's> {
+    local_player: Res<'w, LocalPlayer>,
+    all: Query<'w, 's, (Entity, &'static Selectable, &'static GlobalTransform, &'static Owner)>,
+    selected: Query<'w, 's, Entity, With<Selected>>,
+}
+
+/// How a new selection combines with the current one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SelectMode {
+    Replace,
+    Add,
+}
+
+/// System to select entities from mouse clicks and drags
+pub fn handle_selection_input(
+    mut commands: Commands,
+    time: Res<Time>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    game_cursor: GameCursor,
+    mut input: ResMut<SelectionInput>,
+    selectables: Selectables,
+) {
+    let Some(cursor) = game_cursor.screen_position() else { return };
+
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        input.drag_start = Some(cursor);
+    }
+    if !mouse_buttons.just_released(MouseButton::Left) {
+        return;
+    }
+    // The press was used for something else, such as placing a building
+    if input.drag_start.is_none() {
+        return;
+    }
+
+    let mode = if keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        SelectMode::Add
+    } else {
+        SelectMode::Replace
+    };
+    let to_world = |screen: Vec2| game_cursor.to_world(screen);
+    let own: Vec<Candidate> = selectables.all.iter()
+        .filter(|(.., owner)| owner.0 == selectables.local_player.0)
+        .map(|(entity, selectable, transform, _)| (entity, selectable, transform.translation().truncate()))
+        .collect();
+
+    let picked: Vec<Entity> = if let Some(rect) = input.drag_rect(cursor) {
+        // Box select everything whose centre is inside the dragged rectangle
+        let (Some(a), Some(b)) = (to_world(rect.min), to_world(rect.max)) else { return };
+        input.last_click = None;
+        candidates_in_rect(&own, Rect::from_corners(a, b), None)
+    } else {
+        let Some(point) = to_world(cursor) else { return };
+        match candidate_at(&own, point) {
+            Some((entity, selectable, _)) => {
+                if input.register_click(entity, time.elapsed_seconds_f64()) {
+                    // Select every entity of the same kind currently on screen
+                    let Some(size) = game_cursor.window_size() else { return };
+                    let (Some(a), Some(b)) = (to_world(Vec2::ZERO), to_world(size)) else { return };
+                    candidates_in_rect(&own, Rect::from_corners(a, b), Some(&selectable.kind))
+                } else {
+                    vec![entity]
+                }
+            }
+            None => {
+                input.last_click = None;
+                Vec::new()
+            }
+        }
+    };
+    input.drag_start = None;
+
+    if mode == SelectMode::Replace {
+        for entity in selectables.selected.iter().filter(|entity| !picked.contains(entity)) {
+            commands.entity(entity).remove::<Selected>();
+        }
+    }
+    for entity in picked {
+        if !selectables.selected.contains(entity) {
+            commands.entity(entity).insert(Selected);
+        }
+    }
+}
+
+/// System to announce selection changes, including selected entities being despawned
+pub fn notify_selection_changed(
+    added: Query<(), Added<Selected>>,
+    mut removed: RemovedComponents<Selected>,
+    selected: Query<Entity, With<Selected>>,
+    mut changed_events: EventWriter<SelectionChanged>,
+) {
+    let any_removed = removed.read().count() > 0;
+    if any_removed || !added.is_empty() {
+        let mut selected: Vec<Entity> = selected.iter().collect();
+        selected.sort();
+        changed_events.send(SelectionChanged { selected });
+    }
+}
+
+/// System to clear the selection of entities that changed hands
+pub fn deselect_lost_entities(
+    mut commands: Commands,
+    local_player: Res<LocalPlayer>,
+    selected: Query<(Entity, Option<&Owner>), With<Selected>>,
+) {
+    for (entity, owner) in selected.iter() {
+        if owner.map(|owner| owner.0) != Some(local_player.0) {
+            commands.entity(entity).remove::<Selected>();
+        }
+    }
+}
+
+/// System to draw selection rings and the drag rectangle
+pub fn draw_selection(
+    game_cursor: GameCursor,
+    input: Res<SelectionInput>,
+    focus: Res<SelectionFocus>,
+    selected: Query<(&Selectable, &GlobalTransform), With<Selected>>,
+    mut gizmos: Gizmos,
+) {
+    let ring_color = Color::srgb(0.3, 1.0, 0.4);
+    for (selectable, transform) in selected.iter() {
+        // Units of the focused type stand out from the rest of the selection
+        let focused = focus.kind.as_deref() == Some(selectable.kind.as_str());
+        let color = if focused { ring_color } else { ring_color.with_alpha(0.5) };
+        gizmos.circle_2d(transform.translation().truncate(), selectable.radius, color);
+    }
+
+    let Some(rect) = game_cursor.screen_position().and_then(|cursor| input.drag_rect(cursor)) else { return };
+    let (Some(a), Some(b)) = (game_cursor.to_world(rect.min), game_cursor.to_world(rect.max)) else {
+        return;
+    };
+    let world_rect = Rect::from_corners(a, b);
+    gizmos.rect_2d(world_rect.center(), 0.0, world_rect.size(), ring_color);
+}
+
+/// System to clear the selection when leaving a game
+pub fn clear_selection(mut commands: Commands, selected: Query<Entity, With<Selected>>, mut input: ResMut<SelectionInput>) {
+    for entity in selected.iter() {
+        commands.entity(entity).remove::<Selected>();
+    }
+    *input = SelectionInput::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::PlayerId;
+
+    #[test]
+    fn test_only_local_player_entities_stay_selected() {
+        let mut world = World::new();
+        world.insert_resource(LocalPlayer(PlayerId(0)));
+        let own = world.spawn((Selectable::new("worker", 4.0), Owner(PlayerId(0)), Selected)).id();
+        let enemy = world.spawn((Selectable::new("worker", 4.0), Owner(PlayerId(1)), Selected)).id();
+        let unowned = world.spawn((Selectable::new("worker", 4.0), Selected)).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(deselect_lost_entities);
+        schedule.run(&mut world);
+
+        assert!(world.get::<Selected>(own).is_some());
+        assert!(world.get::<Selected>(enemy).is_none());
+        assert!(world.get::<Selected>(unowned).is_none());
+    }
+
+    #[test]
+    fn test_box_and_double_click_selection() {
+        let mut world = World::new();
+        let tank = Selectable::new("tank", 4.0);
+        let worker = Selectable::new("worker", 4.0);
+        let entities: Vec<Entity> = (0..4).map(|_| world.spawn_empty().id()).collect();
+        let candidates: Vec<Candidate> = vec![
+            (entities[0], &tank, Vec2::new(0.0, 0.0)),
+            (entities[1], &tank, Vec2::new(5.0, 0.0)),
+            (entities[2], &worker, Vec2::new(20.0, 20.0)),
+            (entities[3], &tank, Vec2::new(200.0, 0.0)),
+        ];
+
+        // Boxes pick by centre, whichever way they were dragged
+        let dragged = Rect::from_corners(Vec2::new(30.0, 30.0), Vec2::new(-1.0, -1.0));
+        assert_eq!(candidates_in_rect(&candidates, dragged, None), entities[..3]);
+        assert_eq!(candidates_in_rect(&candidates, dragged, Some("tank")), entities[..2]);
+
+        // Clicks pick the closest of overlapping entities and nothing outside their radius
+        assert_eq!(candidate_at(&candidates, Vec2::new(3.0, 0.0)).map(|c| c.0), Some(entities[1]));
+        assert_eq!(candidate_at(&candidates, Vec2::new(10.0, 10.0)).map(|c| c.0), None);
+
+        // Only two quick clicks on the same entity make a double-click
+        let mut input = SelectionInput::default();
+        assert!(!input.register_click(entities[0], 1.0));
+        assert!(!input.register_click(entities[1], 1.1));
+        assert!(input.register_click(entities[1], 1.3));
+        assert!(!input.register_click(entities[1], 1.4));
+        assert!(!input.register_click(entities[1], 2.0));
+    }
+}
@@ -84,6 +84,21 @@ pub fn spawn_initial_world(
     });
 }
 
+/// Generates the terrain type of every tile, indexed `[y][x]`, without spawning anything
+pub fn generate_terrain(config: &WorldConfig) -> Vec<Vec<TerrainType>> {
+    let height_map = generate_height_map(config);
+    let moisture_map = generate_moisture_map(&height_map, config.seed);
+    height_map.iter()
+        .zip(&moisture_map)
+        .map(|(heights, moistures)| {
+            heights.iter()
+                .zip(moistures)
+                .map(|(&height, &moisture)| determine_terrain_type(config, height, moisture))
+                .collect()
+        })
+        .collect()
+}
+
 /// Resource storing the world dimensions
 #[derive(Resource, Debug, Clone, Copy)]
 pub struct WorldDimensions {
@@ -2,19 +2,21 @@
 
 mod border;
 mod generation;
-mod render;
+pub(crate) mod render;
 mod resource_types;
 mod resources;
 mod terrain_render;
 
 /// Represents different terrain types with their properties
-#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Reflect)]
 pub enum TerrainType {
     Grassland,
     Forest,
     Mountains,
     Desert,
     Water,
+    /// Mountainside whose deposits have been mined out
+    Quarry,
 }
 
 impl Default for TerrainType {
@@ -34,15 +36,22 @@ pub struct TerrainTile {
 
 // Re-exports
 pub use border::{MapBorder, MapBorderPlugin};
+pub use generation::generate_terrain;
 pub use render::ResourceRenderPlugin;
-pub use resource_types::{ResourceType, ResourceNode};
-pub use resources::ResourceNodeMarker;
+pub use resource_types::{
+    ResourceType, ResourceNode, RegenerationProfile, DepletionBehavior, NodeLifecycle, regenerate_nodes,
+};
+pub use resources::{ResourceNodeMarker, ResourceSpawnTable, ResourceSpawnPlanner, StartPositions};
 pub use terrain_render::TerrainRenderPlugin;
 
 use bevy::prelude::*;
 use bevy::reflect::Reflect;
+use serde::{Serialize, Deserialize};
 use crate::state::GameState;
 
+/// Resource density that spawns each deposit at exactly its spawn table density
+pub const DEFAULT_RESOURCE_DENSITY: f32 = 0.1;
+
 /// Plugin for world-related functionality
 pub struct WorldPlugin;
 
@@ -62,11 +71,13 @@ impl Plugin for WorldPlugin {
            ));
         
         // Add systems
-        app.add_systems(OnEnter(GameState::InGame { is_paused: false }), (
+        app.add_systems(Startup, resources::load_resource_spawn_table)
+        .add_systems(OnEnter(GameState::InGame { is_paused: false }), (
             generation::spawn_initial_world,
             resources::spawn_initial_resources.after(generation::spawn_initial_world),
         ))
-        .add_systems(OnExit(GameState::InGame { is_paused: false }), cleanup_world);
+        .add_systems(OnExit(GameState::InGame { is_paused: false }), cleanup_world)
+        .add_systems(Update, regenerate_nodes::<ResourceNode>);
     }
 }
 
@@ -140,6 +151,11 @@ impl WorldConfig {
         self.height_meters - 2.0 * self.border_width
     }
     
+    /// Get the multiplier applied to spawn table densities (1.0 at the default density)
+    pub fn resource_density_scale(&self) -> f32 {
+        self.resource_density / DEFAULT_RESOURCE_DENSITY
+    }
+    
     /// Convert world position to tile coordinates
     pub fn world_to_tile(&self, pos: Vec2) -> (i32, i32) {
         (
@@ -168,7 +184,7 @@ impl Default for WorldConfig {
             // Reduced border width proportionally
             border_width: 50.0,     // 50m border
             seed: rand::random(),
-            resource_density: 0.1,
+            resource_density: DEFAULT_RESOURCE_DENSITY,
             terrain_scale: 5.0,
             water_level: 0.3,
             mountain_level: 0.7,
@@ -1,5 +1,5 @@
 use bevy::prelude::*;
-use crate::world::resource_types::{ResourceType, ResourceNode};
+use crate::world::resource_types::{ResourceType, ResourceNode, NodeLifecycle};
 
 /// Component for resource node visuals
 #[derive(Component)]
@@ -8,9 +8,9 @@ pub struct ResourceNodeVisual;
 /// System to add sprites to resource nodes
 pub fn add_resource_visuals(
     mut commands: Commands,
-    query: Query<(Entity, &ResourceNode), Without<ResourceNodeVisual>>,
+    query: Query<(Entity, &ResourceNode, &Transform), Without<ResourceNodeVisual>>,
 ) {
-    for (entity, resource_node) in &query {
+    for (entity, resource_node, transform) in &query {
         let resource_type = &resource_node.resource_type;
         let color = match resource_type {
             ResourceType::Minerals => Color::srgb(0.5, 0.5, 0.5),   // Gray
@@ -27,6 +27,12 @@ pub fn add_resource_visuals(
                     custom_size: Some(Vec2::splat(10.0)),
                     ..default()
                 },
+                // Keep the node where it was spawned, scaled to its remaining resources
+                transform: transform.with_scale(Vec3::new(
+                    node_visual_scale(resource_node.fill_fraction()),
+                    node_visual_scale(resource_node.fill_fraction()),
+                    1.0,
+                )),
                 ..default()
             },
             ResourceNodeVisual,
@@ -34,11 +40,28 @@ pub fn add_resource_visuals(
     }
 }
 
+/// Get the visual scale for a resource node with the given fraction of its resources left
+///
+/// Nodes shrink as they are harvested but never below 40% so they stay clickable.
+pub fn node_visual_scale(fill_fraction: f32) -> f32 {
+    0.4 + 0.6 * fill_fraction.clamp(0.0, 1.0)
+}
+
+type NodeSprites<'w, 's, T> = Query<'w, 's, (&'static T, &'static mut Transform), (With<Sprite>, Changed<T>)>;
+
+/// System to shrink resource node sprites as their resources run out
+pub fn scale_node_visuals<T: NodeLifecycle>(mut query: NodeSprites<T>) {
+    for (node, mut transform) in &mut query {
+        let scale = node_visual_scale(node.fill_fraction());
+        transform.scale = Vec3::new(scale, scale, 1.0);
+    }
+}
+
 /// Plugin for resource rendering
 pub struct ResourceRenderPlugin;
 
 impl Plugin for ResourceRenderPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, add_resource_visuals);
+        app.add_systems(Update, (add_resource_visuals, scale_node_visuals::<ResourceNode>));
     }
 }
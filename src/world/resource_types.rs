@@ -1,7 +1,9 @@
 use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use super::TerrainType;
 
 /// Types of resources that can exist in the world
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
 pub enum ResourceType {
     Minerals,
     Gas,
@@ -10,6 +12,73 @@ pub enum ResourceType {
     Food,
 }
 
+/// How a resource node recovers over time
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, Reflect)]
+pub enum RegenerationProfile {
+    /// The node never regrows
+    #[default]
+    None,
+    /// Regrows at a constant rate (units per second)
+    Linear {
+        /// Units regained per second
+        rate: f32,
+    },
+    /// Regrows fastest when half full and slows near empty and near full
+    ///
+    /// A node harvested down to nothing never recovers, like a clear-cut forest.
+    Logistic {
+        /// Growth rate per second relative to the current amount
+        rate: f32,
+    },
+    /// Regrows linearly, but only after sitting fully depleted for a while
+    Delayed {
+        /// Seconds the node must stay empty before regrowth starts
+        delay: f32,
+        /// Units regained per second once regrowth has started
+        rate: f32,
+    },
+}
+
+impl RegenerationProfile {
+    /// Whether a node with this profile can recover after being fully depleted
+    pub fn recovers_from_depletion(&self) -> bool {
+        matches!(self, RegenerationProfile::Linear { .. } | RegenerationProfile::Delayed { .. })
+    }
+
+    /// Compute a node's new amount after `delta` seconds
+    ///
+    /// `depleted_for` is how long the node has been fully empty, if it is.
+    pub fn regenerate(&self, amount: f32, max_amount: f32, delta: f32, depleted_for: Option<f32>) -> f32 {
+        if amount >= max_amount || max_amount <= 0.0 {
+            return amount.min(max_amount);
+        }
+        let gained = match *self {
+            RegenerationProfile::None => 0.0,
+            RegenerationProfile::Linear { rate } => rate * delta,
+            RegenerationProfile::Logistic { rate } => {
+                rate * amount * (1.0 - amount / max_amount) * delta
+            }
+            RegenerationProfile::Delayed { delay, rate } => match depleted_for {
+                Some(elapsed) if elapsed < delay => 0.0,
+                _ => rate * delta,
+            },
+        };
+        (amount + gained.max(0.0)).min(max_amount)
+    }
+}
+
+/// What happens to a resource node once it is fully depleted and cannot recover
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, Reflect)]
+pub enum DepletionBehavior {
+    /// The empty node stays in the world
+    #[default]
+    Remain,
+    /// The node is removed
+    Despawn,
+    /// The node is removed and the terrain tile beneath it changes type
+    ConvertTerrain(TerrainType),
+}
+
 /// Component representing a resource node in the world
 #[derive(Component, Debug, Clone, Copy, Reflect)]
 #[reflect(Component)]
@@ -20,33 +89,166 @@ pub struct ResourceNode {
     pub amount: f32,
     /// Maximum capacity of this node
     pub max_amount: f32,
-    /// Whether this resource can regenerate over time
-    pub can_regenerate: bool,
-    /// Current regeneration rate (units per second)
-    pub regen_rate: f32,
+    /// How this resource regenerates over time
+    pub regeneration: RegenerationProfile,
+    /// What happens once the node is exhausted
+    pub on_depleted: DepletionBehavior,
+    /// Seconds the node has been fully depleted, if it is
+    pub depleted_for: Option<f32>,
 }
 
 impl ResourceNode {
     /// Creates a new ResourceNode with the specified properties
-    pub fn new(resource_type: ResourceType, amount: f32, can_regenerate: bool) -> Self {
+    pub fn new(resource_type: ResourceType, amount: f32) -> Self {
         Self {
             resource_type,
             amount,
             max_amount: amount,
-            can_regenerate,
-            regen_rate: if can_regenerate { 1.0 } else { 0.0 },
+            regeneration: RegenerationProfile::None,
+            on_depleted: DepletionBehavior::Remain,
+            depleted_for: None,
         }
     }
+
+    /// Set how the node regenerates
+    pub fn with_regeneration(mut self, regeneration: RegenerationProfile) -> Self {
+        self.regeneration = regeneration;
+        self
+    }
+
+    /// Set what happens when the node is exhausted
+    pub fn with_depletion(mut self, on_depleted: DepletionBehavior) -> Self {
+        self.on_depleted = on_depleted;
+        self
+    }
 }
 
 impl Default for ResourceNode {
     fn default() -> Self {
-        Self {
-            resource_type: ResourceType::Minerals,
-            amount: 100.0,
-            max_amount: 100.0,
-            can_regenerate: false,
-            regen_rate: 0.0,
+        Self::new(ResourceType::Minerals, 100.0)
+    }
+}
+
+/// Lifecycle shared by the world's resource nodes and the economy's gatherable nodes
+pub trait NodeLifecycle: Component {
+    /// Current and maximum amount of the node
+    fn amounts(&self) -> (f32, f32);
+
+    /// How the node regenerates over time
+    fn regeneration(&self) -> RegenerationProfile;
+
+    /// What happens once the node is exhausted and cannot recover
+    fn on_depleted(&self) -> DepletionBehavior;
+
+    /// Seconds the node has been fully depleted, if it is
+    fn depleted_for(&self) -> Option<f32>;
+
+    /// Update the node's amount and how long it has been depleted
+    fn set_progress(&mut self, amount: f32, depleted_for: Option<f32>);
+
+    /// Fraction of the node's capacity remaining (0.0 to 1.0)
+    fn fill_fraction(&self) -> f32 {
+        let (amount, max_amount) = self.amounts();
+        if max_amount > 0.0 {
+            (amount / max_amount).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether the node is empty and will never grow back
+    fn is_exhausted(&self) -> bool {
+        self.amounts().0 <= 0.0 && !self.regeneration().recovers_from_depletion()
+    }
+}
+
+impl NodeLifecycle for ResourceNode {
+    fn amounts(&self) -> (f32, f32) {
+        (self.amount, self.max_amount)
+    }
+
+    fn regeneration(&self) -> RegenerationProfile {
+        self.regeneration
+    }
+
+    fn on_depleted(&self) -> DepletionBehavior {
+        self.on_depleted
+    }
+
+    fn depleted_for(&self) -> Option<f32> {
+        self.depleted_for
+    }
+
+    fn set_progress(&mut self, amount: f32, depleted_for: Option<f32>) {
+        self.amount = amount;
+        self.depleted_for = depleted_for;
+    }
+}
+
+/// System to regenerate resource nodes according to their profiles
+pub fn regenerate_nodes<T: NodeLifecycle>(
+    time: Res<Time>,
+    mut query: Query<&mut T>,
+) {
+    let delta = time.delta_seconds();
+    for mut node in query.iter_mut() {
+        let (amount, max_amount) = node.amounts();
+        // Track how long the node has been empty for delayed regrowth
+        let depleted_for = (amount <= 0.0).then(|| node.depleted_for().unwrap_or(0.0) + delta);
+        let regenerated = node.regeneration().regenerate(amount, max_amount, delta, depleted_for);
+        // Only touch nodes that changed so visuals aren't rescaled every frame
+        if regenerated != amount || depleted_for != node.depleted_for() {
+            node.set_progress(regenerated, depleted_for);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_regeneration_profiles() {
+        assert_eq!(RegenerationProfile::None.regenerate(10.0, 100.0, 1.0, None), 10.0);
+        assert_eq!(RegenerationProfile::Linear { rate: 5.0 }.regenerate(10.0, 100.0, 2.0, None), 20.0);
+        assert_eq!(RegenerationProfile::Linear { rate: 5.0 }.regenerate(98.0, 100.0, 2.0, None), 100.0);
+
+        // Logistic growth is fastest at half capacity and stalls once clear-cut
+        let logistic = RegenerationProfile::Logistic { rate: 0.1 };
+        let near_empty = logistic.regenerate(5.0, 100.0, 1.0, None) - 5.0;
+        let half = logistic.regenerate(50.0, 100.0, 1.0, None) - 50.0;
+        let near_full = logistic.regenerate(95.0, 100.0, 1.0, None) - 95.0;
+        assert!(half > near_empty && half > near_full);
+        assert_eq!(logistic.regenerate(0.0, 100.0, 1.0, Some(10.0)), 0.0);
+        assert!(!logistic.recovers_from_depletion());
+
+        // Delayed regrowth waits for the node to sit empty long enough
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        let node = world.spawn(
+            ResourceNode::new(ResourceType::Gas, 10.0)
+                .with_regeneration(RegenerationProfile::Delayed { delay: 3.0, rate: 1.0 }),
+        ).id();
+        world.get_mut::<ResourceNode>(node).unwrap().amount = 0.0;
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(regenerate_nodes::<ResourceNode>);
+        for _ in 0..2 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            schedule.run(&mut world);
+        }
+        let waiting = *world.get::<ResourceNode>(node).unwrap();
+        assert_eq!(waiting.amount, 0.0);
+        assert_eq!(waiting.depleted_for, Some(2.0));
+        assert!(!waiting.is_exhausted());
+
+        for _ in 0..3 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            schedule.run(&mut world);
         }
+        let regrowing = *world.get::<ResourceNode>(node).unwrap();
+        assert!(regrowing.amount > 0.0);
+        assert_eq!(regrowing.depleted_for, None);
     }
 }
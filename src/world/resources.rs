@@ -1,29 +1,44 @@
 use bevy::prelude::*;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::path::Path;
+use crate::data_file::{parse_builtin, read_data_file, DataFileError};
 use super::{WorldConfig, GameWorld, TerrainType, TerrainTile};
-use super::resource_types::{ResourceType, ResourceNode};
+use super::resource_types::{ResourceType, ResourceNode, RegenerationProfile, DepletionBehavior};
+
+/// Default location of the resource spawn table
+pub const SPAWN_TABLE_FILE: &str = "assets/data/resource_spawns.ron";
 
 /// Component marking a resource node in the world
 #[derive(Component, Debug, Default, Clone, Copy, Reflect)]
 #[reflect(Component)]
 pub struct ResourceNodeMarker;
 
-/// Configuration for resource spawning
-#[derive(Debug, Clone)]
+/// Configuration for spawning one kind of resource deposit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ResourceSpawnConfig {
     /// Type of resource to spawn
     pub resource_type: ResourceType,
     /// Base amount of resources in each node
     pub base_amount: f32,
-    /// Whether the resource can regenerate
-    pub can_regenerate: bool,
-    /// Biomes where this resource can spawn
-    pub spawn_biomes: Vec<TerrainType>,
-    /// Density of this resource (0.0 to 1.0)
-    pub density: f32,
     /// Variation in amount (0.0 to 1.0)
     pub amount_variation: f32,
+    /// How nodes regenerate over time
+    pub regeneration: RegenerationProfile,
+    /// What happens to nodes once they are exhausted
+    pub on_depleted: DepletionBehavior,
+    /// Fraction of matching tiles that seed a deposit field at the default resource density
+    pub density: f32,
+    /// Minimum and maximum number of nodes in each deposit field
+    pub cluster_size: (u32, u32),
+    /// Radius in meters within which a field's nodes are scattered
+    pub cluster_radius: f32,
+    /// Minimum distance in meters between any two nodes
+    pub min_spacing: f32,
 }
 
 impl Default for ResourceSpawnConfig {
@@ -31,12 +46,240 @@ impl Default for ResourceSpawnConfig {
         Self {
             resource_type: ResourceType::Minerals, // Using the default resource type
             base_amount: 50.0,
-            can_regenerate: true,
-            spawn_biomes: vec![TerrainType::Forest, TerrainType::Grassland],
-            density: 0.1,
             amount_variation: 0.3,
+            regeneration: RegenerationProfile::None,
+            on_depleted: DepletionBehavior::Remain,
+            density: 0.1,
+            cluster_size: (1, 1),
+            cluster_radius: 0.0,
+            min_spacing: 10.0,
+        }
+    }
+}
+
+/// A deposit placed near every start position regardless of terrain density
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuaranteedDeposit {
+    /// What to spawn; `density` is ignored
+    pub deposit: ResourceSpawnConfig,
+    /// Biomes the deposit may be placed on (any land if empty)
+    #[serde(default)]
+    pub biomes: Vec<TerrainType>,
+    /// Maximum distance in meters from the start position
+    pub max_distance: f32,
+}
+
+/// Resource deposits to spawn on each biome, loaded from data
+#[derive(Resource, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceSpawnTable {
+    /// Deposits that can appear on each terrain type
+    #[serde(default)]
+    pub biomes: HashMap<TerrainType, Vec<ResourceSpawnConfig>>,
+    /// Deposits guaranteed near every start position
+    #[serde(default)]
+    pub guaranteed: Vec<GuaranteedDeposit>,
+}
+
+impl ResourceSpawnTable {
+    /// Load a spawn table from a RON file
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self, DataFileError> {
+        read_data_file(path)
+    }
+
+    /// Built-in spawn table used when no data file is available
+    pub fn builtin() -> Self {
+        parse_builtin(SPAWN_TABLE_FILE, include_str!("../../assets/data/resource_spawns.ron"))
+    }
+}
+
+/// Resource listing where players start on the current map
+#[derive(Resource, Debug, Clone, Default)]
+pub struct StartPositions(pub Vec<Vec2>);
+
+impl StartPositions {
+    /// Place `count` start positions evenly on a ring inside the playable area
+    pub fn ring(config: &WorldConfig, count: usize) -> Self {
+        let center = Vec2::new(config.width_meters, config.height_meters) / 2.0;
+        let radius = config.playable_width().min(config.playable_height()) * 0.35;
+        Self(
+            (0..count)
+                .map(|i| {
+                    let angle = std::f32::consts::TAU * i as f32 / count.max(1) as f32
+                        + std::f32::consts::FRAC_PI_4;
+                    center + Vec2::new(angle.cos(), angle.sin()) * radius
+                })
+                .collect(),
+        )
+    }
+}
+
+/// A resource node chosen by the spawn planner
+#[derive(Debug, Clone)]
+pub struct PlannedNode {
+    /// Where to place the node
+    pub position: Vec2,
+    /// The node to spawn
+    pub node: ResourceNode,
+}
+
+/// Lookup from world position to terrain type
+struct TerrainLookup<'a> {
+    tiles: HashMap<(i32, i32), TerrainType>,
+    config: &'a WorldConfig,
+}
+
+impl<'a> TerrainLookup<'a> {
+    fn new(tiles: &[(TerrainType, Vec2)], config: &'a WorldConfig) -> Self {
+        Self {
+            tiles: tiles.iter().map(|&(terrain, pos)| (Self::key(config, pos), terrain)).collect(),
+            config,
         }
     }
+
+    fn key(config: &WorldConfig, pos: Vec2) -> (i32, i32) {
+        let local = (pos - Vec2::splat(config.border_width)) / config.tile_size;
+        (local.x.round() as i32, local.y.round() as i32)
+    }
+
+    fn terrain_at(&self, pos: Vec2) -> Option<TerrainType> {
+        self.tiles.get(&Self::key(self.config, pos)).copied()
+    }
+}
+
+/// Decides where resource nodes go, independently of how the map was made
+///
+/// Procedurally generated maps and editor-authored maps both describe their terrain as a
+/// list of tiles and feed it through the same planner.
+pub struct ResourceSpawnPlanner<'a> {
+    table: &'a ResourceSpawnTable,
+    config: &'a WorldConfig,
+    terrain: TerrainLookup<'a>,
+    tiles: &'a [(TerrainType, Vec2)],
+    placed: Vec<PlannedNode>,
+    rng: StdRng,
+}
+
+impl<'a> ResourceSpawnPlanner<'a> {
+    /// Create a planner for a map made of `(terrain, tile position)` pairs
+    pub fn new(table: &'a ResourceSpawnTable, config: &'a WorldConfig, tiles: &'a [(TerrainType, Vec2)]) -> Self {
+        Self {
+            table,
+            config,
+            terrain: TerrainLookup::new(tiles, config),
+            tiles,
+            placed: Vec::new(),
+            rng: StdRng::seed_from_u64(config.seed as u64),
+        }
+    }
+
+    /// Plan every node: guaranteed deposits first so density fields cannot crowd them out
+    pub fn plan(mut self, start_positions: &[Vec2]) -> Vec<PlannedNode> {
+        let table = self.table;
+        for &start in start_positions {
+            for guaranteed in &table.guaranteed {
+                self.place_guaranteed(start, guaranteed);
+            }
+        }
+
+        let mut biomes: Vec<_> = table.biomes.keys().copied().collect();
+        // Iterate in a fixed order so the same seed always yields the same map
+        biomes.sort();
+        for biome in biomes {
+            let mut seeds: Vec<Vec2> = self.tiles.iter()
+                .filter(|(terrain, _)| *terrain == biome)
+                .map(|&(_, pos)| pos)
+                .collect();
+            for deposit in &table.biomes[&biome] {
+                seeds.shuffle(&mut self.rng);
+                let density = deposit.density * self.config.resource_density_scale();
+                let fields = (seeds.len() as f32 * density).round() as usize;
+                for &seed in seeds.iter().take(fields) {
+                    self.place_field(seed, deposit, &[biome]);
+                }
+            }
+        }
+
+        self.placed
+    }
+
+    fn place_guaranteed(&mut self, start: Vec2, guaranteed: &GuaranteedDeposit) {
+        let candidates: Vec<Vec2> = self.tiles.iter()
+            .filter(|(terrain, pos)| {
+                pos.distance(start) <= guaranteed.max_distance
+                    && if guaranteed.biomes.is_empty() {
+                        *terrain != TerrainType::Water
+                    } else {
+                        guaranteed.biomes.contains(terrain)
+                    }
+            })
+            .map(|&(_, pos)| pos)
+            .collect();
+        let Some(&seed) = candidates.choose(&mut self.rng) else {
+            warn!(
+                "No valid tiles for guaranteed {:?} deposit near start position {}",
+                guaranteed.deposit.resource_type, start,
+            );
+            return;
+        };
+        let biomes = guaranteed.biomes.clone();
+        self.place_field(seed, &guaranteed.deposit, &biomes);
+    }
+
+    /// Scatter a field of nodes around a seed point
+    fn place_field(&mut self, seed: Vec2, deposit: &ResourceSpawnConfig, biomes: &[TerrainType]) {
+        let (min_size, max_size) = deposit.cluster_size;
+        let size = self.rng.gen_range(min_size.max(1)..=max_size.max(min_size).max(1));
+        let mut placed = 0;
+        // Allow a few misses per node before giving up on a crowded field
+        for attempt in 0..size * 8 {
+            if placed == size {
+                break;
+            }
+            let position = if attempt == 0 {
+                seed
+            } else {
+                let angle = self.rng.gen::<f32>() * std::f32::consts::TAU;
+                let distance = self.rng.gen::<f32>().sqrt() * deposit.cluster_radius;
+                seed + Vec2::new(angle.cos(), angle.sin()) * distance
+            };
+            if self.can_place(position, deposit, biomes) {
+                let variation = 1.0 + (self.rng.gen::<f32>() * 2.0 - 1.0) * deposit.amount_variation;
+                let amount = (deposit.base_amount * variation).max(1.0);
+                self.placed.push(PlannedNode {
+                    position,
+                    node: ResourceNode::new(deposit.resource_type, amount)
+                        .with_regeneration(deposit.regeneration)
+                        .with_depletion(deposit.on_depleted),
+                });
+                placed += 1;
+            }
+        }
+    }
+
+    fn can_place(&self, position: Vec2, deposit: &ResourceSpawnConfig, biomes: &[TerrainType]) -> bool {
+        let Some(terrain) = self.terrain.terrain_at(position) else {
+            return false;
+        };
+        let terrain_ok = if biomes.is_empty() {
+            terrain != TerrainType::Water
+        } else {
+            biomes.contains(&terrain)
+        };
+        let spacing_sq = deposit.min_spacing * deposit.min_spacing;
+        terrain_ok && self.placed.iter().all(|other| other.position.distance_squared(position) >= spacing_sq)
+    }
+}
+
+/// System to load the resource spawn table, falling back to the built-in table
+pub fn load_resource_spawn_table(mut commands: Commands) {
+    let table = match ResourceSpawnTable::load_from_file(SPAWN_TABLE_FILE) {
+        Ok(table) => table,
+        Err(err) => {
+            warn!("{}; using built-in spawn table", err);
+            ResourceSpawnTable::builtin()
+        }
+    };
+    commands.insert_resource(table);
 }
 
 /// System to spawn initial resources in the world
@@ -44,109 +287,31 @@ pub fn spawn_initial_resources(
     mut commands: Commands,
     world_query: Query<Entity, With<GameWorld>>,
     config: Res<WorldConfig>,
+    table: Res<ResourceSpawnTable>,
+    start_positions: Option<Res<StartPositions>>,
     terrain_query: Query<(&TerrainTile, &Transform), Without<ResourceNodeMarker>>,
 ) {
-    if let Ok(world_entity) = world_query.get_single() {
-        // Define resource spawn configurations based on world config
-        let resource_configs = vec![
-            ResourceSpawnConfig {
-                resource_type: ResourceType::Minerals, // Using Minerals instead of Wood
-                base_amount: 100.0,
-                can_regenerate: true,
-                spawn_biomes: vec![TerrainType::Forest, TerrainType::Grassland],
-                density: 0.15 * config.resource_density * 10.0, // Scale density based on config
-                amount_variation: 0.5,
-            },
-            ResourceSpawnConfig {
-                resource_type: ResourceType::Minerals, // Using Minerals instead of Stone
-                base_amount: 50.0,
-                can_regenerate: false,
-                spawn_biomes: vec![TerrainType::Mountains],
-                density: 0.1 * config.resource_density * 10.0, // Scale density based on config
-                amount_variation: 0.4,
-            },
-            ResourceSpawnConfig {
-                resource_type: ResourceType::Crystals, // Using Crystals instead of Iron
-                base_amount: 25.0,
-                can_regenerate: false,
-                spawn_biomes: vec![TerrainType::Mountains],
-                density: 0.05 * config.resource_density * 10.0, // Scale density based on config
-                amount_variation: 0.3,
-            },
-        ];
-
-        // Spawn each resource type according to its configuration
-        for config in resource_configs {
-            // Collect valid positions for this resource type
-            let mut valid_positions = Vec::new();
-            
-            // Find all valid positions for this resource type
-            for (terrain_tile, transform) in &terrain_query {
-                if config.spawn_biomes.contains(&terrain_tile.terrain_type) {
-                    valid_positions.push(transform.translation.truncate());
-                }
-            }
-            
-            // Shuffle the positions to get random distribution
-            let mut rng = rand::thread_rng();
-            valid_positions.shuffle(&mut rng);
-            
-            // Calculate how many resources to spawn based on density
-            let num_resources = (valid_positions.len() as f32 * config.density) as usize;
-            
-            // Spawn resources at the selected positions
-            for pos in valid_positions.into_iter().take(num_resources) {
-                // Calculate amount with variation
-                let variation = 1.0 + (rng.gen::<f32>() * 2.0 - 1.0) * config.amount_variation;
-                let amount = (config.base_amount * variation).max(1.0);
-                
-                spawn_resource_node(
-                    &mut commands,
-                    world_entity,
-                    config.resource_type,
-                    pos,
-                    amount,
-                    config.can_regenerate,
-                );
-            }
-        }
-        
-        // Add copper to the resource configurations
-        let copper_config = ResourceSpawnConfig {
-            resource_type: ResourceType::Gas, // Using Gas instead of Copper
-            base_amount: 60.0,
-            can_regenerate: false,
-            spawn_biomes: vec![TerrainType::Mountains],
-            density: 0.02,  // Rarer than other resources
-            amount_variation: 0.4,
-        };
-        
-        // Spawn copper nodes
-        let mut valid_positions = Vec::new();
-        for (terrain_tile, transform) in &terrain_query {
-            if copper_config.spawn_biomes.contains(&terrain_tile.terrain_type) {
-                valid_positions.push(transform.translation.truncate());
-            }
-        }
-        
-        // Shuffle and take the required number of positions
-        let mut rng = rand::thread_rng();
-        valid_positions.shuffle(&mut rng);
-        let num_resources = (valid_positions.len() as f32 * copper_config.density) as usize;
-        
-        for pos in valid_positions.into_iter().take(num_resources) {
-            let variation = 1.0 + (rng.gen::<f32>() * 2.0 - 1.0) * copper_config.amount_variation;
-            let amount = (copper_config.base_amount * variation).max(1.0);
-            
-            spawn_resource_node(
-                &mut commands,
-                world_entity,
-                copper_config.resource_type,
-                pos,
-                amount,
-                copper_config.can_regenerate,
-            );
+    let Ok(world_entity) = world_query.get_single() else {
+        return;
+    };
+
+    let start_positions = match start_positions {
+        Some(positions) => positions.clone(),
+        None => {
+            let positions = StartPositions::ring(&config, 2);
+            commands.insert_resource(positions.clone());
+            positions
         }
+    };
+
+    let tiles: Vec<(TerrainType, Vec2)> = terrain_query.iter()
+        .map(|(tile, transform)| (tile.terrain_type, transform.translation.truncate()))
+        .collect();
+    let planned = ResourceSpawnPlanner::new(&table, &config, &tiles).plan(&start_positions.0);
+    info!("Spawning {} resource nodes", planned.len());
+
+    for PlannedNode { position, node } in planned {
+        spawn_resource_node(&mut commands, world_entity, position, node);
     }
 }
 
@@ -154,20 +319,78 @@ pub fn spawn_initial_resources(
 fn spawn_resource_node(
     commands: &mut Commands,
     world_entity: Entity,
-    resource_type: ResourceType,
     position: Vec2,
-    amount: f32,
-    can_regenerate: bool,
+    node: ResourceNode,
 ) -> Entity {
     let node_entity = commands.spawn((
-        ResourceNode::new(resource_type, amount, can_regenerate),
+        node,
         ResourceNodeMarker,
         Transform::from_xyz(position.x, position.y, 1.0),
         GlobalTransform::default(),
     )).id();
-    
+
     // Make the resource node a child of the world
     commands.entity(world_entity).add_child(node_entity);
-    
+
     node_entity
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::DEFAULT_RESOURCE_DENSITY;
+
+    fn forest_tiles(config: &WorldConfig) -> Vec<(TerrainType, Vec2)> {
+        let columns = (config.playable_width() / config.tile_size) as i32;
+        let rows = (config.playable_height() / config.tile_size) as i32;
+        (0..columns)
+            .flat_map(|x| (0..rows).map(move |y| (x, y)))
+            .map(|(x, y)| {
+                let position = Vec2::splat(config.border_width) + Vec2::new(x as f32, y as f32) * config.tile_size;
+                (TerrainType::Forest, position)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_spawn_planner_clusters_spaces_and_scales() {
+        let table = ResourceSpawnTable::builtin();
+        let loaded = ResourceSpawnTable::load_from_file(SPAWN_TABLE_FILE).expect("spawn table loads");
+        assert_eq!(table.biomes.len(), loaded.biomes.len());
+        assert_eq!(table.guaranteed.len(), loaded.guaranteed.len());
+
+        let config = WorldConfig { seed: 7, ..default() };
+        let tiles = forest_tiles(&config);
+        let start = Vec2::new(config.width_meters, config.height_meters) / 2.0;
+        let plan = |config: &WorldConfig, starts: &[Vec2]| ResourceSpawnPlanner::new(&table, config, &tiles).plan(starts);
+
+        // The same seed always yields the same map
+        let nodes = plan(&config, &[start]);
+        let again = plan(&config, &[start]);
+        assert!(!nodes.is_empty());
+        assert_eq!(
+            nodes.iter().map(|node| node.position).collect::<Vec<_>>(),
+            again.iter().map(|node| node.position).collect::<Vec<_>>(),
+        );
+
+        // Forest deposits keep their minimum spacing
+        for (i, a) in nodes.iter().enumerate() {
+            for b in &nodes[i + 1..] {
+                assert!(a.position.distance(b.position) >= 12.0 - 1e-3, "{} and {} are too close", a.position, b.position);
+            }
+        }
+
+        // Each start gets its guaranteed field even with no density fields at all
+        let barren = WorldConfig { resource_density: 0.0, ..config.clone() };
+        let guaranteed = &table.guaranteed[0];
+        let near_start = plan(&barren, &[start]);
+        assert_eq!(near_start.len() as u32, guaranteed.deposit.cluster_size.1);
+        let reach = guaranteed.max_distance + guaranteed.deposit.cluster_radius;
+        assert!(near_start.iter().all(|node| node.position.distance(start) <= reach));
+        assert!(plan(&barren, &[]).is_empty());
+
+        // Doubling the density seeds more fields
+        let dense = WorldConfig { resource_density: DEFAULT_RESOURCE_DENSITY * 2.0, ..config.clone() };
+        assert!(plan(&dense, &[]).len() > plan(&config, &[]).len());
+    }
+}
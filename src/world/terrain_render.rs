@@ -5,19 +5,25 @@ use super::{TerrainTile, TerrainType, ResourceNode};
 #[derive(Component)]
 pub struct TerrainTileVisual;
 
+/// Get the display color for a terrain type
+pub fn terrain_color(terrain_type: TerrainType) -> Color {
+    match terrain_type {
+        TerrainType::Grassland => Color::srgb(0.2, 0.8, 0.2),    // Green
+        TerrainType::Forest => Color::srgb(0.0, 0.6, 0.0),      // Dark Green
+        TerrainType::Mountains => Color::srgb(0.5, 0.5, 0.5),   // Gray
+        TerrainType::Desert => Color::srgb(0.93, 0.79, 0.69),   // Sand
+        TerrainType::Water => Color::srgb(0.0, 0.4, 0.8),       // Blue
+        TerrainType::Quarry => Color::srgb(0.4, 0.36, 0.32),    // Dusty brown
+    }
+}
+
 /// System to add sprites to terrain tiles
 pub fn add_terrain_visuals(
     mut commands: Commands,
     query: Query<(Entity, &TerrainTile), (Without<TerrainTileVisual>, Without<ResourceNode>)>,
 ) {
     for (entity, tile) in &query {
-        let color = match tile.terrain_type {
-            TerrainType::Grassland => Color::srgb(0.2, 0.8, 0.2),    // Green
-            TerrainType::Forest => Color::srgb(0.0, 0.6, 0.0),      // Dark Green
-            TerrainType::Mountains => Color::srgb(0.5, 0.5, 0.5),   // Gray
-            TerrainType::Desert => Color::srgb(0.93, 0.79, 0.69),   // Sand
-            TerrainType::Water => Color::srgb(0.0, 0.4, 0.8),       // Blue
-        };
+        let color = terrain_color(tile.terrain_type);
 
 
         commands.entity(entity).insert((
@@ -35,11 +41,22 @@ pub fn add_terrain_visuals(
     }
 }
 
+/// Terrain tile sprites whose tile changed type
+type TileSprites<'w, 's> =
+    Query<'w, 's, (&'static TerrainTile, &'static mut Sprite), (With<TerrainTileVisual>, Changed<TerrainTile>)>;
+
+/// System to recolor terrain tiles whose type has changed (e.g. a forest cut down)
+pub fn update_terrain_visuals(mut query: TileSprites) {
+    for (tile, mut sprite) in &mut query {
+        sprite.color = terrain_color(tile.terrain_type);
+    }
+}
+
 /// Plugin for terrain rendering
 pub struct TerrainRenderPlugin;
 
 impl Plugin for TerrainRenderPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, add_terrain_visuals);
+        app.add_systems(Update, (add_terrain_visuals, update_terrain_visuals));
     }
 }